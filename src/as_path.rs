@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::ffi::{CStr, CString, OsStr, OsString};
 use std::io;
 use std::os::unix::prelude::*;
@@ -60,7 +61,11 @@ macro_rules! cstr_impl {
     };
 }
 
-asref_impl! { &Path PathBuf &PathBuf &str String &String &OsStr OsString &OsString }
+asref_impl! {
+    &Path PathBuf &PathBuf Cow<'_, Path>
+    &str String &String
+    &OsStr OsString &OsString Cow<'_, OsStr>
+}
 
 cstr_impl! { &CStr CString &CString }
 
@@ -77,6 +82,10 @@ mod tests {
 
         assert_eq!(OsStr::new("abc/def").as_path(), Path::new("abc/def"));
         assert_eq!(OsString::from("abc/def").as_path(), Path::new("abc/def"));
+        assert_eq!(
+            Cow::Borrowed(OsStr::new("abc/def")).as_path(),
+            Path::new("abc/def")
+        );
 
         assert_eq!(
             CStr::from_bytes_with_nul(b"abc/def\0").unwrap().as_path(),
@@ -0,0 +1,90 @@
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A callback that is invoked for every path component that is resolved, for auditing purposes.
+///
+/// Set it with [`OpenBeneath::audit()`] (or [`OpenOptions::audit()`]). The callback receives a
+/// [`ResolveEvent`] describing each step that the path resolver takes, in order, so that it can
+/// be logged (for example, to record exactly how a request from an untrusted client was resolved,
+/// including every symlink that was followed).
+///
+/// `AuditHook`s are cheap to clone (they are reference-counted), so a single hook can be shared by
+/// many builders.
+///
+/// [`OpenBeneath::audit()`]: ./struct.OpenBeneath.html#method.audit
+/// [`OpenOptions::audit()`]: ./struct.OpenOptions.html#method.audit
+/// [`ResolveEvent`]: ./struct.ResolveEvent.html
+#[derive(Clone)]
+pub struct AuditHook(Arc<dyn Fn(&ResolveEvent<'_>) + Send + Sync>);
+
+impl AuditHook {
+    /// Create a new hook that calls `f` for every event.
+    #[inline]
+    pub fn new<F: Fn(&ResolveEvent<'_>) + Send + Sync + 'static>(f: F) -> Self {
+        Self(Arc::new(f))
+    }
+
+    #[inline]
+    pub(crate) fn emit(&self, event: &ResolveEvent<'_>) {
+        (self.0)(event)
+    }
+}
+
+impl fmt::Debug for AuditHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AuditHook")
+            .field(&Arc::as_ptr(&self.0))
+            .finish()
+    }
+}
+
+/// A single step of path resolution, as passed to an [`AuditHook`].
+///
+/// [`AuditHook`]: ./struct.AuditHook.html
+#[derive(Debug)]
+pub struct ResolveEvent<'a> {
+    pub(crate) component: &'a OsStr,
+    pub(crate) flags: libc::c_int,
+    pub(crate) symlink_target: Option<&'a OsStr>,
+    pub(crate) result: Result<(), &'a io::Error>,
+}
+
+impl<'a> ResolveEvent<'a> {
+    /// Get the path component that was resolved.
+    ///
+    /// This is `/` for a leading slash (or the leading slash of an absolute symlink target) and
+    /// `..` for parent directory components. `.` components and empty components are skipped by
+    /// the resolver, so they never appear here.
+    #[inline]
+    pub fn component(&self) -> &'a OsStr {
+        self.component
+    }
+
+    /// Get the flags that were used to open the component.
+    ///
+    /// This is the flags that the file was opened with for the final component, and the flags
+    /// used internally to open directories for every other component.
+    #[inline]
+    pub fn flags(&self) -> libc::c_int {
+        self.flags
+    }
+
+    /// If the component was a symlink, get the symlink's target (which will be resolved next, in
+    /// place of the symlink).
+    #[inline]
+    pub fn symlink_target(&self) -> Option<&'a Path> {
+        self.symlink_target.map(Path::new)
+    }
+
+    /// Get the result of resolving the component: `Ok(())` if it succeeded, or the error that
+    /// caused path resolution to fail.
+    ///
+    /// If this is an error, it is the last event for this attempt.
+    #[inline]
+    pub fn result(&self) -> Result<(), &'a io::Error> {
+        self.result
+    }
+}
@@ -0,0 +1,83 @@
+//! Helpers for using `obnth` inside [Capsicum] sandboxes (FreeBSD only).
+//!
+//! Once the process has entered capability mode (see [`enter()`]), it can only access the
+//! filesystem relative to directory file descriptors it already holds, and the kernel prevents
+//! lookups from escaping those directories. In capability mode, [`open_beneath()`] (and all the
+//! methods of [`Dir`] built on it) let the kernel perform the lookup when `lookup_flags` is empty
+//! (or only contains `NO_MAGICLINKS`). Other lookup flags fall back to the
+//! userspace resolver, which needs to open `..` to verify that it hasn't escaped, so they may
+//! fail with `ENOTCAPABLE`.
+//!
+//! [Capsicum]: https://man.freebsd.org/cgi/man.cgi?query=capsicum&sektion=4
+//! [`enter()`]: fn.enter.html
+//! [`open_beneath()`]: ../fn.open_beneath.html
+//! [`Dir`]: ../struct.Dir.html
+
+use std::io;
+use std::os::unix::prelude::*;
+
+/// The rights required to open and read files beneath a directory (and to list its contents).
+///
+/// This can be passed to [`limit_rights()`] to make a [`Dir`] read-only.
+///
+/// [`limit_rights()`]: fn.limit_rights.html
+/// [`Dir`]: ../struct.Dir.html
+pub const DIR_READ_RIGHTS: &[u64] = &[
+    libc::CAP_LOOKUP,
+    libc::CAP_READ,
+    libc::CAP_SEEK,
+    libc::CAP_FSTAT,
+    libc::CAP_FSTATAT,
+    libc::CAP_FSTATFS,
+    libc::CAP_FCNTL,
+];
+
+/// Enter capability mode (see `cap_enter(2)`).
+///
+/// This cannot be undone, and it applies to the entire process.
+#[inline]
+pub fn enter() -> io::Result<()> {
+    if unsafe { libc::cap_enter() } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Check whether the current process is in capability mode.
+///
+/// This returns `false` if the kernel was built without Capsicum support.
+#[inline]
+pub fn in_capability_mode() -> bool {
+    unsafe { libc::cap_sandboxed() }
+}
+
+/// Limit the rights of the given file descriptor to the `CAP_*` rights listed in `rights` (see
+/// `cap_rights_limit(2)`).
+///
+/// Rights can only be removed, never added back. Note that files opened beneath a [`Dir`] whose
+/// rights have been limited inherit those limits.
+///
+/// [`Dir`]: ../struct.Dir.html
+pub fn limit_rights<F: AsRawFd + ?Sized>(f: &F, rights: &[u64]) -> io::Result<()> {
+    let mut cap_rights = std::mem::MaybeUninit::<libc::cap_rights_t>::uninit();
+
+    unsafe {
+        // The variadic argument lists are terminated by 0
+        libc::__cap_rights_init(libc::CAP_RIGHTS_VERSION, cap_rights.as_mut_ptr(), 0u64);
+
+        for &right in rights {
+            libc::__cap_rights_set(cap_rights.as_mut_ptr(), right, 0u64);
+        }
+
+        if !libc::cap_rights_is_valid(cap_rights.as_ptr()) {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        if libc::cap_rights_limit(f.as_raw_fd(), cap_rights.as_ptr()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
@@ -1,20 +1,56 @@
-// On Linux/Android, O_PATH provides similar (though not *quite* identical) semantics to POSIX's
-// O_SEARCH
-#[cfg(any(target_os = "linux", target_os = "android"))]
-pub const DIR_OPEN_FLAGS: libc::c_int = libc::O_PATH | libc::O_DIRECTORY;
+cfg_if::cfg_if! {
+    if #[cfg(feature = "portable-fallback")] {
+        // With the "portable-fallback" feature, always use the lowest common denominator (see the
+        // final branch below), even if the platform has an equivalent of O_SEARCH.
+        pub const DIR_OPEN_FLAGS: libc::c_int = libc::O_RDONLY | libc::O_DIRECTORY;
+    } else if #[cfg(any(target_os = "linux", target_os = "android"))] {
+        // On Linux/Android, O_PATH provides similar (though not *quite* identical) semantics to
+        // POSIX's O_SEARCH
+        pub const DIR_OPEN_FLAGS: libc::c_int = libc::O_PATH | libc::O_DIRECTORY;
+    } else if #[cfg(target_os = "freebsd")] {
+        // On FreeBSD, O_SEARCH (added in newer versions) is just an alias for O_EXEC, and O_EXEC
+        // on directories has historically had similar semantics to POSIX's O_SEARCH (even before
+        // the alias was added)
+        pub const DIR_OPEN_FLAGS: libc::c_int = libc::O_EXEC | libc::O_DIRECTORY;
+    } else if #[cfg(any(target_os = "solaris", target_os = "illumos"))] {
+        // Solaris/illumos support POSIX's O_SEARCH directly
+        pub const DIR_OPEN_FLAGS: libc::c_int = libc::O_SEARCH | libc::O_DIRECTORY;
+    } else {
+        // No (known) equivalent of O_SEARCH on the current platform.
+        // O_RDONLY will usually work OK, except when encountering directories with modes like
+        // --x------ (0o100). Unfortunately, we just won't be able to handle those cases on these
+        // platforms.
+        pub const DIR_OPEN_FLAGS: libc::c_int = libc::O_RDONLY | libc::O_DIRECTORY;
+    }
+}
 
-// On FreeBSD, O_SEARCH (added in newer versions) is just an alias for O_EXEC, and O_EXEC on
-// directories has historically had similar semantics to POSIX's O_SEARCH (even before the alias was
-// added)
-#[cfg(target_os = "freebsd")]
-pub const DIR_OPEN_FLAGS: libc::c_int = libc::O_EXEC | libc::O_DIRECTORY;
-
-// No (known) equivalent of O_SEARCH on the current platform.
-// O_RDONLY will usually work OK, except when encountering directories with modes like --x------
-// (0o100). Unfortunately, we just won't be able to handle those cases on these platforms.
-#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
-pub const DIR_OPEN_FLAGS: libc::c_int = libc::O_RDONLY | libc::O_DIRECTORY;
+// Flags used to open a file descriptor that refers to a file without needing to read or write it
+// (for example, to change its ownership after following symlinks). Elsewhere (or with the
+// "portable-fallback" feature), we have to open the file for reading; O_NONBLOCK prevents hanging
+// on FIFOs.
+#[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    not(feature = "portable-fallback"),
+))]
+pub const REF_OPEN_FLAGS: libc::c_int = libc::O_PATH;
+#[cfg(not(all(
+    any(target_os = "linux", target_os = "android"),
+    not(feature = "portable-fallback"),
+)))]
+pub const REF_OPEN_FLAGS: libc::c_int = libc::O_RDONLY | libc::O_NONBLOCK;
 
 // Linux's default (it seems sysconf(_SC_SYMLOOP_MAX) always fails on glibc, and this is a
 // reasonable limit)
 pub const DEFAULT_SYMLOOP_MAX: u16 = 40;
+
+// The path resolver stores path components on the stack (and only allocates for paths that are
+// longer than this, or have more components) if the "inline-paths" feature is enabled. Otherwise
+// the buffers are always allocated on the heap.
+#[cfg(feature = "inline-paths")]
+pub const INLINE_PATH_BYTES: usize = 256;
+#[cfg(feature = "inline-paths")]
+pub const INLINE_PATH_COMPONENTS: usize = 32;
+#[cfg(not(feature = "inline-paths"))]
+pub const INLINE_PATH_BYTES: usize = 0;
+#[cfg(not(feature = "inline-paths"))]
+pub const INLINE_PATH_COMPONENTS: usize = 0;
@@ -0,0 +1,169 @@
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::Path;
+
+use crate::{constants, util, AsPath, Dir, LookupFlags, Rights};
+
+use super::{cstr, gid_or_unchanged, prepare_inner_operation, uid_or_unchanged};
+
+/// A builder used to create directories within a `Dir`.
+///
+/// This is analogous to `std::fs::DirBuilder`, except that every lookup is performed within a
+/// specific `Dir` (and is subject to the given lookup flags).
+///
+/// A `DirBuilder` can be created with [`Dir::dir_builder()`].
+///
+/// [`Dir::dir_builder()`]: ./struct.Dir.html#method.dir_builder
+#[derive(Clone, Debug)]
+pub struct DirBuilder<'a> {
+    dir: &'a Dir,
+    recursive: bool,
+    mode: libc::mode_t,
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+    lookup_flags: LookupFlags,
+}
+
+impl<'a> DirBuilder<'a> {
+    #[inline]
+    pub(crate) fn new(dir: &'a Dir) -> Self {
+        Self {
+            dir,
+            recursive: false,
+            mode: 0o777,
+            uid: None,
+            gid: None,
+            lookup_flags: LookupFlags::empty(),
+        }
+    }
+
+    /// Recursively create all missing parent directories.
+    ///
+    /// If this is enabled, creating a directory that already exists is not an error.
+    #[inline]
+    pub fn recursive(&mut self, recursive: bool) -> &mut Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Set the mode with which new directories are created (default `0o777`).
+    ///
+    /// This will be modified by the umask. It applies to every directory that is created, including
+    /// missing parent directories.
+    #[inline]
+    pub fn mode(&mut self, mode: libc::mode_t) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Change the owner of every directory that is created to the given user (default: leave it
+    /// unchanged).
+    ///
+    /// Existing directories are never modified.
+    #[inline]
+    pub fn owner(&mut self, uid: Option<libc::uid_t>) -> &mut Self {
+        self.uid = uid;
+        self
+    }
+
+    /// Change the group of every directory that is created to the given group (default: leave it
+    /// unchanged).
+    ///
+    /// Existing directories are never modified.
+    #[inline]
+    pub fn group(&mut self, gid: Option<libc::gid_t>) -> &mut Self {
+        self.gid = gid;
+        self
+    }
+
+    /// Set the lookup flags used when resolving paths.
+    #[inline]
+    pub fn lookup_flags(&mut self, flags: LookupFlags) -> &mut Self {
+        self.lookup_flags = flags;
+        self
+    }
+
+    /// Create the directory at the given path.
+    pub fn create<P: AsPath>(&self, path: P) -> io::Result<()> {
+        if self.uid.is_some() || self.gid.is_some() {
+            self.dir.check_rights(Rights::CREATE | Rights::SETATTR)?;
+        } else {
+            self.dir.check_rights(Rights::CREATE)?;
+        }
+
+        let path = path.as_path();
+
+        if self.recursive {
+            self.create_all(path)
+        } else {
+            self.create_one(path)
+        }
+    }
+
+    fn create_one(&self, path: &Path) -> io::Result<()> {
+        let (subdir, fname) = prepare_inner_operation(self.dir, path, self.lookup_flags)?;
+        let subdir = subdir.as_ref().unwrap_or(self.dir);
+
+        let fname = match fname {
+            Some(fname) => cstr(&fname)?,
+            None => return Err(io::Error::from_raw_os_error(libc::EEXIST)),
+        };
+
+        util::mkdirat(subdir.as_raw_fd(), &fname, self.mode)?;
+
+        if self.uid.is_some() || self.gid.is_some() {
+            // Open the new directory (without following symlinks, in case it was replaced) and
+            // change its ownership through the file descriptor
+            let new_dir = util::openat(
+                subdir.as_raw_fd(),
+                &fname,
+                constants::DIR_OPEN_FLAGS | libc::O_NOFOLLOW,
+                0,
+            )?;
+
+            util::fchown_any(
+                new_dir.as_raw_fd(),
+                uid_or_unchanged(self.uid),
+                gid_or_unchanged(self.gid),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn create_all(&self, path: &Path) -> io::Result<()> {
+        if path.as_os_str().is_empty() {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        }
+
+        match self.create_one(path) {
+            Ok(()) => return Ok(()),
+            // A parent directory is missing
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => (),
+            Err(e) => return self.check_dir_exists(path, e),
+        }
+
+        match util::path_split(path) {
+            Some((Some(parent), _)) => self.create_all(Path::new(parent))?,
+            // No parent directory to create
+            _ => return Err(io::Error::from_raw_os_error(libc::ENOENT)),
+        }
+
+        match self.create_one(path) {
+            Ok(()) => Ok(()),
+            Err(e) => self.check_dir_exists(path, e),
+        }
+    }
+
+    /// If `err` is `EEXIST` and `path` refers to a directory (possibly one that was created by
+    /// somebody else in the meantime), return `Ok(())`. Otherwise, return `err`.
+    fn check_dir_exists(&self, path: &Path, err: io::Error) -> io::Result<()> {
+        if err.raw_os_error() == Some(libc::EEXIST)
+            && self.dir.sub_dir(path, self.lookup_flags).is_ok()
+        {
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+}
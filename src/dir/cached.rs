@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::{open_beneath, util, AsPath, Dir, LookupFlags};
+
+use super::Metadata;
+
+/// The default maximum number of directories cached by a [`CachedDir`].
+///
+/// [`CachedDir`]: ./struct.CachedDir.html
+pub const DEFAULT_CACHE_SIZE: usize = 64;
+
+#[derive(Debug)]
+struct CacheEntry {
+    dir: Arc<Dir>,
+    dev: u64,
+    ino: u64,
+}
+
+/// A wrapper around a [`Dir`] that caches the subdirectories used to open files.
+///
+/// When a file like `static/css/site.css` is opened through a `CachedDir`, the directory
+/// `static/css` is resolved (safely) and the resulting file descriptor is cached. Later lookups
+/// of other files in the same directory (like `static/css/print.css`) then only need to resolve
+/// the final component.
+///
+/// Before a cached directory is used, its device and inode numbers are compared with the current
+/// result of `stat()`ing its path (relative to the wrapped directory). If they don't match (for
+/// example, because the directory was renamed or replaced), it is resolved again. Regardless of
+/// the result of this check, a cached directory was always resolved safely, so using it can never
+/// escape the wrapped directory. (However, like any open directory, it may be moved outside of it
+/// by another process.)
+///
+/// Caching is not performed for [`LookupFlags::IN_ROOT`] lookups, and if a lookup relative to a
+/// cached directory fails with `EXDEV` (for example, because a symlink in the final component
+/// points to a file in a parent directory), it is retried from the wrapped directory.
+///
+/// A `CachedDir` can be shared between threads.
+///
+/// [`Dir`]: ./struct.Dir.html
+/// [`LookupFlags::IN_ROOT`]: ./struct.LookupFlags.html#associatedconstant.IN_ROOT
+#[derive(Debug)]
+pub struct CachedDir {
+    dir: Dir,
+    max_entries: usize,
+    cache: Mutex<HashMap<(PathBuf, LookupFlags), CacheEntry>>,
+}
+
+impl CachedDir {
+    /// Wrap the given directory, caching up to [`DEFAULT_CACHE_SIZE`] subdirectories.
+    ///
+    /// [`DEFAULT_CACHE_SIZE`]: ./constant.DEFAULT_CACHE_SIZE.html
+    #[inline]
+    pub fn new(dir: Dir) -> Self {
+        Self::with_capacity(dir, DEFAULT_CACHE_SIZE)
+    }
+
+    /// Wrap the given directory, caching up to `max_entries` subdirectories.
+    ///
+    /// When the cache is full, it is cleared before adding more entries.
+    #[inline]
+    pub fn with_capacity(dir: Dir, max_entries: usize) -> Self {
+        Self {
+            dir,
+            max_entries,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get a reference to the wrapped directory.
+    #[inline]
+    pub fn dir(&self) -> &Dir {
+        &self.dir
+    }
+
+    /// Unwrap this `CachedDir`, returning the wrapped directory (and closing all cached
+    /// subdirectories).
+    #[inline]
+    pub fn into_inner(self) -> Dir {
+        self.dir
+    }
+
+    /// Remove all cached subdirectories.
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    /// Open the file at `path` within the wrapped directory.
+    ///
+    /// This is exactly equivalent to `open_beneath(self.dir(), path, flags, mode, lookup_flags)`,
+    /// except that the cache is used to look up the directory containing the file.
+    pub fn open<P: AsPath>(
+        &self,
+        path: P,
+        flags: libc::c_int,
+        mode: libc::mode_t,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<fs::File> {
+        self.dir.check_rights(super::open_rights(flags))?;
+
+        let path = path.as_path();
+
+        self.with_parent(path, lookup_flags, |parent, fname| {
+            open_beneath(parent.as_raw_fd(), fname, flags, mode, lookup_flags)
+        })
+    }
+
+    /// Open a subdirectory of the wrapped directory.
+    ///
+    /// This is equivalent to [`Dir::sub_dir()`], except that the cache is used to look up the
+    /// directory containing the subdirectory.
+    ///
+    /// [`Dir::sub_dir()`]: ./struct.Dir.html#method.sub_dir
+    pub fn sub_dir<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<Dir> {
+        let path = path.as_path();
+
+        self.with_parent(path, lookup_flags, |parent, fname| {
+            parent.sub_dir(fname, lookup_flags)
+        })
+    }
+
+    /// Retrieve information on the file at `path` within the wrapped directory.
+    ///
+    /// This is equivalent to [`Dir::metadata()`] (symlinks in the final component are not
+    /// followed), except that the cache is used to look up the directory containing the file.
+    ///
+    /// [`Dir::metadata()`]: ./struct.Dir.html#method.metadata
+    pub fn metadata<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<Metadata> {
+        let path = path.as_path();
+
+        self.with_parent(path, lookup_flags, |parent, fname| {
+            parent.metadata(fname, lookup_flags)
+        })
+    }
+
+    /// Call `f` with the (possibly cached) directory containing the final component of `path`
+    /// and the final component, falling back on calling it with the wrapped directory and the
+    /// full path.
+    fn with_parent<T, F>(&self, path: &Path, lookup_flags: LookupFlags, mut f: F) -> io::Result<T>
+    where
+        F: FnMut(&Dir, &Path) -> io::Result<T>,
+    {
+        let (parent, fname) = match util::path_split(path) {
+            Some((Some(parent), fname))
+                if !lookup_flags.contains(LookupFlags::IN_ROOT) && is_cacheable(parent) =>
+            {
+                (Path::new(parent), fname)
+            }
+            _ => return f(&self.dir, path),
+        };
+
+        // If the parent directory can't be resolved, let the full lookup report the error
+        let parent = match self.get_parent(parent, lookup_flags) {
+            Ok(parent) => parent,
+            Err(_) => return f(&self.dir, path),
+        };
+
+        match f(&parent, Path::new(fname)) {
+            Err(e) if e.raw_os_error() == Some(libc::EXDEV) => f(&self.dir, path),
+            res => res,
+        }
+    }
+
+    fn get_parent(&self, parent: &Path, lookup_flags: LookupFlags) -> io::Result<Arc<Dir>> {
+        let key = (parent.to_path_buf(), lookup_flags);
+
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(&key)
+            .map(|entry| (entry.dir.clone(), entry.dev, entry.ino));
+
+        if let Some((dir, dev, ino)) = cached {
+            // Make sure the path still refers to the same directory, resolving it the same way
+            // sub_dir() did (so symlinks can't redirect the check outside of the directory)
+            match self.dir.metadata_follow(parent, lookup_flags) {
+                Ok(meta) if meta.dev() == dev && meta.ino() == ino => return Ok(dir),
+                _ => {
+                    self.cache.lock().unwrap().remove(&key);
+                }
+            }
+        }
+
+        let dir = Arc::new(self.dir.sub_dir(parent, lookup_flags)?);
+        let meta = dir.self_metadata()?;
+
+        if self.max_entries > 0 {
+            let mut cache = self.cache.lock().unwrap();
+            if cache.len() >= self.max_entries {
+                cache.clear();
+            }
+
+            cache.insert(
+                key,
+                CacheEntry {
+                    dir: dir.clone(),
+                    dev: meta.dev(),
+                    ino: meta.ino(),
+                },
+            );
+        }
+
+        Ok(dir)
+    }
+}
+
+impl From<Dir> for CachedDir {
+    #[inline]
+    fn from(dir: Dir) -> Self {
+        Self::new(dir)
+    }
+}
+
+/// Only relative paths consisting entirely of normal components (or `.`) are cached.
+fn is_cacheable(parent: &OsStr) -> bool {
+    Path::new(parent)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
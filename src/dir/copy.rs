@@ -0,0 +1,520 @@
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+
+use crate::{open_beneath, util, AsPath, Dir, LookupFlags, Rights};
+
+/// Controls whether [`CopyOptions::copy()`] creates a reflink (a copy-on-write clone that shares
+/// storage with the original file).
+///
+/// [`CopyOptions::copy()`]: ./struct.CopyOptions.html#method.copy
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Reflink {
+    /// Always create a reflink; fail (usually with `EOPNOTSUPP` or `EXDEV`) if that isn't
+    /// possible.
+    ///
+    /// On platforms other than macOS, the reflink is created in a temporary file, which is then
+    /// renamed over the "new" path; if that fails, the "new" path is left untouched. As a result,
+    /// if the "new" path is a symlink, it is replaced instead of followed.
+    Always,
+    /// Try to create a reflink, and fall back on copying the data if that isn't possible.
+    Auto,
+    /// Never create a reflink; always copy the data.
+    ///
+    /// `copy_file_range()` is not used in this mode either, since some filesystems implement it
+    /// by sharing extents.
+    Never,
+}
+
+/// Options for copying files with [`copy()`] semantics.
+///
+/// [`copy()`]: ./fn.copy.html
+#[derive(Clone, Debug)]
+pub struct CopyOptions {
+    reflink: Reflink,
+    sparse: bool,
+    lookup_flags: LookupFlags,
+}
+
+impl CopyOptions {
+    /// Create a new `CopyOptions` with the default settings (`Reflink::Auto`, no sparse copying,
+    /// and no lookup flags).
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            reflink: Reflink::Auto,
+            sparse: false,
+            lookup_flags: LookupFlags::empty(),
+        }
+    }
+
+    /// Set whether a reflink should be created (see [`Reflink`]).
+    ///
+    /// Reflinks are created with the `FICLONE` ioctl on Linux and `fclonefileat()` on macOS. On
+    /// macOS, a reflink can only be created if the "new" file does not exist yet; with
+    /// `Reflink::Always`, the copy will fail with `EEXIST` if it does.
+    ///
+    /// [`Reflink`]: ./enum.Reflink.html
+    #[inline]
+    pub fn reflink(&mut self, reflink: Reflink) -> &mut Self {
+        self.reflink = reflink;
+        self
+    }
+
+    /// Set whether holes in the "old" file should be preserved in the "new" file.
+    ///
+    /// If this is enabled (and a reflink isn't created), the data regions of the "old" file are
+    /// found with `lseek(SEEK_DATA/SEEK_HOLE)`, and only those regions are written to the "new"
+    /// file (so the holes are left unallocated). This is only supported on Linux, FreeBSD, and
+    /// macOS, and only if the filesystem supports `SEEK_DATA`/`SEEK_HOLE`; otherwise, the entire
+    /// file is copied as usual.
+    ///
+    /// In this mode, the returned "number of bytes copied" is the size of the file (including the
+    /// holes).
+    #[inline]
+    pub fn sparse(&mut self, sparse: bool) -> &mut Self {
+        self.sparse = sparse;
+        self
+    }
+
+    /// Set the lookup flags used when resolving both paths.
+    #[inline]
+    pub fn lookup_flags(&mut self, lookup_flags: LookupFlags) -> &mut Self {
+        self.lookup_flags = lookup_flags;
+        self
+    }
+
+    /// Copy the file at `old_path` (in `old_dir`) to `new_path` (in `new_dir`) using these
+    /// options, returning the number of bytes copied.
+    ///
+    /// See [`copy()`] for more details.
+    ///
+    /// [`copy()`]: ./fn.copy.html
+    pub fn copy<P: AsPath, R: AsPath>(
+        &self,
+        old_dir: &Dir,
+        old_path: P,
+        new_dir: &Dir,
+        new_path: R,
+    ) -> io::Result<u64> {
+        old_dir.check_rights(Rights::READ)?;
+        new_dir.check_rights(Rights::CREATE | Rights::WRITE)?;
+
+        let mut old_file =
+            open_beneath(old_dir.fd, old_path, libc::O_RDONLY, 0, self.lookup_flags)?;
+
+        let old_stat = util::fstat(old_file.as_raw_fd())?;
+        if old_stat.st_mode & libc::S_IFMT != libc::S_IFREG {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        let mode = old_stat.st_mode & 0o7777;
+
+        #[cfg(target_os = "macos")]
+        if self.reflink != Reflink::Never {
+            match self.clone_new(&old_file, new_dir, new_path.as_path()) {
+                Ok(()) => return Ok(old_stat.st_size as u64),
+                Err(e) if self.reflink == Reflink::Always || !is_clone_unsupported(&e) => {
+                    return Err(e)
+                }
+                Err(_) => (),
+            }
+        }
+
+        if self.reflink == Reflink::Always {
+            return self.clone_replace(&mut old_file, &old_stat, new_dir, new_path.as_path());
+        }
+
+        let mut new_file = open_beneath(
+            new_dir.fd,
+            new_path,
+            libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC,
+            mode,
+            self.lookup_flags,
+        )?;
+
+        // If the file already existed (or the umask interfered), the mode might not match
+        util::fchmod(new_file.as_raw_fd(), mode)?;
+
+        self.copy_data(&mut old_file, &old_stat, &mut new_file)
+    }
+
+    /// Copy the contents of `old_file` (whose metadata is `old_stat`) to the already opened
+    /// `new_file`, returning the number of bytes copied.
+    fn copy_data(
+        &self,
+        old_file: &mut fs::File,
+        old_stat: &libc::stat,
+        new_file: &mut fs::File,
+    ) -> io::Result<u64> {
+        #[cfg(target_os = "linux")]
+        if self.reflink != Reflink::Never {
+            match util::ficlone(new_file.as_raw_fd(), old_file.as_raw_fd()) {
+                Ok(()) => return Ok(old_stat.st_size as u64),
+                Err(e) if self.reflink == Reflink::Always || !is_clone_unsupported(&e) => {
+                    return Err(e)
+                }
+                Err(_) => (),
+            }
+        }
+
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        if self.reflink == Reflink::Always {
+            return Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP));
+        }
+
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "macos"
+        ))]
+        if self.sparse {
+            if let Some(n) = copy_sparse(old_file, new_file, old_stat.st_size as u64)? {
+                return Ok(n);
+            }
+        }
+
+        copy_contents(old_file, new_file, self.reflink != Reflink::Never)
+    }
+
+    /// Move the file at `old_path` (in `old_dir`) to `new_path` (in `new_dir`) using these
+    /// options.
+    ///
+    /// See [`move_file()`] for more details.
+    ///
+    /// [`move_file()`]: ./fn.move_file.html
+    pub fn move_file<P: AsPath, R: AsPath>(
+        &self,
+        old_dir: &Dir,
+        old_path: P,
+        new_dir: &Dir,
+        new_path: R,
+    ) -> io::Result<()> {
+        let (old_path, new_path) = (old_path.as_path(), new_path.as_path());
+
+        let rename_err =
+            match super::rename(old_dir, old_path, new_dir, new_path, self.lookup_flags) {
+                Err(e) if e.raw_os_error() == Some(libc::EXDEV) => e,
+                res => return res,
+            };
+
+        // The files are on different filesystems (or one of the paths escapes, in which case
+        // the lookups below will fail the same way)
+
+        old_dir.check_rights(Rights::READ | Rights::DELETE)?;
+        new_dir.check_rights(Rights::CREATE | Rights::WRITE)?;
+
+        let mut old_file = match open_beneath(
+            old_dir.fd,
+            old_path,
+            libc::O_RDONLY | libc::O_NOFOLLOW | libc::O_NONBLOCK,
+            0,
+            self.lookup_flags,
+        ) {
+            Ok(file) => file,
+            // Symlinks can't be copied this way
+            Err(e) if e.raw_os_error() == Some(libc::ELOOP) => return Err(rename_err),
+            Err(e) => return Err(e),
+        };
+
+        let old_stat = util::fstat(old_file.as_raw_fd())?;
+        if old_stat.st_mode & libc::S_IFMT != libc::S_IFREG {
+            // Only regular files can be copied
+            return Err(rename_err);
+        }
+        let mode = old_stat.st_mode & 0o7777;
+
+        let (subdir, fname) = super::prepare_inner_operation(new_dir, new_path, self.lookup_flags)?;
+        let fname = match fname {
+            Some(fname) => super::cstr(&fname)?,
+            None => return Err(io::Error::from_raw_os_error(libc::EISDIR)),
+        };
+        let fd = subdir.as_ref().unwrap_or(new_dir).as_raw_fd();
+
+        // Copy to a temporary file, then rename it over the "new" path, so the "new" path is
+        // never left partially written
+        let (tmp_name, mut tmp_file) = super::create_temp_file(fd, mode)?;
+
+        let res = util::fchmod(tmp_file.as_raw_fd(), mode)
+            .and_then(|()| self.copy_data(&mut old_file, &old_stat, &mut tmp_file))
+            .and_then(|_| {
+                let times = [
+                    util::timespec_from_stat(old_stat.st_atime, old_stat.st_atime_nsec),
+                    util::timespec_from_stat(old_stat.st_mtime, old_stat.st_mtime_nsec),
+                ];
+                util::futimens(tmp_file.as_raw_fd(), &times)
+            })
+            .and_then(|()| tmp_file.sync_all())
+            .and_then(|()| util::renameat(fd, &tmp_name, fd, &fname));
+
+        if let Err(e) = res {
+            let _ = util::unlinkat(fd, &tmp_name, false);
+            return Err(e);
+        }
+
+        // Make sure the new file is durable before the old one is removed
+        util::fsync_dir_any(fd)?;
+
+        // Only remove the "old" path if it still refers to the file that was copied
+        let (old_subdir, old_fname) =
+            super::prepare_inner_operation(old_dir, old_path, self.lookup_flags)?;
+        let old_fname = match old_fname {
+            Some(old_fname) => super::cstr(&old_fname)?,
+            None => return Err(io::Error::from_raw_os_error(libc::EISDIR)),
+        };
+        let old_fd = old_subdir.as_ref().unwrap_or(old_dir).as_raw_fd();
+
+        if !util::samestat(
+            &util::fstatat(old_fd, &old_fname, libc::AT_SYMLINK_NOFOLLOW)?,
+            &old_stat,
+        ) {
+            return Err(io::Error::from_raw_os_error(libc::EAGAIN));
+        }
+
+        util::unlinkat(old_fd, &old_fname, false)
+    }
+
+    /// Clone `old_file` into a temporary file in the directory containing `new_path`, then rename
+    /// it over `new_path`, so that a failed clone doesn't leave the "new" file created or
+    /// truncated.
+    fn clone_replace(
+        &self,
+        old_file: &mut fs::File,
+        old_stat: &libc::stat,
+        new_dir: &Dir,
+        new_path: &std::path::Path,
+    ) -> io::Result<u64> {
+        let (subdir, fname) = super::prepare_inner_operation(new_dir, new_path, self.lookup_flags)?;
+        let fname = match fname {
+            Some(fname) => super::cstr(&fname)?,
+            None => return Err(io::Error::from_raw_os_error(libc::EISDIR)),
+        };
+        let fd = subdir.as_ref().unwrap_or(new_dir).as_raw_fd();
+
+        let mode = old_stat.st_mode & 0o7777;
+        let (tmp_name, mut tmp_file) = super::create_temp_file(fd, mode)?;
+
+        let res = util::fchmod(tmp_file.as_raw_fd(), mode)
+            .and_then(|()| self.copy_data(old_file, old_stat, &mut tmp_file))
+            .and_then(|n| util::renameat(fd, &tmp_name, fd, &fname).map(|()| n));
+
+        if res.is_err() {
+            let _ = util::unlinkat(fd, &tmp_name, false);
+        }
+
+        res
+    }
+
+    /// Create the "new" file as a clone of `old_file` with `fclonefileat()`.
+    #[cfg(target_os = "macos")]
+    fn clone_new(
+        &self,
+        old_file: &fs::File,
+        new_dir: &Dir,
+        new_path: &std::path::Path,
+    ) -> io::Result<()> {
+        let (subdir, fname) = super::prepare_inner_operation(new_dir, new_path, self.lookup_flags)?;
+        let subdir = subdir.as_ref().unwrap_or(new_dir);
+
+        match fname {
+            Some(fname) => fname.with_cstr(|fname| {
+                util::fclonefileat(old_file.as_raw_fd(), subdir.as_raw_fd(), fname)
+            }),
+            None => Err(io::Error::from_raw_os_error(libc::EISDIR)),
+        }
+    }
+}
+
+impl Default for CopyOptions {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Copy the contents of a file to another file in (possibly) a different directory.
+///
+/// This is similar to `std::fs::copy()`: the "new" file is created if it does not exist (and
+/// truncated if it does), the permissions of the "old" file are copied to the "new" file, and the
+/// number of bytes copied is returned. Symlinks in either path are followed, but (as with all other
+/// operations) they cannot escape the given directories.
+///
+/// Once both files have been opened, the copy is performed entirely through their file
+/// descriptors, so it cannot be redirected by renaming files or swapping in symlinks partway
+/// through.
+///
+/// This will try to create a reflink if the platform and filesystem support it (see
+/// [`CopyOptions::reflink()`]). Otherwise, on Linux and FreeBSD, this will try to use
+/// `copy_file_range()`, falling back on a normal read/write loop if that isn't supported.
+///
+/// If the "old" file is not a regular file, this will fail with `EINVAL`.
+///
+/// This is equivalent to `CopyOptions::new().lookup_flags(lookup_flags).copy(...)`.
+///
+/// [`CopyOptions::reflink()`]: ./struct.CopyOptions.html#method.reflink
+pub fn copy<P, R>(
+    old_dir: &Dir,
+    old_path: P,
+    new_dir: &Dir,
+    new_path: R,
+    lookup_flags: LookupFlags,
+) -> io::Result<u64>
+where
+    P: AsPath,
+    R: AsPath,
+{
+    CopyOptions::new()
+        .lookup_flags(lookup_flags)
+        .copy(old_dir, old_path, new_dir, new_path)
+}
+
+/// Move a file to a (possibly) different directory, copying it if necessary.
+///
+/// This first tries to rename the file (as with [`rename()`]). If that fails with `EXDEV` because
+/// the two files are on different filesystems, and the "old" file is a regular file (not a
+/// symlink), the file is instead copied to a temporary file next to the "new" path (preserving
+/// its permissions and timestamps, and using reflinks or `copy_file_range()` where possible; see
+/// [`CopyOptions`]), flushed to disk with `fsync()`, renamed over the "new" path (after which the
+/// containing directory is flushed as well), and finally the "old" file is removed. If the "old"
+/// path no longer refers to the file that was copied (because it was replaced in the meantime),
+/// it is left in place and this fails with `EAGAIN`. Other types of files (and directories)
+/// cannot be moved across filesystems; the original `EXDEV` error is returned for them.
+///
+/// As with every other operation, both paths are resolved beneath their respective directories,
+/// so neither the rename nor the copy can escape them. However, unlike a rename, the fallback is
+/// not atomic: if it fails partway through (or the process is killed), the "new" file may
+/// already exist when the "old" one is removed, and if the "old" file is modified during the
+/// copy, the changes may be lost. Additionally, the file will have a new inode number, and
+/// ownership and extended attributes are not preserved.
+///
+/// [`rename()`]: ./fn.rename.html
+/// [`CopyOptions`]: ./struct.CopyOptions.html
+pub fn move_file<P, R>(
+    old_dir: &Dir,
+    old_path: P,
+    new_dir: &Dir,
+    new_path: R,
+    options: &CopyOptions,
+) -> io::Result<()>
+where
+    P: AsPath,
+    R: AsPath,
+{
+    options.move_file(old_dir, old_path, new_dir, new_path)
+}
+
+/// Check whether the given error from `FICLONE`/`fclonefileat()` indicates that reflinks aren't
+/// supported for these files (as opposed to a "real" error).
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[allow(unreachable_patterns)] // ENOTSUP and EOPNOTSUPP are the same on Linux
+fn is_clone_unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EOPNOTSUPP)
+            | Some(libc::ENOTSUP)
+            | Some(libc::EXDEV)
+            | Some(libc::EINVAL)
+            | Some(libc::ENOTTY)
+            | Some(libc::ENOSYS)
+            | Some(libc::EPERM)
+            | Some(libc::EEXIST)
+    )
+}
+
+/// Copy only the data regions of `old_file` to `new_file`, leaving holes in between.
+///
+/// Returns `None` (without having changed either file) if `SEEK_DATA`/`SEEK_HOLE` are not
+/// supported.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos"
+))]
+fn copy_sparse(
+    old_file: &mut fs::File,
+    new_file: &mut fs::File,
+    len: u64,
+) -> io::Result<Option<u64>> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let old_fd = old_file.as_raw_fd();
+    let mut pos = 0;
+
+    loop {
+        let data = match util::lseek(old_fd, pos, libc::SEEK_DATA) {
+            Ok(data) => data,
+            // No more data after this offset
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => break,
+            // Not supported by the filesystem
+            Err(e) if pos == 0 && e.raw_os_error() == Some(libc::EINVAL) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let hole = util::lseek(old_fd, data, libc::SEEK_HOLE)?;
+
+        old_file.seek(SeekFrom::Start(data as u64))?;
+        new_file.seek(SeekFrom::Start(data as u64))?;
+        let n = io::copy(
+            &mut Read::by_ref(old_file).take((hole - data) as u64),
+            new_file,
+        )?;
+        if n != (hole - data) as u64 {
+            // The file was truncated while we were copying it
+            break;
+        }
+
+        pos = hole;
+    }
+
+    // Create the trailing hole (if any)
+    new_file.set_len(len)?;
+
+    Ok(Some(len))
+}
+
+fn copy_contents(
+    old_file: &mut fs::File,
+    new_file: &mut fs::File,
+    #[allow(unused_variables)] use_copy_file_range: bool,
+) -> io::Result<u64> {
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    if use_copy_file_range {
+        let mut total = 0;
+
+        loop {
+            match util::copy_file_range(
+                old_file.as_raw_fd(),
+                new_file.as_raw_fd(),
+                libc::ssize_t::MAX as usize,
+            ) {
+                // Some "special" files (for example, on procfs) report a size of 0 and return 0
+                // from copy_file_range() even though they aren't empty. So if we get 0 on the
+                // first call, try again with the fallback.
+                Ok(0) if total == 0 => break,
+                Ok(0) => return Ok(total),
+                Ok(n) => total += n as u64,
+
+                // These errors indicate that copy_file_range() isn't available, isn't supported
+                // for these files, or is blocked (e.g. by seccomp)
+                Err(e)
+                    if total == 0
+                        && matches!(
+                            e.raw_os_error(),
+                            Some(libc::ENOSYS)
+                                | Some(libc::EXDEV)
+                                | Some(libc::EINVAL)
+                                | Some(libc::EOPNOTSUPP)
+                                | Some(libc::EPERM)
+                        ) =>
+                {
+                    break
+                }
+
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    io::copy(old_file, new_file)
+}
@@ -0,0 +1,121 @@
+use std::fmt;
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::{util, Dir};
+
+/// A snapshot of the state of a [`Dir`], as returned by [`Dir::diagnostics()`].
+///
+/// This is intended for logging and crash reports. The `Display` implementation prints everything
+/// on a single line. If the `serde` feature is enabled, this also implements `serde::Serialize`.
+///
+/// Note that the recovered path is provided for informational purposes only; see the warnings on
+/// [`Dir::recover_path()`].
+///
+/// [`Dir`]: ./struct.Dir.html
+/// [`Dir::diagnostics()`]: ./struct.Dir.html#method.diagnostics
+/// [`Dir::recover_path()`]: ./struct.Dir.html#method.recover_path
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde_rs::Serialize))]
+#[cfg_attr(feature = "serde", serde(crate = "serde_rs"))]
+pub struct DirDiagnostics {
+    fd: RawFd,
+    path: Option<PathBuf>,
+    dev: u64,
+    ino: u64,
+    mount_id: Option<u64>,
+    flags: libc::c_int,
+}
+
+impl DirDiagnostics {
+    /// Get the file descriptor number.
+    #[inline]
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Get the path that was recovered with [`Dir::recover_path()`], or `None` if that failed.
+    ///
+    /// [`Dir::recover_path()`]: ./struct.Dir.html#method.recover_path
+    #[inline]
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Get the ID of the device containing the directory.
+    #[inline]
+    pub fn dev(&self) -> u64 {
+        self.dev
+    }
+
+    /// Get the inode number of the directory.
+    #[inline]
+    pub fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    /// Get the raw ID of the mount containing the directory (see [`MountId::as_raw()`]), or
+    /// `None` if it could not be determined.
+    ///
+    /// [`MountId::as_raw()`]: ./struct.MountId.html#method.as_raw
+    #[inline]
+    pub fn mount_id(&self) -> Option<u64> {
+        self.mount_id
+    }
+
+    /// Get the file status flags of the file descriptor, as returned by `fcntl(F_GETFL)`.
+    #[inline]
+    pub fn flags(&self) -> libc::c_int {
+        self.flags
+    }
+}
+
+impl fmt::Display for DirDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fd {} (", self.fd)?;
+
+        match self.path {
+            Some(ref path) => write!(f, "path {:?}", path)?,
+            None => f.write_str("path unknown")?,
+        }
+
+        write!(f, ", dev {}, ino {}", self.dev, self.ino)?;
+
+        if let Some(mount_id) = self.mount_id {
+            write!(f, ", mount {}", mount_id)?;
+        }
+
+        write!(f, ", flags {:#o})", self.flags)
+    }
+}
+
+impl Dir {
+    /// Collect information about this directory for diagnostic purposes (see
+    /// [`DirDiagnostics`]).
+    ///
+    /// Recovering the path and identifying the mount are done on a best-effort basis; if either
+    /// fails, the corresponding field is left empty. This only fails if the file descriptor
+    /// itself cannot be examined.
+    ///
+    /// [`DirDiagnostics`]: ./struct.DirDiagnostics.html
+    pub fn diagnostics(&self) -> io::Result<DirDiagnostics> {
+        let st = util::fstat(self.as_raw_fd())?;
+
+        let flags = unsafe { libc::fcntl(self.as_raw_fd(), libc::F_GETFL) };
+        if flags < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(DirDiagnostics {
+            fd: self.as_raw_fd(),
+            path: self.recover_path().ok(),
+            dev: st.st_dev as u64,
+            ino: st.st_ino as u64,
+            mount_id: crate::mntid::identify_mount(self.as_raw_fd())
+                .ok()
+                .map(|id| id.as_raw()),
+            flags,
+        })
+    }
+}
@@ -1,5 +1,8 @@
+use std::ffi::CStr;
 use std::fs;
+use std::io;
 use std::os::unix::prelude::*;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Represents the possible file types.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
@@ -14,17 +17,133 @@ pub enum FileType {
     Fifo,
 }
 
+// Extra information that is only available from statx()
+#[cfg(target_os = "linux")]
+#[derive(Copy, Clone, Debug)]
+struct StatxExtra {
+    mask: libc::c_uint,
+    btime: crate::sys::statx_timestamp,
+    mnt_id: u64,
+    attributes: u64,
+    attributes_mask: u64,
+}
+
 /// Represents metadata information about a file. Similar to `std::fs::Metadata`.
 #[derive(Copy, Clone, Debug)]
 pub struct Metadata {
     stat: libc::stat,
+    #[cfg(target_os = "linux")]
+    extra: Option<StatxExtra>,
 }
 
 #[allow(clippy::len_without_is_empty)]
 impl Metadata {
     #[inline]
     pub(crate) fn new(stat: libc::stat) -> Self {
-        Self { stat }
+        Self {
+            stat,
+            #[cfg(target_os = "linux")]
+            extra: None,
+        }
+    }
+
+    /// Retrieve the metadata of the file at `path` (relative to `dir_fd`). This is like
+    /// `fstatat()`, but on Linux it uses `statx()` if available, which provides extra information.
+    pub(crate) fn stat_at(dir_fd: RawFd, path: &CStr, flags: libc::c_int) -> io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        if let Some(meta) = Self::statx(dir_fd, path, flags)? {
+            return Ok(meta);
+        }
+
+        crate::util::fstatat(dir_fd, path, flags).map(Self::new)
+    }
+
+    /// Retrieve the metadata of the file open as `fd` (which may be an `O_PATH` file descriptor
+    /// on Linux).
+    pub(crate) fn stat_fd(fd: RawFd) -> io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        if let Some(meta) = Self::statx(
+            fd,
+            unsafe { CStr::from_bytes_with_nul_unchecked(b"\0") },
+            libc::AT_EMPTY_PATH,
+        )? {
+            return Ok(meta);
+        }
+
+        crate::util::fstat(fd).map(Self::new)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn statx(dir_fd: RawFd, path: &CStr, flags: libc::c_int) -> io::Result<Option<Self>> {
+        use crate::sys;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        static HAS_STATX: AtomicBool = AtomicBool::new(true);
+        if !HAS_STATX.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+
+        let mut stx = std::mem::MaybeUninit::<sys::statx>::uninit();
+
+        if unsafe {
+            libc::syscall(
+                libc::SYS_statx,
+                dir_fd,
+                path.as_ptr(),
+                flags,
+                sys::STATX_MASK,
+                stx.as_mut_ptr(),
+            )
+        } < 0
+        {
+            let err = io::Error::last_os_error();
+
+            return match err.raw_os_error() {
+                // ENOSYS (older kernels) and EPERM (possibly from seccomp) mean it's not available
+                Some(libc::ENOSYS) | Some(libc::EPERM) => {
+                    HAS_STATX.store(false, Ordering::Relaxed);
+                    Ok(None)
+                }
+
+                _ => Err(err),
+            };
+        }
+
+        Ok(Some(Self::from_statx(&unsafe { stx.assume_init() })))
+    }
+
+    /// Build a `Metadata` from the information returned by `statx()` (which must have been
+    /// called with the mask `STATX_MASK`).
+    #[cfg(target_os = "linux")]
+    pub(crate) fn from_statx(stx: &crate::sys::statx) -> Self {
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        stat.st_dev = makedev(stx.stx_dev_major, stx.stx_dev_minor);
+        stat.st_ino = stx.stx_ino as _;
+        stat.st_mode = stx.stx_mode as _;
+        stat.st_nlink = stx.stx_nlink as _;
+        stat.st_uid = stx.stx_uid;
+        stat.st_gid = stx.stx_gid;
+        stat.st_rdev = makedev(stx.stx_rdev_major, stx.stx_rdev_minor);
+        stat.st_size = stx.stx_size as _;
+        stat.st_blksize = stx.stx_blksize as _;
+        stat.st_blocks = stx.stx_blocks as _;
+        stat.st_atime = stx.stx_atime.tv_sec as _;
+        stat.st_atime_nsec = stx.stx_atime.tv_nsec as _;
+        stat.st_mtime = stx.stx_mtime.tv_sec as _;
+        stat.st_mtime_nsec = stx.stx_mtime.tv_nsec as _;
+        stat.st_ctime = stx.stx_ctime.tv_sec as _;
+        stat.st_ctime_nsec = stx.stx_ctime.tv_nsec as _;
+
+        Self {
+            stat,
+            extra: Some(StatxExtra {
+                mask: stx.stx_mask,
+                btime: stx.stx_btime,
+                mnt_id: stx.stx_mnt_id,
+                attributes: stx.stx_attributes,
+                attributes_mask: stx.stx_attributes_mask,
+            }),
+        }
     }
 
     /// Get the type of this file.
@@ -83,4 +202,157 @@ impl Metadata {
     pub fn ino(&self) -> u64 {
         self.stat.st_ino as u64
     }
+
+    /// Return the file's mode (which includes the file type and the permission bits).
+    #[inline]
+    pub fn mode(&self) -> u32 {
+        self.stat.st_mode as u32
+    }
+
+    /// Return the number of hard links to the file.
+    #[inline]
+    pub fn nlink(&self) -> u64 {
+        self.stat.st_nlink as u64
+    }
+
+    /// Return the user ID of the file's owner.
+    #[inline]
+    pub fn uid(&self) -> u32 {
+        self.stat.st_uid as u32
+    }
+
+    /// Return the group ID of the file's owner.
+    #[inline]
+    pub fn gid(&self) -> u32 {
+        self.stat.st_gid as u32
+    }
+
+    /// Return the device ID that this file represents (only meaningful for block and character
+    /// special files).
+    #[inline]
+    pub fn rdev(&self) -> u64 {
+        self.stat.st_rdev as u64
+    }
+
+    /// Return the "preferred" block size for efficient filesystem I/O.
+    #[inline]
+    pub fn blksize(&self) -> u64 {
+        self.stat.st_blksize as u64
+    }
+
+    /// Return the number of 512-byte blocks allocated to the file.
+    #[inline]
+    pub fn blocks(&self) -> u64 {
+        self.stat.st_blocks as u64
+    }
+
+    /// Get the last access time of this file.
+    #[inline]
+    pub fn accessed(&self) -> SystemTime {
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "netbsd")] {
+                systime(self.stat.st_atime as i64, self.stat.st_atimensec as i64)
+            } else {
+                systime(self.stat.st_atime as i64, self.stat.st_atime_nsec as i64)
+            }
+        }
+    }
+
+    /// Get the last modification time of this file.
+    #[inline]
+    pub fn modified(&self) -> SystemTime {
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "netbsd")] {
+                systime(self.stat.st_mtime as i64, self.stat.st_mtimensec as i64)
+            } else {
+                systime(self.stat.st_mtime as i64, self.stat.st_mtime_nsec as i64)
+            }
+        }
+    }
+
+    /// Get the last status change time of this file (i.e. the last time its metadata was
+    /// changed).
+    #[inline]
+    pub fn changed(&self) -> SystemTime {
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "netbsd")] {
+                systime(self.stat.st_ctime as i64, self.stat.st_ctimensec as i64)
+            } else {
+                systime(self.stat.st_ctime as i64, self.stat.st_ctime_nsec as i64)
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Metadata {
+    /// Linux-specific: Get the creation ("birth") time of this file.
+    ///
+    /// This is only available if the kernel supports `statx()` (Linux 4.11+) and the filesystem
+    /// records creation times; otherwise, it returns `None`.
+    #[inline]
+    pub fn created(&self) -> Option<SystemTime> {
+        match self.extra {
+            Some(extra) if extra.mask & crate::sys::STATX_BTIME != 0 => {
+                Some(systime(extra.btime.tv_sec, extra.btime.tv_nsec as i64))
+            }
+            _ => None,
+        }
+    }
+
+    /// Linux-specific: Get the ID of the mount containing this file.
+    ///
+    /// This is only available on Linux 5.8+ (it returns `None` on older kernels).
+    #[inline]
+    pub fn mount_id(&self) -> Option<u64> {
+        match self.extra {
+            Some(extra) if extra.mask & crate::sys::STATX_MNT_ID != 0 => Some(extra.mnt_id),
+            _ => None,
+        }
+    }
+
+    /// Linux-specific: Check whether this file is marked as immutable (see chattr(1)).
+    ///
+    /// This returns `None` if the kernel or the filesystem does not report this attribute.
+    #[inline]
+    pub fn is_immutable(&self) -> Option<bool> {
+        self.attribute(crate::sys::STATX_ATTR_IMMUTABLE)
+    }
+
+    /// Linux-specific: Check whether this file is marked as append-only (see chattr(1)).
+    ///
+    /// This returns `None` if the kernel or the filesystem does not report this attribute.
+    #[inline]
+    pub fn is_append_only(&self) -> Option<bool> {
+        self.attribute(crate::sys::STATX_ATTR_APPEND)
+    }
+
+    #[inline]
+    fn attribute(&self, attr: u64) -> Option<bool> {
+        match self.extra {
+            Some(extra) if extra.attributes_mask & attr != 0 => Some(extra.attributes & attr != 0),
+            _ => None,
+        }
+    }
+}
+
+// The same encoding glibc uses
+#[cfg(target_os = "linux")]
+#[inline]
+fn makedev(major: u32, minor: u32) -> libc::dev_t {
+    let (major, minor) = (major as u64, minor as u64);
+
+    (((major & 0xffff_f000) << 32)
+        | ((major & 0x0000_0fff) << 8)
+        | ((minor & 0xffff_ff00) << 12)
+        | (minor & 0x0000_00ff)) as libc::dev_t
+}
+
+fn systime(secs: i64, nsecs: i64) -> SystemTime {
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::new(secs as u64, nsecs as u32)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.wrapping_neg() as u64)
+            + Duration::from_nanos(nsecs as u64)
+    }
 }
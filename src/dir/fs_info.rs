@@ -0,0 +1,157 @@
+use std::io;
+use std::os::unix::prelude::*;
+
+use crate::{util, Dir};
+
+/// Information about a filesystem, as returned by [`Dir::filesystem_info()`].
+///
+/// [`Dir::filesystem_info()`]: ./struct.Dir.html#method.filesystem_info
+#[derive(Copy, Clone, Debug)]
+pub struct FsInfo {
+    vfs: libc::statvfs,
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "macos"
+    ))]
+    fs: libc::statfs,
+}
+
+impl FsInfo {
+    /// Returns a reference to the underlying `libc::statvfs` structure.
+    #[inline]
+    pub fn statvfs(&self) -> &libc::statvfs {
+        &self.vfs
+    }
+
+    /// Get the size of the blocks reported by the other methods (the "fragment size", in
+    /// `statvfs()` terms), in bytes.
+    #[inline]
+    pub fn block_size(&self) -> u64 {
+        self.vfs.f_frsize as u64
+    }
+
+    /// Get the total number of blocks on the filesystem.
+    #[inline]
+    pub fn blocks(&self) -> u64 {
+        self.vfs.f_blocks as u64
+    }
+
+    /// Get the number of free blocks on the filesystem.
+    #[inline]
+    pub fn blocks_free(&self) -> u64 {
+        self.vfs.f_bfree as u64
+    }
+
+    /// Get the number of blocks available to unprivileged users.
+    ///
+    /// This may be less than [`blocks_free()`] if some blocks are reserved for the superuser.
+    ///
+    /// [`blocks_free()`]: #method.blocks_free
+    #[inline]
+    pub fn blocks_available(&self) -> u64 {
+        self.vfs.f_bavail as u64
+    }
+
+    /// Get the space available to unprivileged users, in bytes.
+    ///
+    /// This is `blocks_available() * block_size()`.
+    #[inline]
+    pub fn bytes_available(&self) -> u64 {
+        self.blocks_available().saturating_mul(self.block_size())
+    }
+
+    /// Get the total number of inodes on the filesystem.
+    #[inline]
+    pub fn files(&self) -> u64 {
+        self.vfs.f_files as u64
+    }
+
+    /// Get the number of free inodes on the filesystem.
+    #[inline]
+    pub fn files_free(&self) -> u64 {
+        self.vfs.f_ffree as u64
+    }
+
+    /// Get the number of inodes available to unprivileged users.
+    #[inline]
+    pub fn files_available(&self) -> u64 {
+        self.vfs.f_favail as u64
+    }
+
+    /// Get the maximum length of file names on the filesystem.
+    #[inline]
+    pub fn name_max(&self) -> u64 {
+        self.vfs.f_namemax as u64
+    }
+
+    /// Check whether the filesystem is mounted read-only.
+    #[inline]
+    pub fn is_readonly(&self) -> bool {
+        self.vfs.f_flag as u64 & libc::ST_RDONLY as u64 != 0
+    }
+
+    /// Check whether the filesystem is mounted with `nosuid` (set-user-ID and set-group-ID bits
+    /// are ignored).
+    #[inline]
+    pub fn is_nosuid(&self) -> bool {
+        self.vfs.f_flag as u64 & libc::ST_NOSUID as u64 != 0
+    }
+
+    /// Check whether the filesystem is mounted with `noexec` (files cannot be executed).
+    ///
+    /// This returns `None` on platforms where this information is not available.
+    #[inline]
+    pub fn is_noexec(&self) -> Option<bool> {
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "android"))] {
+                Some(self.vfs.f_flag as u64 & libc::ST_NOEXEC as u64 != 0)
+            } else if #[cfg(any(target_os = "freebsd", target_os = "macos"))] {
+                Some(self.fs.f_flags as u64 & libc::MNT_NOEXEC as u64 != 0)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl FsInfo {
+    /// Linux-specific: Get the "magic number" identifying the filesystem type (for example,
+    /// `0x01021994` for tmpfs; see statfs(2)).
+    #[inline]
+    pub fn fs_type(&self) -> u64 {
+        self.fs.f_type as u64
+    }
+}
+
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+impl FsInfo {
+    /// FreeBSD/macOS-specific: Get the name of the filesystem type (for example, `ufs` or `apfs`).
+    pub fn fs_type_name(&self) -> &std::ffi::OsStr {
+        let name = unsafe { std::ffi::CStr::from_ptr(self.fs.f_fstypename.as_ptr()) };
+        std::ffi::OsStr::from_bytes(name.to_bytes())
+    }
+}
+
+impl Dir {
+    /// Retrieve information about the filesystem containing this directory (such as the amount
+    /// of free space).
+    ///
+    /// This operates on the open directory (with `fstatvfs()` and, where available,
+    /// `fstatfs()`), so it always refers to the filesystem this `Dir` is actually on, even if
+    /// the directory has since been moved.
+    pub fn filesystem_info(&self) -> io::Result<FsInfo> {
+        Ok(FsInfo {
+            vfs: util::fstatvfs(self.as_raw_fd())?,
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "macos"
+            ))]
+            fs: util::fstatfs(self.as_raw_fd())?,
+        })
+    }
+}
@@ -0,0 +1,410 @@
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{Dir, LookupFlags};
+
+use super::{WalkEntry, WalkIter};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum PatComponent {
+    // "**"
+    AnyDirs,
+    Pattern(Vec<u8>),
+}
+
+#[inline]
+fn has_wildcards(component: &[u8]) -> bool {
+    component
+        .iter()
+        .any(|ch| matches!(ch, b'*' | b'?' | b'[' | b'\\'))
+}
+
+// Split the pattern into a literal "base" path (which is resolved normally) and the list of
+// components that have to be matched against directory entries.
+fn parse_pattern(pattern: &OsStr) -> io::Result<(PathBuf, Vec<PatComponent>)> {
+    let mut base = PathBuf::new();
+    let mut components = Vec::new();
+
+    for component in Path::new(pattern).components() {
+        match component {
+            Component::RootDir => base.push("/"),
+            Component::CurDir => (),
+
+            Component::ParentDir if components.is_empty() => base.push(".."),
+            // ".." can never match a directory entry
+            Component::ParentDir => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+
+            Component::Normal(name) => {
+                let name = name.as_bytes();
+
+                if name == b"**" {
+                    components.push(PatComponent::AnyDirs);
+                } else if components.is_empty() && !has_wildcards(name) {
+                    base.push(OsStr::from_bytes(name));
+                } else {
+                    components.push(PatComponent::Pattern(name.to_vec()));
+                }
+            }
+
+            Component::Prefix(_) => unreachable!(),
+        }
+    }
+
+    // The final component is always matched against the directory entries, so that we only ever
+    // return entries that actually exist
+    if components.is_empty() {
+        match base.file_name() {
+            Some(name) => {
+                components.push(PatComponent::Pattern(name.as_bytes().to_vec()));
+                base.pop();
+            }
+
+            None => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        }
+    }
+
+    Ok((base, components))
+}
+
+// Parse a bracket expression starting just after the "["; returns whether `ch` matched and the
+// remainder of the pattern after the closing "]" (or None if the expression was unterminated).
+fn match_bracket(pat: &[u8], ch: u8) -> Option<(bool, &[u8])> {
+    let (negate, mut pat) = match pat.first() {
+        Some(b'!') | Some(b'^') => (true, &pat[1..]),
+        _ => (false, pat),
+    };
+
+    let mut matched = false;
+    let mut first = true;
+
+    loop {
+        let start = match pat.first() {
+            // A "]" at the very beginning is treated literally
+            Some(b']') if !first => return Some((matched != negate, &pat[1..])),
+            Some(b'\\') if pat.len() > 1 => {
+                pat = &pat[1..];
+                pat[0]
+            }
+            Some(&c) => c,
+            None => return None,
+        };
+        pat = &pat[1..];
+        first = false;
+
+        if pat.len() > 1 && pat[0] == b'-' && pat[1] != b']' {
+            let end = pat[1];
+            pat = &pat[2..];
+
+            if start <= ch && ch <= end {
+                matched = true;
+            }
+        } else if start == ch {
+            matched = true;
+        }
+    }
+}
+
+fn match_component(mut pat: &[u8], mut name: &[u8]) -> bool {
+    // Where to resume if the current attempt fails after the last "*"
+    let mut backtrack: Option<(&[u8], &[u8])> = None;
+
+    loop {
+        let matched = match (pat.first(), name.first()) {
+            (None, None) => return true,
+
+            (Some(b'*'), _) => {
+                pat = &pat[1..];
+                backtrack = Some((pat, name));
+                continue;
+            }
+
+            (Some(b'?'), Some(_)) => {
+                pat = &pat[1..];
+                true
+            }
+
+            (Some(b'['), Some(&ch)) => match match_bracket(&pat[1..], ch) {
+                Some((matched, rest)) => {
+                    pat = rest;
+                    matched
+                }
+
+                // Unterminated; treat the "[" literally
+                None => {
+                    pat = &pat[1..];
+                    ch == b'['
+                }
+            },
+
+            (Some(b'\\'), Some(&ch)) if pat.len() > 1 => {
+                let matched = pat[1] == ch;
+                pat = &pat[2..];
+                matched
+            }
+
+            (Some(&pch), Some(&ch)) => {
+                pat = &pat[1..];
+                pch == ch
+            }
+
+            _ => false,
+        };
+
+        if matched {
+            name = &name[1..];
+        } else {
+            match backtrack {
+                Some((bt_pat, bt_name)) if !bt_name.is_empty() => {
+                    // Let the "*" consume one more character
+                    pat = bt_pat;
+                    name = &bt_name[1..];
+                    backtrack = Some((bt_pat, name));
+                }
+
+                _ => return false,
+            }
+        }
+    }
+}
+
+fn match_name(pat: &[u8], name: &[u8]) -> bool {
+    // As in the shell, wildcards never match a leading "."
+    if name.first() == Some(&b'.') && pat.first() != Some(&b'.') {
+        return false;
+    }
+
+    match_component(pat, name)
+}
+
+fn match_path(pats: &[PatComponent], names: &[&[u8]]) -> bool {
+    match (pats.split_first(), names.split_first()) {
+        (None, None) => true,
+
+        (Some((PatComponent::AnyDirs, rest)), _) => {
+            if match_path(rest, names) {
+                return true;
+            }
+
+            match names.split_first() {
+                Some((name, names_rest)) if name.first() != Some(&b'.') => {
+                    match_path(pats, names_rest)
+                }
+                _ => false,
+            }
+        }
+
+        (Some((PatComponent::Pattern(pat), rest)), Some((name, names_rest))) => {
+            match_name(pat, name) && match_path(rest, names_rest)
+        }
+
+        _ => false,
+    }
+}
+
+/// An iterator over the files matching a glob pattern.
+///
+/// This is created with [`Dir::glob()`].
+///
+/// [`Dir::glob()`]: ./struct.Dir.html#method.glob
+#[derive(Debug)]
+pub struct GlobIter {
+    walker: Option<WalkIter>,
+    base: PathBuf,
+    components: Vec<PatComponent>,
+}
+
+impl Iterator for GlobIter {
+    type Item = io::Result<WalkEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let walker = self.walker.as_mut()?;
+
+        loop {
+            let mut entry = match walker.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let names: Vec<&[u8]> = entry.path().iter().map(|name| name.as_bytes()).collect();
+
+            if match_path(&self.components, &names) {
+                entry.prefix_path(&self.base);
+                return Some(Ok(entry));
+            }
+        }
+    }
+}
+
+impl Dir {
+    /// Find all files within this directory whose paths match the given glob pattern.
+    ///
+    /// The following syntax is supported:
+    ///
+    /// - `*` matches any sequence of characters (except `/`), and `?` matches any single
+    ///   character.
+    /// - `[abc]` matches any of the listed characters, and `[a-z]` matches any character in the
+    ///   given range. `[!...]` or `[^...]` negates the set.
+    /// - `**` (as an entire path component) matches zero or more directories.
+    /// - `\` escapes the following character.
+    ///
+    /// As in the shell, wildcards do not match a leading `.` in a file name (and `**` does not
+    /// descend into directories whose names begin with `.`); the `.` has to be specified
+    /// explicitly.
+    ///
+    /// Any leading components of the pattern that do not contain wildcards are resolved like any
+    /// other path (subject to `lookup_flags`). The rest of the pattern is matched against the
+    /// entries found by walking the tree with [`Dir::walk()`], so symlinks are matched but never
+    /// descended into. The paths of the returned entries are relative to this directory (they
+    /// include the leading components of the pattern).
+    ///
+    /// If the leading directory does not exist, the returned iterator is empty. If the pattern is
+    /// empty, or it contains `..` components after a wildcard, this fails with `EINVAL`.
+    ///
+    /// [`Dir::walk()`]: #method.walk
+    pub fn glob<P: AsRef<OsStr>>(
+        &self,
+        pattern: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<GlobIter> {
+        let (base, components) = parse_pattern(pattern.as_ref())?;
+
+        let max_depth = if components.contains(&PatComponent::AnyDirs) {
+            None
+        } else {
+            Some(components.len())
+        };
+
+        let base_path = if base.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            base.as_path()
+        };
+
+        let walker = match self
+            .walk()
+            .max_depth(max_depth)
+            .lookup_flags(lookup_flags)
+            .walk(base_path)
+        {
+            Ok(walker) => Some(walker),
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ENOENT) | Some(libc::ENOTDIR)) => None,
+            Err(e) => return Err(e),
+        };
+
+        Ok(GlobIter {
+            walker,
+            base,
+            components,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_name() {
+        assert!(match_name(b"abc", b"abc"));
+        assert!(!match_name(b"abc", b"abd"));
+        assert!(!match_name(b"abc", b"ab"));
+
+        assert!(match_name(b"*", b"abc"));
+        assert!(match_name(b"*", b""));
+        assert!(match_name(b"a*", b"abc"));
+        assert!(match_name(b"*c", b"abc"));
+        assert!(match_name(b"a*c", b"abc"));
+        assert!(match_name(b"a*c", b"ac"));
+        assert!(match_name(b"*.html", b"index.html"));
+        assert!(match_name(b"*a*a*", b"banana"));
+        assert!(!match_name(b"*.html", b"index.htm"));
+        assert!(!match_name(b"a*d", b"abc"));
+
+        assert!(match_name(b"a?c", b"abc"));
+        assert!(!match_name(b"a?c", b"ac"));
+
+        assert!(match_name(b"[ab]c", b"bc"));
+        assert!(!match_name(b"[ab]c", b"cc"));
+        assert!(match_name(b"[a-c]x", b"bx"));
+        assert!(!match_name(b"[a-c]x", b"dx"));
+        assert!(match_name(b"[!a-c]x", b"dx"));
+        assert!(match_name(b"[^a-c]x", b"dx"));
+        assert!(!match_name(b"[!a-c]x", b"ax"));
+        assert!(match_name(b"[]]", b"]"));
+        assert!(match_name(b"[a-]", b"-"));
+        assert!(match_name(b"[", b"["));
+        assert!(match_name(b"a[b", b"a[b"));
+
+        assert!(match_name(b"\\*", b"*"));
+        assert!(!match_name(b"\\*", b"a"));
+        assert!(match_name(b"[\\]]", b"]"));
+
+        assert!(!match_name(b"*", b".hidden"));
+        assert!(!match_name(b"?hidden", b".hidden"));
+        assert!(match_name(b".*", b".hidden"));
+    }
+
+    #[test]
+    fn test_parse_pattern() {
+        fn pat(s: &str) -> PatComponent {
+            PatComponent::Pattern(s.as_bytes().to_vec())
+        }
+
+        assert_eq!(
+            parse_pattern(OsStr::new("a/b/*.html")).unwrap(),
+            (PathBuf::from("a/b"), vec![pat("*.html")])
+        );
+        assert_eq!(
+            parse_pattern(OsStr::new("./a/**/x")).unwrap(),
+            (PathBuf::from("a"), vec![PatComponent::AnyDirs, pat("x")])
+        );
+        assert_eq!(
+            parse_pattern(OsStr::new("/a*/b")).unwrap(),
+            (PathBuf::from("/"), vec![pat("a*"), pat("b")])
+        );
+        assert_eq!(
+            parse_pattern(OsStr::new("../a/b")).unwrap(),
+            (PathBuf::from("../a"), vec![pat("b")])
+        );
+
+        assert_eq!(
+            parse_pattern(OsStr::new("")).unwrap_err().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+        assert_eq!(
+            parse_pattern(OsStr::new("/")).unwrap_err().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+        assert_eq!(
+            parse_pattern(OsStr::new("a*/.."))
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EINVAL)
+        );
+    }
+
+    #[test]
+    fn test_match_path() {
+        fn check(pattern: &str, path: &str) -> bool {
+            let (_, components) = parse_pattern(OsStr::new(pattern)).unwrap();
+            let names: Vec<&[u8]> = path.split('/').map(|s| s.as_bytes()).collect();
+            match_path(&components, &names)
+        }
+
+        assert!(check("*/b", "a/b"));
+        assert!(!check("*/b", "a/c"));
+        assert!(!check("*/b", "a"));
+
+        assert!(check("**/x", "x"));
+        assert!(check("**/x", "a/x"));
+        assert!(check("**/x", "a/b/x"));
+        assert!(!check("**/x", "a/b/y"));
+        assert!(!check("**/x", ".a/x"));
+        assert!(check("**", "a/b"));
+        assert!(check("*/**/*.html", "a/b/c.html"));
+        assert!(check("*/**/*.html", "a/c.html"));
+        assert!(!check("*/**/*.html", "c.html"));
+    }
+}
@@ -0,0 +1,170 @@
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+use std::path::PathBuf;
+
+use crate::{AsPath, Dir, LookupFlags, Rights};
+
+use super::{check_rights, open_rights, Metadata};
+
+/// A reference to a file that was found by safely resolving a path, which can later be reopened
+/// with different flags.
+///
+/// This separates "resolving the path once" from "opening the file (possibly several times) with
+/// specific access modes". A `Handle` can be created with [`Dir::open_handle()`].
+///
+/// On Linux and Android, this holds an `O_PATH` file descriptor, and [`reopen()`] goes through
+/// `/proc/self/fd` (so `/proc` must be mounted). On other platforms, there is no way to reopen a
+/// file descriptor with different flags, so the `Handle` remembers the directory and path, and
+/// [`reopen()`] resolves the path again and verifies that it still refers to the same file.
+///
+/// A `Handle` keeps the rights of the directory it was created from (see [`Dir::restrict()`]), and
+/// [`reopen()`] fails if they don't allow opening the file with the given flags.
+///
+/// [`Dir::restrict()`]: ./struct.Dir.html#method.restrict
+/// [`Dir::open_handle()`]: ./struct.Dir.html#method.open_handle
+/// [`reopen()`]: #method.reopen
+#[derive(Debug)]
+pub struct Handle {
+    rights: Rights,
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    file: fs::File,
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    dir: Dir,
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    path: PathBuf,
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    lookup_flags: LookupFlags,
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    dev: u64,
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    ino: u64,
+}
+
+impl Handle {
+    fn new(dir: &Dir, path: &std::path::Path, lookup_flags: LookupFlags) -> io::Result<Self> {
+        dir.check_rights(Rights::READ)?;
+
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "android"))] {
+                Ok(Self {
+                    rights: dir.rights(),
+                    file: crate::open_beneath(
+                        dir.as_raw_fd(),
+                        path,
+                        libc::O_PATH,
+                        0,
+                        lookup_flags,
+                    )?,
+                })
+            } else {
+                let meta = dir.metadata_follow(path, lookup_flags)?;
+
+                Ok(Self {
+                    rights: dir.rights(),
+                    dir: dir.try_clone()?,
+                    path: path.to_path_buf(),
+                    lookup_flags,
+                    dev: meta.dev(),
+                    ino: meta.ino(),
+                })
+            }
+        }
+    }
+
+    /// Open the file referred to by this handle with the given flags (for example,
+    /// `libc::O_RDONLY`).
+    ///
+    /// `O_CREAT` cannot be specified (this fails with `EINVAL`). The returned file is always
+    /// opened with `O_CLOEXEC`.
+    ///
+    /// On platforms other than Linux/Android, this fails with `ESTALE` if the path no longer
+    /// refers to the same file (and with `O_TRUNC`, the file is only truncated once that has been
+    /// verified).
+    pub fn reopen(&self, flags: libc::c_int) -> io::Result<fs::File> {
+        if flags & libc::O_CREAT == libc::O_CREAT {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        check_rights(self.rights, open_rights(flags))?;
+
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "android"))] {
+                crate::util::openat(
+                    libc::AT_FDCWD,
+                    &crate::util::proc_fd_path(self.file.as_raw_fd()),
+                    flags,
+                    0,
+                )
+            } else {
+                // Don't truncate the file until we know it's the same one
+                crate::open::check_flags(flags)?;
+                let file = crate::open_beneath(
+                    self.dir.as_raw_fd(),
+                    &self.path,
+                    flags & !libc::O_TRUNC,
+                    0,
+                    self.lookup_flags,
+                )?;
+
+                let meta = Metadata::stat_fd(file.as_raw_fd())?;
+                self.check_same(&meta)?;
+
+                // O_TRUNC is ignored for files other than regular files
+                if flags & libc::O_TRUNC == libc::O_TRUNC && meta.is_file() {
+                    file.set_len(0)?;
+                }
+
+                Ok(file)
+            }
+        }
+    }
+
+    /// Retrieve the metadata of the file referred to by this handle.
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        check_rights(self.rights, Rights::READ)?;
+
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "android"))] {
+                Metadata::stat_fd(self.file.as_raw_fd())
+            } else {
+                let meta = self.dir.metadata_follow(&self.path, self.lookup_flags)?;
+                self.check_same(&meta)?;
+                Ok(meta)
+            }
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn check_same(&self, meta: &Metadata) -> io::Result<()> {
+        if meta.dev() == self.dev && meta.ino() == self.ino {
+            Ok(())
+        } else {
+            Err(io::Error::from_raw_os_error(libc::ESTALE))
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl AsRawFd for Handle {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Dir {
+    /// Resolve the given path within this directory and return a [`Handle`] referring to the
+    /// file, which can later be opened (possibly several times) with [`Handle::reopen()`].
+    ///
+    /// Symlinks are followed (within this directory), and the file must exist.
+    ///
+    /// [`Handle`]: ./struct.Handle.html
+    /// [`Handle::reopen()`]: ./struct.Handle.html#method.reopen
+    pub fn open_handle<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<Handle> {
+        Handle::new(self, path.as_path(), lookup_flags)
+    }
+}
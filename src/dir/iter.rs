@@ -1,18 +1,24 @@
+#[cfg(target_os = "linux")]
+use std::convert::TryInto;
 use std::ffi::{CStr, CString, OsStr};
+use std::fs;
 use std::io;
 use std::os::unix::prelude::*;
+use std::path::PathBuf;
+#[cfg(not(target_os = "linux"))]
 use std::ptr::NonNull;
-use std::sync::Arc;
 
-use crate::util;
+use crate::{constants, util};
 
-use super::{FileType, Metadata};
+use super::{Dir, FileType, Metadata, OpenOptions, Rights};
 
+#[cfg(not(target_os = "linux"))]
 #[derive(Debug)]
 struct Dstream {
     dir: NonNull<libc::DIR>,
 }
 
+#[cfg(not(target_os = "linux"))]
 impl Dstream {
     #[inline]
     fn as_ptr(&self) -> *mut libc::DIR {
@@ -20,6 +26,7 @@ impl Dstream {
     }
 }
 
+#[cfg(not(target_os = "linux"))]
 impl AsRawFd for Dstream {
     #[inline]
     fn as_raw_fd(&self) -> RawFd {
@@ -27,6 +34,12 @@ impl AsRawFd for Dstream {
     }
 }
 
+// A DIR stream can safely be used from any thread, as long as it isn't used from several at once
+// (which the `&mut self` methods of `ReadDirIter` prevent).
+#[cfg(not(target_os = "linux"))]
+unsafe impl Send for Dstream {}
+
+#[cfg(not(target_os = "linux"))]
 impl Drop for Dstream {
     #[inline]
     fn drop(&mut self) {
@@ -36,18 +49,61 @@ impl Drop for Dstream {
     }
 }
 
+// On Linux, we call getdents64() directly (instead of going through readdir()), so the "stream"
+// is just the directory file descriptor.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+struct Dstream {
+    fd: RawFd,
+}
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for Dstream {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Dstream {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+const GETDENTS_BUFSIZE: usize = 32768;
+
 /// An iterator over the entries of a directory.
+///
+/// This is `Send`, so a directory can be listed on a different thread from the one that opened
+/// it.
 #[derive(Debug)]
 pub struct ReadDirIter {
-    dstream: Arc<Dstream>,
+    dstream: Dstream,
+    // Buffer of raw `struct linux_dirent64`s, and the range within it that hasn't been consumed
+    #[cfg(target_os = "linux")]
+    buf: Box<[u8]>,
+    #[cfg(target_os = "linux")]
+    buf_pos: usize,
+    #[cfg(target_os = "linux")]
+    buf_len: usize,
+    // The offset of the next entry (for tell())
+    #[cfg(target_os = "linux")]
+    offset: i64,
 }
 
 impl ReadDirIter {
+    #[cfg(not(target_os = "linux"))]
     #[inline]
     pub(crate) fn new_consume(fd: RawFd) -> io::Result<Self> {
         match NonNull::new(unsafe { libc::fdopendir(fd) }) {
             Some(dir) => Ok(Self {
-                dstream: Arc::new(Dstream { dir }),
+                dstream: Dstream { dir },
             }),
 
             None => {
@@ -60,13 +116,39 @@ impl ReadDirIter {
         }
     }
 
+    #[cfg(target_os = "linux")]
+    #[inline]
+    pub(crate) fn new_consume(fd: RawFd) -> io::Result<Self> {
+        let dstream = Dstream { fd };
+
+        // fdopendir() would fail with EBADF for invalid file descriptors and ENOTDIR for files
+        // that aren't directories; do the same
+        if util::fstat(fd)?.st_mode & libc::S_IFMT != libc::S_IFDIR {
+            return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+        }
+
+        Ok(Self {
+            dstream,
+            buf: vec![0; GETDENTS_BUFSIZE].into_boxed_slice(),
+            buf_pos: 0,
+            buf_len: 0,
+            offset: util::lseek(fd, 0, libc::SEEK_CUR)?,
+        })
+    }
+
     /// Rewind to the beginning of the directory.
     ///
     /// This directly corresponds to rewinddir(3).
     #[inline]
     pub fn rewind(&mut self) {
-        unsafe {
-            libc::rewinddir(self.dstream.as_ptr());
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                self.seek(SeekPos(0));
+            } else {
+                unsafe {
+                    libc::rewinddir(self.dstream.as_ptr());
+                }
+            }
         }
     }
 
@@ -75,7 +157,13 @@ impl ReadDirIter {
     /// This directly corresponds to telldir(3).
     #[inline]
     pub fn tell(&self) -> SeekPos {
-        SeekPos(unsafe { libc::telldir(self.dstream.as_ptr()) })
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                SeekPos(self.offset)
+            } else {
+                SeekPos(unsafe { libc::telldir(self.dstream.as_ptr()) })
+            }
+        }
     }
 
     /// Set the new seek position.
@@ -86,16 +174,32 @@ impl ReadDirIter {
     /// [`tell()`]: #method.tell
     #[inline]
     pub fn seek(&mut self, pos: SeekPos) {
-        unsafe {
-            libc::seekdir(self.dstream.as_ptr(), pos.0);
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                // Like seekdir(), this can't report errors (and lseek() on a directory only fails
+                // if the offset is invalid, which it won't be if it came from tell())
+                let _ = util::lseek(self.dstream.fd, pos.0, libc::SEEK_SET);
+                self.buf_pos = 0;
+                self.buf_len = 0;
+                self.offset = pos.0;
+            } else {
+                unsafe {
+                    libc::seekdir(self.dstream.as_ptr(), pos.0);
+                }
+            }
         }
     }
-}
 
-impl Iterator for ReadDirIter {
-    type Item = io::Result<Entry>;
-
-    fn next(&mut self) -> Option<Self::Item> {
+    /// Get the next entry in the directory without copying its name.
+    ///
+    /// This is like `next()`, but the returned [`RawEntry`] borrows the name directly from this
+    /// iterator's internal buffer, so no allocations are performed. This may be useful when
+    /// listing very large directories, or if the names are only inspected as raw bytes.
+    ///
+    /// [`RawEntry`]: ./struct.RawEntry.html
+    #[cfg(not(target_os = "linux"))]
+    #[allow(clippy::should_implement_trait)]
+    pub fn next_raw(&mut self) -> Option<io::Result<RawEntry<'_>>> {
         unsafe {
             *util::errno_ptr() = 0;
         }
@@ -108,11 +212,101 @@ impl Iterator for ReadDirIter {
                     0 => None,
                     eno => Some(Err(io::Error::from_raw_os_error(eno))),
                 };
-            } else if let Some(entry) = unsafe { Entry::from_raw(self, raw_entry) } {
+            } else if let Some(entry) = unsafe { RawEntry::from_raw(raw_entry) } {
                 return Some(Ok(entry));
             }
         }
     }
+
+    /// Get the next entry in the directory without copying its name.
+    ///
+    /// This is like `next()`, but the returned [`RawEntry`] borrows the name directly from this
+    /// iterator's internal buffer, so no allocations are performed. This may be useful when
+    /// listing very large directories, or if the names are only inspected as raw bytes.
+    ///
+    /// [`RawEntry`]: ./struct.RawEntry.html
+    #[cfg(target_os = "linux")]
+    #[allow(clippy::should_implement_trait)]
+    pub fn next_raw(&mut self) -> Option<io::Result<RawEntry<'_>>> {
+        // Offsets of the fields in struct linux_dirent64
+        const D_INO: usize = 0;
+        const D_OFF: usize = 8;
+        const D_RECLEN: usize = 16;
+        const D_TYPE: usize = 18;
+        const D_NAME: usize = 19;
+
+        let (name_range, ino, d_type) = loop {
+            if self.buf_pos >= self.buf_len {
+                let n = unsafe {
+                    libc::syscall(
+                        libc::SYS_getdents64,
+                        self.dstream.fd,
+                        self.buf.as_mut_ptr(),
+                        self.buf.len(),
+                    )
+                };
+
+                if n < 0 {
+                    return Some(Err(io::Error::last_os_error()));
+                } else if n == 0 {
+                    return None;
+                }
+
+                self.buf_pos = 0;
+                self.buf_len = n as usize;
+            }
+
+            let rec = &self.buf[self.buf_pos..self.buf_len];
+
+            let field = |start: usize, len: usize| &rec[start..start + len];
+
+            let ino = u64::from_ne_bytes(field(D_INO, 8).try_into().unwrap());
+            let off = i64::from_ne_bytes(field(D_OFF, 8).try_into().unwrap());
+            let reclen = u16::from_ne_bytes(field(D_RECLEN, 2).try_into().unwrap()) as usize;
+            // With the "portable-fallback" feature, the file type is ignored here too (see
+            // RawEntry::from_raw())
+            let d_type = if cfg!(feature = "portable-fallback") {
+                0
+            } else {
+                rec[D_TYPE]
+            };
+
+            debug_assert!(reclen > D_NAME && reclen <= rec.len());
+
+            let name_start = self.buf_pos + D_NAME;
+            let name_len = rec[D_NAME..reclen].iter().position(|&c| c == 0);
+
+            self.buf_pos += reclen;
+            self.offset = off;
+
+            let name_len = match name_len {
+                Some(name_len) => name_len,
+                // The kernel always nul-terminates names; this shouldn't happen
+                None => return Some(Err(io::Error::from_raw_os_error(libc::EIO))),
+            };
+
+            let name = &self.buf[name_start..name_start + name_len];
+            if name != b"." && name != b".." {
+                // Borrowing the name for the return value has to happen outside the loop
+                break (name_start..name_start + name_len + 1, ino, d_type);
+            }
+        };
+
+        Some(Ok(RawEntry::new(
+            unsafe { CStr::from_bytes_with_nul_unchecked(&self.buf[name_range]) },
+            ino,
+            d_type,
+        )))
+    }
+}
+
+impl Iterator for ReadDirIter {
+    type Item = io::Result<Entry>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_raw()?.map(|entry| entry.to_entry()))
+    }
 }
 
 /// Represents a seek position for a `ReadDirIter` struct.
@@ -122,20 +316,167 @@ impl Iterator for ReadDirIter {
 ///
 /// [`tell()`]: ./struct.ReadDirIter.html#method.tell
 #[derive(Copy, Clone, Debug)]
-pub struct SeekPos(libc::c_long);
+pub struct SeekPos(
+    #[cfg(target_os = "linux")] i64,
+    #[cfg(not(target_os = "linux"))] libc::c_long,
+);
 
 /// An entry encountered when iterating over a directory.
+///
+/// This is a plain owned value; it does not keep the directory open. Methods that need to access
+/// the file it names (such as [`metadata_in()`]) must be passed the directory that was listed.
+/// They check that the file they find still has the inode recorded in this entry, and fail with
+/// `ESTALE` if it does not (i.e. if the entry was replaced after the directory was listed, or if
+/// `dir` is not the directory that was listed).
+///
+/// [`metadata_in()`]: #method.metadata_in
 #[derive(Clone, Debug)]
 pub struct Entry {
     fname: CString,
     ino: u64,
     ftype: Option<FileType>,
-    dstream: Arc<Dstream>,
 }
 
 impl Entry {
+    /// Get the name of this entry.
     #[inline]
-    unsafe fn from_raw(rdir_it: &ReadDirIter, entry: *const libc::dirent) -> Option<Self> {
+    pub fn name(&self) -> &OsStr {
+        OsStr::from_bytes(self.fname.as_bytes())
+    }
+
+    /// Get the name of this entry as raw bytes.
+    #[inline]
+    pub fn name_bytes(&self) -> &[u8] {
+        self.fname.as_bytes()
+    }
+
+    /// Get the name of this entry as a `CStr`.
+    #[inline]
+    pub fn name_cstr(&self) -> &CStr {
+        &self.fname
+    }
+
+    /// Get this entry's inode.
+    ///
+    /// Note: If this entry refers to a mountpoint (including bind mounts on Linux), this may be
+    /// the inode of the *underlying directory* on which the filesystem is mounted. So this value
+    /// may not match, for example, `self.metadata_in(dir)?.ino()` (which looks up the actual root
+    /// directory of the mountpoint).
+    #[inline]
+    pub fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    /// Get the entry's file type without making any additional syscalls, if possible.
+    ///
+    /// If this returns `None`, the OS didn't specify a file type.
+    #[inline]
+    pub fn file_type(&self) -> Option<FileType> {
+        self.ftype
+    }
+
+    /// Get the metadata for the file named by this entry, which must be located in `dir` (i.e.
+    /// `dir` should refer to the directory that was being listed).
+    ///
+    /// This method will not traverse symlinks.
+    pub fn metadata_in(&self, dir: &Dir) -> io::Result<Metadata> {
+        dir.check_rights(Rights::READ)?;
+
+        let meta = Metadata::stat_at(dir.as_raw_fd(), &self.fname, libc::AT_SYMLINK_NOFOLLOW)?;
+        self.check_same(dir, &meta)?;
+        Ok(meta)
+    }
+
+    /// Read the target of the symlink named by this entry, which must be located in `dir`.
+    ///
+    /// The link is read directly relative to `dir` (with `readlinkat()`). This fails with `EINVAL`
+    /// if the entry is not a symlink.
+    ///
+    /// The entry's inode is checked immediately before the link is read; since there is no way to
+    /// read a symlink through a file descriptor portably, a very narrow race remains.
+    pub fn read_link_in(&self, dir: &Dir) -> io::Result<PathBuf> {
+        dir.check_rights(Rights::READ)?;
+
+        let meta = Metadata::stat_at(dir.as_raw_fd(), &self.fname, libc::AT_SYMLINK_NOFOLLOW)?;
+        self.check_same(dir, &meta)?;
+
+        util::readlinkat(dir.as_raw_fd(), &self.fname)
+    }
+
+    /// Open the file named by this entry, which must be located in `dir`, with the given options.
+    ///
+    /// The file is opened directly relative to `dir`, so it is guaranteed to be inside that
+    /// directory. Symlinks are not followed (this fails with `ELOOP` if the entry is a symlink).
+    /// The directory associated with `options` and its lookup flags are ignored.
+    ///
+    /// The inode is checked after the file has been opened, so if `options` specify creation or
+    /// truncation, those will already have been performed when `ESTALE` is returned.
+    pub fn open_in(&self, dir: &Dir, options: &OpenOptions) -> io::Result<fs::File> {
+        let file = options.open_child(dir, &self.fname)?;
+        self.check_same(dir, &Metadata::stat_fd(file.as_raw_fd())?)?;
+        Ok(file)
+    }
+
+    /// Open the directory named by this entry, which must be located in `dir`.
+    ///
+    /// Like [`open_in()`], this does not follow symlinks; it fails with `ENOTDIR` if the entry is
+    /// not a directory (or is a symlink).
+    ///
+    /// [`open_in()`]: #method.open_in
+    pub fn open_dir_in(&self, dir: &Dir) -> io::Result<Dir> {
+        let file = util::openat(
+            dir.as_raw_fd(),
+            &self.fname,
+            constants::DIR_OPEN_FLAGS | libc::O_NOFOLLOW,
+            0,
+        )
+        .map_err(|e| {
+            if e.raw_os_error() == Some(libc::ELOOP) {
+                io::Error::from_raw_os_error(libc::ENOTDIR)
+            } else {
+                e
+            }
+        })?;
+        self.check_same(dir, &Metadata::stat_fd(file.as_raw_fd())?)?;
+
+        Ok(Dir::derived(file.into_raw_fd(), dir))
+    }
+
+    fn check_same(&self, dir: &Dir, meta: &Metadata) -> io::Result<()> {
+        if meta.ino() == self.ino {
+            return Ok(());
+        }
+
+        // For mountpoints, the listing may report the inode of the underlying directory (see
+        // ino()), so a directory on a different device than `dir` can't be checked this way.
+        if meta.is_dir() && meta.dev() != Metadata::stat_fd(dir.as_raw_fd())?.dev() {
+            return Ok(());
+        }
+
+        Err(io::Error::from_raw_os_error(libc::ESTALE))
+    }
+}
+
+/// A directory entry whose name is borrowed from a [`ReadDirIter`]'s internal buffer.
+///
+/// This is returned by [`ReadDirIter::next_raw()`]. Use [`to_entry()`] to convert it into an owned
+/// [`Entry`].
+///
+/// [`ReadDirIter`]: ./struct.ReadDirIter.html
+/// [`ReadDirIter::next_raw()`]: ./struct.ReadDirIter.html#method.next_raw
+/// [`to_entry()`]: #method.to_entry
+/// [`Entry`]: ./struct.Entry.html
+#[derive(Copy, Clone, Debug)]
+pub struct RawEntry<'a> {
+    fname: &'a CStr,
+    ino: u64,
+    ftype: Option<FileType>,
+}
+
+impl<'a> RawEntry<'a> {
+    #[cfg(not(target_os = "linux"))]
+    #[inline]
+    unsafe fn from_raw(entry: *const libc::dirent) -> Option<Self> {
         let entry = &*entry;
 
         cfg_if::cfg_if! {
@@ -161,12 +502,6 @@ impl Entry {
             }
         }
 
-        let fname_bytes = c_fname.to_bytes();
-
-        if fname_bytes == b"." || fname_bytes == b".." {
-            return None;
-        }
-
         cfg_if::cfg_if! {
             if #[cfg(any(
                 target_os = "freebsd",
@@ -180,10 +515,40 @@ impl Entry {
             }
         }
 
-        Some(Self {
-            fname: c_fname.to_owned(),
+        let fname_bytes = c_fname.to_bytes();
+
+        if fname_bytes == b"." || fname_bytes == b".." {
+            return None;
+        }
+
+        // Solaris/illumos and Haiku don't report the file type in directory entries. With the
+        // "portable-fallback" feature, we ignore it on every platform (including in the Linux
+        // getdents64() path above) and always fall back to fstatat(). (This is DT_UNKNOWN, which
+        // isn't defined by libc on all of these platforms.)
+        #[cfg(any(
+            target_os = "solaris",
+            target_os = "illumos",
+            target_os = "haiku",
+            feature = "portable-fallback",
+        ))]
+        let d_type = 0;
+        #[cfg(not(any(
+            target_os = "solaris",
+            target_os = "illumos",
+            target_os = "haiku",
+            feature = "portable-fallback",
+        )))]
+        let d_type = entry.d_type;
+
+        Some(Self::new(c_fname, ino, d_type))
+    }
+
+    #[inline]
+    fn new(c_fname: &'a CStr, ino: u64, d_type: u8) -> Self {
+        Self {
+            fname: c_fname,
             ino,
-            ftype: match entry.d_type {
+            ftype: match d_type {
                 libc::DT_REG => Some(FileType::File),
                 libc::DT_DIR => Some(FileType::Directory),
                 libc::DT_LNK => Some(FileType::Symlink),
@@ -193,22 +558,32 @@ impl Entry {
                 libc::DT_FIFO => Some(FileType::Fifo),
                 _ => None,
             },
-            dstream: rdir_it.dstream.clone(),
-        })
+        }
     }
 
     /// Get the name of this entry.
     #[inline]
-    pub fn name(&self) -> &OsStr {
-        OsStr::from_bytes(self.fname.as_bytes())
+    pub fn name(&self) -> &'a OsStr {
+        OsStr::from_bytes(self.fname.to_bytes())
+    }
+
+    /// Get the name of this entry as raw bytes.
+    #[inline]
+    pub fn name_bytes(&self) -> &'a [u8] {
+        self.fname.to_bytes()
+    }
+
+    /// Get the name of this entry as a `CStr`.
+    #[inline]
+    pub fn name_cstr(&self) -> &'a CStr {
+        self.fname
     }
 
     /// Get this entry's inode.
     ///
-    /// Note: If this entry refers to a mountpoint (including bind mounts on Linux), this may be
-    /// the inode of the *underlying directory* on which the filesystem is mounted. So this value
-    /// may not match, for example, `self.metadata()?.ino()` (which looks up the actual root
-    /// directory of the mountpoint).
+    /// See [`Entry::ino()`] for caveats.
+    ///
+    /// [`Entry::ino()`]: ./struct.Entry.html#method.ino
     #[inline]
     pub fn ino(&self) -> u64 {
         self.ino
@@ -222,16 +597,16 @@ impl Entry {
         self.ftype
     }
 
-    /// Get the metadata for the file named by this entry.
+    /// Copy this entry's name into an owned [`Entry`].
     ///
-    /// This method will not traverse symlinks.
-    pub fn metadata(&self) -> io::Result<Metadata> {
-        util::fstatat(
-            self.dstream.as_raw_fd(),
-            &self.fname,
-            libc::AT_SYMLINK_NOFOLLOW,
-        )
-        .map(Metadata::new)
+    /// [`Entry`]: ./struct.Entry.html
+    #[inline]
+    pub fn to_entry(&self) -> Entry {
+        Entry {
+            fname: self.fname.to_owned(),
+            ino: self.ino,
+            ftype: self.ftype,
+        }
     }
 }
 
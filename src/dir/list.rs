@@ -0,0 +1,187 @@
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::prelude::*;
+
+use crate::{AsPath, Dir, LookupFlags};
+
+use super::{Entry, FileType, Metadata};
+
+/// A struct that can be used to configure how the contents of a directory are listed.
+///
+/// Unlike [`ReadDirIter`], this collects all of the entries at once, which allows them to be
+/// sorted.
+///
+/// A `ListOptions` struct can be created with [`Dir::list_options()`].
+///
+/// [`ReadDirIter`]: ./struct.ReadDirIter.html
+/// [`Dir::list_options()`]: ./struct.Dir.html#method.list_options
+#[derive(Clone, Debug)]
+pub struct ListOptions<'a> {
+    dir: &'a Dir,
+    sort_by_name: bool,
+    file_types: Vec<FileType>,
+    include_hidden: bool,
+    with_metadata: bool,
+    lookup_flags: LookupFlags,
+}
+
+impl<'a> ListOptions<'a> {
+    #[inline]
+    pub(crate) fn beneath(dir: &'a Dir) -> Self {
+        Self {
+            dir,
+            sort_by_name: false,
+            file_types: Vec::new(),
+            include_hidden: true,
+            with_metadata: false,
+            lookup_flags: LookupFlags::empty(),
+        }
+    }
+
+    /// Sort the entries by name (bytewise).
+    ///
+    /// By default, entries are returned in the order that the OS returns them.
+    #[inline]
+    pub fn sort_by_name(&mut self, sort_by_name: bool) -> &mut Self {
+        self.sort_by_name = sort_by_name;
+        self
+    }
+
+    /// Only include entries with one of the given file types.
+    ///
+    /// If this is empty (the default), entries of all types are included. If the OS doesn't
+    /// report the file type of an entry, it is looked up with `fstatat()` (without following
+    /// symlinks).
+    #[inline]
+    pub fn file_types(&mut self, file_types: &[FileType]) -> &mut Self {
+        self.file_types = file_types.to_vec();
+        self
+    }
+
+    /// Include "hidden" entries (whose names start with a `.`). This is enabled by default.
+    #[inline]
+    pub fn include_hidden(&mut self, include_hidden: bool) -> &mut Self {
+        self.include_hidden = include_hidden;
+        self
+    }
+
+    /// Retrieve the metadata of every entry (without following symlinks) while listing the
+    /// directory.
+    ///
+    /// The metadata can then be accessed with [`ListEntry::metadata()`]. If retrieving it fails
+    /// because the entry was removed in the meantime, the entry is omitted.
+    ///
+    /// [`ListEntry::metadata()`]: ./struct.ListEntry.html#method.metadata
+    #[inline]
+    pub fn with_metadata(&mut self, with_metadata: bool) -> &mut Self {
+        self.with_metadata = with_metadata;
+        self
+    }
+
+    /// Set the "lookup flags" used when resolving the path passed to [`.list()`].
+    ///
+    /// [`.list()`]: #method.list
+    #[inline]
+    pub fn lookup_flags(&mut self, lookup_flags: LookupFlags) -> &mut Self {
+        self.lookup_flags = lookup_flags;
+        self
+    }
+
+    /// List the contents of the subdirectory at `path` with the options specified by `self`.
+    pub fn list<P: AsPath>(&self, path: P) -> io::Result<Vec<ListEntry>> {
+        self.collect(&self.dir.sub_dir(path, self.lookup_flags)?)
+    }
+
+    /// List the contents of the directory itself with the options specified by `self`.
+    pub fn list_self(&self) -> io::Result<Vec<ListEntry>> {
+        self.collect(self.dir)
+    }
+
+    fn collect(&self, dir: &Dir) -> io::Result<Vec<ListEntry>> {
+        let mut entries = Vec::new();
+
+        for entry in dir.list_self()? {
+            let entry = entry?;
+
+            if !self.include_hidden && entry.name().as_bytes().starts_with(b".") {
+                continue;
+            }
+
+            let mut metadata = None;
+
+            if self.with_metadata || (!self.file_types.is_empty() && entry.file_type().is_none()) {
+                match entry.metadata_in(dir) {
+                    Ok(meta) => metadata = Some(meta),
+                    // Removed in the meantime
+                    Err(e) if e.raw_os_error() == Some(libc::ENOENT) => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if !self.file_types.is_empty() {
+                let ftype = entry
+                    .file_type()
+                    .or_else(|| metadata.as_ref().map(|m| m.file_type()));
+
+                match ftype {
+                    Some(ftype) if self.file_types.contains(&ftype) => (),
+                    _ => continue,
+                }
+            }
+
+            if !self.with_metadata {
+                metadata = None;
+            }
+
+            entries.push(ListEntry { entry, metadata });
+        }
+
+        if self.sort_by_name {
+            entries.sort_unstable_by(|a, b| a.name().cmp(b.name()));
+        }
+
+        Ok(entries)
+    }
+}
+
+/// An entry returned by [`ListOptions::list()`] or [`ListOptions::list_self()`].
+///
+/// [`ListOptions::list()`]: ./struct.ListOptions.html#method.list
+/// [`ListOptions::list_self()`]: ./struct.ListOptions.html#method.list_self
+#[derive(Clone, Debug)]
+pub struct ListEntry {
+    entry: Entry,
+    metadata: Option<Metadata>,
+}
+
+impl ListEntry {
+    /// Get the name of this entry.
+    #[inline]
+    pub fn name(&self) -> &OsStr {
+        self.entry.name()
+    }
+
+    /// Get the underlying [`Entry`].
+    ///
+    /// [`Entry`]: ./struct.Entry.html
+    #[inline]
+    pub fn entry(&self) -> &Entry {
+        &self.entry
+    }
+
+    /// Get the metadata of this entry, if [`ListOptions::with_metadata()`] was enabled.
+    ///
+    /// [`ListOptions::with_metadata()`]: ./struct.ListOptions.html#method.with_metadata
+    #[inline]
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Convert this into the underlying [`Entry`].
+    ///
+    /// [`Entry`]: ./struct.Entry.html
+    #[inline]
+    pub fn into_entry(self) -> Entry {
+        self.entry
+    }
+}
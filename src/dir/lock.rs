@@ -0,0 +1,83 @@
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+
+/// The type of lock to acquire with [`Dir::lock_file()`].
+///
+/// [`Dir::lock_file()`]: ./struct.Dir.html#method.lock_file
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum LockType {
+    /// A shared lock; multiple processes may hold shared locks on the same file at once.
+    Shared,
+    /// An exclusive lock; only one process may hold it at a time.
+    Exclusive,
+}
+
+/// A guard that holds a `flock()` lock on a file.
+///
+/// The lock is released when this is dropped (or when [`unlock()`] is called).
+///
+/// `flock()` locks are associated with the open file description, so any duplicates of the file
+/// descriptor (for example, created with `try_clone()`) share the same lock.
+///
+/// [`unlock()`]: #method.unlock
+#[derive(Debug)]
+pub struct FileLock {
+    file: fs::File,
+}
+
+impl FileLock {
+    fn flock(file: &fs::File, op: libc::c_int) -> io::Result<()> {
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), op) } == 0 {
+                return Ok(());
+            }
+
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EINTR) {
+                return Err(err);
+            }
+        }
+    }
+
+    fn lock_op(lock_type: LockType) -> libc::c_int {
+        match lock_type {
+            LockType::Shared => libc::LOCK_SH,
+            LockType::Exclusive => libc::LOCK_EX,
+        }
+    }
+
+    /// Acquire a lock on the given file, blocking until it becomes available.
+    pub fn lock(file: fs::File, lock_type: LockType) -> io::Result<Self> {
+        Self::flock(&file, Self::lock_op(lock_type))?;
+        Ok(Self { file })
+    }
+
+    /// Try to acquire a lock on the given file without blocking.
+    ///
+    /// If the lock is already held, this fails with `EWOULDBLOCK` (which has an `ErrorKind` of
+    /// `WouldBlock`).
+    pub fn try_lock(file: fs::File, lock_type: LockType) -> io::Result<Self> {
+        Self::flock(&file, Self::lock_op(lock_type) | libc::LOCK_NB)?;
+        Ok(Self { file })
+    }
+
+    /// Get a reference to the locked file.
+    #[inline]
+    pub fn file(&self) -> &fs::File {
+        &self.file
+    }
+
+    /// Release the lock, reporting any errors that occur.
+    #[inline]
+    pub fn unlock(self) -> io::Result<()> {
+        Self::flock(&self.file, libc::LOCK_UN)
+    }
+}
+
+impl Drop for FileLock {
+    #[inline]
+    fn drop(&mut self) {
+        let _ = Self::flock(&self.file, libc::LOCK_UN);
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt;
+use std::io;
+use std::ops::Deref;
+use std::os::unix::prelude::*;
+
+use crate::{AsPath, Dir, LookupFlags};
+
+use super::Metadata;
+
+/// Options for memory-mapping a file with [`Dir::mmap_file()`].
+///
+/// [`Dir::mmap_file()`]: ./struct.Dir.html#method.mmap_file
+#[derive(Clone, Debug)]
+pub struct MmapOptions {
+    lookup_flags: LookupFlags,
+    max_len: Option<u64>,
+    populate: bool,
+}
+
+impl MmapOptions {
+    /// Create a new set of options with the defaults (no size limit, not pre-populated, and an
+    /// empty set of lookup flags).
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            lookup_flags: LookupFlags::empty(),
+            max_len: None,
+            populate: false,
+        }
+    }
+
+    /// Set the lookup flags used to resolve the path.
+    #[inline]
+    pub fn lookup_flags(&mut self, lookup_flags: LookupFlags) -> &mut Self {
+        self.lookup_flags = lookup_flags;
+        self
+    }
+
+    /// Refuse to map files larger than `max_len` bytes (failing with `EFBIG`), or `None` to allow
+    /// files of any size.
+    #[inline]
+    pub fn max_len(&mut self, max_len: Option<u64>) -> &mut Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Linux-specific: Pre-fault the pages of the mapping (with `MAP_POPULATE`). This is ignored
+    /// on other platforms.
+    #[inline]
+    pub fn populate(&mut self, populate: bool) -> &mut Self {
+        self.populate = populate;
+        self
+    }
+}
+
+impl Default for MmapOptions {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A read-only memory mapping of a file, as returned by [`Dir::mmap_file()`].
+///
+/// This dereferences to the contents of the file. The mapping is removed when the `Mmap` is
+/// dropped.
+///
+/// [`Dir::mmap_file()`]: ./struct.Dir.html#method.mmap_file
+pub struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+    meta: Metadata,
+}
+
+// The mapping is read-only and is not tied to the thread that created it
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Get the metadata of the file that was mapped (retrieved from the opened file descriptor).
+    #[inline]
+    pub fn metadata(&self) -> &Metadata {
+        &self.meta
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+        }
+    }
+}
+
+impl AsRef<[u8]> for Mmap {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl fmt::Debug for Mmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mmap")
+            .field("ptr", &self.ptr)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+impl Dir {
+    /// Open the file at `path` and map its contents into memory (read-only).
+    ///
+    /// The file must be a regular file; this fails with `EISDIR` if it is a directory, or
+    /// `EINVAL` if it is any other type of file. The size of the file is checked (with `fstat()`)
+    /// both before and after it is mapped, and this fails with `EAGAIN` if it changed in between.
+    /// Empty files are not actually mapped (an empty `Mmap` is returned).
+    ///
+    /// # Safety
+    ///
+    /// The returned `Mmap` dereferences to a `&[u8]` that is backed directly by the file (the
+    /// mapping is shared), so the caller must ensure that the file is not modified or truncated
+    /// while it is mapped, by this process or any other. If the file is modified, the contents of
+    /// the slice may change underneath any references to it (which is undefined behavior), and if
+    /// it is truncated, accessing the truncated part of the mapping raises `SIGBUS`. In practice,
+    /// this means only mapping files that cannot be written by untrusted users.
+    pub unsafe fn mmap_file<P: AsPath>(&self, path: P, options: &MmapOptions) -> io::Result<Mmap> {
+        let (file, meta) = self
+            .open_file()
+            .read(true)
+            .nonblock(true)
+            .lookup_flags(options.lookup_flags)
+            .open_with_metadata(path)?;
+
+        if meta.is_dir() {
+            return Err(io::Error::from_raw_os_error(libc::EISDIR));
+        } else if !meta.is_file() {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        } else if matches!(options.max_len, Some(max_len) if meta.len() > max_len)
+            || meta.len() > isize::MAX as u64
+        {
+            return Err(io::Error::from_raw_os_error(libc::EFBIG));
+        }
+
+        let len = meta.len() as usize;
+        if len == 0 {
+            return Ok(Mmap {
+                ptr: std::ptr::null_mut(),
+                len,
+                meta,
+            });
+        }
+
+        #[allow(unused_mut)]
+        let mut flags = libc::MAP_SHARED;
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if options.populate {
+            flags |= libc::MAP_POPULATE;
+        }
+
+        let ptr = libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ,
+            flags,
+            file.as_raw_fd(),
+            0,
+        );
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Construct it now so the mapping is removed if the check fails
+        let map = Mmap { ptr, len, meta };
+
+        if Metadata::stat_fd(file.as_raw_fd())?.len() != map.meta.len() {
+            return Err(io::Error::from_raw_os_error(libc::EAGAIN));
+        }
+
+        Ok(map)
+    }
+}
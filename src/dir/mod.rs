@@ -1,18 +1,107 @@
+use std::borrow::Cow;
 use std::collections::VecDeque;
-use std::ffi::{CString, OsStr, OsString};
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs;
 use std::io;
+use std::os::unix::io::{AsFd, BorrowedFd, OwnedFd};
 use std::os::unix::prelude::*;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
 
 use crate::{constants, open_beneath, util, AsPath, LookupFlags};
 
+mod builder;
+mod cached;
+mod copy;
+mod diag;
 mod file_meta;
+mod fs_info;
+mod glob;
+mod handle;
 mod iter;
+mod list;
+mod lock;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod open_dir_opts;
 mod open_opts;
-
+mod readonly;
+mod recover;
+mod resolve;
+mod serve;
+mod socket;
+mod temp;
+mod usage;
+mod walk;
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+mod xattr;
+
+pub use builder::DirBuilder;
+pub use cached::{CachedDir, DEFAULT_CACHE_SIZE};
+pub use copy::{copy, move_file, CopyOptions, Reflink};
+pub use diag::DirDiagnostics;
 pub use file_meta::{FileType, Metadata};
-pub use iter::{Entry, ReadDirIter, SeekPos};
+pub use fs_info::FsInfo;
+pub use glob::GlobIter;
+pub use handle::Handle;
+pub use iter::{Entry, RawEntry, ReadDirIter, SeekPos};
+pub use list::{ListEntry, ListOptions};
+pub use lock::{FileLock, LockType};
+#[cfg(feature = "mmap")]
+pub use mmap::{Mmap, MmapOptions};
+pub use open_dir_opts::DirOpenOptions;
 pub use open_opts::OpenOptions;
+pub use readonly::ReadOnlyDir;
+pub use recover::recover_file_path;
+pub use resolve::{resolve_beneath, ResolvedPath};
+pub use serve::{ServedFile, SNIFF_LEN};
+pub use temp::{TempDir, TempFile};
+pub use usage::DiskUsage;
+pub use walk::{WalkEntry, WalkErrorPolicy, WalkIter, WalkOptions, WalkOrder};
+
+bitflags::bitflags! {
+    /// The permissions to check for with [`Dir::access()`].
+    ///
+    /// An empty set only checks whether the file exists.
+    ///
+    /// [`Dir::access()`]: ./struct.Dir.html#method.access
+    pub struct AccessMode: libc::c_int {
+        /// Check for read permission.
+        const READ = libc::R_OK;
+        /// Check for write permission.
+        const WRITE = libc::W_OK;
+        /// Check for execute (or, for directories, search) permission.
+        const EXEC = libc::X_OK;
+    }
+}
+
+bitflags::bitflags! {
+    /// The operations that a [`Dir`] may be used to perform.
+    ///
+    /// Every `Dir` starts out with all rights; they can be removed with [`Dir::restrict()`]. See
+    /// that method for more information.
+    ///
+    /// [`Dir`]: ./struct.Dir.html
+    /// [`Dir::restrict()`]: ./struct.Dir.html#method.restrict
+    pub struct Rights: u32 {
+        /// Read the contents and metadata of files, list directories, and read symlinks.
+        const READ = 0x01;
+        /// Open existing files for writing (including truncating or allocating space in them).
+        const WRITE = 0x02;
+        /// Create files, directories, symlinks, hard links, FIFOs, and device nodes.
+        const CREATE = 0x04;
+        /// Remove files and directories (including by replacing them, e.g. with
+        /// [`Dir::write_atomic()`], which renames a new file over the old one).
+        ///
+        /// [`Dir::write_atomic()`]: ./struct.Dir.html#method.write_atomic
+        const DELETE = 0x08;
+        /// Rename files (out of or into this directory).
+        const RENAME = 0x10;
+        /// Change the permissions, ownership, timestamps, and extended attributes of files.
+        const SETATTR = 0x20;
+    }
+}
 
 #[cfg(target_os = "linux")]
 bitflags::bitflags! {
@@ -44,10 +133,52 @@ fn cstr(s: &OsStr) -> io::Result<CString> {
     Ok(CString::new(s.as_bytes())?)
 }
 
+/// Check that `have` includes all of the rights in `need`.
+#[inline]
+pub(crate) fn check_rights(have: Rights, need: Rights) -> io::Result<()> {
+    let missing = need - have;
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(crate::Error::MissingRights(missing).into())
+    }
+}
+
+/// Get the rights needed to open a file with the given `open()` flags.
+pub(crate) fn open_rights(flags: libc::c_int) -> Rights {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if flags & libc::O_PATH == libc::O_PATH {
+        // O_PATH ignores the access mode, and the file can't be read or written through it
+        return Rights::empty();
+    }
+
+    let mut rights = match flags & libc::O_ACCMODE {
+        libc::O_WRONLY => Rights::WRITE,
+        libc::O_RDWR => Rights::READ | Rights::WRITE,
+        _ => Rights::READ,
+    };
+
+    if flags & libc::O_CREAT == libc::O_CREAT {
+        rights |= Rights::CREATE;
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if flags & libc::O_TMPFILE == libc::O_TMPFILE {
+        rights |= Rights::CREATE;
+    }
+    if flags & libc::O_TRUNC == libc::O_TRUNC {
+        rights |= Rights::WRITE;
+    }
+
+    rights
+}
+
 /// A wrapper around a directory file descriptor that allows opening files within that directory.
 #[derive(Debug)]
 pub struct Dir {
     fd: RawFd,
+    resolve_only: bool,
+    rights: Rights,
 }
 
 impl Dir {
@@ -56,10 +187,25 @@ impl Dir {
         path.with_cstr(|s| {
             Ok(Self {
                 fd: util::openat_raw(libc::AT_FDCWD, s, constants::DIR_OPEN_FLAGS, 0)?,
+                resolve_only: false,
+                rights: Rights::all(),
             })
         })
     }
 
+    /// Wrap a new directory file descriptor that was derived from `parent` (for example, by
+    /// opening one of its subdirectories).
+    ///
+    /// The new `Dir` inherits the rights and resolve-only mode of `parent`.
+    #[inline]
+    pub(crate) fn derived(fd: RawFd, parent: &Dir) -> Self {
+        Self {
+            fd,
+            resolve_only: parent.resolve_only,
+            rights: parent.rights,
+        }
+    }
+
     #[inline]
     fn reopen_raw(&self, flags: libc::c_int) -> io::Result<RawFd> {
         util::open_dot(self.fd, flags, 0).map(|f| f.into_raw_fd())
@@ -74,10 +220,10 @@ impl Dir {
     /// [`try_clone()`]: #method.try_clone
     #[inline]
     pub fn parent_unchecked(&self) -> io::Result<Self> {
-        Ok(Self {
-            fd: util::open_dotdot(self.fd, constants::DIR_OPEN_FLAGS, 0)
-                .map(|f| f.into_raw_fd())?,
-        })
+        Ok(Self::derived(
+            util::open_dotdot(self.fd, constants::DIR_OPEN_FLAGS, 0)?.into_raw_fd(),
+            self,
+        ))
     }
 
     /// Open the parent directory of this directory.
@@ -99,9 +245,76 @@ impl Dir {
     /// is passed), but the specified subdirectory must be contained within this directory.
     #[inline]
     pub fn sub_dir<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<Self> {
+        Ok(Self::derived(
+            open_beneath(self.fd, path, constants::DIR_OPEN_FLAGS, 0, lookup_flags)?.into_raw_fd(),
+            self,
+        ))
+    }
+
+    /// Open a subdirectory of this directory, specifying custom flags.
+    ///
+    /// This is like [`sub_dir()`], except that the given `flags` are used to open the directory
+    /// instead of the default ones (`O_DIRECTORY` is always added). For example, on Linux,
+    /// `libc::O_PATH` could be passed to open a directory that can't be listed, or
+    /// `libc::O_RDONLY` to ensure that it can be.
+    ///
+    /// [`sub_dir()`]: #method.sub_dir
+    #[inline]
+    pub fn open_dir_beneath<P: AsPath>(
+        &self,
+        path: P,
+        flags: libc::c_int,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<Self> {
+        Ok(Self::derived(
+            open_beneath(self.fd, path, flags | libc::O_DIRECTORY, 0, lookup_flags)?.into_raw_fd(),
+            self,
+        ))
+    }
+
+    /// Open the directory containing the final component of `path`, and return it along with the
+    /// name of that final component.
+    ///
+    /// This is a shorthand for [`resolve()`] that returns the parent directory and the final
+    /// component directly: every component except the last is resolved within this directory
+    /// (subject to `lookup_flags`), and the last component is returned as-is (it is not resolved,
+    /// and may not exist). This makes it possible to implement custom operations on the final
+    /// component (for example, with `*at()` syscalls that this crate doesn't wrap) relative to the
+    /// returned directory.
+    ///
+    /// If `path` refers to a directory itself rather than naming an entry in one (for example,
+    /// `.`, `a/..`, `a/`, or `/` with `LookupFlags::IN_ROOT`), the returned name is `None`, and
+    /// the returned directory is the one referred to by `path`.
+    ///
+    /// Like [`resolve()`], this requires `Rights::READ`, and the returned directory inherits this
+    /// directory's rights (see [`restrict()`]).
+    ///
+    /// [`resolve()`]: #method.resolve
+    /// [`restrict()`]: #method.restrict
+    pub fn open_parent<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<(Self, Option<OsString>)> {
+        let resolved = self.resolve(path, lookup_flags)?;
+        let fname = resolved.file_name().map(OsStr::to_os_string);
+
+        Ok((resolved.into_parent(), fname))
+    }
+
+    /// Convert the given `File` (which must be open to a directory) into a `Dir`.
+    ///
+    /// This fails with `ENOTDIR` if `file` does not refer to a directory (in which case `file` is
+    /// closed).
+    pub fn from_file(file: fs::File) -> io::Result<Self> {
+        if util::fstat(file.as_raw_fd())?.st_mode & libc::S_IFMT != libc::S_IFDIR {
+            return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+        }
+
         Ok(Self {
-            fd: open_beneath(self.fd, path, constants::DIR_OPEN_FLAGS, 0, lookup_flags)?
-                .into_raw_fd(),
+            fd: file.into_raw_fd(),
+            resolve_only: false,
+            rights: Rights::all(),
         })
     }
 
@@ -112,25 +325,50 @@ impl Dir {
         mode: libc::mode_t,
         lookup_flags: LookupFlags,
     ) -> io::Result<()> {
+        self.check_rights(Rights::CREATE)?;
+
         let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
 
         if let Some(fname) = fname {
             let fd = subdir.as_ref().unwrap_or(self).as_raw_fd();
 
-            util::mkdirat(fd, &cstr(fname)?, mode)
+            util::mkdirat(fd, &cstr(&fname)?, mode)
         } else {
             Err(io::Error::from_raw_os_error(libc::EEXIST))
         }
     }
 
+    /// Recursively create a directory within this directory, along with all of its missing parent
+    /// directories.
+    ///
+    /// This is similar to `std::fs::create_dir_all()`. Every directory that is created is created
+    /// with the given `mode`, and the lookup of each one is subject to the same restrictions as
+    /// [`create_dir()`]. If the specified directory already exists, this returns `Ok(())`.
+    ///
+    /// [`create_dir()`]: #method.create_dir
+    pub fn create_dir_all<P: AsPath>(
+        &self,
+        path: P,
+        mode: libc::mode_t,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        self.dir_builder()
+            .recursive(true)
+            .mode(mode)
+            .lookup_flags(lookup_flags)
+            .create(path)
+    }
+
     /// Remove a subdirectory of this directory.
     pub fn remove_dir<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<()> {
+        self.check_rights(Rights::DELETE)?;
+
         let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
 
         if let Some(fname) = fname {
             let fd = subdir.as_ref().unwrap_or(self).as_raw_fd();
 
-            match util::unlinkat(fd, &cstr(fname)?, true) {
+            match util::unlinkat(fd, &cstr(&fname)?, true) {
                 Err(e) => {
                     #[cfg(not(any(
                         target_os = "linux",
@@ -155,159 +393,1466 @@ impl Dir {
         }
     }
 
-    /// Remove a file within this directory.
-    pub fn remove_file<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<()> {
+    /// Remove a subdirectory of this directory, after recursively removing all of its contents.
+    ///
+    /// Each directory in the tree is opened (without following symlinks) and its contents are
+    /// removed relative to that file descriptor, so paths are never re-resolved from the top. As a
+    /// result, symlinks inside the tree are removed rather than traversed, and renaming directories
+    /// or swapping in symlinks partway through cannot redirect the removal outside of the tree.
+    ///
+    /// If the final component of `path` is a symlink (or any other non-directory), it is simply
+    /// removed.
+    pub fn remove_dir_all<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<()> {
+        self.check_rights(Rights::DELETE)?;
+
+        self.check_listable()?;
+
         let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
 
         if let Some(fname) = fname {
             let fd = subdir.as_ref().unwrap_or(self).as_raw_fd();
 
-            util::unlinkat(fd, &cstr(fname)?, false)
+            remove_all_at(fd, &cstr(&fname)?, None)
         } else {
-            Err(io::Error::from_raw_os_error(libc::EISDIR))
+            Err(std::io::Error::from_raw_os_error(libc::EBUSY))
         }
     }
 
-    /// Create a symlink within this directory.
+    /// Remove the subdirectory at `path` (if it exists) and create a new, empty directory in its
+    /// place with the given `mode`.
     ///
-    /// `path` specifies the path where the symlink is created, and `target` specifies the file
-    /// that the symlink will point to. Note that the order is swapped compared to the C `symlink()`
-    /// function (and Rust's `std::os::unix::fs::symlink()`).
-    pub fn symlink<P: AsPath, T: AsPath>(
+    /// If `recursive` is `true`, the existing directory's contents are removed first (as if by
+    /// [`remove_dir_all()`]); otherwise, this fails with `ENOTEMPTY` if the directory is not
+    /// empty. If `path` exists but is not a directory (including if it is a symlink to a
+    /// directory), this fails with `ENOTDIR` and nothing is removed.
+    ///
+    /// The containing directory is only resolved once, and the removal and creation are both
+    /// performed relative to it, so the two steps cannot end up operating on different
+    /// directories if `path` is modified concurrently. However, another process could still
+    /// create a file at `path` between the two steps, in which case this fails with `EEXIST`.
+    ///
+    /// [`remove_dir_all()`]: #method.remove_dir_all
+    pub fn recreate_dir<P: AsPath>(
         &self,
         path: P,
-        target: T,
+        mode: libc::mode_t,
+        recursive: bool,
         lookup_flags: LookupFlags,
     ) -> io::Result<()> {
+        self.check_rights(Rights::CREATE | Rights::DELETE)?;
+
+        if recursive {
+            self.check_listable()?;
+        }
+
+        let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
+
+        let fname = match fname {
+            Some(fname) => cstr(&fname)?,
+            None => return Err(io::Error::from_raw_os_error(libc::EBUSY)),
+        };
+
+        let fd = subdir.as_ref().unwrap_or(self).as_raw_fd();
+
+        let res = match util::fstatat(fd, &fname, libc::AT_SYMLINK_NOFOLLOW) {
+            Ok(st) if Metadata::new(st).file_type() != FileType::Directory => {
+                return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+            }
+
+            // If it was replaced with a non-directory after we checked, this will fail
+            Ok(_) if recursive => remove_all_at(fd, &fname, Some(FileType::Directory)),
+            Ok(_) => util::unlinkat(fd, &fname, true),
+
+            Err(e) => Err(e),
+        };
+
+        match res {
+            // It doesn't exist (or somebody else removed it first)
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => (),
+            res => res?,
+        }
+
+        util::mkdirat(fd, &fname, mode)
+    }
+
+    /// Remove a file within this directory.
+    pub fn remove_file<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<()> {
+        self.check_rights(Rights::DELETE)?;
+
         let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
 
         if let Some(fname) = fname {
             let fd = subdir.as_ref().unwrap_or(self).as_raw_fd();
 
-            target.with_cstr(|target| util::symlinkat(target, fd, &cstr(fname)?))
+            util::unlinkat(fd, &cstr(&fname)?, false)
         } else {
-            Err(io::Error::from_raw_os_error(libc::EEXIST))
+            Err(io::Error::from_raw_os_error(libc::EISDIR))
         }
     }
 
-    /// Read the contents of the specified symlink.
-    pub fn read_link<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<PathBuf> {
-        cfg_if::cfg_if! {
-            if #[cfg(all(target_os = "linux", feature = "openat2"))] {
-                // On Linux, we can actually get a file descriptor to the *symlink*, then
-                // readlink() that. However, if we don't have openat2() then this costs an extra
-                // syscall, so let's only do it if the `openat2` feature is enabled.
-                use std::ffi::CStr;
+    /// Open the file at `path` and acquire a `flock()` lock on it, blocking until the lock is
+    /// available.
+    ///
+    /// The file is opened for reading, and it must already exist and be a regular file (this
+    /// fails with `EISDIR` for directories and `EINVAL` for other types of files, and opening a
+    /// FIFO won't block). The lock is held until the returned [`FileLock`] is dropped.
+    ///
+    /// [`FileLock`]: ./struct.FileLock.html
+    pub fn lock_file<P: AsPath>(
+        &self,
+        path: P,
+        lock_type: LockType,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<FileLock> {
+        let file = self.open_regular(path, false, lookup_flags)?;
 
-                let file = open_beneath(
-                    self.fd,
-                    path,
-                    libc::O_PATH | libc::O_NOFOLLOW,
-                    0,
-                    lookup_flags,
-                )?;
+        FileLock::lock(file, lock_type)
+    }
 
-                match util::readlinkat(file.as_raw_fd(), unsafe {
-                    CStr::from_bytes_with_nul_unchecked(b"\0".as_ref())
-                }) {
-                    Ok(target) => Ok(target),
+    /// Like [`lock_file()`], but fails with `EWOULDBLOCK` instead of blocking if the lock is
+    /// already held.
+    ///
+    /// [`lock_file()`]: #method.lock_file
+    pub fn try_lock_file<P: AsPath>(
+        &self,
+        path: P,
+        lock_type: LockType,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<FileLock> {
+        let file = self.open_regular(path, false, lookup_flags)?;
 
-                    // This error means we got a file descriptor that doesn't point to a symlink
-                    Err(e) if e.raw_os_error() == Some(libc::ENOENT) => {
-                        Err(io::Error::from_raw_os_error(libc::EINVAL))
-                    }
+        FileLock::try_lock(file, lock_type)
+    }
 
-                    Err(e) => Err(e),
-                }
-            } else {
-                // On other OSes (or without openat2()), we have to split the path and perform a
-                // few more allocations.
+    /// Open the regular file at `path` for reading (or for writing, if `write` is `true`).
+    ///
+    /// The file is opened with `O_NONBLOCK` (which is cleared afterward), so a FIFO at `path`
+    /// can't block. This fails with `EISDIR` if the file is a directory, or `EINVAL` if it is any
+    /// other non-regular file (except that opening a FIFO with no readers for writing fails with
+    /// `ENXIO`).
+    fn open_regular<P: AsPath>(
+        &self,
+        path: P,
+        write: bool,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<fs::File> {
+        let file = self
+            .open_file()
+            .read(!write)
+            .write(write)
+            .nonblock(true)
+            .lookup_flags(lookup_flags)
+            .open(path)?;
+
+        match Metadata::stat_fd(file.as_raw_fd())?.file_type() {
+            FileType::File => (),
+            FileType::Directory => return Err(io::Error::from_raw_os_error(libc::EISDIR)),
+            _ => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        }
 
-                let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
+        util::set_nonblock(file.as_raw_fd(), false)?;
 
-                if let Some(fname) = fname {
-                    let fd = subdir.as_ref().unwrap_or(self).as_raw_fd();
+        Ok(file)
+    }
 
-                    util::readlinkat(fd, &cstr(fname)?)
-                } else {
-                    Err(io::Error::from_raw_os_error(libc::EINVAL))
-                }
-            }
+    /// Atomically replace the file at `path` with a file containing `contents` (or create it if it
+    /// doesn't exist).
+    ///
+    /// This creates a uniquely named temporary file in the directory containing `path` (with the
+    /// given `mode`, which will be modified by the umask), writes `contents` to it, flushes it to
+    /// disk with `fsync()`, and finally renames it over `path`. As a result, other processes will
+    /// see either the old file or the new file, never a partially written one. If any step fails,
+    /// the temporary file is removed.
+    ///
+    /// If the final component of `path` is a symlink, it is replaced (not followed).
+    ///
+    /// Note that this does not `fsync()` the containing directory, so the rename itself may not
+    /// yet be durable when this returns. Use [`sync_parent()`] if that is required.
+    ///
+    /// [`sync_parent()`]: #method.sync_parent
+    pub fn write_atomic<P: AsPath, C: AsRef<[u8]>>(
+        &self,
+        path: P,
+        contents: C,
+        mode: libc::mode_t,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        use std::io::Write;
+
+        // Renaming over `path` removes whatever was there
+        self.check_rights(Rights::CREATE | Rights::WRITE | Rights::DELETE)?;
+
+        let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
+
+        let fname = match fname {
+            Some(fname) => cstr(&fname)?,
+            None => return Err(io::Error::from_raw_os_error(libc::EISDIR)),
+        };
+
+        let fd = subdir.as_ref().unwrap_or(self).as_raw_fd();
+
+        let (tmp_name, mut file) = create_temp_file(fd, mode)?;
+
+        let res = file
+            .write_all(contents.as_ref())
+            .and_then(|()| file.sync_all())
+            .and_then(|()| util::renameat(fd, &tmp_name, fd, &fname));
+
+        if res.is_err() {
+            let _ = util::unlinkat(fd, &tmp_name, false);
         }
+
+        res
     }
 
-    /// Rename a file in this directory.
+    /// Atomically replace the existing regular file at `path` with a file containing `contents`,
+    /// keeping the permission bits of the original file.
     ///
-    /// This is exactly equivalent to `rename(self, old, self, new, lookup_flags)`.
-    #[inline]
-    pub fn local_rename<P: AsPath, R: AsPath>(
+    /// This works like [`write_atomic()`] (the new contents are written to a temporary file in
+    /// the same directory, which is then renamed over `path`), except that the file must already
+    /// exist, and the new file is given the same permission bits as the old one (not modified by
+    /// the umask), except for the setuid and setgid bits, which are cleared. If `path` refers to a
+    /// directory, this fails with `EISDIR`; if it refers to any other non-regular file (including
+    /// a symlink), this fails with `EINVAL`.
+    ///
+    /// Note that the ownership, extended attributes, and hard links of the original file are not
+    /// preserved.
+    ///
+    /// [`write_atomic()`]: #method.write_atomic
+    pub fn replace_file<P: AsPath, C: AsRef<[u8]>>(
         &self,
-        old: P,
-        new: R,
+        path: P,
+        contents: C,
         lookup_flags: LookupFlags,
     ) -> io::Result<()> {
-        rename(self, old, self, new, lookup_flags)
+        use std::io::Write;
+
+        // Renaming over `path` removes whatever was there
+        self.check_rights(Rights::CREATE | Rights::WRITE | Rights::DELETE)?;
+
+        let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
+
+        let fname = match fname {
+            Some(fname) => cstr(&fname)?,
+            None => return Err(io::Error::from_raw_os_error(libc::EISDIR)),
+        };
+
+        let fd = subdir.as_ref().unwrap_or(self).as_raw_fd();
+
+        let meta = Metadata::new(util::fstatat(fd, &fname, libc::AT_SYMLINK_NOFOLLOW)?);
+        match meta.file_type() {
+            FileType::File => (),
+            FileType::Directory => return Err(io::Error::from_raw_os_error(libc::EISDIR)),
+            _ => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        }
+        // Don't carry over the setuid/setgid bits (the new file may be owned by someone else)
+        let mode = meta.stat().st_mode & 0o1777;
+
+        let (tmp_name, mut file) = create_temp_file(fd, 0o600)?;
+
+        let res = util::fchmod(file.as_raw_fd(), mode)
+            .and_then(|()| file.write_all(contents.as_ref()))
+            .and_then(|()| file.sync_all())
+            .and_then(|()| util::renameat(fd, &tmp_name, fd, &fname));
+
+        if res.is_err() {
+            let _ = util::unlinkat(fd, &tmp_name, false);
+        }
+
+        res
     }
 
-    /// List the contents of this directory.
-    pub fn list_self(&self) -> io::Result<ReadDirIter> {
-        ReadDirIter::new_consume(self.reopen_raw(libc::O_DIRECTORY | libc::O_RDONLY)?)
+    /// Read the entire contents of the file at `path` into a bytes vector.
+    ///
+    /// This is analogous to `std::fs::read()`; the file is opened with [`open_file()`] (so
+    /// `lookup_flags` is honored and the file cannot escape this directory).
+    ///
+    /// [`open_file()`]: #method.open_file
+    pub fn read<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<Vec<u8>> {
+        use std::io::Read;
+
+        let mut file = self
+            .open_file()
+            .read(true)
+            .lookup_flags(lookup_flags)
+            .open(path)?;
+
+        let mut buf = Vec::with_capacity(initial_buffer_size(&file));
+        file.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read the entire contents of the file at `path` into a string.
+    ///
+    /// This is analogous to `std::fs::read_to_string()`, and it fails with `InvalidData` if the
+    /// contents are not valid UTF-8. See [`read()`] for more information.
+    ///
+    /// [`read()`]: #method.read
+    pub fn read_to_string<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<String> {
+        use std::io::Read;
+
+        let mut file = self
+            .open_file()
+            .read(true)
+            .lookup_flags(lookup_flags)
+            .open(path)?;
+
+        let mut buf = String::with_capacity(initial_buffer_size(&file));
+        file.read_to_string(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read up to `len` bytes from the file at `path`, starting at `offset`.
+    ///
+    /// This is useful for serving HTTP range requests. The file is opened with [`open_file()`]
+    /// (so `lookup_flags` is honored and the file cannot escape this directory), and then read
+    /// with [`read_file_range()`]; see that function for more details.
+    ///
+    /// The file must be a regular file; this fails with `EISDIR` for directories and `EINVAL` for
+    /// other types of files (without blocking on FIFOs).
+    ///
+    /// [`open_file()`]: #method.open_file
+    /// [`read_file_range()`]: ./fn.read_file_range.html
+    pub fn read_range<P: AsPath>(
+        &self,
+        path: P,
+        offset: u64,
+        len: usize,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<Vec<u8>> {
+        let file = self.open_regular(path, false, lookup_flags)?;
+
+        read_file_range(&file, offset, len)
+    }
+
+    /// Stream the contents of the file at `path` into `hasher`, and return the number of bytes
+    /// that were written.
+    ///
+    /// `hasher` can be anything that implements `io::Write`; for example, the hashers from the
+    /// RustCrypto project (`sha2::Sha256`, etc.) implement it if their `std` feature is enabled.
+    /// This is useful for computing checksums or ETags without having to read the entire file into
+    /// memory, and without a window between resolving the path and opening the file.
+    ///
+    /// The file is opened with [`open_file()`] (so `lookup_flags` is honored and the file cannot
+    /// escape this directory). It must be a regular file; this fails with `EISDIR` for
+    /// directories and `EINVAL` for other types of files (without blocking on FIFOs).
+    ///
+    /// [`open_file()`]: #method.open_file
+    pub fn hash_file<P: AsPath, W: io::Write + ?Sized>(
+        &self,
+        path: P,
+        hasher: &mut W,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<u64> {
+        let mut file = self.open_regular(path, false, lookup_flags)?;
+
+        io::copy(&mut file, hasher)
+    }
+
+    /// Write `contents` to the file at `path`, creating it if it does not exist and truncating it
+    /// if it does.
+    ///
+    /// This is analogous to `std::fs::write()`. New files are created with mode `0o666` (modified
+    /// by the umask). Unlike [`write_atomic()`], other processes may observe a partially written
+    /// file.
+    ///
+    /// [`write_atomic()`]: #method.write_atomic
+    pub fn write<P: AsPath, C: AsRef<[u8]>>(
+        &self,
+        path: P,
+        contents: C,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        use std::io::Write;
+
+        self.open_file()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .lookup_flags(lookup_flags)
+            .open(path)?
+            .write_all(contents.as_ref())
+    }
+
+    /// Truncate or extend the file at `path` to exactly `len` bytes.
+    ///
+    /// This is analogous to `truncate()`; the file is opened for writing (it must already exist
+    /// and be a regular file) and `ftruncate()` is called on it. Extending a file fills it with
+    /// zeroes.
+    pub fn truncate<P: AsPath>(
+        &self,
+        path: P,
+        len: u64,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        self.open_regular(path, true, lookup_flags)?.set_len(len)
+    }
+
+    /// Allocate disk space for the byte range `offset..offset + len` of the file at `path`.
+    ///
+    /// The file is opened for writing (it must already exist and be a regular file). If the range
+    /// extends past the end of the file, the file size is increased. After this succeeds, writes
+    /// to the range will not fail because of a lack of disk space.
+    ///
+    /// This uses `fallocate()` on Linux/Android and `posix_fallocate()` on FreeBSD; it fails with
+    /// `EOPNOTSUPP` on other platforms (or if the filesystem does not support it).
+    pub fn allocate<P: AsPath>(
+        &self,
+        path: P,
+        offset: u64,
+        len: u64,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        let file = self.open_regular(path, true, lookup_flags)?;
+
+        util::fallocate(file.as_raw_fd(), offset, len)
+    }
+
+    /// Create a symlink within this directory.
+    ///
+    /// `path` specifies the path where the symlink is created, and `target` specifies the file
+    /// that the symlink will point to. Note that the order is swapped compared to the C `symlink()`
+    /// function (and Rust's `std::os::unix::fs::symlink()`).
+    ///
+    /// This fails with `EEXIST` if `path` already exists; use [`symlink_replace()`] to replace it
+    /// instead.
+    ///
+    /// [`symlink_replace()`]: #method.symlink_replace
+    pub fn symlink<P: AsPath, T: AsPath>(
+        &self,
+        path: P,
+        target: T,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        self.check_rights(Rights::CREATE)?;
+
+        let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
+
+        if let Some(fname) = fname {
+            let fd = subdir.as_ref().unwrap_or(self).as_raw_fd();
+
+            target.with_cstr(|target| util::symlinkat(target, fd, &cstr(&fname)?))
+        } else {
+            Err(io::Error::from_raw_os_error(libc::EEXIST))
+        }
+    }
+
+    /// Create a symlink within this directory, atomically replacing any existing file at `path`.
+    ///
+    /// This is like [`symlink()`] (and the arguments are in the same order), except that if `path`
+    /// already exists, it is replaced instead of failing with `EEXIST`. The new symlink is created
+    /// with a unique temporary name in the directory containing `path`, and then renamed over
+    /// `path`, so other processes will see either the old file or the new symlink, never a missing
+    /// file. This makes it suitable for "flipping" a `current -> releases/N` symlink. If any step
+    /// fails, the temporary symlink is removed.
+    ///
+    /// If the final component of `path` is a symlink, it is replaced (not followed). Directories
+    /// cannot be replaced (this fails with `EISDIR`).
+    ///
+    /// [`symlink()`]: #method.symlink
+    pub fn symlink_replace<P: AsPath, T: AsPath>(
+        &self,
+        path: P,
+        target: T,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        self.check_rights(Rights::CREATE | Rights::DELETE)?;
+
+        let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
+
+        let fname = match fname {
+            Some(fname) => cstr(&fname)?,
+            None => return Err(io::Error::from_raw_os_error(libc::EISDIR)),
+        };
+
+        let fd = subdir.as_ref().unwrap_or(self).as_raw_fd();
+
+        let tmp_name = target.with_cstr(|target| create_temp_symlink(target, fd))?;
+
+        let res = util::renameat(fd, &tmp_name, fd, &fname);
+
+        if res.is_err() {
+            let _ = util::unlinkat(fd, &tmp_name, false);
+        }
+
+        res
+    }
+
+    /// Create a FIFO (named pipe) within this directory.
+    ///
+    /// The permission bits of the new FIFO are taken from `mode` (masked by the process umask).
+    pub fn mkfifo<P: AsPath>(
+        &self,
+        path: P,
+        mode: libc::mode_t,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        self.check_rights(Rights::CREATE)?;
+
+        let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
+
+        if let Some(fname) = fname {
+            let fd = subdir.as_ref().unwrap_or(self).as_raw_fd();
+
+            util::mkfifoat(fd, &cstr(&fname)?, mode)
+        } else {
+            Err(io::Error::from_raw_os_error(libc::EEXIST))
+        }
+    }
+
+    /// Create a filesystem node (a device file, FIFO, socket, or regular file) within this
+    /// directory.
+    ///
+    /// `mode` specifies both the type of the node (e.g. `libc::S_IFCHR`) and its permission bits;
+    /// `dev` is the device number for block and character special files (and is otherwise
+    /// ignored). See mknod(2) for more details.
+    ///
+    /// Creating device nodes usually requires special privileges (e.g. `CAP_MKNOD` on Linux).
+    pub fn mknod<P: AsPath>(
+        &self,
+        path: P,
+        mode: libc::mode_t,
+        dev: libc::dev_t,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        self.check_rights(Rights::CREATE)?;
+
+        let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
+
+        if let Some(fname) = fname {
+            let fd = subdir.as_ref().unwrap_or(self).as_raw_fd();
+
+            util::mknodat(fd, &cstr(&fname)?, mode, dev)
+        } else {
+            Err(io::Error::from_raw_os_error(libc::EEXIST))
+        }
+    }
+
+    /// Linux-specific: Give a name within this directory to a file created with
+    /// [`OpenOptions::tmpfile()`].
+    ///
+    /// `path` must not already exist (if it does, this fails with `EEXIST`). To atomically
+    /// *replace* an existing file, link the temporary file under a different name and then
+    /// [`rename()`] it over the original.
+    ///
+    /// Note that `file` does not have to have been created in this directory, but it must be on
+    /// the same filesystem.
+    ///
+    /// This first tries `linkat()` with `AT_EMPTY_PATH`, which requires the `CAP_DAC_READ_SEARCH`
+    /// capability. If that fails, it falls back on linking `/proc/self/fd/$fd`, which requires
+    /// `/proc` to be mounted.
+    ///
+    /// [`OpenOptions::tmpfile()`]: ./struct.OpenOptions.html#method.tmpfile
+    /// [`rename()`]: ./fn.rename.html
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn link_tmpfile<P: AsPath>(
+        &self,
+        file: &fs::File,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        self.check_rights(Rights::CREATE)?;
+
+        let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
+
+        if let Some(fname) = fname {
+            let fd = subdir.as_ref().unwrap_or(self).as_raw_fd();
+
+            util::link_tmpfile(file.as_raw_fd(), fd, &cstr(&fname)?)
+        } else {
+            Err(io::Error::from_raw_os_error(libc::EEXIST))
+        }
+    }
+
+    /// Read the contents of the specified symlink.
+    pub fn read_link<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<PathBuf> {
+        self.check_rights(Rights::READ)?;
+
+        cfg_if::cfg_if! {
+            if #[cfg(all(target_os = "linux", feature = "openat2"))] {
+                // On Linux, we can actually get a file descriptor to the *symlink*, then
+                // readlink() that. However, if we don't have openat2() then this costs an extra
+                // syscall, so let's only do it if the `openat2` feature is enabled.
+                use std::ffi::CStr;
+
+                let file = open_beneath(
+                    self.fd,
+                    path,
+                    libc::O_PATH | libc::O_NOFOLLOW,
+                    0,
+                    lookup_flags,
+                )?;
+
+                match util::readlinkat(file.as_raw_fd(), unsafe {
+                    CStr::from_bytes_with_nul_unchecked(b"\0".as_ref())
+                }) {
+                    Ok(target) => Ok(target),
+
+                    // This error means we got a file descriptor that doesn't point to a symlink
+                    Err(e) if e.raw_os_error() == Some(libc::ENOENT) => {
+                        Err(io::Error::from_raw_os_error(libc::EINVAL))
+                    }
+
+                    Err(e) => Err(e),
+                }
+            } else {
+                // On other OSes (or without openat2()), we have to split the path and perform a
+                // few more allocations.
+
+                let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
+
+                if let Some(fname) = fname {
+                    let fd = subdir.as_ref().unwrap_or(self).as_raw_fd();
+
+                    util::readlinkat(fd, &cstr(&fname)?)
+                } else {
+                    Err(io::Error::from_raw_os_error(libc::EINVAL))
+                }
+            }
+        }
+    }
+
+    /// Read the contents of the specified symlink as raw bytes.
+    ///
+    /// This is equivalent to [`read_link()`] (which is also lossless, since a `PathBuf` can hold
+    /// arbitrary bytes on Unix), but it avoids the conversion for callers that need the exact bytes
+    /// (for example, when writing them to an archive).
+    ///
+    /// [`read_link()`]: #method.read_link
+    #[inline]
+    pub fn read_link_bytes<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<Vec<u8>> {
+        Ok(self
+            .read_link(path, lookup_flags)?
+            .into_os_string()
+            .into_vec())
+    }
+
+    /// Resolve the given path within this directory, and return the canonical path of the file
+    /// it refers to (relative to this directory).
+    ///
+    /// This is similar to `std::fs::canonicalize()`: all symlinks are expanded, and `.` and `..`
+    /// components are removed, so the returned path never contains `..` components (and it has no
+    /// leading slash). If the path refers to this directory itself, `.` is returned. The file must
+    /// exist.
+    ///
+    /// Path resolution follows the same rules as when opening files (for example, absolute
+    /// symlinks only work if `LookupFlags::IN_ROOT` is passed, and otherwise escaping the
+    /// directory fails with `EXDEV`), with the same exceptions as [`resolve_beneath()`].
+    ///
+    /// Note that the returned path reflects the state of the filesystem at the time of the call;
+    /// if files are moved or symlinks are changed, it may no longer refer to the same file.
+    ///
+    /// [`resolve_beneath()`]: ./fn.resolve_beneath.html
+    pub fn canonicalize<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<PathBuf> {
+        self.check_rights(Rights::READ)?;
+
+        let (names, _, _) = self.resolve_components(path.as_path(), lookup_flags, false)?;
+
+        if names.is_empty() {
+            Ok(PathBuf::from("."))
+        } else {
+            Ok(names.iter().collect())
+        }
+    }
+
+    /// Helper for `canonicalize()` and `resolve()`.
+    ///
+    /// Returns the components of the resolved path and the directories they refer to (except the
+    /// final component, which may not be a directory). If `stop_at_leaf` is true, the final
+    /// component is not resolved at all (it doesn't have to exist, and if it's a symlink it isn't
+    /// followed); instead, it is returned separately.
+    fn resolve_components(
+        &self,
+        path: &Path,
+        lookup_flags: LookupFlags,
+        stop_at_leaf: bool,
+    ) -> io::Result<(Vec<OsString>, Vec<Dir>, Option<OsString>)> {
+        let normalized = crate::safe_path::normalize_components(path, lookup_flags)?;
+        let path = normalized.as_deref().unwrap_or(path);
+
+        if path.as_os_str().is_empty() {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        }
+
+        // This is always resolved manually, which may block
+        if lookup_flags.contains(LookupFlags::CACHED_ONLY) {
+            return Err(io::Error::from_raw_os_error(libc::EAGAIN));
+        }
+
+        let dir_mnt_id = if lookup_flags.contains(LookupFlags::NO_XDEV) {
+            Some(crate::mntid::identify_mount(self.fd)?)
+        } else {
+            None
+        };
+
+        let mut links = if lookup_flags.contains(LookupFlags::NO_SYMLINKS) {
+            util::SymlinkCounter::nolinks()
+        } else {
+            util::SymlinkCounter::new()
+        };
+
+        let mut queue = VecDeque::new();
+        push_path_components(&mut queue, path);
+
+        // The components of the resolved path, and the directories they refer to (except the
+        // final component, which may not be a directory)
+        let mut names: Vec<OsString> = Vec::new();
+        let mut dirs: Vec<Dir> = Vec::new();
+
+        // Whether the final component of `names` must be a directory
+        let mut want_dir = ends_with_dir_marker(path);
+
+        while let Some(component) = queue.pop_front() {
+            let cur_fd = dirs.last().map_or(self.fd, |d| d.fd);
+
+            match component.as_bytes() {
+                b"/" => {
+                    if !lookup_flags.contains(LookupFlags::IN_ROOT) {
+                        return Err(io::Error::from_raw_os_error(libc::EXDEV));
+                    }
+
+                    names.clear();
+                    dirs.clear();
+                }
+
+                b".." => {
+                    if lookup_flags.contains(LookupFlags::NO_DOTDOT) {
+                        return Err(io::Error::from_raw_os_error(libc::EXDEV));
+                    }
+
+                    if names.pop().is_none() && !lookup_flags.contains(LookupFlags::IN_ROOT) {
+                        return Err(io::Error::from_raw_os_error(libc::EXDEV));
+                    }
+                    dirs.truncate(names.len());
+                }
+
+                _ => {
+                    if stop_at_leaf && queue.is_empty() && !want_dir {
+                        return Ok((names, dirs, Some(component)));
+                    }
+
+                    let c_name = cstr(&component)?;
+
+                    match util::readlinkat(cur_fd, &c_name) {
+                        Ok(target) => {
+                            links.advance()?;
+                            crate::open::check_magiclink(cur_fd, lookup_flags)?;
+                            crate::open::check_protected_symlink(
+                                cur_fd,
+                                cur_fd,
+                                &c_name,
+                                lookup_flags,
+                            )?;
+
+                            if queue.is_empty() && ends_with_dir_marker(&target) {
+                                want_dir = true;
+                            }
+
+                            push_path_components(&mut queue, &target);
+                            continue;
+                        }
+
+                        // Not a symlink
+                        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => (),
+
+                        Err(e) => return Err(e),
+                    }
+
+                    if queue.is_empty() && !want_dir {
+                        // The final component; it only has to exist (and be on the same mount)
+                        if let Some(dir_mnt_id) = dir_mnt_id {
+                            let file = util::openat(
+                                cur_fd,
+                                &c_name,
+                                constants::REF_OPEN_FLAGS | libc::O_NOFOLLOW,
+                                0,
+                            )?;
+
+                            if crate::mntid::identify_mount(file.as_raw_fd())? != dir_mnt_id {
+                                return Err(io::Error::from_raw_os_error(libc::EXDEV));
+                            }
+                        } else {
+                            util::fstatat(cur_fd, &c_name, libc::AT_SYMLINK_NOFOLLOW)?;
+                        }
+                    } else {
+                        let dir = Self::derived(
+                            util::openat_raw(
+                                cur_fd,
+                                &c_name,
+                                constants::DIR_OPEN_FLAGS | libc::O_NOFOLLOW,
+                                0,
+                            )?,
+                            self,
+                        );
+
+                        if let Some(dir_mnt_id) = dir_mnt_id {
+                            if crate::mntid::identify_mount(dir.fd)? != dir_mnt_id {
+                                return Err(io::Error::from_raw_os_error(libc::EXDEV));
+                            }
+                        }
+
+                        dirs.push(dir);
+                    }
+
+                    names.push(component);
+                }
+            }
+        }
+
+        Ok((names, dirs, None))
+    }
+
+    /// Rename a file in this directory.
+    ///
+    /// This is exactly equivalent to `rename(self, old, self, new, lookup_flags)`.
+    #[inline]
+    pub fn local_rename<P: AsPath, R: AsPath>(
+        &self,
+        old: P,
+        new: R,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        rename(self, old, self, new, lookup_flags)
+    }
+
+    /// List the contents of this directory.
+    ///
+    /// If this directory was opened in resolve-only mode (see [`DirOpenOptions::resolve_only()`]),
+    /// this fails with an [`Error::ResolveOnly`] error (wrapped in an `io::Error`).
+    ///
+    /// [`DirOpenOptions::resolve_only()`]: ./struct.DirOpenOptions.html#method.resolve_only
+    /// [`Error::ResolveOnly`]: ./enum.Error.html#variant.ResolveOnly
+    pub fn list_self(&self) -> io::Result<ReadDirIter> {
+        self.check_rights(Rights::READ)?;
+
+        self.check_listable()?;
+        self.list_self_unchecked()
+    }
+
+    #[inline]
+    fn list_self_unchecked(&self) -> io::Result<ReadDirIter> {
+        ReadDirIter::new_consume(self.reopen_raw(libc::O_DIRECTORY | libc::O_RDONLY)?)
+    }
+
+    #[inline]
+    fn check_listable(&self) -> io::Result<()> {
+        if self.resolve_only {
+            Err(crate::Error::ResolveOnly.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check whether this directory was opened in resolve-only mode (see
+    /// [`DirOpenOptions::resolve_only()`]).
+    ///
+    /// [`DirOpenOptions::resolve_only()`]: ./struct.DirOpenOptions.html#method.resolve_only
+    #[inline]
+    pub fn is_resolve_only(&self) -> bool {
+        self.resolve_only
+    }
+
+    /// Convert this directory into a resolve-only directory (see
+    /// [`DirOpenOptions::resolve_only()`]).
+    ///
+    /// This cannot be undone. Note that on platforms other than Linux, Android, and FreeBSD, the
+    /// underlying file descriptor is left as-is (it may still be readable).
+    ///
+    /// [`DirOpenOptions::resolve_only()`]: ./struct.DirOpenOptions.html#method.resolve_only
+    #[inline]
+    pub fn into_resolve_only(mut self) -> Self {
+        self.resolve_only = true;
+        self
+    }
+
+    /// Get the operations that this directory may be used to perform (see [`restrict()`]).
+    ///
+    /// [`restrict()`]: #method.restrict
+    #[inline]
+    pub fn rights(&self) -> Rights {
+        self.rights
+    }
+
+    /// Restrict the operations that this directory may be used to perform to `rights`.
+    ///
+    /// Rights can only be removed, never added back: the new rights are the intersection of the
+    /// current rights and `rights`. Directories derived from this one (with [`sub_dir()`],
+    /// [`parent()`], [`try_clone()`], etc.) inherit its rights. Each method that operates on files
+    /// within the directory checks for the rights it needs before doing anything else, and fails
+    /// with an [`Error::MissingRights`] error (wrapped in an `io::Error`) if any are missing. For
+    /// example, after `dir.restrict(Rights::READ)`, files can only be opened for reading, and
+    /// creating, removing, or renaming files fails.
+    ///
+    /// This emulates capability-based systems like Capsicum in a portable way, which can help
+    /// structure privilege separation within a single process. However, it is only enforced by
+    /// this crate's API; the underlying file descriptor (for example, from `as_raw_fd()`) can
+    /// still be used to perform any operation. Similarly, [`change_cwd_to()`] requires all rights,
+    /// since the directory could otherwise be accessed with ordinary path-based functions.
+    ///
+    /// [`sub_dir()`]: #method.sub_dir
+    /// [`parent()`]: #method.parent
+    /// [`try_clone()`]: #method.try_clone
+    /// [`change_cwd_to()`]: #method.change_cwd_to
+    /// [`Error::MissingRights`]: ./enum.Error.html#variant.MissingRights
+    #[inline]
+    pub fn restrict(&mut self, rights: Rights) {
+        self.rights &= rights;
+    }
+
+    #[inline]
+    pub(crate) fn check_rights(&self, rights: Rights) -> io::Result<()> {
+        check_rights(self.rights, rights)
+    }
+
+    /// Convert this directory into a [`ReadOnlyDir`], which only allows reading files and
+    /// metadata.
+    ///
+    /// This cannot be undone.
+    ///
+    /// [`ReadOnlyDir`]: ./struct.ReadOnlyDir.html
+    #[inline]
+    pub fn into_read_only(self) -> ReadOnlyDir {
+        ReadOnlyDir::new(self)
+    }
+
+    /// Create a [`ListOptions`] struct that can be used to list the contents of this directory (or
+    /// one of its subdirectories) with sorting and filtering.
+    ///
+    /// [`ListOptions`]: ./struct.ListOptions.html
+    #[inline]
+    pub fn list_options(&self) -> ListOptions {
+        ListOptions::beneath(self)
+    }
+
+    /// Create a [`WalkOptions`] struct that can be used to recursively walk a directory tree
+    /// within this directory.
+    ///
+    /// For example, to list every file beneath `sub` (not following symlinks):
+    /// ```no_run
+    /// # use obnth::Dir;
+    /// let dir = Dir::open("/srv/user1").unwrap();
+    ///
+    /// for entry in dir.walk().walk("sub").unwrap() {
+    ///     println!("{}", entry.unwrap().path().display());
+    /// }
+    /// ```
+    ///
+    /// [`WalkOptions`]: ./struct.WalkOptions.html
+    #[inline]
+    pub fn walk(&self) -> WalkOptions {
+        WalkOptions::beneath(self)
+    }
+
+    /// List the contents of the specified subdirectory.
+    ///
+    /// This is equivalent to `self.sub_dir(path, lookup_flags)?.list_self()`, but more efficient.
+    pub fn list_dir<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<ReadDirIter> {
+        self.check_rights(Rights::READ)?;
+
+        self.check_listable()?;
+
+        ReadDirIter::new_consume(
+            open_beneath(
+                self.fd,
+                path,
+                libc::O_DIRECTORY | libc::O_RDONLY,
+                0,
+                lookup_flags,
+            )?
+            .into_raw_fd(),
+        )
+    }
+
+    /// Flush this directory to disk (with `fsync()`).
+    ///
+    /// This ensures that changes to the directory's entries (for example, files that were
+    /// created, removed, or renamed within it) are durable. Note that on some platforms (such as
+    /// Linux), this may require reopening the directory, which requires read permission on it.
+    #[inline]
+    pub fn sync_all(&self) -> io::Result<()> {
+        self.check_rights(Rights::READ)?;
+
+        util::fsync_dir_any(self.fd)
+    }
+
+    /// Flush the directory containing `path` to disk (with `fsync()`).
+    ///
+    /// This is useful after operations like [`create_dir()`], [`write_atomic()`], or
+    /// [`local_rename()`] to ensure that the new directory entry is durable. (For renames between
+    /// different directories, both of them should be synced.) Symlinks in the final component of
+    /// `path` are not followed, and the file itself does not need to exist.
+    ///
+    /// If `path` has no final component (for example, `.` or `a/..`), the directory that it refers
+    /// to is synced.
+    ///
+    /// [`create_dir()`]: #method.create_dir
+    /// [`write_atomic()`]: #method.write_atomic
+    /// [`local_rename()`]: #method.local_rename
+    pub fn sync_parent<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<()> {
+        self.check_rights(Rights::READ)?;
+
+        let (subdir, _) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
+
+        subdir.as_ref().unwrap_or(self).sync_all()
+    }
+
+    /// Try to "clone" this `Dir`.
+    ///
+    /// This is equivalent to `self.sub_dir(".")`, but more efficient. (`Dir` also implements
+    /// `Clone`, which does the same thing but panics on failure.)
+    #[inline]
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self::derived(util::dup(self.fd)?, self))
+    }
+
+    /// Change the permissions of this directory.
+    ///
+    /// This is equivalent to `self.set_permissions(".", perm, LookupFlags::empty())`, but more
+    /// efficient.
+    #[inline]
+    pub fn set_self_permissions(&self, perm: fs::Permissions) -> io::Result<()> {
+        self.check_rights(Rights::SETATTR)?;
+
+        util::fchmod_any(self.fd, perm.mode() as libc::mode_t)
+    }
+
+    /// Change the permissions of the file with the given path.
+    ///
+    /// The specified file must be located within this directory. Symlinks in the final component
+    /// of the path are not followed. Most platforms do not allow changing the permissions of
+    /// symlinks, so if `path` refers to a symlink this will usually fail with `EOPNOTSUPP`.
+    ///
+    /// On Linux, this opens an `O_PATH` file descriptor to the file and then changes the
+    /// permissions through `/proc/self/fd`, so it requires `/proc` to be mounted. On other
+    /// platforms, it uses `fchmodat()` with `AT_SYMLINK_NOFOLLOW`.
+    pub fn set_permissions<P: AsPath>(
+        &self,
+        path: P,
+        perm: fs::Permissions,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        self.check_rights(Rights::SETATTR)?;
+
+        let mode = perm.mode() as libc::mode_t;
+
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "android"))] {
+                // Linux doesn't support AT_SYMLINK_NOFOLLOW for fchmodat(), so we can't use the
+                // same approach as on other platforms.
+                let file =
+                    open_beneath(self.fd, path, libc::O_PATH | libc::O_NOFOLLOW, 0, lookup_flags)?;
+
+                if util::fstat(file.as_raw_fd())?.st_mode & libc::S_IFMT == libc::S_IFLNK {
+                    return Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP));
+                }
+
+                util::fchmod_any(file.as_raw_fd(), mode)
+            } else {
+                let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
+                let subdir = subdir.as_ref().unwrap_or(self);
+
+                if let Some(fname) = fname {
+                    util::fchmodat(subdir.fd, &cstr(&fname)?, mode, libc::AT_SYMLINK_NOFOLLOW)
+                } else {
+                    subdir.set_self_permissions(perm)
+                }
+            }
+        }
+    }
+
+    /// Change the owner and/or group of this directory.
+    ///
+    /// If `uid` or `gid` is `None`, the corresponding ID is left unchanged.
+    ///
+    /// This is equivalent to `self.lchown(".", uid, gid, LookupFlags::empty())`, but more
+    /// efficient.
+    #[inline]
+    pub fn set_self_owner(
+        &self,
+        uid: Option<libc::uid_t>,
+        gid: Option<libc::gid_t>,
+    ) -> io::Result<()> {
+        self.check_rights(Rights::SETATTR)?;
+
+        util::fchown_any(self.fd, uid_or_unchanged(uid), gid_or_unchanged(gid))
+    }
+
+    /// Change the owner and/or group of this directory.
+    ///
+    /// This is an alias for [`set_self_owner()`], which is named consistently with
+    /// [`set_self_permissions()`] and [`set_self_times()`].
+    ///
+    /// [`set_self_owner()`]: #method.set_self_owner
+    /// [`set_self_permissions()`]: #method.set_self_permissions
+    /// [`set_self_times()`]: #method.set_self_times
+    #[inline]
+    pub fn self_chown(&self, uid: Option<libc::uid_t>, gid: Option<libc::gid_t>) -> io::Result<()> {
+        self.set_self_owner(uid, gid)
+    }
+
+    /// Change the owner and/or group of the file with the given path.
+    ///
+    /// If `uid` or `gid` is `None`, the corresponding ID is left unchanged.
+    ///
+    /// The specified file must be located within this directory. If the final component of the
+    /// path is a symlink, it will be followed (within this directory); see [`lchown()`] to change
+    /// the ownership of the symlink itself.
+    ///
+    /// **Note**: On platforms other than Linux, the file has to be opened for reading in order to
+    /// follow the symlink safely, so this will fail with `EACCES` if the file is not readable.
+    ///
+    /// [`lchown()`]: #method.lchown
+    pub fn chown<P: AsPath>(
+        &self,
+        path: P,
+        uid: Option<libc::uid_t>,
+        gid: Option<libc::gid_t>,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        self.check_rights(Rights::SETATTR)?;
+
+        let file = open_beneath(self.fd, path, constants::REF_OPEN_FLAGS, 0, lookup_flags)?;
+
+        util::fchown_any(
+            file.as_raw_fd(),
+            uid_or_unchanged(uid),
+            gid_or_unchanged(gid),
+        )
+    }
+
+    /// Change the owner and/or group of the file with the given path, without following symlinks
+    /// in the final component.
+    ///
+    /// This is identical to [`chown()`], except that if the final component of `path` is a
+    /// symlink, the ownership of the symlink itself is changed.
+    ///
+    /// [`chown()`]: #method.chown
+    pub fn lchown<P: AsPath>(
+        &self,
+        path: P,
+        uid: Option<libc::uid_t>,
+        gid: Option<libc::gid_t>,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        self.check_rights(Rights::SETATTR)?;
+
+        let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
+        let subdir = subdir.as_ref().unwrap_or(self);
+
+        if let Some(fname) = fname {
+            util::fchownat(
+                subdir.fd,
+                &cstr(&fname)?,
+                uid_or_unchanged(uid),
+                gid_or_unchanged(gid),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        } else {
+            subdir.set_self_owner(uid, gid)
+        }
+    }
+
+    /// Change the access and modification times of this directory.
+    ///
+    /// If `atime` or `mtime` is `None`, the corresponding timestamp is left unchanged.
+    ///
+    /// This is equivalent to `self.set_times_nofollow(".", atime, mtime, LookupFlags::empty())`,
+    /// but more efficient.
+    pub fn set_self_times(
+        &self,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> io::Result<()> {
+        self.check_rights(Rights::SETATTR)?;
+
+        let times = [
+            util::timespec_from_systime(atime)?,
+            util::timespec_from_systime(mtime)?,
+        ];
+
+        util::futimens_any(self.fd, &times)
+    }
+
+    /// Change the access and modification times of the file with the given path.
+    ///
+    /// If `atime` or `mtime` is `None`, the corresponding timestamp is left unchanged.
+    ///
+    /// The specified file must be located within this directory. If the final component of the
+    /// path is a symlink, it will be followed (within this directory); see [`set_times_nofollow()`]
+    /// to change the timestamps of the symlink itself.
+    ///
+    /// **Note**: On platforms other than Linux, the file has to be opened for reading in order to
+    /// follow the symlink safely, so this will fail with `EACCES` if the file is not readable.
+    ///
+    /// [`set_times_nofollow()`]: #method.set_times_nofollow
+    pub fn set_times<P: AsPath>(
+        &self,
+        path: P,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        self.check_rights(Rights::SETATTR)?;
+
+        let times = [
+            util::timespec_from_systime(atime)?,
+            util::timespec_from_systime(mtime)?,
+        ];
+
+        let file = open_beneath(self.fd, path, constants::REF_OPEN_FLAGS, 0, lookup_flags)?;
+
+        util::futimens_any(file.as_raw_fd(), &times)
+    }
+
+    /// Change the access and modification times of the file with the given path, without
+    /// following symlinks in the final component.
+    ///
+    /// This is identical to [`set_times()`], except that if the final component of `path` is a
+    /// symlink, the timestamps of the symlink itself are changed.
+    ///
+    /// [`set_times()`]: #method.set_times
+    pub fn set_times_nofollow<P: AsPath>(
+        &self,
+        path: P,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        self.check_rights(Rights::SETATTR)?;
+
+        let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
+        let subdir = subdir.as_ref().unwrap_or(self);
+
+        if let Some(fname) = fname {
+            let times = [
+                util::timespec_from_systime(atime)?,
+                util::timespec_from_systime(mtime)?,
+            ];
+
+            util::utimensat(subdir.fd, &cstr(&fname)?, &times, libc::AT_SYMLINK_NOFOLLOW)
+        } else {
+            subdir.set_self_times(atime, mtime)
+        }
+    }
+
+    /// Retrieve metadata of this directory.
+    ///
+    /// This is equivalent to `self.metadata(".", LookupFlags::empty())`, but it's significantly
+    /// more efficient.
+    #[inline]
+    pub fn self_metadata(&self) -> io::Result<Metadata> {
+        Metadata::stat_fd(self.fd)
+    }
+
+    /// Retrieve information on the file with the given path.
+    ///
+    /// The specified file must be located within this directory. Symlinks in the final component
+    /// of the path are not followed.
+    pub fn metadata<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<Metadata> {
+        self.check_rights(Rights::READ)?;
+
+        let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
+
+        let subdir = subdir.as_ref().unwrap_or(self);
+
+        if let Some(fname) = fname {
+            fname.with_cstr(|s| Metadata::stat_at(subdir.as_raw_fd(), s, libc::AT_SYMLINK_NOFOLLOW))
+        } else {
+            subdir.self_metadata()
+        }
+    }
+
+    /// Retrieve information on the file with the given path, following symlinks in the final
+    /// component.
+    ///
+    /// This is identical to [`metadata()`], except that if the final component of `path` is a
+    /// symlink, it will be followed (within this directory), and the metadata of its target will
+    /// be returned. This is similar to `stat()` (as opposed to `lstat()`).
+    ///
+    /// **Note**: On platforms other than Linux, the file has to be opened for reading in order to
+    /// follow the symlink safely, so this will fail with `EACCES` if the file is not readable.
+    ///
+    /// [`metadata()`]: #method.metadata
+    pub fn metadata_follow<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<Metadata> {
+        self.check_rights(Rights::READ)?;
+
+        let file = open_beneath(self.fd, path, constants::REF_OPEN_FLAGS, 0, lookup_flags)?;
+
+        Metadata::stat_fd(file.as_raw_fd())
+    }
+
+    /// Retrieve information on each of the given paths, as if by calling [`metadata()`] on each
+    /// one.
+    ///
+    /// The results are returned in the same order as the paths. Unlike calling [`metadata()`]
+    /// repeatedly, this caches the directories containing the files, so paths that share the same
+    /// parent directory (for example, `static/css/a.css` and `static/css/b.css`) only need to
+    /// resolve it once. (A limited number of directories are cached at once, to avoid exhausting
+    /// file descriptors.)
+    ///
+    /// [`metadata()`]: #method.metadata
+    pub fn metadata_many<I, P>(
+        &self,
+        paths: I,
+        lookup_flags: LookupFlags,
+    ) -> Vec<io::Result<Metadata>>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsPath,
+    {
+        const MAX_CACHED: usize = 64;
+
+        let mut cache: std::collections::HashMap<PathBuf, Dir> = std::collections::HashMap::new();
+
+        paths
+            .into_iter()
+            .map(|path| {
+                self.check_rights(Rights::READ)?;
+
+                let normalized =
+                    crate::safe_path::normalize_components(path.as_path(), lookup_flags)?;
+                let path = normalized.as_deref().unwrap_or_else(|| path.as_path());
+
+                // Let metadata() handle all the special cases (absolute paths, paths ending in
+                // "..", and files directly within this directory)
+                let (parent, fname) = match util::path_split(path) {
+                    Some((Some(parent), fname)) if !path.has_root() => (Path::new(parent), fname),
+                    _ => return self.metadata(path, lookup_flags),
+                };
+
+                let subdir = match cache.get(parent) {
+                    Some(subdir) => subdir,
+                    None => {
+                        if cache.len() >= MAX_CACHED {
+                            cache.clear();
+                        }
+
+                        let subdir = self.sub_dir(parent, lookup_flags)?;
+                        cache.entry(parent.to_path_buf()).or_insert(subdir)
+                    }
+                };
+
+                if fname.as_bytes() == b"." {
+                    subdir.self_metadata()
+                } else {
+                    fname.with_cstr(|s| {
+                        Metadata::stat_at(subdir.as_raw_fd(), s, libc::AT_SYMLINK_NOFOLLOW)
+                    })
+                }
+            })
+            .collect()
+    }
+
+    /// Check whether the file with the given path exists within this directory.
+    ///
+    /// Like `std::path::Path::try_exists()`, this follows symlinks in the final component (using
+    /// [`metadata_follow()`]), so a dangling symlink is reported as not existing. This returns
+    /// `Ok(false)` only if the lookup fails with `ENOENT`; any other errors (for example,
+    /// `EACCES`, or `EXDEV` if the path would escape this directory) are returned.
+    ///
+    /// [`metadata_follow()`]: #method.metadata_follow
+    pub fn exists<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<bool> {
+        match self.metadata_follow(path, lookup_flags) {
+            Ok(_) => Ok(true),
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(false),
+            Err(e) => Err(e),
+        }
     }
 
-    /// List the contents of the specified subdirectory.
+    /// Check whether the current process can access the file with the given path using the
+    /// permissions in `mode`, without opening it.
     ///
-    /// This is equivalent to `self.sub_dir(path, lookup_flags)?.list_self()`, but more efficient.
-    pub fn list_dir<P: AsPath>(
+    /// This is like `faccessat()` with `AT_EACCESS`: the check uses the effective user and group
+    /// IDs (not the real IDs). Symlinks are followed within this directory (escaping it fails with
+    /// `EXDEV`, as usual). If access is denied, this fails with `EACCES` (or `EROFS`, etc.).
+    ///
+    /// Note that the result only reflects the state of the file at the time of the call.
+    ///
+    /// **Note**: On platforms where `faccessat()` does not support `AT_SYMLINK_NOFOLLOW` (Android,
+    /// Solaris/illumos, Haiku, and Redox), if the final component is replaced with a symlink after
+    /// it has been resolved, the check will follow that symlink, which may point outside of this
+    /// directory.
+    pub fn access<P: AsPath>(
         &self,
         path: P,
+        mode: AccessMode,
         lookup_flags: LookupFlags,
-    ) -> io::Result<ReadDirIter> {
-        ReadDirIter::new_consume(
-            open_beneath(
-                self.fd,
-                path,
-                libc::O_DIRECTORY | libc::O_RDONLY,
-                0,
-                lookup_flags,
-            )?
-            .into_raw_fd(),
-        )
-    }
+    ) -> io::Result<()> {
+        self.check_rights(Rights::READ)?;
 
-    /// Try to "clone" this `Dir`.
-    ///
-    /// This is equivalent to `self.sub_dir(".")`, but more efficient.
-    #[inline]
-    pub fn try_clone(&self) -> io::Result<Self> {
-        Ok(Self {
-            fd: util::dup(self.fd)?,
-        })
+        let (names, dirs, _) = self.resolve_components(path.as_path(), lookup_flags, false)?;
+
+        let fd = dirs.last().unwrap_or(self).fd;
+        // If the final component is a directory, it's the last entry in `dirs`
+        let fname = if dirs.len() == names.len() {
+            CString::new(".").unwrap()
+        } else {
+            cstr(names.last().unwrap())?
+        };
+
+        #[allow(unused_mut)]
+        let mut flags = libc::AT_EACCESS;
+        // resolve_components() has checked that the final component is not a symlink, but it
+        // could have been swapped out since then
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "freebsd",
+            target_os = "dragonfly",
+            target_os = "netbsd",
+            target_os = "openbsd",
+            target_os = "macos",
+            target_os = "ios",
+        ))]
+        {
+            flags |= libc::AT_SYMLINK_NOFOLLOW;
+        }
+
+        util::faccessat(fd, &fname, mode.bits(), flags)
     }
 
-    /// Retrieve metadata of this directory.
+    /// Check whether the given path refers to a regular file within this directory.
     ///
-    /// This is equivalent to `self.metadata(".", LookupFlags::empty())`, but it's significantly
-    /// more efficient.
-    #[inline]
-    pub fn self_metadata(&self) -> io::Result<Metadata> {
-        util::fstat(self.fd).map(Metadata::new)
+    /// This follows symlinks, and reports errors, in the same way as [`exists()`].
+    ///
+    /// [`exists()`]: #method.exists
+    pub fn is_file<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<bool> {
+        match self.metadata_follow(path, lookup_flags) {
+            Ok(meta) => Ok(meta.is_file()),
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(false),
+            Err(e) => Err(e),
+        }
     }
 
-    /// Retrieve information on the file with the given path.
+    /// Check whether the given path refers to a directory within this directory.
     ///
-    /// The specified file must be located within this directory. Symlinks in the final component
-    /// of the path are not followed.
-    pub fn metadata<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<Metadata> {
-        let (subdir, fname) = prepare_inner_operation(self, path.as_path(), lookup_flags)?;
-
-        let subdir = subdir.as_ref().unwrap_or(self);
-
-        if let Some(fname) = fname {
-            fname.with_cstr(|s| {
-                util::fstatat(subdir.as_raw_fd(), s, libc::AT_SYMLINK_NOFOLLOW).map(Metadata::new)
-            })
-        } else {
-            subdir.self_metadata()
+    /// This follows symlinks, and reports errors, in the same way as [`exists()`].
+    ///
+    /// [`exists()`]: #method.exists
+    pub fn is_dir<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<bool> {
+        match self.metadata_follow(path, lookup_flags) {
+            Ok(meta) => Ok(meta.is_dir()),
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(false),
+            Err(e) => Err(e),
         }
     }
 
@@ -367,32 +1912,6 @@ impl Dir {
             }
         }
 
-        #[inline]
-        fn recover_entry(parent: &Dir, sub_meta: &Metadata) -> io::Result<Entry> {
-            for entry in parent.list_self()? {
-                let entry = entry?;
-
-                // Only check directories (or files with unknown types)
-                match entry.file_type() {
-                    Some(FileType::Directory) | None => {
-                        // stat() the entry and see if it matches.
-                        //
-                        // We can't check entry.ino() to avoid stat() because that doesn't work
-                        // when you cross filesystem boundaries.
-                        if let Ok(entry_meta) = entry.metadata() {
-                            if same_meta(sub_meta, &entry_meta) {
-                                return Ok(entry);
-                            }
-                        }
-                    }
-
-                    _ => (),
-                }
-            }
-
-            Err(io::Error::from_raw_os_error(libc::ENOENT))
-        }
-
         let mut res = VecDeque::new();
 
         let mut sub_meta = self_meta;
@@ -432,6 +1951,8 @@ impl Dir {
     /// is **much** more efficient, and 2) it is more secure (notably, it avoids race conditions).
     #[inline]
     pub fn change_cwd_to(&self) -> io::Result<()> {
+        self.check_rights(Rights::all())?;
+
         if unsafe { libc::fchdir(self.fd) } < 0 {
             Err(io::Error::last_os_error())
         } else {
@@ -439,6 +1960,14 @@ impl Dir {
         }
     }
 
+    /// Return a [`DirBuilder`] that can be used to create directories within this directory.
+    ///
+    /// [`DirBuilder`]: ./struct.DirBuilder.html
+    #[inline]
+    pub fn dir_builder(&self) -> DirBuilder {
+        DirBuilder::new(self)
+    }
+
     /// Return an `OpenOptions` struct that can be use to open files within this directory.
     ///
     /// See the documentation of [`OpenOptions`] for more details.
@@ -450,6 +1979,22 @@ impl Dir {
     }
 }
 
+/// Cloning a `Dir` duplicates the underlying file descriptor (with `F_DUPFD_CLOEXEC`).
+///
+/// # Panics
+///
+/// Panics if the file descriptor cannot be duplicated (usually because the process has run out
+/// of file descriptors). Use [`Dir::try_clone()`] to handle that case.
+///
+/// [`Dir::try_clone()`]: #method.try_clone
+impl Clone for Dir {
+    #[inline]
+    fn clone(&self) -> Self {
+        self.try_clone()
+            .expect("failed to duplicate directory file descriptor")
+    }
+}
+
 impl Drop for Dir {
     #[inline]
     fn drop(&mut self) {
@@ -478,8 +2023,104 @@ impl IntoRawFd for Dir {
 impl FromRawFd for Dir {
     #[inline]
     unsafe fn from_raw_fd(fd: RawFd) -> Self {
-        Self { fd }
+        Self {
+            fd,
+            resolve_only: false,
+            rights: Rights::all(),
+        }
+    }
+}
+
+impl AsFd for Dir {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        unsafe { BorrowedFd::borrow_raw(self.fd) }
+    }
+}
+
+impl From<Dir> for OwnedFd {
+    #[inline]
+    fn from(dir: Dir) -> Self {
+        unsafe { OwnedFd::from_raw_fd(dir.into_raw_fd()) }
+    }
+}
+
+impl TryFrom<OwnedFd> for Dir {
+    type Error = io::Error;
+
+    /// Convert the given file descriptor into a `Dir`, failing with `ENOTDIR` if it does not refer
+    /// to a directory.
+    #[inline]
+    fn try_from(fd: OwnedFd) -> io::Result<Self> {
+        Self::from_file(fs::File::from(fd))
+    }
+}
+
+impl TryFrom<fs::File> for Dir {
+    type Error = io::Error;
+
+    /// Equivalent to [`Dir::from_file()`](#method.from_file).
+    #[inline]
+    fn try_from(file: fs::File) -> io::Result<Self> {
+        Self::from_file(file)
+    }
+}
+
+/// Change the access and modification times of an open file.
+///
+/// If `atime` or `mtime` is `None`, the corresponding timestamp is left unchanged. This is a thin
+/// wrapper around `futimens()`; it is mainly useful for files that were opened with
+/// [`OpenOptions`].
+///
+/// [`OpenOptions`]: ./struct.OpenOptions.html
+pub fn set_file_times(
+    file: &fs::File,
+    atime: Option<SystemTime>,
+    mtime: Option<SystemTime>,
+) -> io::Result<()> {
+    util::futimens(
+        file.as_raw_fd(),
+        &[
+            util::timespec_from_systime(atime)?,
+            util::timespec_from_systime(mtime)?,
+        ],
+    )
+}
+
+/// Read up to `len` bytes from an open file, starting at `offset`.
+///
+/// This uses `pread()`, so it does not use or change the file offset, and it can be used
+/// concurrently on a shared file descriptor. Fewer than `len` bytes are returned only if the end
+/// of the file is reached first (so an empty vector means `offset` is at or past the end of the
+/// file).
+pub fn read_file_range(file: &fs::File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    const MIN_GROW: usize = 8192;
+
+    let hint = (initial_buffer_size(file) as u64).saturating_sub(offset);
+    let mut buf = vec![0; std::cmp::min(len as u64, hint) as usize];
+    let mut filled = 0;
+
+    loop {
+        if filled == buf.len() {
+            if filled == len {
+                break;
+            }
+
+            // The file may have grown
+            let new_len = std::cmp::min(len, std::cmp::max(filled * 2, MIN_GROW));
+            buf.resize(new_len, 0);
+        }
+
+        match file.read_at(&mut buf[filled..], offset + filled as u64) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
     }
+
+    buf.truncate(filled);
+    Ok(buf)
 }
 
 /// Create a hardlink to a file in (possibly) a different directory.
@@ -494,6 +2135,9 @@ where
     P: AsPath,
     R: AsPath,
 {
+    old_dir.check_rights(Rights::READ)?;
+    new_dir.check_rights(Rights::CREATE)?;
+
     let (old_subdir, old_fname) =
         prepare_inner_operation(old_dir, old_path.as_path(), lookup_flags)?;
 
@@ -541,34 +2185,14 @@ where
     P: AsPath,
     R: AsPath,
 {
-    let (old_subdir, old_fname) =
-        prepare_inner_operation(old_dir, old_path.as_path(), lookup_flags)?;
-    let old_subdir = old_subdir.as_ref().unwrap_or(old_dir);
-
-    let old_fname = if let Some(old_fname) = old_fname {
-        old_fname
-    } else {
-        return Err(std::io::Error::from_raw_os_error(libc::EBUSY));
-    };
-
-    let (new_subdir, new_fname) =
-        prepare_inner_operation(new_dir, new_path.as_path(), lookup_flags)?;
-    let new_subdir = new_subdir.as_ref().unwrap_or(new_dir);
-
-    if let Some(new_fname) = new_fname {
-        old_fname.with_cstr(|old_fname| {
-            new_fname.with_cstr(|new_fname| {
-                util::renameat(
-                    old_subdir.as_raw_fd(),
-                    old_fname,
-                    new_subdir.as_raw_fd(),
-                    new_fname,
-                )
-            })
-        })
-    } else {
-        Err(std::io::Error::from_raw_os_error(libc::EBUSY))
-    }
+    rename_with(
+        old_dir,
+        old_path.as_path(),
+        new_dir,
+        new_path.as_path(),
+        lookup_flags,
+        util::renameat,
+    )
 }
 
 /// Linux-specific: Rename a file across directories, specifying extra flags to modify behavior.
@@ -593,8 +2217,144 @@ where
     P: AsPath,
     R: AsPath,
 {
-    let (old_subdir, old_fname) =
-        prepare_inner_operation(old_dir, old_path.as_path(), lookup_flags)?;
+    rename_with(
+        old_dir,
+        old_path.as_path(),
+        new_dir,
+        new_path.as_path(),
+        lookup_flags,
+        |old_fd, old_fname, new_fd, new_fname| {
+            util::renameat2(old_fd, old_fname, new_fd, new_fname, flags.bits)
+        },
+    )
+}
+
+/// Rename a file across directories, failing with `EEXIST` if the "new" path already exists.
+///
+/// This uses `renameat2()` with `RENAME_NOREPLACE` on Linux and `renameatx_np()` with
+/// `RENAME_EXCL` on macOS. If those are not available (or not supported by the filesystem), or
+/// on other platforms, it falls back on creating a hard link at the "new" path and then removing
+/// the "old" path. That fallback is not atomic (both paths will briefly refer to the file), and it
+/// cannot be used to rename directories (it will fail with `EPERM` in that case).
+///
+/// Otherwise, the semantics of this are identical to [`rename()`].
+///
+/// [`rename()`]: ./fn.rename.html
+pub fn rename_noreplace<P, R>(
+    old_dir: &Dir,
+    old_path: P,
+    new_dir: &Dir,
+    new_path: R,
+    lookup_flags: LookupFlags,
+) -> io::Result<()>
+where
+    P: AsPath,
+    R: AsPath,
+{
+    rename_with(
+        old_dir,
+        old_path.as_path(),
+        new_dir,
+        new_path.as_path(),
+        lookup_flags,
+        |old_fd, old_fname, new_fd, new_fname| {
+            #[cfg(target_os = "linux")]
+            let res = util::renameat2(
+                old_fd,
+                old_fname,
+                new_fd,
+                new_fname,
+                libc::RENAME_NOREPLACE as _,
+            );
+            #[cfg(target_os = "macos")]
+            let res = util::renameatx_np(old_fd, old_fname, new_fd, new_fname, libc::RENAME_EXCL);
+
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            match res {
+                Err(e)
+                    if matches!(
+                        e.raw_os_error(),
+                        Some(libc::EINVAL) | Some(libc::ENOSYS) | Some(libc::ENOTSUP)
+                    ) => {}
+                res => return res,
+            }
+
+            util::linkat(old_fd, old_fname, new_fd, new_fname, 0)?;
+
+            if let Err(e) = util::unlinkat(old_fd, old_fname, false) {
+                let _ = util::unlinkat(new_fd, new_fname, false);
+                return Err(e);
+            }
+
+            Ok(())
+        },
+    )
+}
+
+/// Atomically exchange two files in (possibly) different directories.
+///
+/// This uses `renameat2()` with `RENAME_EXCHANGE` on Linux and `renameatx_np()` with
+/// `RENAME_SWAP` on macOS. There is no way to do this safely on other platforms, so it always
+/// fails with `EOPNOTSUPP` there. Both paths must exist.
+///
+/// Otherwise, the semantics of this are identical to [`rename()`].
+///
+/// [`rename()`]: ./fn.rename.html
+pub fn rename_exchange<P, R>(
+    old_dir: &Dir,
+    old_path: P,
+    new_dir: &Dir,
+    new_path: R,
+    lookup_flags: LookupFlags,
+) -> io::Result<()>
+where
+    P: AsPath,
+    R: AsPath,
+{
+    rename_with(
+        old_dir,
+        old_path.as_path(),
+        new_dir,
+        new_path.as_path(),
+        lookup_flags,
+        |old_fd, old_fname, new_fd, new_fname| {
+            cfg_if::cfg_if! {
+                if #[cfg(target_os = "linux")] {
+                    util::renameat2(
+                        old_fd,
+                        old_fname,
+                        new_fd,
+                        new_fname,
+                        libc::RENAME_EXCHANGE as _,
+                    )
+                } else if #[cfg(target_os = "macos")] {
+                    util::renameatx_np(old_fd, old_fname, new_fd, new_fname, libc::RENAME_SWAP)
+                } else {
+                    let _ = (old_fd, old_fname, new_fd, new_fname);
+                    Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+                }
+            }
+        },
+    )
+}
+
+/// Helper for the `rename*()` functions; it resolves both paths and then calls `f` with the
+/// parent directories and the final components.
+fn rename_with<F>(
+    old_dir: &Dir,
+    old_path: &Path,
+    new_dir: &Dir,
+    new_path: &Path,
+    lookup_flags: LookupFlags,
+    mut f: F,
+) -> io::Result<()>
+where
+    F: FnMut(RawFd, &CStr, RawFd, &CStr) -> io::Result<()>,
+{
+    old_dir.check_rights(Rights::RENAME)?;
+    new_dir.check_rights(Rights::RENAME)?;
+
+    let (old_subdir, old_fname) = prepare_inner_operation(old_dir, old_path, lookup_flags)?;
     let old_subdir = old_subdir.as_ref().unwrap_or(old_dir);
 
     let old_fname = if let Some(old_fname) = old_fname {
@@ -603,19 +2363,17 @@ where
         return Err(std::io::Error::from_raw_os_error(libc::EBUSY));
     };
 
-    let (new_subdir, new_fname) =
-        prepare_inner_operation(new_dir, new_path.as_path(), lookup_flags)?;
+    let (new_subdir, new_fname) = prepare_inner_operation(new_dir, new_path, lookup_flags)?;
     let new_subdir = new_subdir.as_ref().unwrap_or(new_dir);
 
     if let Some(new_fname) = new_fname {
         old_fname.with_cstr(|old_fname| {
             new_fname.with_cstr(|new_fname| {
-                util::renameat2(
+                f(
                     old_subdir.as_raw_fd(),
                     old_fname,
                     new_subdir.as_raw_fd(),
                     new_fname,
-                    flags.bits,
                 )
             })
         })
@@ -624,12 +2382,214 @@ where
     }
 }
 
+/// Get the size of the buffer that should be allocated to read the given file (falling back on 0
+/// if it can't be determined).
+fn initial_buffer_size(file: &fs::File) -> usize {
+    file.metadata().map(|m| m.len() as usize).unwrap_or(0)
+}
+
+/// Create a new file with a random name in the given directory.
+fn create_temp_file(dir_fd: RawFd, mode: libc::mode_t) -> io::Result<(CString, fs::File)> {
+    const MAX_ATTEMPTS: usize = 100;
+
+    for _ in 0..MAX_ATTEMPTS {
+        let name = util::temp_name();
+
+        match util::openat(
+            dir_fd,
+            &name,
+            libc::O_RDWR | libc::O_CREAT | libc::O_EXCL | libc::O_NOFOLLOW,
+            mode,
+        ) {
+            Ok(file) => return Ok((name, file)),
+            Err(e) if e.raw_os_error() == Some(libc::EEXIST) => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(io::Error::from_raw_os_error(libc::EEXIST))
+}
+
+fn create_temp_symlink(target: &CStr, dir_fd: RawFd) -> io::Result<CString> {
+    const MAX_ATTEMPTS: usize = 100;
+
+    for _ in 0..MAX_ATTEMPTS {
+        let name = util::temp_name();
+
+        match util::symlinkat(target, dir_fd, &name) {
+            Ok(()) => return Ok(name),
+            Err(e) if e.raw_os_error() == Some(libc::EEXIST) => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(io::Error::from_raw_os_error(libc::EEXIST))
+}
+
+fn remove_all_at(parent_fd: RawFd, fname: &CStr, ftype: Option<FileType>) -> io::Result<()> {
+    let ftype = match ftype {
+        Some(ftype) => ftype,
+        None => {
+            Metadata::new(util::fstatat(parent_fd, fname, libc::AT_SYMLINK_NOFOLLOW)?).file_type()
+        }
+    };
+
+    if ftype != FileType::Directory {
+        return util::unlinkat(parent_fd, fname, false);
+    }
+
+    // If it was replaced with a symlink after we checked, O_NOFOLLOW will make this fail
+    let open_subdir = |fd: RawFd, name: &CStr| -> io::Result<Dir> {
+        Ok(Dir {
+            fd: util::openat_raw(
+                fd,
+                name,
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW,
+                0,
+            )?,
+            resolve_only: false,
+            rights: Rights::all(),
+        })
+    };
+
+    // The directories that are currently being emptied (innermost last), along with their names
+    // in the directory above them. This is done iteratively rather than recursively so that a
+    // deeply nested tree can't overflow the stack.
+    let mut stack = vec![(open_subdir(parent_fd, fname)?, fname.to_owned())];
+
+    while let Some((dir, _)) = stack.last() {
+        // Remove everything except subdirectories; stop at the first subdirectory and descend
+        // into it (this directory is listed again once it has been removed)
+        let mut subdir_name = None;
+
+        for entry in dir.list_self()? {
+            let entry = entry?;
+
+            let ftype = match entry.file_type() {
+                Some(ftype) => ftype,
+                None => match util::fstatat(dir.fd, entry.name_cstr(), libc::AT_SYMLINK_NOFOLLOW) {
+                    Ok(st) => Metadata::new(st).file_type(),
+                    // Somebody else removed it first
+                    Err(e) if e.raw_os_error() == Some(libc::ENOENT) => continue,
+                    Err(e) => return Err(e),
+                },
+            };
+
+            if ftype == FileType::Directory {
+                subdir_name = Some(entry.name_cstr().to_owned());
+                break;
+            }
+
+            match util::unlinkat(dir.fd, entry.name_cstr(), false) {
+                Err(e) if e.raw_os_error() == Some(libc::ENOENT) => (),
+                res => res?,
+            }
+        }
+
+        if let Some(name) = subdir_name {
+            match open_subdir(dir.fd, &name) {
+                Ok(subdir) => stack.push((subdir, name)),
+                Err(e) if e.raw_os_error() == Some(libc::ENOENT) => (),
+                Err(e) => return Err(e),
+            }
+        } else {
+            // It's empty now, so remove it
+            let (dir, name) = stack.pop().unwrap();
+            drop(dir);
+
+            match stack.last() {
+                Some((parent, _)) => match util::unlinkat(parent.fd, &name, true) {
+                    Err(e) if e.raw_os_error() == Some(libc::ENOENT) => (),
+                    res => res?,
+                },
+                None => return util::unlinkat(parent_fd, &name, true),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[inline]
+fn uid_or_unchanged(uid: Option<libc::uid_t>) -> libc::uid_t {
+    uid.unwrap_or(!0)
+}
+
+#[inline]
+fn gid_or_unchanged(gid: Option<libc::gid_t>) -> libc::gid_t {
+    gid.unwrap_or(!0)
+}
+
+/// Find the entry in `parent` that refers to the directory described by `sub_meta`.
+fn recover_entry(parent: &Dir, sub_meta: &Metadata) -> io::Result<Entry> {
+    for entry in parent.list_self_unchecked()? {
+        let entry = entry?;
+
+        // Only check directories (or files with unknown types)
+        match entry.file_type() {
+            Some(FileType::Directory) | None => {
+                // stat() the entry and see if it matches.
+                //
+                // We can't check entry.ino() to avoid stat() because that doesn't work
+                // when you cross filesystem boundaries.
+                if let Ok(entry_meta) = entry.metadata_in(parent) {
+                    if same_meta(sub_meta, &entry_meta) {
+                        return Ok(entry);
+                    }
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    Err(io::Error::from_raw_os_error(libc::ENOENT))
+}
+
 #[inline]
 fn same_meta(a: &Metadata, b: &Metadata) -> bool {
     util::samestat(a.stat(), b.stat())
 }
 
+#[inline]
+fn ends_with_dir_marker(path: &Path) -> bool {
+    let path = path.as_os_str().as_bytes();
+    path.ends_with(b"/") || path.ends_with(b"/.")
+}
+
+// Add the components of `path` to the front of `queue` (in order); a leading slash is represented
+// by a "/" component
+fn push_path_components(queue: &mut VecDeque<OsString>, path: &Path) {
+    for component in path.components().rev() {
+        match component {
+            Component::RootDir => queue.push_front(OsString::from("/")),
+            Component::ParentDir => queue.push_front(OsString::from("..")),
+            Component::Normal(name) => queue.push_front(name.to_os_string()),
+            Component::CurDir | Component::Prefix(_) => (),
+        }
+    }
+}
+
 fn prepare_inner_operation<'a>(
+    dir: &Dir,
+    path: &'a Path,
+    lookup_flags: LookupFlags,
+) -> io::Result<(Option<Dir>, Option<Cow<'a, OsStr>>)> {
+    // Normalize the whole path here so the final component gets the same treatment as the ones
+    // that `sub_dir()` will resolve
+    match crate::safe_path::normalize_components(path, lookup_flags)? {
+        Some(path) => {
+            let lookup_flags =
+                lookup_flags - (LookupFlags::NORMALIZE_NFC | LookupFlags::NORMALIZE_NFD);
+            let (subdir, fname) = prepare_inner_operation_impl(dir, &path, lookup_flags)?;
+            Ok((subdir, fname.map(|fname| Cow::Owned(fname.to_os_string()))))
+        }
+        None => prepare_inner_operation_impl(dir, path, lookup_flags)
+            .map(|(subdir, fname)| (subdir, fname.map(Cow::Borrowed))),
+    }
+}
+
+fn prepare_inner_operation_impl<'a>(
     dir: &Dir,
     mut path: &'a Path,
     lookup_flags: LookupFlags,
@@ -739,7 +2699,7 @@ mod tests {
                 assert!(subdir.is_none());
             }
 
-            assert_eq!(expect_fname.map(OsStr::new), fname);
+            assert_eq!(expect_fname.map(OsStr::new), fname.as_deref());
         }
 
         for (path, lookup_flags, eno) in [
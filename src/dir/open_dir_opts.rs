@@ -0,0 +1,169 @@
+use std::io;
+use std::os::unix::prelude::*;
+
+use crate::{constants, util, AsPath, Dir, Rights};
+
+/// Options for opening a `Dir` while verifying properties of the directory itself.
+///
+/// This is useful, for example, in set-UID programs that need to make sure that the directory
+/// they are about to confine themselves to is actually the one they expect (and not, say, a
+/// symlink planted by the user). All of the checks are performed on the opened file descriptor
+/// (after it has been opened), so they cannot be bypassed by replacing the directory after it has
+/// been checked.
+///
+/// If any of the checks fail, opening the directory fails with `EPERM` (except for
+/// [`nofollow()`]).
+///
+/// A `DirOpenOptions` can be used with [`Dir::open_with()`] or [`open()`].
+///
+/// [`nofollow()`]: #method.nofollow
+/// [`Dir::open_with()`]: ./struct.Dir.html#method.open_with
+/// [`open()`]: #method.open
+#[derive(Clone, Debug, Default)]
+pub struct DirOpenOptions {
+    nofollow: bool,
+    mountpoint: bool,
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+    forbid_mode: libc::mode_t,
+    resolve_only: bool,
+}
+
+impl DirOpenOptions {
+    /// Create a new set of options with all of the checks disabled.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Refuse to open the directory if the final component of the path is a symlink (i.e. open
+    /// it with `O_NOFOLLOW`).
+    ///
+    /// Symlinks in earlier components of the path are still followed. If the final component is
+    /// a symlink, opening the directory fails with `ELOOP` (or `ENOTDIR` on Linux, where a
+    /// symlink can be opened directly with `O_PATH | O_NOFOLLOW`).
+    #[inline]
+    pub fn nofollow(&mut self, nofollow: bool) -> &mut Self {
+        self.nofollow = nofollow;
+        self
+    }
+
+    /// Require the directory to be a mount point (i.e. to be on a different mount than its parent
+    /// directory). The root directory is always considered to be a mount point.
+    ///
+    /// See [`MountId`] for how mounts are identified on each platform. In particular, on platforms
+    /// other than Linux, bind mounts of directories on the same filesystem are not detected.
+    ///
+    /// [`MountId`]: ./struct.MountId.html
+    #[inline]
+    pub fn mountpoint(&mut self, mountpoint: bool) -> &mut Self {
+        self.mountpoint = mountpoint;
+        self
+    }
+
+    /// Require the directory to be owned by the given user (or `None` to not check the owner).
+    #[inline]
+    pub fn owner(&mut self, uid: Option<libc::uid_t>) -> &mut Self {
+        self.uid = uid;
+        self
+    }
+
+    /// Require the directory to be owned by the given group (or `None` to not check the group).
+    #[inline]
+    pub fn group(&mut self, gid: Option<libc::gid_t>) -> &mut Self {
+        self.gid = gid;
+        self
+    }
+
+    /// Require none of the given permission bits to be set on the directory.
+    ///
+    /// For example, `0o022` rejects directories that are writable by the group or by other users.
+    #[inline]
+    pub fn forbid_mode(&mut self, mode: libc::mode_t) -> &mut Self {
+        self.forbid_mode = mode;
+        self
+    }
+
+    /// Open the directory in "resolve-only" mode.
+    ///
+    /// A resolve-only `Dir` can be used to open files (and subdirectories) with known names, but
+    /// it refuses to list its own contents: [`Dir::list_self()`], [`Dir::list_dir()`],
+    /// [`Dir::remove_dir_all()`], and anything built on them (such as [`Dir::walk()`]) fail with
+    /// [`Error::ResolveOnly`]. Subdirectories opened through it (as well as its parent and any
+    /// clones) are resolve-only as well.
+    ///
+    /// On Linux/Android (`O_PATH`) and FreeBSD (`O_EXEC`), the directory is opened without
+    /// requiring read permission. On other platforms, read permission on the directory is still
+    /// required to open it.
+    ///
+    /// [`Dir::list_self()`]: ./struct.Dir.html#method.list_self
+    /// [`Dir::list_dir()`]: ./struct.Dir.html#method.list_dir
+    /// [`Dir::remove_dir_all()`]: ./struct.Dir.html#method.remove_dir_all
+    /// [`Dir::walk()`]: ./struct.Dir.html#method.walk
+    /// [`Error::ResolveOnly`]: ./enum.Error.html#variant.ResolveOnly
+    #[inline]
+    pub fn resolve_only(&mut self, resolve_only: bool) -> &mut Self {
+        self.resolve_only = resolve_only;
+        self
+    }
+
+    /// Open the directory at `path` (relative to the current directory, like [`Dir::open()`]),
+    /// and verify that it satisfies these options.
+    ///
+    /// [`Dir::open()`]: ./struct.Dir.html#method.open
+    pub fn open<P: AsPath>(&self, path: P) -> io::Result<Dir> {
+        let mut flags = constants::DIR_OPEN_FLAGS;
+        if self.nofollow {
+            flags |= libc::O_NOFOLLOW;
+        }
+
+        let dir = path.with_cstr(|s| {
+            Ok(Dir {
+                fd: util::openat_raw(libc::AT_FDCWD, s, flags, 0)?,
+                resolve_only: self.resolve_only,
+                rights: Rights::all(),
+            })
+        })?;
+
+        self.verify(&dir)?;
+
+        Ok(dir)
+    }
+
+    fn verify(&self, dir: &Dir) -> io::Result<()> {
+        let st = util::fstat(dir.as_raw_fd())?;
+
+        if matches!(self.uid, Some(uid) if uid != st.st_uid)
+            || matches!(self.gid, Some(gid) if gid != st.st_gid)
+            || st.st_mode & self.forbid_mode & 0o7777 != 0
+        {
+            return Err(io::Error::from_raw_os_error(libc::EPERM));
+        }
+
+        if self.mountpoint {
+            let parent = dir.parent_unchecked()?;
+
+            // The root directory is its own parent
+            if !util::samestat(&st, &util::fstat(parent.as_raw_fd())?)
+                && crate::mntid::identify_mount(dir.as_raw_fd())?
+                    == crate::mntid::identify_mount(parent.as_raw_fd())?
+            {
+                return Err(io::Error::from_raw_os_error(libc::EPERM));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Dir {
+    /// Open the specified directory, and verify that it satisfies the given options.
+    ///
+    /// This is equivalent to `options.open(path)`; see [`DirOpenOptions`] for more details.
+    ///
+    /// [`DirOpenOptions`]: ./struct.DirOpenOptions.html
+    #[inline]
+    pub fn open_with<P: AsPath>(path: P, options: &DirOpenOptions) -> io::Result<Self> {
+        options.open(path)
+    }
+}
@@ -1,34 +1,86 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, OsStr};
 use std::fs;
 use std::io;
 use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-use crate::{AsPath, Dir, LookupFlags};
+use crate::mntid::MountId;
+use crate::{util, AsPath, AuditHook, ComponentFilter, Dir, LookupFlags, OpenBeneath};
+
+use super::{FileType, Metadata};
 
 /// A struct that can be used to open files within a directory.
 ///
 /// This is directly analogous to `std::fs::OpenOptions`, except that it only looks up files within
 /// a specific `Dir`.
 ///
-/// An `OpenOptions` struct can be created with [`Dir::open_file()`].
+/// An `OpenOptions` struct can be created with [`Dir::open_file()`], which binds it to that
+/// directory (so [`open()`] can be used). Alternatively, [`OpenOptions::new()`] creates one that
+/// isn't bound to any directory; it can be stored (for example, in long-lived configuration) and
+/// applied to any number of directories with [`open_at()`].
 ///
 /// [`Dir::open_file()`]: ./struct.Dir.html#method.open_file
+/// [`open()`]: #method.open
+/// [`OpenOptions::new()`]: #method.new
+/// [`open_at()`]: #method.open_at
 #[derive(Clone, Debug)]
 pub struct OpenOptions<'a> {
-    dir: &'a Dir,
+    dir: Option<&'a Dir>,
     read: bool,
     write: bool,
     create: bool,
     create_new: bool,
     append: bool,
     truncate: bool,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    tmpfile: bool,
+    nonblock: bool,
+    cloexec: bool,
+    max_symlinks: Option<usize>,
+    retries: Option<u32>,
+    allowed_mounts: Vec<MountId>,
+    deadline: Option<Instant>,
+    required_owner: Option<libc::uid_t>,
+    forbid_world_writable: bool,
+    component_filter: Option<ComponentFilter>,
+    audit: Option<AuditHook>,
+    expect: Option<FileType>,
     custom_flags: libc::c_int,
     mode: libc::mode_t,
     lookup_flags: LookupFlags,
 }
 
+impl OpenOptions<'static> {
+    /// Create a new set of options that isn't bound to a directory.
+    ///
+    /// All options are initially disabled, as with [`Dir::open_file()`]. Files can be opened with
+    /// [`open_at()`].
+    ///
+    /// [`Dir::open_file()`]: ./struct.Dir.html#method.open_file
+    /// [`open_at()`]: #method.open_at
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_dir(None)
+    }
+}
+
+impl Default for OpenOptions<'static> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'a> OpenOptions<'a> {
     #[inline]
     pub(crate) fn beneath(dir: &'a Dir) -> Self {
+        Self::with_dir(Some(dir))
+    }
+
+    #[inline]
+    fn with_dir(dir: Option<&'a Dir>) -> Self {
         Self {
             dir,
             read: false,
@@ -37,6 +89,19 @@ impl<'a> OpenOptions<'a> {
             create_new: false,
             append: false,
             truncate: false,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            tmpfile: false,
+            nonblock: false,
+            cloexec: true,
+            max_symlinks: None,
+            retries: None,
+            allowed_mounts: Vec::new(),
+            deadline: None,
+            required_owner: None,
+            forbid_world_writable: false,
+            component_filter: None,
+            audit: None,
+            expect: None,
             custom_flags: 0,
             mode: 0o666,
             lookup_flags: LookupFlags::empty(),
@@ -90,6 +155,179 @@ impl<'a> OpenOptions<'a> {
         self
     }
 
+    /// Linux-specific: Create an unnamed temporary file instead of opening an existing file.
+    ///
+    /// If this is enabled, the path passed to [`.open()`] must refer to a directory, and an
+    /// anonymous regular file will be created on the filesystem containing that directory (using
+    /// `O_TMPFILE`). Write access must be enabled, and [`.create()`], [`.create_new()`], and
+    /// [`.truncate()`] are ignored.
+    ///
+    /// The file can later be given a name with [`Dir::link_tmpfile()`]. This can be used to create
+    /// a file, populate it, and then atomically make it visible.
+    ///
+    /// Note that not all filesystems support `O_TMPFILE`; on those that don't, opening will fail
+    /// with `EOPNOTSUPP` (or `EISDIR` on kernels older than 3.11).
+    ///
+    /// [`.open()`]: #method.open
+    /// [`.create()`]: #method.create
+    /// [`.create_new()`]: #method.create_new
+    /// [`.truncate()`]: #method.truncate
+    /// [`Dir::link_tmpfile()`]: ./struct.Dir.html#method.link_tmpfile
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[inline]
+    pub fn tmpfile(&mut self, tmpfile: bool) -> &mut Self {
+        self.tmpfile = tmpfile;
+        self
+    }
+
+    /// Open the file in non-blocking mode (with `O_NONBLOCK`).
+    ///
+    /// This is mainly useful for opening FIFOs: without it, opening a FIFO blocks until the other
+    /// end is opened as well. (With it, opening a FIFO for reading succeeds immediately, and
+    /// opening one for writing fails with `ENXIO` if there are no readers.)
+    ///
+    /// Only the file itself is opened with `O_NONBLOCK`; it has no effect on the directories that
+    /// are opened while resolving the path. The returned file will remain in non-blocking mode.
+    #[inline]
+    pub fn nonblock(&mut self, nonblock: bool) -> &mut Self {
+        self.nonblock = nonblock;
+        self
+    }
+
+    /// Set whether the returned file descriptor will have its close-on-exec flag set.
+    ///
+    /// This is `true` by default. Setting it to `false` allows the file to be inherited by child
+    /// processes.
+    ///
+    /// Note that the file is still opened with `O_CLOEXEC` (as are any other file descriptors that
+    /// are opened while resolving the path), and the flag is cleared afterward. This avoids
+    /// accidentally leaking any of them into processes that are spawned by other threads in the
+    /// meantime.
+    #[inline]
+    pub fn cloexec(&mut self, cloexec: bool) -> &mut Self {
+        self.cloexec = cloexec;
+        self
+    }
+
+    /// Set the maximum number of symlinks that may be followed while resolving the path.
+    ///
+    /// See [`OpenBeneath::max_symlinks()`] for more information.
+    ///
+    /// [`OpenBeneath::max_symlinks()`]: ./struct.OpenBeneath.html#method.max_symlinks
+    #[inline]
+    pub fn max_symlinks(&mut self, max_symlinks: Option<usize>) -> &mut Self {
+        self.max_symlinks = max_symlinks;
+        self
+    }
+
+    /// Set the number of times that opening the file will be retried if path resolution fails
+    /// with `EAGAIN`.
+    ///
+    /// See [`OpenBeneath::retries()`] for more information.
+    ///
+    /// [`OpenBeneath::retries()`]: ./struct.OpenBeneath.html#method.retries
+    #[inline]
+    pub fn retries(&mut self, retries: Option<u32>) -> &mut Self {
+        self.retries = retries;
+        self
+    }
+
+    /// With [`LookupFlags::NO_XDEV`], allow crossing into any of the given mounts.
+    ///
+    /// See [`OpenBeneath::allow_mounts()`] for more information.
+    ///
+    /// [`LookupFlags::NO_XDEV`]: ./struct.LookupFlags.html#associatedconstant.NO_XDEV
+    /// [`OpenBeneath::allow_mounts()`]: ./struct.OpenBeneath.html#method.allow_mounts
+    #[cfg(feature = "mount-id")]
+    #[inline]
+    pub fn allow_mounts(&mut self, mounts: &[MountId]) -> &mut Self {
+        self.allowed_mounts = mounts.to_vec();
+        self
+    }
+
+    /// Set a deadline for resolving the path; if it passes, opening the file fails with
+    /// `ETIMEDOUT`.
+    ///
+    /// See [`OpenBeneath::deadline()`] for more information.
+    ///
+    /// [`OpenBeneath::deadline()`]: ./struct.OpenBeneath.html#method.deadline
+    #[inline]
+    pub fn deadline(&mut self, deadline: Option<Instant>) -> &mut Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Require every component of the path to be owned by the given user; otherwise, opening the
+    /// file fails with `EPERM`.
+    ///
+    /// See [`OpenBeneath::require_owner()`] for more information.
+    ///
+    /// [`OpenBeneath::require_owner()`]: ./struct.OpenBeneath.html#method.require_owner
+    #[inline]
+    pub fn require_owner(&mut self, uid: Option<libc::uid_t>) -> &mut Self {
+        self.required_owner = uid;
+        self
+    }
+
+    /// Refuse to traverse or open world-writable components; opening the file fails with `EPERM`
+    /// instead.
+    ///
+    /// See [`OpenBeneath::forbid_world_writable()`] for more information.
+    ///
+    /// [`OpenBeneath::forbid_world_writable()`]: ./struct.OpenBeneath.html#method.forbid_world_writable
+    #[inline]
+    pub fn forbid_world_writable(&mut self, forbid: bool) -> &mut Self {
+        self.forbid_world_writable = forbid;
+        self
+    }
+
+    /// Reject components that are not allowed by the given filter; opening the file fails with
+    /// `EPERM` instead.
+    ///
+    /// See [`OpenBeneath::component_filter()`] for more information.
+    ///
+    /// [`OpenBeneath::component_filter()`]: ./struct.OpenBeneath.html#method.component_filter
+    #[inline]
+    pub fn component_filter(&mut self, filter: Option<ComponentFilter>) -> &mut Self {
+        self.component_filter = filter;
+        self
+    }
+
+    /// Set a hook that will be called for every step of path resolution (or `None` to remove
+    /// it).
+    ///
+    /// See [`OpenBeneath::audit()`] for more information.
+    ///
+    /// [`OpenBeneath::audit()`]: ./struct.OpenBeneath.html#method.audit
+    #[inline]
+    pub fn audit(&mut self, hook: Option<AuditHook>) -> &mut Self {
+        self.audit = hook;
+        self
+    }
+
+    /// Require the opened file to be of the given type (or `None` to accept any type).
+    ///
+    /// The type is checked with `fstat()` on the file descriptor that was opened, so it describes
+    /// the file that was actually opened even if the file at the path is replaced concurrently. If
+    /// it doesn't match, the file is closed and opening fails with an
+    /// [`Error::UnexpectedFileType`] error (wrapped in an `io::Error`).
+    ///
+    /// Unless the expected type is [`FileType::Fifo`] (or [`nonblock()`] is enabled), the file is
+    /// opened with `O_NONBLOCK`, which is cleared again once its type has been verified. This
+    /// prevents opening a FIFO from blocking until the other end is opened (for example, if a
+    /// FIFO is swapped in where a regular file is expected). Note that opening a FIFO for writing
+    /// in this mode fails with `ENXIO` if it has no readers, rather than with an
+    /// [`Error::UnexpectedFileType`] error.
+    ///
+    /// [`Error::UnexpectedFileType`]: ./enum.Error.html#variant.UnexpectedFileType
+    /// [`FileType::Fifo`]: ./enum.FileType.html#variant.Fifo
+    /// [`nonblock()`]: #method.nonblock
+    #[inline]
+    pub fn expect(&mut self, ftype: Option<FileType>) -> &mut Self {
+        self.expect = ftype;
+        self
+    }
+
     /// Set the mode with which the file will be opened (e.g `0o777`).
     ///
     /// The OS will mask out the system umask value.
@@ -102,6 +340,19 @@ impl<'a> OpenOptions<'a> {
     /// Pass custom flags when opening the file.
     ///
     /// Like `std::fs::OpenOptions`, `O_ACCMODE` is masked out from the given flags.
+    ///
+    /// This can be used to pass platform-specific flags such as `O_NOATIME`, `O_DIRECT`, `O_SYNC`,
+    /// or `O_DSYNC`. Like [`nonblock()`], the flags only apply to the file itself (including if it
+    /// is reached by following a symlink); the directories that are opened while resolving the
+    /// path are always opened with a fixed set of flags, regardless of how the path is resolved.
+    /// So, for example, `O_DIRECT` or `O_NOATIME` will not cause opening an intermediate
+    /// directory to fail.
+    ///
+    /// Note that the flags are still subject to the usual restrictions (for example, opening a
+    /// file with `O_NOATIME` fails with `EPERM` unless the caller owns it, and some filesystems
+    /// reject `O_DIRECT` with `EINVAL`).
+    ///
+    /// [`nonblock()`]: #method.nonblock
     #[inline]
     pub fn custom_flags(&mut self, flags: libc::c_int) -> &mut Self {
         self.custom_flags = flags;
@@ -119,9 +370,29 @@ impl<'a> OpenOptions<'a> {
         self
     }
 
+    /// Get the lookup flags set with `lookup_flags()`.
+    #[inline]
+    pub(crate) fn get_lookup_flags(&self) -> LookupFlags {
+        self.lookup_flags
+    }
+
+    #[inline]
+    fn tmpfile_flags(&self) -> libc::c_int {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if self.tmpfile {
+            return libc::O_TMPFILE;
+        }
+
+        0
+    }
+
     fn flags(&self) -> io::Result<libc::c_int> {
         let mut flags = self.custom_flags & !libc::O_ACCMODE;
 
+        if self.nonblock {
+            flags |= libc::O_NONBLOCK;
+        }
+
         if self.write || self.append {
             if self.read {
                 flags |= libc::O_RDWR;
@@ -129,7 +400,11 @@ impl<'a> OpenOptions<'a> {
                 flags |= libc::O_WRONLY;
             }
 
-            if self.create_new {
+            let tmpfile_flags = self.tmpfile_flags();
+
+            if tmpfile_flags != 0 {
+                flags |= tmpfile_flags;
+            } else if self.create_new {
                 flags |= libc::O_CREAT | libc::O_EXCL;
             } else {
                 if self.create {
@@ -147,7 +422,7 @@ impl<'a> OpenOptions<'a> {
         } else if self.read {
             flags |= libc::O_RDONLY;
 
-            if self.create_new || self.create || self.truncate {
+            if self.create_new || self.create || self.truncate || self.tmpfile_flags() != 0 {
                 return Err(io::Error::from_raw_os_error(libc::EINVAL));
             }
         } else {
@@ -157,16 +432,218 @@ impl<'a> OpenOptions<'a> {
         Ok(flags)
     }
 
-    /// Open the file at `path` with the options specified by `self`.
-    #[inline]
+    /// Returns `true` if the file should be opened with `O_NONBLOCK` (given the flags from
+    /// `flags()`) until its type has been checked by `check_expected()`.
+    fn nonblock_probe(&self, flags: libc::c_int) -> bool {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if flags & libc::O_PATH == libc::O_PATH {
+            // O_PATH never blocks, and F_SETFL doesn't work on O_PATH file descriptors
+            return false;
+        }
+
+        match self.expect {
+            Some(ftype) => ftype != FileType::Fifo && flags & libc::O_NONBLOCK == 0,
+            None => false,
+        }
+    }
+
+    /// Check that `file` is of the type given to `expect()` (if any), and clear `O_NONBLOCK` if it
+    /// was only added by `nonblock_probe()`.
+    fn check_expected(&self, file: fs::File, nonblock_probe: bool) -> io::Result<fs::File> {
+        if let Some(expected) = self.expect {
+            let found = Metadata::stat_fd(file.as_raw_fd())?.file_type();
+            if found != expected {
+                return Err(crate::Error::UnexpectedFileType { expected, found }.into());
+            }
+        }
+
+        if nonblock_probe {
+            util::set_nonblock(file.as_raw_fd(), false)?;
+        }
+
+        Ok(file)
+    }
+
+    /// Open the file at `path` (within the directory these options were created from) with the
+    /// options specified by `self`.
+    ///
+    /// If these options were created with [`OpenOptions::new()`] (so they aren't bound to a
+    /// directory), this fails with `EBADF`; use [`open_at()`] instead.
+    ///
+    /// [`OpenOptions::new()`]: #method.new
+    /// [`open_at()`]: #method.open_at
     pub fn open<P: AsPath>(&self, path: P) -> io::Result<fs::File> {
-        crate::open_beneath(
-            self.dir.as_raw_fd(),
-            path,
-            self.flags()?,
-            self.mode,
-            self.lookup_flags,
-        )
+        match self.dir {
+            Some(dir) => self.open_at(dir, path),
+            None => Err(io::Error::from_raw_os_error(libc::EBADF)),
+        }
+    }
+
+    /// Open the file at `path` within the given directory with the options specified by `self`.
+    ///
+    /// This can be used regardless of whether these options are bound to a directory (if they
+    /// are, that directory is ignored).
+    pub fn open_at<P: AsPath>(&self, dir: &Dir, path: P) -> io::Result<fs::File> {
+        let mut flags = self.flags()?;
+        dir.check_rights(super::open_rights(flags))?;
+
+        let nonblock_probe = self.nonblock_probe(flags);
+        if nonblock_probe {
+            flags |= libc::O_NONBLOCK;
+        }
+
+        let mut builder = OpenBeneath::new(path);
+        builder
+            .flags(flags)
+            .mode(self.mode)
+            .lookup_flags(self.lookup_flags)
+            .cloexec(self.cloexec)
+            .max_symlinks(self.max_symlinks)
+            .retries(self.retries)
+            .deadline(self.deadline)
+            .require_owner(self.required_owner)
+            .forbid_world_writable(self.forbid_world_writable)
+            .component_filter(self.component_filter.clone())
+            .audit(self.audit.clone());
+        #[cfg(feature = "mount-id")]
+        builder.allow_mounts(&self.allowed_mounts);
+
+        let file = builder.open_at(dir)?;
+
+        self.check_expected(file, nonblock_probe)
+    }
+
+    /// Open the file at `path` (within the directory these options were created from), and
+    /// retrieve its metadata.
+    ///
+    /// The metadata is retrieved from the opened file descriptor (with `fstat()`, or `statx()` on
+    /// Linux), so it is guaranteed to describe the file that was actually opened, even if the
+    /// file at `path` is replaced concurrently. This is useful, for example, when serving files
+    /// over HTTP, where the size, modification time, and cache validators (such as ETags) sent to
+    /// the client must match the contents being sent.
+    ///
+    /// Like [`open()`], this fails with `EBADF` if these options aren't bound to a directory.
+    ///
+    /// [`open()`]: #method.open
+    pub fn open_with_metadata<P: AsPath>(&self, path: P) -> io::Result<(fs::File, Metadata)> {
+        let file = self.open(path)?;
+        let meta = Metadata::stat_fd(file.as_raw_fd())?;
+        Ok((file, meta))
+    }
+
+    /// Open the file named `name` directly within `dir`, without following symlinks. `self.dir` and
+    /// the lookup-related options are ignored.
+    pub(crate) fn open_child(&self, dir: &Dir, name: &CStr) -> io::Result<fs::File> {
+        let mut flags = self.flags()?;
+        dir.check_rights(super::open_rights(flags))?;
+
+        let nonblock_probe = self.nonblock_probe(flags);
+        if nonblock_probe {
+            flags |= libc::O_NONBLOCK;
+        }
+
+        let file = util::openat(dir.as_raw_fd(), name, flags | libc::O_NOFOLLOW, self.mode)?;
+
+        if !self.cloexec {
+            util::set_cloexec(file.as_raw_fd(), false)?;
+        }
+
+        self.check_expected(file, nonblock_probe)
+    }
+}
+
+impl Dir {
+    /// Open the file at `path` with the given options, and retrieve the metadata of the file that
+    /// was opened.
+    ///
+    /// This is equivalent to `opts.open_at(self, path)` followed by retrieving the metadata of the
+    /// returned file (any directory the options are bound to is ignored). See
+    /// [`OpenOptions::open_with_metadata()`] for more details.
+    ///
+    /// [`OpenOptions::open_with_metadata()`]: ./struct.OpenOptions.html#method.open_with_metadata
+    pub fn open_with_metadata<P: AsPath>(
+        &self,
+        path: P,
+        opts: &OpenOptions,
+    ) -> io::Result<(fs::File, Metadata)> {
+        let file = opts.open_at(self, path)?;
+        let meta = Metadata::stat_fd(file.as_raw_fd())?;
+        Ok((file, meta))
+    }
+
+    /// Open each of the given paths within this directory, with the corresponding options.
+    ///
+    /// This is equivalent to calling `opts.open_at(self, path)` for each `(path, opts)` pair (any
+    /// directory the options are bound to is ignored), and the results are returned in the same
+    /// order. However, the directories containing the files are cached, so paths that share the
+    /// same parent directory (for example, `site/index.html` and `site/index.html.headers`) only
+    /// need to resolve it once. (A limited number of directories are cached at once, to avoid
+    /// exhausting file descriptors.)
+    ///
+    /// Paths whose options specify [`LookupFlags::IN_ROOT`], a [`max_symlinks()`] limit, a
+    /// [`deadline()`], mounts that may be crossed with `NO_XDEV`, or a policy that every component
+    /// must satisfy (such as [`require_owner()`]) are always resolved from this directory.
+    ///
+    /// [`LookupFlags::IN_ROOT`]: ./struct.LookupFlags.html#associatedconstant.IN_ROOT
+    /// [`max_symlinks()`]: ./struct.OpenOptions.html#method.max_symlinks
+    /// [`deadline()`]: ./struct.OpenOptions.html#method.deadline
+    /// [`require_owner()`]: ./struct.OpenOptions.html#method.require_owner
+    pub fn open_many<P: AsPath>(&self, entries: &[(P, OpenOptions)]) -> Vec<io::Result<fs::File>> {
+        const MAX_CACHED: usize = 64;
+
+        let mut cache: HashMap<(PathBuf, LookupFlags), Dir> = HashMap::new();
+
+        entries
+            .iter()
+            .map(|(path, opts)| {
+                let path = path.as_path();
+
+                // With IN_ROOT, symlinks would be resolved relative to the wrong root, and we
+                // can't count the symlinks that were followed to reach the parent directory (or
+                // bound the time spent resolving it, let it cross allowed mounts, or check or
+                // audit its components)
+                if opts.lookup_flags.contains(LookupFlags::IN_ROOT)
+                    || opts.max_symlinks.is_some()
+                    || !opts.allowed_mounts.is_empty()
+                    || opts.deadline.is_some()
+                    || opts.required_owner.is_some()
+                    || opts.forbid_world_writable
+                    || opts.component_filter.is_some()
+                    || opts.audit.is_some()
+                {
+                    return opts.open_at(self, path);
+                }
+
+                // Let open_at() handle all the special cases (absolute paths, paths ending in "..",
+                // and files directly within this directory)
+                let parent = match util::path_split(path) {
+                    Some((Some(parent), _)) if !path.has_root() => parent,
+                    _ => return opts.open_at(self, path),
+                };
+                // Keep any trailing slashes
+                let rest = OsStr::from_bytes(&path.as_os_str().as_bytes()[parent.len()..]);
+
+                let key = (PathBuf::from(parent), opts.lookup_flags);
+                let subdir = match cache.get(&key) {
+                    Some(subdir) => subdir,
+                    None => {
+                        if cache.len() >= MAX_CACHED {
+                            cache.clear();
+                        }
+
+                        let subdir = self.sub_dir(Path::new(parent), opts.lookup_flags)?;
+                        cache.entry(key).or_insert(subdir)
+                    }
+                };
+
+                match opts.open_at(subdir, rest) {
+                    // If the final component is a symlink that points outside of the parent
+                    // directory, it may still point somewhere within this directory
+                    Err(e) if e.raw_os_error() == Some(libc::EXDEV) => opts.open_at(self, path),
+                    res => res,
+                }
+            })
+            .collect()
     }
 }
 
@@ -263,6 +740,39 @@ mod tests {
         );
     }
 
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn test_tmpfile_flags() {
+        let dir = Dir::open("/").unwrap();
+        let opts = dir.open_file();
+
+        assert_eq!(
+            opts.clone().write(true).tmpfile(true).flags().unwrap(),
+            libc::O_WRONLY | libc::O_TMPFILE
+        );
+        assert_eq!(
+            opts.clone()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .truncate(true)
+                .tmpfile(true)
+                .flags()
+                .unwrap(),
+            libc::O_RDWR | libc::O_TMPFILE
+        );
+
+        assert_eq!(
+            opts.clone()
+                .read(true)
+                .tmpfile(true)
+                .flags()
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EINVAL)
+        );
+    }
+
     #[test]
     fn test_custom_flags() {
         let dir = Dir::open("/").unwrap();
@@ -276,5 +786,10 @@ mod tests {
                 .unwrap(),
             libc::O_RDONLY | libc::O_NOFOLLOW
         );
+
+        assert_eq!(
+            opts.clone().read(true).nonblock(true).flags().unwrap(),
+            libc::O_RDONLY | libc::O_NONBLOCK
+        );
     }
 }
@@ -0,0 +1,228 @@
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::PathBuf;
+
+use crate::{open_beneath, AsPath, Dir, LookupFlags, Rights};
+
+use super::{Metadata, ReadDirIter};
+
+/// A wrapper around a [`Dir`] that only allows reading.
+///
+/// A `ReadOnlyDir` exposes the subset of the [`Dir`] API that cannot modify the filesystem: files
+/// are always opened with `O_RDONLY`, and there are no methods to create, remove, or rename files,
+/// create symlinks, or change permissions, ownership, or timestamps. Subdirectories opened through
+/// it are also `ReadOnlyDir`s, there is no way to get the wrapped [`Dir`] back out, and (since
+/// all lookups are confined to the directory) there is no way to reach its parent. This makes it
+/// possible to pass a directory to other code (for example, plugins) with a compile-time
+/// guarantee that it will not be used to modify anything outside of, or access anything above,
+/// that directory.
+///
+/// Note that this is only a restriction on the API. It does not prevent code with access to the
+/// raw file descriptor (through `AsRawFd`) from performing other operations with it, and it does
+/// not prevent other processes (or other handles to the same directory) from modifying it.
+///
+/// [`Dir`]: ./struct.Dir.html
+#[derive(Debug)]
+pub struct ReadOnlyDir {
+    dir: Dir,
+}
+
+impl ReadOnlyDir {
+    /// Open the specified directory for reading only.
+    #[inline]
+    pub fn open<P: AsPath>(path: P) -> io::Result<Self> {
+        Dir::open(path).map(Self::new)
+    }
+
+    /// Wrap the given directory.
+    ///
+    /// The directory's [`Rights`] are restricted to [`Rights::READ`] (see [`Dir::restrict()`]).
+    /// This is equivalent to [`Dir::into_read_only()`].
+    ///
+    /// [`Rights`]: ./struct.Rights.html
+    /// [`Rights::READ`]: ./struct.Rights.html#associatedconstant.READ
+    /// [`Dir::restrict()`]: ./struct.Dir.html#method.restrict
+    /// [`Dir::into_read_only()`]: ./struct.Dir.html#method.into_read_only
+    #[inline]
+    pub fn new(mut dir: Dir) -> Self {
+        dir.restrict(Rights::READ);
+        Self { dir }
+    }
+
+    /// Open a subdirectory of this directory (which will also be read-only).
+    ///
+    /// See [`Dir::sub_dir()`].
+    ///
+    /// [`Dir::sub_dir()`]: ./struct.Dir.html#method.sub_dir
+    #[inline]
+    pub fn sub_dir<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<Self> {
+        self.dir.sub_dir(path, lookup_flags).map(Self::new)
+    }
+
+    /// Try to "clone" this `ReadOnlyDir`.
+    #[inline]
+    pub fn try_clone(&self) -> io::Result<Self> {
+        self.dir.try_clone().map(Self::new)
+    }
+
+    /// Open the file at `path` within this directory for reading.
+    ///
+    /// The file is opened with `O_RDONLY` (see [`open_beneath()`]).
+    ///
+    /// [`open_beneath()`]: ./fn.open_beneath.html
+    #[inline]
+    pub fn open_file<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<fs::File> {
+        self.dir.check_rights(Rights::READ)?;
+
+        open_beneath(self.dir.as_raw_fd(), path, libc::O_RDONLY, 0, lookup_flags)
+    }
+
+    /// Read the entire contents of the file at `path` into a bytes vector.
+    ///
+    /// See [`Dir::read()`].
+    ///
+    /// [`Dir::read()`]: ./struct.Dir.html#method.read
+    #[inline]
+    pub fn read<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<Vec<u8>> {
+        self.dir.read(path, lookup_flags)
+    }
+
+    /// Read the entire contents of the file at `path` into a string.
+    ///
+    /// See [`Dir::read_to_string()`].
+    ///
+    /// [`Dir::read_to_string()`]: ./struct.Dir.html#method.read_to_string
+    #[inline]
+    pub fn read_to_string<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<String> {
+        self.dir.read_to_string(path, lookup_flags)
+    }
+
+    /// Read the contents of the specified symlink.
+    ///
+    /// See [`Dir::read_link()`].
+    ///
+    /// [`Dir::read_link()`]: ./struct.Dir.html#method.read_link
+    #[inline]
+    pub fn read_link<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<PathBuf> {
+        self.dir.read_link(path, lookup_flags)
+    }
+
+    /// Resolve the given path within this directory, and return its canonical path.
+    ///
+    /// See [`Dir::canonicalize()`].
+    ///
+    /// [`Dir::canonicalize()`]: ./struct.Dir.html#method.canonicalize
+    #[inline]
+    pub fn canonicalize<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<PathBuf> {
+        self.dir.canonicalize(path, lookup_flags)
+    }
+
+    /// List the contents of this directory.
+    ///
+    /// See [`Dir::list_self()`].
+    ///
+    /// [`Dir::list_self()`]: ./struct.Dir.html#method.list_self
+    #[inline]
+    pub fn list_self(&self) -> io::Result<ReadDirIter> {
+        self.dir.list_self()
+    }
+
+    /// List the contents of the specified subdirectory.
+    ///
+    /// See [`Dir::list_dir()`].
+    ///
+    /// [`Dir::list_dir()`]: ./struct.Dir.html#method.list_dir
+    #[inline]
+    pub fn list_dir<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<ReadDirIter> {
+        self.dir.list_dir(path, lookup_flags)
+    }
+
+    /// Retrieve metadata of this directory.
+    #[inline]
+    pub fn self_metadata(&self) -> io::Result<Metadata> {
+        self.dir.self_metadata()
+    }
+
+    /// Retrieve information on the file with the given path, without following symlinks in the
+    /// final component.
+    ///
+    /// See [`Dir::metadata()`].
+    ///
+    /// [`Dir::metadata()`]: ./struct.Dir.html#method.metadata
+    #[inline]
+    pub fn metadata<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<Metadata> {
+        self.dir.metadata(path, lookup_flags)
+    }
+
+    /// Retrieve information on the file with the given path, following symlinks in the final
+    /// component.
+    ///
+    /// See [`Dir::metadata_follow()`].
+    ///
+    /// [`Dir::metadata_follow()`]: ./struct.Dir.html#method.metadata_follow
+    #[inline]
+    pub fn metadata_follow<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<Metadata> {
+        self.dir.metadata_follow(path, lookup_flags)
+    }
+
+    /// Check whether the given path exists within this directory.
+    ///
+    /// See [`Dir::exists()`].
+    ///
+    /// [`Dir::exists()`]: ./struct.Dir.html#method.exists
+    #[inline]
+    pub fn exists<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<bool> {
+        self.dir.exists(path, lookup_flags)
+    }
+
+    /// Check whether the given path refers to a regular file within this directory.
+    ///
+    /// See [`Dir::is_file()`].
+    ///
+    /// [`Dir::is_file()`]: ./struct.Dir.html#method.is_file
+    #[inline]
+    pub fn is_file<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<bool> {
+        self.dir.is_file(path, lookup_flags)
+    }
+
+    /// Check whether the given path refers to a directory within this directory.
+    ///
+    /// See [`Dir::is_dir()`].
+    ///
+    /// [`Dir::is_dir()`]: ./struct.Dir.html#method.is_dir
+    #[inline]
+    pub fn is_dir<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<bool> {
+        self.dir.is_dir(path, lookup_flags)
+    }
+}
+
+impl From<Dir> for ReadOnlyDir {
+    #[inline]
+    fn from(dir: Dir) -> Self {
+        Self::new(dir)
+    }
+}
+
+impl AsRawFd for ReadOnlyDir {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.dir.as_raw_fd()
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::PathBuf;
+
+use crate::{util, Dir, LookupFlags};
+
+use super::{recover_entry, same_meta, WalkErrorPolicy, WalkOrder};
+
+/// Determine the current path of the given open file, relative to `dir`.
+///
+/// This is useful for logging which file (within a "sandbox" directory) was actually opened. If
+/// `file` is open to `dir` itself, this returns `.`. If the file has multiple hard links within
+/// `dir`, any one of them may be returned.
+///
+/// If `file` refers to a directory, this is equivalent to [`Dir::recover_path_from()`].
+///
+/// Otherwise, a few OS-specific optimizations are tried first:
+/// - On Linux, this will try `readlink("/proc/self/fd/$fd")`.
+/// - On macOS, this will try `fcntl(fd, F_GETPATH)`.
+///
+/// The path found this way is verified by looking it up again beneath `dir` and checking that it
+/// refers to the same file. If that fails (or on other platforms), this falls back on searching
+/// the entire directory tree beneath `dir` (breadth-first, skipping directories that cannot be
+/// read), which may be very slow for large trees.
+///
+/// The same warnings that apply to [`Dir::recover_path()`] apply here: the returned path is only
+/// accurate at the time it was found, and it should be used for informational purposes only.
+///
+/// # Errors
+///
+/// Fails with `ENOENT` if the file could not be found beneath `dir` (for example, if it has been
+/// deleted, or if it was never inside `dir` in the first place).
+///
+/// [`Dir::recover_path()`]: ./struct.Dir.html#method.recover_path
+/// [`Dir::recover_path_from()`]: ./struct.Dir.html#method.recover_path_from
+pub fn recover_file_path(file: &fs::File, dir: &Dir) -> io::Result<PathBuf> {
+    let file_stat = util::fstat(file.as_raw_fd())?;
+
+    if util::samestat(&file_stat, dir.self_metadata()?.stat()) {
+        return Ok(PathBuf::from("."));
+    }
+
+    if file_stat.st_mode & libc::S_IFMT == libc::S_IFDIR {
+        // For directories, we can just walk back up the tree
+        let sub = Dir::from_file(file.try_clone()?)?;
+
+        return sub.recover_path_from(dir);
+    }
+
+    if let Some(path) = fast_path(file.as_raw_fd(), dir) {
+        if let Ok(meta) = dir.metadata(&path, LookupFlags::NO_SYMLINKS) {
+            if util::samestat(&file_stat, meta.stat()) {
+                return Ok(path);
+            }
+        }
+    }
+
+    for entry in dir
+        .walk()
+        .order(WalkOrder::BreadthFirst)
+        .error_policy(WalkErrorPolicy::Skip)
+        .walk(".")?
+    {
+        let entry = entry?;
+
+        if let Ok(meta) = entry.metadata() {
+            if util::samestat(&file_stat, meta.stat()) {
+                return Ok(entry.path().to_path_buf());
+            }
+        }
+    }
+
+    Err(io::Error::from_raw_os_error(libc::ENOENT))
+}
+
+impl Dir {
+    /// Determine the path of this directory relative to `ancestor`.
+    ///
+    /// This walks up the directory tree with `..` (like the fallback strategy used by
+    /// [`recover_path()`]), but it stops as soon as it reaches `ancestor`. As a result, it only
+    /// needs permission to list the directories between this directory and `ancestor`, and it
+    /// works even if the directories above `ancestor` are unreadable. If this directory is
+    /// `ancestor`, this returns `.`.
+    ///
+    /// The same warnings that apply to [`recover_path()`] apply here: the returned path is only
+    /// accurate at the time it was found, and it should be used for informational purposes only.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `ENOENT` if this directory is not beneath `ancestor` (or if it has been
+    /// deleted).
+    ///
+    /// [`recover_path()`]: #method.recover_path
+    pub fn recover_path_from(&self, ancestor: &Dir) -> io::Result<PathBuf> {
+        let ancestor_meta = ancestor.self_metadata()?;
+
+        let mut sub_meta = self.self_metadata()?;
+        let mut parent = self.parent_unchecked()?;
+        let mut names = Vec::new();
+
+        while !same_meta(&sub_meta, &ancestor_meta) {
+            let parent_meta = parent.self_metadata()?;
+
+            if same_meta(&sub_meta, &parent_meta) {
+                // We hit the real root without finding `ancestor`
+                return Err(io::Error::from_raw_os_error(libc::ENOENT));
+            }
+
+            names.push(recover_entry(&parent, &sub_meta)?.name().to_os_string());
+
+            parent = parent.parent_unchecked()?;
+            sub_meta = parent_meta;
+        }
+
+        if names.is_empty() {
+            Ok(PathBuf::from("."))
+        } else {
+            Ok(names.iter().rev().collect())
+        }
+    }
+}
+
+/// Try to find the path of `fd` relative to `dir` using OS-specific interfaces. The result must be
+/// verified by the caller.
+#[allow(unused_variables)]
+fn fast_path(fd: RawFd, dir: &Dir) -> Option<PathBuf> {
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+    {
+        let dir_path = dir.recover_path().ok()?;
+        let file_path = os_path(fd)?;
+
+        let rel = file_path.strip_prefix(&dir_path).ok()?;
+        if rel.as_os_str().is_empty() {
+            None
+        } else {
+            Some(rel.to_path_buf())
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+    None
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn os_path(fd: RawFd) -> Option<PathBuf> {
+    let path = fs::read_link(format!("/proc/self/fd/{}", fd)).ok()?;
+    let path_bytes = path.as_os_str().as_bytes();
+
+    if path_bytes.starts_with(b"/") && !path_bytes.ends_with(b" (deleted)") {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn os_path(fd: RawFd) -> Option<PathBuf> {
+    use std::ffi::OsStr;
+
+    let mut buf = [0u8; libc::PATH_MAX as usize];
+
+    if unsafe { libc::fcntl(fd, libc::F_GETPATH, buf.as_mut_ptr()) } == 0 {
+        let index = buf.iter().position(|&c| c == 0).unwrap();
+        Some(PathBuf::from(OsStr::from_bytes(&buf[..index])))
+    } else {
+        None
+    }
+}
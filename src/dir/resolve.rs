@@ -0,0 +1,144 @@
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::mem::ManuallyDrop;
+use std::os::unix::prelude::*;
+use std::path::PathBuf;
+
+use crate::{util, AsPath, Dir, LookupFlags, Rights};
+
+/// The result of resolving a path with [`resolve_beneath()`] or [`Dir::resolve()`].
+///
+/// [`resolve_beneath()`]: ./fn.resolve_beneath.html
+/// [`Dir::resolve()`]: ./struct.Dir.html#method.resolve
+#[derive(Debug)]
+pub struct ResolvedPath {
+    parent: Dir,
+    components: Vec<OsString>,
+    file_name: Option<OsString>,
+}
+
+impl ResolvedPath {
+    /// Get the directory containing the final component of the path.
+    ///
+    /// If the path has no final component (for example, `.` or `a/..`), this is the directory the
+    /// path refers to.
+    #[inline]
+    pub fn parent(&self) -> &Dir {
+        &self.parent
+    }
+
+    /// Consume this `ResolvedPath` and return the directory returned by [`parent()`].
+    ///
+    /// [`parent()`]: #method.parent
+    #[inline]
+    pub fn into_parent(self) -> Dir {
+        self.parent
+    }
+
+    /// Get the final component of the path, which has not been resolved (it may not exist, and
+    /// it may be a symlink).
+    #[inline]
+    pub fn file_name(&self) -> Option<&OsStr> {
+        self.file_name.as_deref()
+    }
+
+    /// Get the normalized components of the path to [`parent()`], relative to the starting
+    /// directory.
+    ///
+    /// These never contain `.` or `..` components, and all symlinks in them have been expanded.
+    ///
+    /// [`parent()`]: #method.parent
+    #[inline]
+    pub fn parent_components(&self) -> &[OsString] {
+        &self.components
+    }
+
+    /// Get the normalized path (relative to the starting directory), including the final
+    /// component.
+    ///
+    /// If the path refers to the starting directory itself, `.` is returned.
+    pub fn path(&self) -> PathBuf {
+        let mut path: PathBuf = self.components.iter().collect();
+        if let Some(file_name) = self.file_name.as_ref() {
+            path.push(file_name);
+        }
+
+        if path.as_os_str().is_empty() {
+            PathBuf::from(".")
+        } else {
+            path
+        }
+    }
+}
+
+/// Resolve `path` beneath the directory referred to by `dir_fd`, without opening the final
+/// component.
+///
+/// This resolves the path component by component, applying the same rules as [`open_beneath()`]
+/// for the given `lookup_flags`, except that the final component of the path is not resolved. It
+/// returns the directory containing the final component and the normalized path, which can be
+/// used to validate the path and report precise errors before deciding how to open it (for
+/// example, with `resolved.parent().open_file()`).
+///
+/// Unlike [`open_beneath()`], this always uses the manual resolver. As a result,
+/// `LookupFlags::CACHED_ONLY` always fails with `EAGAIN`, and `LookupFlags::PARANOID` has no
+/// effect. None of the options available through [`OpenBeneath`] are applied: there is no limit
+/// on the number of symlinks (beyond the default), no allowed mounts, no deadline, no owner or
+/// world-writable checks, no component filter, and no audit hook. (Retries are never needed,
+/// since `..` components are resolved using the directories that were already opened.)
+///
+/// Note that if the final component is a symlink, opening it relative to [`parent()`] resolves
+/// the symlink relative to that directory, not the original one.
+///
+/// [`open_beneath()`]: ./fn.open_beneath.html
+/// [`OpenBeneath`]: ./struct.OpenBeneath.html
+/// [`parent()`]: ./struct.ResolvedPath.html#method.parent
+pub fn resolve_beneath<P: AsPath>(
+    dir_fd: RawFd,
+    path: P,
+    lookup_flags: LookupFlags,
+) -> io::Result<ResolvedPath> {
+    if dir_fd == libc::AT_FDCWD {
+        return Err(io::Error::from_raw_os_error(libc::EBADF));
+    } else if util::fstat(dir_fd)?.st_mode & libc::S_IFMT != libc::S_IFDIR {
+        return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+    }
+
+    // Borrow the file descriptor without closing it
+    let dir = ManuallyDrop::new(unsafe { Dir::from_raw_fd(dir_fd) });
+    dir.resolve(path, lookup_flags)
+}
+
+impl Dir {
+    /// Resolve `path` within this directory, without opening the final component.
+    ///
+    /// This is equivalent to `resolve_beneath(dir.as_raw_fd(), path, lookup_flags)`; see
+    /// [`resolve_beneath()`] for more details.
+    ///
+    /// The directories opened along the way (including the one returned by
+    /// [`ResolvedPath::parent()`]) inherit this directory's rights, and `Rights::READ` is required.
+    ///
+    /// [`resolve_beneath()`]: ./fn.resolve_beneath.html
+    /// [`ResolvedPath::parent()`]: ./struct.ResolvedPath.html#method.parent
+    pub fn resolve<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<ResolvedPath> {
+        self.check_rights(Rights::READ)?;
+
+        let (components, mut dirs, file_name) =
+            self.resolve_components(path.as_path(), lookup_flags, true)?;
+
+        let parent = match dirs.pop() {
+            Some(parent) => parent,
+            None => self.try_clone()?,
+        };
+
+        Ok(ResolvedPath {
+            parent,
+            components,
+            file_name,
+        })
+    }
+}
@@ -0,0 +1,107 @@
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+
+use crate::{AsPath, Dir, LookupFlags};
+
+use super::Metadata;
+
+/// The number of bytes that [`Dir::open_for_serving()`] reads from the start of the file.
+///
+/// This is enough for the content sniffing algorithms used by most web servers and browsers.
+///
+/// [`Dir::open_for_serving()`]: ./struct.Dir.html#method.open_for_serving
+pub const SNIFF_LEN: usize = 512;
+
+/// A regular file that was opened for serving, as returned by [`Dir::open_for_serving()`].
+///
+/// [`Dir::open_for_serving()`]: ./struct.Dir.html#method.open_for_serving
+#[derive(Debug)]
+pub struct ServedFile {
+    file: fs::File,
+    meta: Metadata,
+    head: Vec<u8>,
+}
+
+impl ServedFile {
+    /// Get a reference to the opened file.
+    ///
+    /// The file offset is still at the start of the file (the data returned by [`head()`] was read
+    /// without moving it).
+    ///
+    /// [`head()`]: #method.head
+    #[inline]
+    pub fn file(&self) -> &fs::File {
+        &self.file
+    }
+
+    /// Get the metadata of the opened file.
+    ///
+    /// This was retrieved from the opened file descriptor, so it describes the file that is
+    /// actually being served.
+    #[inline]
+    pub fn metadata(&self) -> &Metadata {
+        &self.meta
+    }
+
+    /// Get the first bytes of the file (up to [`SNIFF_LEN`] bytes; fewer if the file is shorter),
+    /// for use in content sniffing.
+    ///
+    /// [`SNIFF_LEN`]: ./constant.SNIFF_LEN.html
+    #[inline]
+    pub fn head(&self) -> &[u8] {
+        &self.head
+    }
+
+    /// Consume this `ServedFile` and return the opened file.
+    #[inline]
+    pub fn into_file(self) -> fs::File {
+        self.file
+    }
+
+    /// Consume this `ServedFile` and return the opened file, its metadata, and the first bytes of
+    /// the file.
+    #[inline]
+    pub fn into_parts(self) -> (fs::File, Metadata, Vec<u8>) {
+        (self.file, self.meta, self.head)
+    }
+}
+
+impl Dir {
+    /// Open the regular file at `path` for serving (for example, over HTTP), retrieve its
+    /// metadata, and read its first [`SNIFF_LEN`] bytes for content sniffing.
+    ///
+    /// Everything is done on the opened file descriptor, so the metadata and the data are
+    /// guaranteed to come from the same file. The initial data is read with `pread()`, so the file
+    /// offset is left at the start of the file.
+    ///
+    /// The file is opened for reading with `O_NONBLOCK` (so that opening FIFOs doesn't hang), which
+    /// is cleared again before the file is returned. This fails with `EISDIR` if `path` refers to
+    /// a directory, or `EINVAL` if it refers to anything else that is not a regular file.
+    ///
+    /// [`SNIFF_LEN`]: ./constant.SNIFF_LEN.html
+    pub fn open_for_serving<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<ServedFile> {
+        let file = self.open_regular(path, false, lookup_flags)?;
+        let meta = Metadata::stat_fd(file.as_raw_fd())?;
+
+        let mut head = vec![0; std::cmp::min(SNIFF_LEN as u64, meta.len()) as usize];
+        let mut nread = 0;
+
+        while nread < head.len() {
+            match file.read_at(&mut head[nread..], nread as u64) {
+                Ok(0) => break,
+                Ok(n) => nread += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        head.truncate(nread);
+
+        Ok(ServedFile { file, meta, head })
+    }
+}
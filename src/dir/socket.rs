@@ -0,0 +1,192 @@
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+
+use crate::Dir;
+
+/// The single byte of regular data sent along with the file descriptor (some platforms refuse to
+/// pass ancillary data without at least one byte of "real" data).
+const PAYLOAD: u8 = b'D';
+
+impl Dir {
+    /// Send this directory's file descriptor over the given Unix socket (using `SCM_RIGHTS`).
+    ///
+    /// `socket` must be a connected Unix domain socket, usually a `UnixStream` or a connected
+    /// `UnixDatagram`. The peer should receive it with [`Dir::recv_from()`]. The file descriptor
+    /// is duplicated into the receiving process; this `Dir` remains open.
+    ///
+    /// This is mainly useful for privilege-separated programs, where a privileged parent opens
+    /// the root directory and passes it to unprivileged workers.
+    ///
+    /// [`Dir::recv_from()`]: #method.recv_from
+    pub fn send_over<S: AsRawFd + ?Sized>(&self, socket: &S) -> io::Result<()> {
+        let mut payload = [PAYLOAD];
+        let mut iov = libc::iovec {
+            iov_base: payload.as_mut_ptr() as *mut libc::c_void,
+            iov_len: payload.len(),
+        };
+
+        let mut cmsg_buf = CmsgBuf::new();
+
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buf.as_mut_ptr();
+        msg.msg_controllen = CmsgBuf::space() as _;
+
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as u32) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, self.as_raw_fd());
+        }
+
+        // macOS doesn't have MSG_NOSIGNAL (SO_NOSIGPIPE has to be set on the socket instead)
+        #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+        let flags = libc::MSG_NOSIGNAL;
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        let flags = 0;
+
+        loop {
+            match unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, flags) } {
+                -1 => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+                0 => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Receive a directory file descriptor sent with [`Dir::send_over()`] on the given Unix
+    /// socket.
+    ///
+    /// The received file descriptor is always marked close-on-exec, and it is checked to make sure
+    /// that it refers to a directory before being wrapped in a `Dir`.
+    ///
+    /// # Errors
+    ///
+    /// - Fails with `UnexpectedEof` if the peer closed the connection.
+    /// - Fails with `EBADMSG` if the message did not contain exactly one file descriptor (any
+    ///   file descriptors that were received are closed).
+    /// - Fails with `ENOTDIR` if the received file descriptor does not refer to a directory (it is
+    ///   closed in this case).
+    ///
+    /// [`Dir::send_over()`]: #method.send_over
+    pub fn recv_from<S: AsRawFd + ?Sized>(socket: &S) -> io::Result<Self> {
+        let mut payload = [0u8];
+        let mut iov = libc::iovec {
+            iov_base: payload.as_mut_ptr() as *mut libc::c_void,
+            iov_len: payload.len(),
+        };
+
+        let mut cmsg_buf = CmsgBuf::new();
+
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buf.as_mut_ptr();
+        msg.msg_controllen = CmsgBuf::space() as _;
+
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "openbsd",
+            target_os = "dragonfly",
+        ))]
+        let flags = libc::MSG_CMSG_CLOEXEC;
+        #[cfg(not(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "openbsd",
+            target_os = "dragonfly",
+        )))]
+        let flags = 0;
+
+        let n = loop {
+            match unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, flags) } {
+                -1 => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+                n => break n,
+            }
+        };
+
+        // Collect every file descriptor we were sent, so none of them are leaked if the message
+        // turns out to be invalid
+        let mut files = Vec::new();
+
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let data = libc::CMSG_DATA(cmsg);
+                    let len = (*cmsg).cmsg_len as usize - (data as usize - cmsg as usize);
+
+                    for i in 0..len / std::mem::size_of::<RawFd>() {
+                        let fd = std::ptr::read_unaligned((data as *const RawFd).add(i));
+                        files.push(fs::File::from_raw_fd(fd));
+                    }
+                }
+
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+
+        if n == 0 && files.is_empty() {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        } else if files.len() != 1 || msg.msg_flags & libc::MSG_CTRUNC != 0 {
+            return Err(io::Error::from_raw_os_error(libc::EBADMSG));
+        }
+
+        let file = files.pop().unwrap();
+
+        #[cfg(not(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "openbsd",
+            target_os = "dragonfly",
+        )))]
+        crate::util::set_cloexec(file.as_raw_fd(), true)?;
+
+        Self::from_file(file)
+    }
+}
+
+/// A correctly aligned buffer large enough to hold a control message with one file descriptor.
+struct CmsgBuf {
+    // u64 is enough to satisfy the alignment requirements of cmsghdr on every supported platform
+    buf: [u64; 4],
+}
+
+impl CmsgBuf {
+    #[inline]
+    fn new() -> Self {
+        debug_assert!(Self::space() <= std::mem::size_of::<[u64; 4]>());
+        Self { buf: [0; 4] }
+    }
+
+    #[inline]
+    fn space() -> usize {
+        unsafe { libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) as usize }
+    }
+
+    #[inline]
+    fn as_mut_ptr(&mut self) -> *mut libc::c_void {
+        self.buf.as_mut_ptr() as *mut libc::c_void
+    }
+}
@@ -0,0 +1,250 @@
+use std::ffi::{CString, OsStr, OsString};
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+
+use crate::{constants, util, AsPath, LookupFlags};
+
+use super::{cstr, prepare_inner_operation, remove_all_at, Dir, FileType, Rights};
+
+const MAX_ATTEMPTS: usize = 100;
+
+/// A temporary file created with [`Dir::tempfile()`].
+///
+/// The file is removed when this is dropped, unless [`persist()`] or [`keep()`] is called first.
+///
+/// [`Dir::tempfile()`]: ./struct.Dir.html#method.tempfile
+/// [`persist()`]: #method.persist
+/// [`keep()`]: #method.keep
+#[derive(Debug)]
+pub struct TempFile<'a> {
+    dir: &'a Dir,
+    name: CString,
+    file: fs::File,
+}
+
+impl<'a> TempFile<'a> {
+    /// Get the name of the temporary file (within the directory it was created in).
+    #[inline]
+    pub fn name(&self) -> &OsStr {
+        OsStr::from_bytes(self.name.as_bytes())
+    }
+
+    /// Get a reference to the open file.
+    #[inline]
+    pub fn file(&self) -> &fs::File {
+        &self.file
+    }
+
+    /// Get a mutable reference to the open file.
+    #[inline]
+    pub fn file_mut(&mut self) -> &mut fs::File {
+        &mut self.file
+    }
+
+    /// Rename the temporary file to `path` (which is looked up relative to the directory the file
+    /// was created in), replacing any existing file at that path, and return the open file.
+    ///
+    /// Since any existing file is removed, this requires [`Rights::DELETE`] (in addition to
+    /// [`Rights::CREATE`]) on the directory. If this fails, the temporary file is removed.
+    ///
+    /// [`Rights::DELETE`]: ./struct.Rights.html#associatedconstant.DELETE
+    /// [`Rights::CREATE`]: ./struct.Rights.html#associatedconstant.CREATE
+    pub fn persist<P: AsPath>(self, path: P, lookup_flags: LookupFlags) -> io::Result<fs::File> {
+        self.dir.check_rights(Rights::CREATE | Rights::DELETE)?;
+
+        let (subdir, fname) = prepare_inner_operation(self.dir, path.as_path(), lookup_flags)?;
+
+        let fname = match fname {
+            Some(fname) => cstr(&fname)?,
+            None => return Err(io::Error::from_raw_os_error(libc::EISDIR)),
+        };
+
+        let new_fd = subdir.as_ref().unwrap_or(self.dir).as_raw_fd();
+
+        util::renameat(self.dir.as_raw_fd(), &self.name, new_fd, &fname)?;
+
+        Ok(self.into_parts().0)
+    }
+
+    /// Keep the temporary file (do not remove it when this is dropped), and return the open file
+    /// and its name.
+    pub fn keep(self) -> (fs::File, OsString) {
+        let (file, name) = self.into_parts();
+        (file, OsString::from_vec(name.into_bytes()))
+    }
+
+    fn into_parts(self) -> (fs::File, CString) {
+        let this = std::mem::ManuallyDrop::new(self);
+        // SAFETY: `this` is never dropped, so each field is moved out exactly once
+        unsafe { (std::ptr::read(&this.file), std::ptr::read(&this.name)) }
+    }
+}
+
+impl Drop for TempFile<'_> {
+    fn drop(&mut self) {
+        let _ = util::unlinkat(self.dir.as_raw_fd(), &self.name, false);
+    }
+}
+
+/// A temporary directory created with [`Dir::tempdir_in()`].
+///
+/// The directory and its contents are removed when this is dropped, unless [`keep()`] is called
+/// first.
+///
+/// [`Dir::tempdir_in()`]: ./struct.Dir.html#method.tempdir_in
+/// [`keep()`]: #method.keep
+#[derive(Debug)]
+pub struct TempDir<'a> {
+    parent: &'a Dir,
+    name: Option<CString>,
+    dir: Dir,
+}
+
+impl<'a> TempDir<'a> {
+    /// Get the name of the temporary directory (within the directory it was created in).
+    #[inline]
+    pub fn name(&self) -> &OsStr {
+        OsStr::from_bytes(self.name.as_ref().unwrap().as_bytes())
+    }
+
+    /// Get a reference to the open temporary directory.
+    #[inline]
+    pub fn dir(&self) -> &Dir {
+        &self.dir
+    }
+
+    /// Keep the temporary directory (do not remove it when this is dropped), and return its name.
+    pub fn keep(mut self) -> OsString {
+        OsString::from_vec(self.name.take().unwrap().into_bytes())
+    }
+}
+
+impl Drop for TempDir<'_> {
+    fn drop(&mut self) {
+        if let Some(name) = self.name.take() {
+            let _ = remove_all_at(self.parent.as_raw_fd(), &name, Some(FileType::Directory));
+        }
+    }
+}
+
+fn temp_name(prefix: &OsStr, suffix: &OsStr) -> io::Result<CString> {
+    if prefix.as_bytes().contains(&b'/') || suffix.as_bytes().contains(&b'/') {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+
+    let mut name = Vec::with_capacity(prefix.len() + suffix.len() + 16);
+    name.extend_from_slice(prefix.as_bytes());
+    name.extend_from_slice(format!("{:016x}", util::random_u64()).as_bytes());
+    name.extend_from_slice(suffix.as_bytes());
+
+    cstr(OsStr::from_bytes(&name))
+}
+
+impl Dir {
+    /// Create a new temporary file directly within this directory, with a random name built from
+    /// the given `prefix` and `suffix` (which may not contain slashes).
+    ///
+    /// The file is created with `O_EXCL` (so an existing file is never reused) and opened for
+    /// reading and writing. It is removed when the returned [`TempFile`] is dropped.
+    ///
+    /// Unlike the `tempfile` crate, this always operates relative to this directory, so the
+    /// temporary file is guaranteed to be created within it.
+    ///
+    /// [`TempFile`]: ./struct.TempFile.html
+    pub fn tempfile<P: AsRef<OsStr>, S: AsRef<OsStr>>(
+        &self,
+        prefix: P,
+        suffix: S,
+        mode: libc::mode_t,
+    ) -> io::Result<TempFile> {
+        self.check_rights(Rights::READ | Rights::WRITE | Rights::CREATE)?;
+
+        for _ in 0..MAX_ATTEMPTS {
+            let name = temp_name(prefix.as_ref(), suffix.as_ref())?;
+
+            match util::openat(
+                self.as_raw_fd(),
+                &name,
+                libc::O_RDWR | libc::O_CREAT | libc::O_EXCL | libc::O_NOFOLLOW,
+                mode,
+            ) {
+                Ok(file) => {
+                    return Ok(TempFile {
+                        dir: self,
+                        name,
+                        file,
+                    })
+                }
+                Err(e) if e.raw_os_error() == Some(libc::EEXIST) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(io::Error::from_raw_os_error(libc::EEXIST))
+    }
+
+    /// Create a new temporary directory directly within this directory, with a random name
+    /// starting with `prefix` (which may not contain slashes).
+    ///
+    /// The directory is created with mode 0o700. It (and all of its contents) are removed when the
+    /// returned [`TempDir`] is dropped.
+    ///
+    /// [`TempDir`]: ./struct.TempDir.html
+    pub fn tempdir_in<P: AsRef<OsStr>>(&self, prefix: P) -> io::Result<TempDir> {
+        self.check_rights(Rights::CREATE)?;
+
+        for _ in 0..MAX_ATTEMPTS {
+            let name = temp_name(prefix.as_ref(), OsStr::new(""))?;
+
+            match util::mkdirat(self.as_raw_fd(), &name, 0o700) {
+                Ok(()) => (),
+                Err(e) if e.raw_os_error() == Some(libc::EEXIST) => continue,
+                Err(e) => return Err(e),
+            }
+
+            let guard = TempDirGuard {
+                parent: self,
+                name: Some(name),
+            };
+
+            let dir = Dir::derived(
+                util::openat_raw(
+                    self.as_raw_fd(),
+                    guard.name.as_ref().unwrap(),
+                    constants::DIR_OPEN_FLAGS | libc::O_NOFOLLOW,
+                    0,
+                )?,
+                self,
+            );
+
+            return Ok(guard.into_temp_dir(dir));
+        }
+
+        Err(io::Error::from_raw_os_error(libc::EEXIST))
+    }
+}
+
+// Removes the directory if opening it fails
+struct TempDirGuard<'a> {
+    parent: &'a Dir,
+    name: Option<CString>,
+}
+
+impl<'a> TempDirGuard<'a> {
+    fn into_temp_dir(mut self, dir: Dir) -> TempDir<'a> {
+        TempDir {
+            parent: self.parent,
+            name: self.name.take(),
+            dir,
+        }
+    }
+}
+
+impl Drop for TempDirGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(name) = self.name.take() {
+            let _ = util::unlinkat(self.parent.as_raw_fd(), &name, true);
+        }
+    }
+}
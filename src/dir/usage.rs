@@ -0,0 +1,108 @@
+use std::collections::HashSet;
+use std::io;
+
+use crate::{AsPath, Dir, LookupFlags};
+
+use super::{FileType, Metadata, WalkErrorPolicy};
+
+/// The disk usage of a directory tree, as computed by [`Dir::disk_usage()`].
+///
+/// [`Dir::disk_usage()`]: ./struct.Dir.html#method.disk_usage
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct DiskUsage {
+    apparent_size: u64,
+    disk_size: u64,
+    files: u64,
+    dirs: u64,
+}
+
+impl DiskUsage {
+    fn add(&mut self, meta: &Metadata) {
+        self.apparent_size += meta.len();
+        // st_blocks is always in units of 512 bytes
+        self.disk_size += meta.blocks() * 512;
+
+        if meta.file_type() == FileType::Directory {
+            self.dirs += 1;
+        } else {
+            self.files += 1;
+        }
+    }
+
+    /// Get the total apparent size (i.e. the sum of the file lengths) of the tree, in bytes.
+    #[inline]
+    pub fn apparent_size(&self) -> u64 {
+        self.apparent_size
+    }
+
+    /// Get the total amount of disk space used by the tree, in bytes.
+    ///
+    /// This may be smaller than [`apparent_size()`] (for example, for sparse files), or larger
+    /// (because of block rounding).
+    ///
+    /// [`apparent_size()`]: #method.apparent_size
+    #[inline]
+    pub fn disk_size(&self) -> u64 {
+        self.disk_size
+    }
+
+    /// Get the number of non-directory entries (including symlinks) that were counted.
+    #[inline]
+    pub fn files(&self) -> u64 {
+        self.files
+    }
+
+    /// Get the number of directories that were counted (including the starting directory).
+    #[inline]
+    pub fn dirs(&self) -> u64 {
+        self.dirs
+    }
+}
+
+impl Dir {
+    /// Compute the disk usage of the directory tree rooted at `path` (similar to `du`).
+    ///
+    /// The tree is walked entirely through file descriptors, without following symlinks (each
+    /// symlink is counted as a file). If `dedup_hardlinks` is `true`, files with multiple hard
+    /// links within the tree are only counted once.
+    ///
+    /// Errors encountered while walking the tree (for example, a subdirectory that can't be read)
+    /// are returned immediately.
+    pub fn disk_usage<P: AsPath>(
+        &self,
+        path: P,
+        dedup_hardlinks: bool,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<DiskUsage> {
+        let mut usage = DiskUsage::default();
+        let mut seen = HashSet::new();
+
+        let start = self.sub_dir(path, lookup_flags)?;
+        usage.add(&start.self_metadata()?);
+
+        for entry in start
+            .walk()
+            .error_policy(WalkErrorPolicy::Abort)
+            .walk(".")?
+        {
+            let meta = match entry?.metadata() {
+                Ok(meta) => meta,
+                // Removed since the directory was read
+                Err(e) if e.raw_os_error() == Some(libc::ENOENT) => continue,
+                Err(e) => return Err(e),
+            };
+
+            if dedup_hardlinks
+                && meta.file_type() != FileType::Directory
+                && meta.nlink() > 1
+                && !seen.insert((meta.dev(), meta.ino()))
+            {
+                continue;
+            }
+
+            usage.add(&meta);
+        }
+
+        Ok(usage)
+    }
+}
@@ -0,0 +1,463 @@
+use std::collections::VecDeque;
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{constants, util, AsPath, Dir, LookupFlags};
+
+use super::{FileType, Metadata, ReadDirIter};
+
+/// The order in which a [`WalkIter`] visits the entries of a directory tree.
+///
+/// [`WalkIter`]: ./struct.WalkIter.html
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum WalkOrder {
+    /// Visit the contents of each directory immediately after the directory itself (the default).
+    ///
+    /// This keeps one directory stream open for each level of the tree currently being walked.
+    DepthFirst,
+    /// Visit every entry at a given depth before any of the entries at the next depth.
+    ///
+    /// This keeps a file descriptor open for every directory that has been found but not yet
+    /// listed, which may be a large number for wide trees.
+    BreadthFirst,
+}
+
+/// Specifies how a [`WalkIter`] handles errors encountered while walking a directory tree (for
+/// example, a subdirectory that cannot be opened or read).
+///
+/// [`WalkIter`]: ./struct.WalkIter.html
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum WalkErrorPolicy {
+    /// Yield the error from the iterator, then continue with the rest of the tree (the default).
+    Yield,
+    /// Silently skip the entry or directory that caused the error.
+    Skip,
+    /// Yield the error from the iterator, then stop walking.
+    Abort,
+}
+
+/// A struct that can be used to configure a recursive walk over a directory tree.
+///
+/// A `WalkOptions` struct can be created with [`Dir::walk()`].
+///
+/// [`Dir::walk()`]: ./struct.Dir.html#method.walk
+#[derive(Clone, Debug)]
+pub struct WalkOptions<'a> {
+    dir: &'a Dir,
+    order: WalkOrder,
+    max_depth: Option<usize>,
+    follow_symlinks: bool,
+    error_policy: WalkErrorPolicy,
+    lookup_flags: LookupFlags,
+}
+
+impl<'a> WalkOptions<'a> {
+    #[inline]
+    pub(crate) fn beneath(dir: &'a Dir) -> Self {
+        Self {
+            dir,
+            order: WalkOrder::DepthFirst,
+            max_depth: None,
+            follow_symlinks: false,
+            error_policy: WalkErrorPolicy::Yield,
+            lookup_flags: LookupFlags::empty(),
+        }
+    }
+
+    /// Set the order in which entries are visited.
+    ///
+    /// See [`WalkOrder`] for more information. (The default is `WalkOrder::DepthFirst`.)
+    ///
+    /// [`WalkOrder`]: ./enum.WalkOrder.html
+    #[inline]
+    pub fn order(&mut self, order: WalkOrder) -> &mut Self {
+        self.order = order;
+        self
+    }
+
+    /// Set the maximum depth of entries that will be yielded.
+    ///
+    /// The entries directly inside the starting directory have a depth of 1, so `max_depth(1)`
+    /// just lists the starting directory. `None` (the default) means there is no limit.
+    #[inline]
+    pub fn max_depth(&mut self, max_depth: Option<usize>) -> &mut Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Descend into symlinks that point to directories.
+    ///
+    /// Symlinks are resolved within the `Dir` that the walk was started from (subject to the
+    /// "lookup flags" specified with [`.lookup_flags()`]), so they can never lead outside of it.
+    /// Symlink loops are detected and reported as `ELOOP` errors.
+    ///
+    /// Regardless of this setting, symlinks are always yielded as entries with a file type of
+    /// `FileType::Symlink`. (By default, symlinks are not followed.)
+    ///
+    /// [`.lookup_flags()`]: #method.lookup_flags
+    #[inline]
+    pub fn follow_symlinks(&mut self, follow_symlinks: bool) -> &mut Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Set how errors encountered during the walk are handled.
+    ///
+    /// See [`WalkErrorPolicy`] for more information. (The default is `WalkErrorPolicy::Yield`.)
+    /// Errors resolving the starting directory are always returned directly from [`.walk()`].
+    ///
+    /// [`WalkErrorPolicy`]: ./enum.WalkErrorPolicy.html
+    /// [`.walk()`]: #method.walk
+    #[inline]
+    pub fn error_policy(&mut self, error_policy: WalkErrorPolicy) -> &mut Self {
+        self.error_policy = error_policy;
+        self
+    }
+
+    /// Set the "lookup flags" used when resolving the starting directory (and any symlinks that
+    /// are followed).
+    ///
+    /// See [`LookupFlags`] for more information. (By default, none of the "lookup flags" are
+    /// enabled.)
+    ///
+    /// [`LookupFlags`]: ./struct.LookupFlags.html
+    #[inline]
+    pub fn lookup_flags(&mut self, lookup_flags: LookupFlags) -> &mut Self {
+        self.lookup_flags = lookup_flags;
+        self
+    }
+
+    /// Start walking the directory tree rooted at `path` with the options specified by `self`.
+    ///
+    /// The starting directory itself is not yielded.
+    pub fn walk<P: AsPath>(&self, path: P) -> io::Result<WalkIter> {
+        let start = self.dir.sub_dir(path.as_path(), self.lookup_flags)?;
+
+        let (root, ancestors) = if self.follow_symlinks {
+            let ancestors = check_ancestors(&start, None)?;
+            (Some(self.dir.try_clone()?), ancestors)
+        } else {
+            (None, None)
+        };
+
+        let mut dirs = VecDeque::new();
+        if self.max_depth != Some(0) {
+            dirs.push_back(DirState {
+                dir: Arc::new(start),
+                iter: None,
+                path: PathBuf::new(),
+                depth: 1,
+                ancestors,
+            });
+        }
+
+        Ok(WalkIter {
+            root,
+            start_path: path.as_path().to_path_buf(),
+            dirs,
+            descend: None,
+            order: self.order,
+            max_depth: self.max_depth,
+            follow_symlinks: self.follow_symlinks,
+            error_policy: self.error_policy,
+            lookup_flags: self.lookup_flags,
+            done: false,
+        })
+    }
+}
+
+// Used to detect symlink loops when following symlinks
+#[derive(Debug)]
+struct Ancestor {
+    dev: u64,
+    ino: u64,
+    parent: Option<Arc<Ancestor>>,
+}
+
+fn check_ancestors(dir: &Dir, parent: Option<Arc<Ancestor>>) -> io::Result<Option<Arc<Ancestor>>> {
+    let meta = dir.self_metadata()?;
+
+    let mut cur = parent.as_deref();
+    while let Some(ancestor) = cur {
+        if ancestor.dev == meta.dev() && ancestor.ino == meta.ino() {
+            return Err(io::Error::from_raw_os_error(libc::ELOOP));
+        }
+        cur = ancestor.parent.as_deref();
+    }
+
+    Ok(Some(Arc::new(Ancestor {
+        dev: meta.dev(),
+        ino: meta.ino(),
+        parent,
+    })))
+}
+
+#[derive(Debug)]
+struct DirState {
+    dir: Arc<Dir>,
+    // Opened lazily, so that queued directories only hold one file descriptor
+    iter: Option<ReadDirIter>,
+    path: PathBuf,
+    // The depth of the entries in this directory
+    depth: usize,
+    ancestors: Option<Arc<Ancestor>>,
+}
+
+/// An iterator that recursively walks a directory tree.
+///
+/// This is created with [`WalkOptions::walk()`]. Subdirectories are opened relative to their
+/// parent directories' file descriptors (without following symlinks, unless
+/// [`WalkOptions::follow_symlinks()`] was enabled), so renaming or replacing directories during the
+/// walk cannot cause it to leave the tree.
+///
+/// [`WalkOptions::walk()`]: ./struct.WalkOptions.html#method.walk
+/// [`WalkOptions::follow_symlinks()`]: ./struct.WalkOptions.html#method.follow_symlinks
+#[derive(Debug)]
+pub struct WalkIter {
+    root: Option<Dir>,
+    start_path: PathBuf,
+    dirs: VecDeque<DirState>,
+    descend: Option<(WalkEntry, Option<Arc<Ancestor>>)>,
+    order: WalkOrder,
+    max_depth: Option<usize>,
+    follow_symlinks: bool,
+    error_policy: WalkErrorPolicy,
+    lookup_flags: LookupFlags,
+    done: bool,
+}
+
+impl WalkIter {
+    #[inline]
+    fn current(&mut self) -> Option<&mut DirState> {
+        match self.order {
+            WalkOrder::DepthFirst => self.dirs.back_mut(),
+            WalkOrder::BreadthFirst => self.dirs.front_mut(),
+        }
+    }
+
+    #[inline]
+    fn pop_current(&mut self) {
+        match self.order {
+            WalkOrder::DepthFirst => self.dirs.pop_back(),
+            WalkOrder::BreadthFirst => self.dirs.pop_front(),
+        };
+    }
+
+    fn handle_error(&mut self, err: io::Error) -> Option<io::Error> {
+        match self.error_policy {
+            WalkErrorPolicy::Yield => Some(err),
+            WalkErrorPolicy::Skip => None,
+            WalkErrorPolicy::Abort => {
+                self.done = true;
+                Some(err)
+            }
+        }
+    }
+
+    fn open_child(
+        &self,
+        entry: &WalkEntry,
+        ancestors: Option<Arc<Ancestor>>,
+    ) -> io::Result<Option<DirState>> {
+        let dir = if entry.ftype == FileType::Symlink {
+            // Resolve the symlink from the original directory, so that it can point anywhere
+            // within that directory
+            let root = self.root.as_ref().unwrap();
+
+            match root.sub_dir(self.start_path.join(&entry.path), self.lookup_flags) {
+                Ok(dir) => dir,
+
+                // Not a directory, or a dangling symlink
+                Err(e) if matches!(e.raw_os_error(), Some(libc::ENOTDIR) | Some(libc::ENOENT)) => {
+                    return Ok(None)
+                }
+
+                Err(e) => return Err(e),
+            }
+        } else {
+            Dir::derived(
+                util::openat_raw(
+                    entry.parent.fd,
+                    &entry.fname,
+                    constants::DIR_OPEN_FLAGS | libc::O_NOFOLLOW,
+                    0,
+                )?,
+                &entry.parent,
+            )
+        };
+
+        let ancestors = if self.follow_symlinks {
+            check_ancestors(&dir, ancestors)?
+        } else {
+            None
+        };
+
+        Ok(Some(DirState {
+            dir: Arc::new(dir),
+            iter: None,
+            path: entry.path.clone(),
+            depth: entry.depth + 1,
+            ancestors,
+        }))
+    }
+}
+
+impl Iterator for WalkIter {
+    type Item = io::Result<WalkEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.done {
+                return None;
+            }
+
+            if let Some((entry, ancestors)) = self.descend.take() {
+                match self.open_child(&entry, ancestors) {
+                    Ok(Some(state)) => self.dirs.push_back(state),
+                    Ok(None) => (),
+                    Err(e) => {
+                        if let Some(e) = self.handle_error(e) {
+                            return Some(Err(e));
+                        }
+                    }
+                }
+            }
+
+            let state = self.current()?;
+
+            if state.iter.is_none() {
+                match state.dir.list_self() {
+                    Ok(iter) => state.iter = Some(iter),
+                    Err(e) => {
+                        self.pop_current();
+                        match self.handle_error(e) {
+                            Some(e) => return Some(Err(e)),
+                            None => continue,
+                        }
+                    }
+                }
+            }
+
+            let dir_entry = match state.iter.as_mut().unwrap().next() {
+                Some(Ok(dir_entry)) => dir_entry,
+
+                Some(Err(e)) => {
+                    self.pop_current();
+                    match self.handle_error(e) {
+                        Some(e) => return Some(Err(e)),
+                        None => continue,
+                    }
+                }
+
+                None => {
+                    self.pop_current();
+                    continue;
+                }
+            };
+
+            let ftype = match dir_entry.file_type() {
+                Some(ftype) => ftype,
+
+                None => match dir_entry.metadata_in(&state.dir) {
+                    Ok(meta) => meta.file_type(),
+
+                    // Removed since the directory was read
+                    Err(e) if e.raw_os_error() == Some(libc::ENOENT) => continue,
+
+                    Err(e) => match self.handle_error(e) {
+                        Some(e) => return Some(Err(e)),
+                        None => continue,
+                    },
+                },
+            };
+
+            let entry = WalkEntry {
+                parent: state.dir.clone(),
+                fname: dir_entry.name_cstr().to_owned(),
+                path: state.path.join(dir_entry.name()),
+                depth: state.depth,
+                ftype,
+            };
+            let ancestors = state.ancestors.clone();
+
+            if !matches!(self.max_depth, Some(max_depth) if entry.depth >= max_depth)
+                && (ftype == FileType::Directory
+                    || (ftype == FileType::Symlink && self.follow_symlinks))
+            {
+                self.descend = Some((entry.clone(), ancestors));
+            }
+
+            return Some(Ok(entry));
+        }
+    }
+}
+
+/// An entry encountered while walking a directory tree with a [`WalkIter`].
+///
+/// [`WalkIter`]: ./struct.WalkIter.html
+#[derive(Clone, Debug)]
+pub struct WalkEntry {
+    parent: Arc<Dir>,
+    fname: CString,
+    path: PathBuf,
+    depth: usize,
+    ftype: FileType,
+}
+
+impl WalkEntry {
+    #[inline]
+    pub(super) fn prefix_path(&mut self, prefix: &Path) {
+        if !prefix.as_os_str().is_empty() {
+            self.path = prefix.join(&self.path);
+        }
+    }
+
+    /// Get the path of this entry, relative to the directory the walk was started from.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the name of this entry (the final component of [`path()`]).
+    ///
+    /// [`path()`]: #method.path
+    #[inline]
+    pub fn file_name(&self) -> &OsStr {
+        OsStr::from_bytes(self.fname.as_bytes())
+    }
+
+    /// Get the depth of this entry. Entries directly inside the starting directory have a depth
+    /// of 1.
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Get the type of this entry.
+    ///
+    /// Symlinks are always reported as `FileType::Symlink`, even if they were followed.
+    #[inline]
+    pub fn file_type(&self) -> FileType {
+        self.ftype
+    }
+
+    /// Get the directory that contains this entry.
+    ///
+    /// This can be used to safely operate on the entry itself; for example,
+    /// `entry.parent_dir().open_file().read(true).open(entry.file_name())`.
+    #[inline]
+    pub fn parent_dir(&self) -> &Dir {
+        &self.parent
+    }
+
+    /// Get the metadata for the file named by this entry.
+    ///
+    /// This method will not traverse symlinks.
+    #[inline]
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        Metadata::stat_at(self.parent.fd, &self.fname, libc::AT_SYMLINK_NOFOLLOW)
+    }
+}
@@ -0,0 +1,149 @@
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+
+use super::cstr;
+use crate::{constants, open_beneath, util, AsPath, Dir, LookupFlags, Rights};
+
+impl Dir {
+    /// Retrieve the value of the extended attribute `name` on the file with the given path.
+    ///
+    /// The specified file must be located within this directory. If the final component of the
+    /// path is a symlink, it will be followed (within this directory).
+    ///
+    /// **Note**: On macOS, the file has to be opened for reading in order to follow the symlink
+    /// safely, so this will fail with `EACCES` if the file is not readable. The same applies to
+    /// the other extended attribute methods.
+    ///
+    /// The extended attribute methods are only available on Linux, Android, and macOS.
+    pub fn get_xattr<P: AsPath, N: AsRef<OsStr>>(
+        &self,
+        path: P,
+        name: N,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<Vec<u8>> {
+        self.check_rights(Rights::READ)?;
+
+        let name = cstr(name.as_ref())?;
+        let file = self.open_xattr_ref(path, lookup_flags)?;
+
+        let mut buf = Vec::new();
+
+        loop {
+            let size = util::fgetxattr_any(file.as_raw_fd(), &name, &mut [])?;
+            buf.resize(size, 0);
+
+            match util::fgetxattr_any(file.as_raw_fd(), &name, &mut buf) {
+                Ok(n) => {
+                    buf.truncate(n);
+                    return Ok(buf);
+                }
+
+                // The value grew between the two calls; try again
+                Err(e) if e.raw_os_error() == Some(libc::ERANGE) => (),
+
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Set the value of the extended attribute `name` on the file with the given path, creating
+    /// the attribute if it does not exist.
+    ///
+    /// See [`get_xattr()`] for details on how the path is resolved. This is only available on
+    /// Linux, Android, and macOS.
+    ///
+    /// [`get_xattr()`]: #method.get_xattr
+    pub fn set_xattr<P: AsPath, N: AsRef<OsStr>>(
+        &self,
+        path: P,
+        name: N,
+        value: &[u8],
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        self.check_rights(Rights::SETATTR)?;
+
+        let name = cstr(name.as_ref())?;
+        let file = self.open_xattr_ref(path, lookup_flags)?;
+
+        util::fsetxattr_any(file.as_raw_fd(), &name, value)
+    }
+
+    /// List the names of the extended attributes set on the file with the given path.
+    ///
+    /// See [`get_xattr()`] for details on how the path is resolved. This is only available on
+    /// Linux, Android, and macOS.
+    ///
+    /// [`get_xattr()`]: #method.get_xattr
+    pub fn list_xattr<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<Vec<OsString>> {
+        self.check_rights(Rights::READ)?;
+
+        let file = self.open_xattr_ref(path, lookup_flags)?;
+
+        let mut buf = Vec::new();
+
+        loop {
+            let size = util::flistxattr_any(file.as_raw_fd(), &mut [])?;
+            buf.resize(size, 0);
+
+            match util::flistxattr_any(file.as_raw_fd(), &mut buf) {
+                Ok(n) => {
+                    buf.truncate(n);
+                    break;
+                }
+
+                // The list grew between the two calls; try again
+                Err(e) if e.raw_os_error() == Some(libc::ERANGE) => (),
+
+                Err(e) => return Err(e),
+            }
+        }
+
+        // The names are separated (and terminated) by NUL bytes
+        Ok(buf
+            .split(|&ch| ch == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| OsStr::from_bytes(name).to_os_string())
+            .collect())
+    }
+
+    /// Remove the extended attribute `name` from the file with the given path.
+    ///
+    /// See [`get_xattr()`] for details on how the path is resolved. This is only available on
+    /// Linux, Android, and macOS.
+    ///
+    /// [`get_xattr()`]: #method.get_xattr
+    pub fn remove_xattr<P: AsPath, N: AsRef<OsStr>>(
+        &self,
+        path: P,
+        name: N,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        self.check_rights(Rights::SETATTR)?;
+
+        let name = cstr(name.as_ref())?;
+        let file = self.open_xattr_ref(path, lookup_flags)?;
+
+        util::fremovexattr_any(file.as_raw_fd(), &name)
+    }
+
+    #[inline]
+    fn open_xattr_ref<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<fs::File> {
+        open_beneath(
+            self.as_raw_fd(),
+            path,
+            constants::REF_OPEN_FLAGS,
+            0,
+            lookup_flags,
+        )
+    }
+}
@@ -0,0 +1,356 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{FileType, Rights};
+
+/// A structured description of why opening a file beneath a directory failed.
+///
+/// Most functions in this crate return plain `std::io::Error`s, which only carry an error number.
+/// That makes it hard to tell, for example, whether `EXDEV` means that the path tried to escape
+/// the directory or that it crossed a mount point (with [`LookupFlags::NO_XDEV`]). This type is
+/// returned by [`OpenBeneath::open_at_detailed()`], and it distinguishes those cases and records
+/// which component of the path caused the failure (and how much of the path had been resolved
+/// before that).
+///
+/// It can be converted back into a `std::io::Error` (with the original error number) using
+/// `From`/`Into`.
+///
+/// [`LookupFlags::NO_XDEV`]: ./struct.LookupFlags.html#associatedconstant.NO_XDEV
+/// [`OpenBeneath::open_at_detailed()`]: ./struct.OpenBeneath.html#method.open_at_detailed
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The path tried to escape the directory (through `..`, an absolute path, or a symlink).
+    ///
+    /// This corresponds to `EXDEV`.
+    EscapeAttempt {
+        /// The part of the path that had been resolved before the failure.
+        resolved: PathBuf,
+        /// The component that would have escaped.
+        component: OsString,
+        /// The rest of the path, after `component`.
+        remaining: PathBuf,
+    },
+
+    /// A symlink was found, but it could not be followed (because of
+    /// [`LookupFlags::NO_SYMLINKS`] or [`LookupFlags::NO_MAGICLINKS`], because it was the final
+    /// component and `O_NOFOLLOW` was given, or because too many symlinks were encountered).
+    ///
+    /// This corresponds to `ELOOP`.
+    ///
+    /// [`LookupFlags::NO_SYMLINKS`]: ./struct.LookupFlags.html#associatedconstant.NO_SYMLINKS
+    /// [`LookupFlags::NO_MAGICLINKS`]: ./struct.LookupFlags.html#associatedconstant.NO_MAGICLINKS
+    SymlinkForbidden {
+        /// The part of the path that had been resolved before the failure.
+        resolved: PathBuf,
+        /// The symlink.
+        component: OsString,
+        /// The rest of the path, after `component`.
+        remaining: PathBuf,
+    },
+
+    /// The path crossed a mount point, and [`LookupFlags::NO_XDEV`] was given.
+    ///
+    /// This corresponds to `EXDEV`.
+    ///
+    /// [`LookupFlags::NO_XDEV`]: ./struct.LookupFlags.html#associatedconstant.NO_XDEV
+    CrossesMount {
+        /// The part of the path that had been resolved before the failure.
+        resolved: PathBuf,
+        /// The component that is on a different mount.
+        component: OsString,
+        /// The rest of the path, after `component`.
+        remaining: PathBuf,
+    },
+
+    /// A component of the path did not satisfy the policy set with
+    /// [`OpenBeneath::require_owner()`], [`OpenBeneath::forbid_world_writable()`], or
+    /// [`OpenBeneath::component_filter()`].
+    ///
+    /// This corresponds to `EPERM`.
+    ///
+    /// [`OpenBeneath::require_owner()`]: ./struct.OpenBeneath.html#method.require_owner
+    /// [`OpenBeneath::forbid_world_writable()`]: ./struct.OpenBeneath.html#method.forbid_world_writable
+    /// [`OpenBeneath::component_filter()`]: ./struct.OpenBeneath.html#method.component_filter
+    PolicyViolation {
+        /// The part of the path that had been resolved before the failure.
+        resolved: PathBuf,
+        /// The component that violated the policy.
+        component: OsString,
+        /// The rest of the path, after `component`.
+        remaining: PathBuf,
+    },
+
+    /// A race condition (usually, a directory being moved while `..` components were being
+    /// resolved) prevented safely resolving the path.
+    ///
+    /// This corresponds to `EAGAIN`.
+    RaceDetected {
+        /// The part of the path that had been resolved before the failure.
+        resolved: PathBuf,
+        /// The component that was being resolved.
+        component: OsString,
+        /// The rest of the path, after `component`.
+        remaining: PathBuf,
+    },
+
+    /// A component of the path does not exist.
+    ///
+    /// This corresponds to `ENOENT`.
+    NotFound {
+        /// The part of the path that had been resolved before the failure.
+        resolved: PathBuf,
+        /// The component that does not exist.
+        component: OsString,
+        /// The rest of the path, after `component`.
+        remaining: PathBuf,
+    },
+
+    /// Any other error that occurred while resolving a component of the path (for example,
+    /// `EACCES` or `ENOTDIR`).
+    Other {
+        /// The error.
+        error: io::Error,
+        /// The part of the path that had been resolved before the failure.
+        resolved: PathBuf,
+        /// The component that was being resolved.
+        component: OsString,
+        /// The rest of the path, after `component`.
+        remaining: PathBuf,
+    },
+
+    /// The operation would have listed the contents of a directory that was opened in
+    /// resolve-only mode (see [`DirOpenOptions::resolve_only()`]).
+    ///
+    /// This does not correspond to an error number; when converted into an `io::Error`, it has
+    /// the kind `PermissionDenied`, and the original `Error` can be retrieved with
+    /// `io::Error::get_ref()`.
+    ///
+    /// [`DirOpenOptions::resolve_only()`]: ./struct.DirOpenOptions.html#method.resolve_only
+    ResolveOnly,
+
+    /// The operation required rights that the directory does not have (see [`Dir::restrict()`]).
+    /// The missing rights are included.
+    ///
+    /// Like [`ResolveOnly`](#variant.ResolveOnly), this does not correspond to an error number;
+    /// when converted into an `io::Error`, it has the kind `PermissionDenied`.
+    ///
+    /// [`Dir::restrict()`]: ./struct.Dir.html#method.restrict
+    MissingRights(Rights),
+
+    /// The file that was opened was not of the type that was expected (see
+    /// [`OpenOptions::expect()`]).
+    ///
+    /// This does not correspond to an error number; when converted into an `io::Error`, it has
+    /// the kind `InvalidInput`.
+    ///
+    /// [`OpenOptions::expect()`]: ./struct.OpenOptions.html#method.expect
+    UnexpectedFileType {
+        /// The type that was expected.
+        expected: FileType,
+        /// The type of the file that was actually opened.
+        found: FileType,
+    },
+
+    /// Any other error, which did not occur while resolving a specific component.
+    Io(io::Error),
+}
+
+impl Error {
+    /// Build an `Error` from an `io::Error` that occurred while resolving `component`.
+    pub(crate) fn with_context(
+        err: io::Error,
+        resolved: PathBuf,
+        component: OsString,
+        remaining: PathBuf,
+        crossed_mount: bool,
+        policy_violation: bool,
+    ) -> Self {
+        match err.raw_os_error() {
+            Some(libc::EPERM) if policy_violation => Self::PolicyViolation {
+                resolved,
+                component,
+                remaining,
+            },
+            Some(libc::EXDEV) if crossed_mount => Self::CrossesMount {
+                resolved,
+                component,
+                remaining,
+            },
+            Some(libc::EXDEV) => Self::EscapeAttempt {
+                resolved,
+                component,
+                remaining,
+            },
+            Some(libc::ELOOP) => Self::SymlinkForbidden {
+                resolved,
+                component,
+                remaining,
+            },
+            Some(libc::EAGAIN) => Self::RaceDetected {
+                resolved,
+                component,
+                remaining,
+            },
+            Some(libc::ENOENT) => Self::NotFound {
+                resolved,
+                component,
+                remaining,
+            },
+            _ => Self::Other {
+                error: err,
+                resolved,
+                component,
+                remaining,
+            },
+        }
+    }
+
+    /// Get the error number corresponding to this error (if any).
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match self {
+            Self::EscapeAttempt { .. } | Self::CrossesMount { .. } => Some(libc::EXDEV),
+            Self::SymlinkForbidden { .. } => Some(libc::ELOOP),
+            Self::PolicyViolation { .. } => Some(libc::EPERM),
+            Self::RaceDetected { .. } => Some(libc::EAGAIN),
+            Self::NotFound { .. } => Some(libc::ENOENT),
+            Self::Other { error, .. } | Self::Io(error) => error.raw_os_error(),
+            Self::ResolveOnly | Self::MissingRights(_) | Self::UnexpectedFileType { .. } => None,
+        }
+    }
+
+    /// Returns `true` if this error indicates that the path was rejected for security reasons
+    /// (i.e. it is an `EscapeAttempt`, `SymlinkForbidden`, `CrossesMount`, or `PolicyViolation`
+    /// error), as opposed to an "ordinary" failure.
+    pub fn is_rejection(&self) -> bool {
+        matches!(
+            self,
+            Self::EscapeAttempt { .. }
+                | Self::SymlinkForbidden { .. }
+                | Self::CrossesMount { .. }
+                | Self::PolicyViolation { .. }
+        )
+    }
+
+    /// Get the path component that caused the failure (if known).
+    pub fn component(&self) -> Option<&OsStr> {
+        match self {
+            Self::EscapeAttempt { component, .. }
+            | Self::SymlinkForbidden { component, .. }
+            | Self::CrossesMount { component, .. }
+            | Self::PolicyViolation { component, .. }
+            | Self::RaceDetected { component, .. }
+            | Self::NotFound { component, .. }
+            | Self::Other { component, .. } => Some(component),
+            Self::ResolveOnly
+            | Self::MissingRights(_)
+            | Self::UnexpectedFileType { .. }
+            | Self::Io(_) => None,
+        }
+    }
+
+    /// Get the rest of the path after the component that caused the failure (if known).
+    pub fn remaining(&self) -> Option<&Path> {
+        match self {
+            Self::EscapeAttempt { remaining, .. }
+            | Self::SymlinkForbidden { remaining, .. }
+            | Self::CrossesMount { remaining, .. }
+            | Self::PolicyViolation { remaining, .. }
+            | Self::RaceDetected { remaining, .. }
+            | Self::NotFound { remaining, .. }
+            | Self::Other { remaining, .. } => Some(remaining),
+            Self::ResolveOnly
+            | Self::MissingRights(_)
+            | Self::UnexpectedFileType { .. }
+            | Self::Io(_) => None,
+        }
+    }
+
+    /// Get the part of the path that had been successfully resolved before the failure (if
+    /// known).
+    ///
+    /// This is the normalized path (relative to the starting directory) of the directory in which
+    /// [`component()`] was looked up: `.` and `..` components have been applied, and symlinks have
+    /// been expanded. If the failure occurred in the starting directory itself, this is empty.
+    ///
+    /// [`component()`]: #method.component
+    pub fn resolved(&self) -> Option<&Path> {
+        match self {
+            Self::EscapeAttempt { resolved, .. }
+            | Self::SymlinkForbidden { resolved, .. }
+            | Self::CrossesMount { resolved, .. }
+            | Self::PolicyViolation { resolved, .. }
+            | Self::RaceDetected { resolved, .. }
+            | Self::NotFound { resolved, .. }
+            | Self::Other { resolved, .. } => Some(resolved),
+            Self::ResolveOnly
+            | Self::MissingRights(_)
+            | Self::UnexpectedFileType { .. }
+            | Self::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EscapeAttempt { .. } => f.write_str("path escapes the directory")?,
+            Self::SymlinkForbidden { .. } => f.write_str("symlink not allowed")?,
+            Self::CrossesMount { .. } => f.write_str("path crosses a mount point")?,
+            Self::PolicyViolation { .. } => f.write_str("path component violates policy")?,
+            Self::RaceDetected { .. } => f.write_str("race condition detected")?,
+            Self::NotFound { .. } => f.write_str("no such file or directory")?,
+            Self::Other { error, .. } => error.fmt(f)?,
+            Self::ResolveOnly => return f.write_str("directory is resolve-only"),
+            Self::MissingRights(rights) => {
+                return write!(f, "directory is missing rights: {:?}", rights)
+            }
+            Self::UnexpectedFileType { expected, found } => {
+                return write!(f, "expected file type {:?}, found {:?}", expected, found)
+            }
+            Self::Io(error) => return error.fmt(f),
+        }
+
+        if let (Some(resolved), Some(component)) = (self.resolved(), self.component()) {
+            let resolved = if resolved.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                resolved
+            };
+            write!(f, " (at {:?} in {:?})", component, resolved)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Other { error, .. } | Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    #[inline]
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Other { error, .. } | Error::Io(error) => error,
+            Error::ResolveOnly | Error::MissingRights(_) => {
+                io::Error::new(io::ErrorKind::PermissionDenied, err)
+            }
+            Error::UnexpectedFileType { .. } => io::Error::new(io::ErrorKind::InvalidInput, err),
+            err => io::Error::from_raw_os_error(err.raw_os_error().unwrap()),
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Executing programs from within a directory without path-based races (Linux, Android, and
+//! FreeBSD only).
+//!
+//! The typical use case is a set-UID launcher that needs to run a binary from a tree that
+//! untrusted users can modify. Resolving the path and then calling `execve()` on it is racy; an
+//! attacker can swap in a symlink between the two steps. Instead, [`Executable::open_beneath()`]
+//! opens the binary beneath a [`Dir`] (with `O_PATH` on Linux/Android, and `O_EXEC` on FreeBSD),
+//! and [`Executable::exec()`] then runs it with `fexecve()`.
+//!
+//! [`Dir`]: ../struct.Dir.html
+//! [`Executable::open_beneath()`]: struct.Executable.html#method.open_beneath
+//! [`Executable::exec()`]: struct.Executable.html#method.exec
+
+use std::ffi::{CString, OsStr};
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+
+use crate::{open_beneath, AsPath, Dir, LookupFlags, Metadata, Rights};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const EXEC_OPEN_FLAGS: libc::c_int = libc::O_PATH;
+#[cfg(target_os = "freebsd")]
+const EXEC_OPEN_FLAGS: libc::c_int = libc::O_EXEC;
+
+fn cstring_vec<A: AsRef<OsStr>>(args: &[A]) -> io::Result<Vec<CString>> {
+    args.iter()
+        .map(|arg| Ok(CString::new(arg.as_ref().as_bytes())?))
+        .collect()
+}
+
+fn ptr_vec(args: &[CString]) -> Vec<*const libc::c_char> {
+    args.iter()
+        .map(|arg| arg.as_ptr())
+        .chain(std::iter::once(std::ptr::null()))
+        .collect()
+}
+
+/// An executable file that has been opened so it can later be run with `fexecve()`.
+#[derive(Debug)]
+pub struct Executable {
+    file: fs::File,
+}
+
+impl Executable {
+    /// Open the executable file at `path` beneath the given directory.
+    ///
+    /// The path is resolved exactly as for [`Dir::open_file()`], and the same `lookup_flags` are
+    /// supported. The file descriptor is opened with `O_CLOEXEC`.
+    ///
+    /// Note that this does not check that the file is actually executable; that happens when
+    /// [`exec()`] is called.
+    ///
+    /// [`Dir::open_file()`]: ../struct.Dir.html#method.open_file
+    /// [`exec()`]: #method.exec
+    pub fn open_beneath<P: AsPath>(
+        dir: &Dir,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<Self> {
+        dir.check_rights(Rights::READ)?;
+
+        let file = open_beneath(
+            dir.as_raw_fd(),
+            path,
+            EXEC_OPEN_FLAGS | libc::O_CLOEXEC,
+            0,
+            lookup_flags,
+        )?;
+
+        Ok(Self { file })
+    }
+
+    /// Retrieve metadata of the opened file.
+    ///
+    /// This can be used, for example, to check the owner or permission bits of the file before
+    /// executing it.
+    #[inline]
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        Metadata::stat_fd(self.file.as_raw_fd())
+    }
+
+    /// Replace the current process image with this executable, using `fexecve()`.
+    ///
+    /// `argv` and `envp` are passed as the argument vector and environment (each entry of `envp`
+    /// should be of the form `KEY=VALUE`). On success, this never returns; the returned error
+    /// describes why the execution failed.
+    ///
+    /// Because the file descriptor is close-on-exec, interpreter scripts (`#!`) cannot be run this
+    /// way; the kernel will fail with `ENOENT` when the interpreter tries to open the script.
+    pub fn exec<A: AsRef<OsStr>, E: AsRef<OsStr>>(&self, argv: &[A], envp: &[E]) -> io::Error {
+        let argv = match cstring_vec(argv) {
+            Ok(argv) => argv,
+            Err(e) => return e,
+        };
+        let envp = match cstring_vec(envp) {
+            Ok(envp) => envp,
+            Err(e) => return e,
+        };
+
+        let argv_ptrs = ptr_vec(&argv);
+        let envp_ptrs = ptr_vec(&envp);
+
+        unsafe {
+            libc::fexecve(
+                self.file.as_raw_fd(),
+                argv_ptrs.as_ptr(),
+                envp_ptrs.as_ptr(),
+            );
+        }
+
+        io::Error::last_os_error()
+    }
+}
+
+impl AsRawFd for Executable {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for Executable {
+    #[inline]
+    fn into_raw_fd(self) -> RawFd {
+        self.file.into_raw_fd()
+    }
+}
+
+impl From<Executable> for fs::File {
+    #[inline]
+    fn from(exe: Executable) -> Self {
+        exe.file
+    }
+}
@@ -0,0 +1,535 @@
+//! Safely extract archive entries beneath a directory.
+//!
+//! Archive formats such as tar and zip store entries with arbitrary paths, which can contain `..`
+//! components or be absolute; a naive extractor that joins these paths onto the destination
+//! directory can be tricked into overwriting files anywhere on the system. A more subtle attack
+//! uses a symlink entry (such as `a -> /etc`) followed by a file entry that is written through it
+//! (`a/passwd`).
+//!
+//! [`ExtractOptions`] writes entries beneath a [`Dir`], so (like every other operation in this
+//! crate) nothing can be created outside of it. On top of that, paths that try to escape are
+//! either rejected or clamped (see [`PathPolicy`]), symlink targets can be validated (see
+//! [`SymlinkPolicy`]), and by default symlinks are not followed at all while resolving entry
+//! paths.
+//!
+//! This module does not parse any archive format itself; the caller converts the entries of their
+//! archive library into [`ExtractEntry`]s:
+//!
+//! ```no_run
+//! # use obnth::Dir;
+//! # use obnth::extract::{EntryKind, ExtractEntry, ExtractOptions};
+//! let dir = Dir::open("/srv/upload").unwrap();
+//!
+//! let entries = vec![
+//!     ExtractEntry::new("a", EntryKind::Directory, 0o755),
+//!     ExtractEntry::new("a/file", EntryKind::File(&b"contents"[..]), 0o644),
+//!     ExtractEntry::new("a/link", EntryKind::Symlink("file".into()), 0o777),
+//! ];
+//!
+//! ExtractOptions::new().extract(&dir, entries.into_iter().map(Ok)).unwrap();
+//! ```
+//!
+//! [`ExtractOptions`]: ./struct.ExtractOptions.html
+//! [`Dir`]: ../struct.Dir.html
+//! [`PathPolicy`]: ./enum.PathPolicy.html
+//! [`SymlinkPolicy`]: ./enum.SymlinkPolicy.html
+//! [`ExtractEntry`]: ./struct.ExtractEntry.html
+
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::{util, Dir, LookupFlags};
+
+/// The type (and, for regular files, the contents) of an [`ExtractEntry`].
+///
+/// [`ExtractEntry`]: ./struct.ExtractEntry.html
+#[derive(Clone, Debug)]
+pub enum EntryKind<R> {
+    /// A directory.
+    Directory,
+    /// A regular file, whose contents will be read from the given reader.
+    File(R),
+    /// A symlink with the given target.
+    Symlink(PathBuf),
+}
+
+/// A single entry to be extracted with [`ExtractOptions`].
+///
+/// [`ExtractOptions`]: ./struct.ExtractOptions.html
+#[derive(Clone, Debug)]
+pub struct ExtractEntry<R> {
+    path: PathBuf,
+    kind: EntryKind<R>,
+    mode: libc::mode_t,
+    mtime: Option<SystemTime>,
+}
+
+impl<R> ExtractEntry<R> {
+    /// Create a new entry with the given path (as stored in the archive), type, and permission
+    /// bits.
+    ///
+    /// The mode of symlinks is ignored.
+    #[inline]
+    pub fn new<P: Into<PathBuf>>(path: P, kind: EntryKind<R>, mode: libc::mode_t) -> Self {
+        Self {
+            path: path.into(),
+            kind,
+            mode,
+            mtime: None,
+        }
+    }
+
+    /// Set the modification time of the entry.
+    ///
+    /// By default, the modification time is not changed (so it will be the time of extraction).
+    #[inline]
+    pub fn mtime(mut self, mtime: SystemTime) -> Self {
+        self.mtime = Some(mtime);
+        self
+    }
+
+    /// Get the path of the entry, as stored in the archive.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the type of the entry.
+    #[inline]
+    pub fn kind(&self) -> &EntryKind<R> {
+        &self.kind
+    }
+}
+
+/// How paths that are absolute or contain `..` components are handled.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum PathPolicy {
+    /// Fail with `EXDEV` if the path is absolute or contains any `..` components.
+    Reject,
+    /// Treat the destination directory as the root: leading slashes are stripped, and `..`
+    /// components are resolved lexically, but can never go above the destination directory (so
+    /// `../../a` is extracted to `a`). This mirrors `LookupFlags::IN_ROOT`.
+    Clamp,
+}
+
+/// How symlink entries are handled.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum SymlinkPolicy {
+    /// Create symlinks with any target.
+    ///
+    /// This is still safe with respect to this crate (symlinks are resolved beneath the
+    /// destination directory, so they cannot escape it), but other programs that access the
+    /// extracted files may follow them outside the directory.
+    Allow,
+    /// Only create symlinks whose targets are relative and (lexically) stay within the
+    /// destination directory; fail with `EXDEV` otherwise.
+    ///
+    /// Since earlier entries may have created other symlinks, `..` components are only allowed
+    /// at the start of the target (before any other components); otherwise a target like
+    /// `x/../..` could escape through a symlink `x` even though it lexically stays inside.
+    ///
+    /// If the [lookup flags] allow following symlinks, the directory that will contain the
+    /// symlink is resolved first, and the `..` components are checked against its actual
+    /// location (so `a -> .` followed by `a/l -> ..` is rejected).
+    ///
+    /// [lookup flags]: ./struct.ExtractOptions.html#method.lookup_flags
+    Contained,
+    /// Fail with `EPERM` for all symlink entries.
+    Reject,
+}
+
+/// Options for extracting archive entries beneath a directory.
+///
+/// See the [module-level documentation](./index.html) for more details.
+#[derive(Clone, Debug)]
+pub struct ExtractOptions {
+    path_policy: PathPolicy,
+    symlink_policy: SymlinkPolicy,
+    overwrite: bool,
+    mode_mask: libc::mode_t,
+    lookup_flags: LookupFlags,
+}
+
+impl ExtractOptions {
+    /// Create a new `ExtractOptions` with the default settings (`PathPolicy::Reject`,
+    /// `SymlinkPolicy::Contained`, no overwriting, a mode mask of `0o777`, and
+    /// `LookupFlags::NO_SYMLINKS`).
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            path_policy: PathPolicy::Reject,
+            symlink_policy: SymlinkPolicy::Contained,
+            overwrite: false,
+            mode_mask: 0o777,
+            lookup_flags: LookupFlags::NO_SYMLINKS,
+        }
+    }
+
+    /// Set how paths that are absolute or contain `..` components are handled (see
+    /// [`PathPolicy`]).
+    ///
+    /// [`PathPolicy`]: ./enum.PathPolicy.html
+    #[inline]
+    pub fn path_policy(&mut self, policy: PathPolicy) -> &mut Self {
+        self.path_policy = policy;
+        self
+    }
+
+    /// Set how symlink entries are handled (see [`SymlinkPolicy`]).
+    ///
+    /// [`SymlinkPolicy`]: ./enum.SymlinkPolicy.html
+    #[inline]
+    pub fn symlink_policy(&mut self, policy: SymlinkPolicy) -> &mut Self {
+        self.symlink_policy = policy;
+        self
+    }
+
+    /// Set whether existing files and symlinks should be replaced.
+    ///
+    /// If this is `false` (the default), extracting a file or symlink over an existing file fails
+    /// with `EEXIST`. If it is `true`, the existing file is removed first (it is never written
+    /// through, even if it is a symlink). Existing directories are never removed (a file or
+    /// symlink entry for an existing directory fails with `EISDIR`).
+    ///
+    /// Directory entries for existing directories are always merged into them.
+    #[inline]
+    pub fn overwrite(&mut self, overwrite: bool) -> &mut Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Set the mask that is applied to the mode of every file and directory entry.
+    ///
+    /// The default of `0o777` strips the set-user-ID, set-group-ID, and sticky bits. The masked
+    /// mode is applied exactly (it is not modified by the umask).
+    #[inline]
+    pub fn mode_mask(&mut self, mask: libc::mode_t) -> &mut Self {
+        self.mode_mask = mask;
+        self
+    }
+
+    /// Set the lookup flags used when resolving entry paths.
+    ///
+    /// The default is `LookupFlags::NO_SYMLINKS`, so symlinks (including ones created by earlier
+    /// entries in the same archive) are never followed. This may be relaxed if the destination
+    /// directory contains symlinks that entries should be extracted through.
+    #[inline]
+    pub fn lookup_flags(&mut self, lookup_flags: LookupFlags) -> &mut Self {
+        self.lookup_flags = lookup_flags;
+        self
+    }
+
+    /// Extract all of the given entries beneath `dir`, stopping at the first error.
+    ///
+    /// Missing parent directories are created as necessary. The permissions and modification
+    /// times of directory entries are applied after all other entries have been extracted, so
+    /// that read-only directories can still be populated and their modification times are not
+    /// changed by the entries created inside them.
+    pub fn extract<I, R>(&self, dir: &Dir, entries: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = io::Result<ExtractEntry<R>>>,
+        R: io::Read,
+    {
+        let mut deferred = Vec::new();
+
+        for entry in entries {
+            self.extract_inner(dir, entry?, &mut deferred)?;
+        }
+
+        self.finish_dirs(dir, deferred)
+    }
+
+    /// Extract a single entry beneath `dir`.
+    ///
+    /// This is equivalent to calling [`extract()`] with a single entry.
+    ///
+    /// [`extract()`]: #method.extract
+    pub fn extract_entry<R: io::Read>(&self, dir: &Dir, entry: ExtractEntry<R>) -> io::Result<()> {
+        let mut deferred = Vec::new();
+        self.extract_inner(dir, entry, &mut deferred)?;
+        self.finish_dirs(dir, deferred)
+    }
+
+    fn extract_inner<R: io::Read>(
+        &self,
+        dir: &Dir,
+        entry: ExtractEntry<R>,
+        deferred: &mut Vec<(PathBuf, libc::mode_t, Option<SystemTime>)>,
+    ) -> io::Result<()> {
+        let path = sanitize_path(&entry.path, self.path_policy)?;
+        let mode = entry.mode & self.mode_mask;
+
+        if path.as_os_str().is_empty() {
+            // The destination directory itself
+            return match entry.kind {
+                EntryKind::Directory => {
+                    deferred.push((PathBuf::from("."), mode, entry.mtime));
+                    Ok(())
+                }
+                _ => Err(io::Error::from_raw_os_error(libc::EISDIR)),
+            };
+        }
+
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                dir.create_dir_all(parent, 0o777, self.lookup_flags)?;
+            }
+        }
+
+        match entry.kind {
+            EntryKind::Directory => {
+                match dir.create_dir(&path, 0o700, self.lookup_flags) {
+                    Ok(()) => (),
+                    Err(e) if e.raw_os_error() == Some(libc::EEXIST) => {
+                        if !dir.is_dir(&path, self.lookup_flags)? {
+                            if !self.overwrite {
+                                return Err(e);
+                            }
+                            dir.remove_file(&path, self.lookup_flags)?;
+                            dir.create_dir(&path, 0o700, self.lookup_flags)?;
+                        }
+                    }
+                    Err(e) => return Err(e),
+                }
+
+                deferred.push((path, mode, entry.mtime));
+            }
+
+            EntryKind::File(mut reader) => {
+                self.remove_existing(dir, &path)?;
+
+                let mut file = dir
+                    .open_file()
+                    .write(true)
+                    .create_new(true)
+                    .mode(mode)
+                    .lookup_flags(self.lookup_flags)
+                    .open(&path)?;
+
+                // The umask may have interfered
+                util::fchmod(file.as_raw_fd(), mode)?;
+
+                io::copy(&mut reader, &mut file)?;
+
+                if let Some(mtime) = entry.mtime {
+                    let times = [
+                        util::timespec_from_systime(None)?,
+                        util::timespec_from_systime(Some(mtime))?,
+                    ];
+                    util::futimens(file.as_raw_fd(), &times)?;
+                }
+            }
+
+            EntryKind::Symlink(target) => {
+                match self.symlink_policy {
+                    SymlinkPolicy::Allow => (),
+                    SymlinkPolicy::Contained => {
+                        check_symlink_target(self.parent_depth(dir, &path)?, &target)?
+                    }
+                    SymlinkPolicy::Reject => return Err(io::Error::from_raw_os_error(libc::EPERM)),
+                }
+
+                if self.overwrite {
+                    dir.symlink_replace(&path, &target, self.lookup_flags)?;
+                } else {
+                    dir.symlink(&path, &target, self.lookup_flags)?;
+                }
+
+                if let Some(mtime) = entry.mtime {
+                    dir.set_times_nofollow(&path, None, Some(mtime), self.lookup_flags)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the number of directories between `dir` and the directory containing `path` (which
+    /// has been sanitized).
+    fn parent_depth(&self, dir: &Dir, path: &Path) -> io::Result<usize> {
+        if self.lookup_flags.contains(LookupFlags::NO_SYMLINKS) {
+            return Ok(path.components().count() - 1);
+        }
+
+        // The parent directories may be (or be reached through) symlinks, so the path can't be
+        // trusted; find out where the parent actually is
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => Ok(dir
+                .canonicalize(parent, self.lookup_flags)?
+                .components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .count()),
+            _ => Ok(0),
+        }
+    }
+
+    fn remove_existing(&self, dir: &Dir, path: &Path) -> io::Result<()> {
+        if !self.overwrite {
+            return Ok(());
+        }
+
+        match dir.remove_file(path, self.lookup_flags) {
+            Ok(()) => Ok(()),
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(()),
+            Err(e)
+                if e.raw_os_error() == Some(libc::EPERM)
+                    && dir.is_dir(path, self.lookup_flags)? =>
+            {
+                Err(io::Error::from_raw_os_error(libc::EISDIR))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn finish_dirs(
+        &self,
+        dir: &Dir,
+        deferred: Vec<(PathBuf, libc::mode_t, Option<SystemTime>)>,
+    ) -> io::Result<()> {
+        // Work from the innermost directories outward, so that changing the permissions of a
+        // parent can't prevent us from accessing its children
+        for (path, mode, mtime) in deferred.into_iter().rev() {
+            let subdir = dir.sub_dir(&path, self.lookup_flags)?;
+
+            util::fchmod_any(subdir.as_raw_fd(), mode)?;
+
+            if let Some(mtime) = mtime {
+                subdir.set_times_nofollow(".", None, Some(mtime), LookupFlags::empty())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ExtractOptions {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Normalize an entry path according to the given policy.
+///
+/// The returned path only contains normal components (it is empty if the path refers to the
+/// destination directory itself).
+fn sanitize_path(path: &Path, policy: PathPolicy) -> io::Result<PathBuf> {
+    let mut res = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => res.push(name),
+            Component::CurDir => (),
+
+            Component::RootDir | Component::Prefix(_) | Component::ParentDir
+                if policy == PathPolicy::Reject =>
+            {
+                return Err(io::Error::from_raw_os_error(libc::EXDEV))
+            }
+
+            Component::ParentDir => {
+                res.pop();
+            }
+            Component::RootDir | Component::Prefix(_) => (),
+        }
+    }
+
+    Ok(res)
+}
+
+/// Check that the target of a symlink in a directory `depth` levels below the destination
+/// directory is relative and stays within the destination directory.
+///
+/// `..` components are only accepted before any normal components, since a normal component
+/// could itself be a symlink (so going back up from it can't be checked lexically).
+fn check_symlink_target(mut depth: usize, target: &Path) -> io::Result<()> {
+    let mut seen_normal = false;
+
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => seen_normal = true,
+            Component::CurDir => (),
+            Component::ParentDir if depth > 0 && !seen_normal => depth -= 1,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(io::Error::from_raw_os_error(libc::EXDEV))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_path() {
+        for &(path, expected) in [("a/b", "a/b"), ("./a/./b/", "a/b"), ("", ""), (".", "")].iter() {
+            for &policy in [PathPolicy::Reject, PathPolicy::Clamp].iter() {
+                assert_eq!(
+                    sanitize_path(Path::new(path), policy).unwrap(),
+                    Path::new(expected)
+                );
+            }
+        }
+
+        for &(path, expected) in [
+            ("/a/b", "a/b"),
+            ("../a", "a"),
+            ("a/../../b", "b"),
+            ("a/b/../c", "a/c"),
+            ("/..", ""),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                sanitize_path(Path::new(path), PathPolicy::Clamp).unwrap(),
+                Path::new(expected)
+            );
+            assert_eq!(
+                sanitize_path(Path::new(path), PathPolicy::Reject)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some(libc::EXDEV)
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_symlink_target() {
+        for &(path, target) in [
+            ("link", "file"),
+            ("link", "./a/./b"),
+            ("a/link", ".."),
+            ("a/b/link", "../../c"),
+            ("a/link", "../a/b"),
+        ]
+        .iter()
+        {
+            check_symlink_target(Path::new(path).components().count() - 1, Path::new(target))
+                .unwrap();
+        }
+
+        for &(path, target) in [
+            ("link", ".."),
+            ("link", "/etc"),
+            ("a/link", "../.."),
+            ("a/link", "b/../../../c"),
+            // `x` may be a symlink
+            ("link", "x/.."),
+            ("a/link", "../x/../b"),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                check_symlink_target(Path::new(path).components().count() - 1, Path::new(target))
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some(libc::EXDEV)
+            );
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::ffi::OsStr;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::Metadata;
+
+type FilterFn = dyn Fn(&OsStr, &Metadata) -> bool + Send + Sync;
+
+/// A callback that decides whether each component of a path may be traversed or opened.
+///
+/// Set it with [`OpenBeneath::component_filter()`] (or [`OpenOptions::component_filter()`]). The
+/// callback is given the name of each component and its metadata, and returns `true` to allow it
+/// or `false` to reject it. This can be used, for example, to prevent a web server from serving
+/// anything inside `.git` directories or named `.env`, including when such a component is only
+/// reached through a symlink (which filtering the requested path before opening it would miss).
+///
+/// `ComponentFilter`s are cheap to clone (they are reference-counted), so a single filter can be
+/// shared by many builders.
+///
+/// [`OpenBeneath::component_filter()`]: ./struct.OpenBeneath.html#method.component_filter
+/// [`OpenOptions::component_filter()`]: ./struct.OpenOptions.html#method.component_filter
+#[derive(Clone)]
+pub struct ComponentFilter(Arc<FilterFn>);
+
+impl ComponentFilter {
+    /// Create a new filter that calls `f` to check every component.
+    #[inline]
+    pub fn new<F: Fn(&OsStr, &Metadata) -> bool + Send + Sync + 'static>(f: F) -> Self {
+        Self(Arc::new(f))
+    }
+
+    /// Create a filter that rejects every component whose name is one of `names`.
+    ///
+    /// ```
+    /// # use obnth::ComponentFilter;
+    /// let filter = ComponentFilter::deny_names(&[".git", ".env"]);
+    /// ```
+    pub fn deny_names<S: AsRef<OsStr>>(names: &[S]) -> Self {
+        let names: Vec<_> = names.iter().map(|s| s.as_ref().to_os_string()).collect();
+        Self::new(move |name, _| !names.iter().any(|n| n == name))
+    }
+
+    #[inline]
+    pub(crate) fn allows(&self, name: &OsStr, meta: &Metadata) -> bool {
+        (self.0)(name, meta)
+    }
+}
+
+impl fmt::Debug for ComponentFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ComponentFilter")
+            .field(&Arc::as_ptr(&self.0))
+            .finish()
+    }
+}
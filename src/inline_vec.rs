@@ -0,0 +1,158 @@
+use std::mem::MaybeUninit;
+
+/// A vector that stores up to `N` elements inline (on the stack) and only allocates if it grows
+/// beyond that.
+///
+/// This only supports the handful of operations the path resolver needs, and only `Copy` element
+/// types (so nothing ever needs to be dropped).
+pub enum InlineVec<T: Copy, const N: usize> {
+    Inline([MaybeUninit<T>; N], usize),
+    Heap(Vec<T>),
+}
+
+impl<T: Copy, const N: usize> InlineVec<T, N> {
+    #[inline]
+    pub fn new() -> Self {
+        Self::Inline([MaybeUninit::uninit(); N], 0)
+    }
+
+    /// Create a new `InlineVec` that can hold at least `capacity` elements without reallocating.
+    ///
+    /// This only allocates if `capacity > N`.
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> Self {
+        if capacity > N {
+            Self::Heap(Vec::with_capacity(capacity))
+        } else {
+            Self::new()
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Inline(_, len) => *len,
+            Self::Heap(vec) => vec.len(),
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if this vector has moved its contents to the heap.
+    #[allow(dead_code)]
+    #[inline]
+    pub fn spilled(&self) -> bool {
+        matches!(self, Self::Heap(_))
+    }
+
+    #[inline]
+    pub fn as_slice(&self) -> &[T] {
+        match self {
+            // SAFETY: The first `len` elements have been initialized
+            Self::Inline(buf, len) => unsafe {
+                std::slice::from_raw_parts(buf.as_ptr() as *const T, *len)
+            },
+            Self::Heap(vec) => vec,
+        }
+    }
+
+    /// Move the contents to the heap, reserving space for at least `additional` more elements.
+    #[cold]
+    fn spill(&mut self, additional: usize) {
+        let mut vec = Vec::with_capacity(self.len() + additional);
+        vec.extend_from_slice(self.as_slice());
+        *self = Self::Heap(vec);
+    }
+
+    pub fn extend_from_slice(&mut self, items: &[T]) {
+        match self {
+            Self::Inline(buf, len) if N - *len >= items.len() => {
+                for (slot, item) in buf[*len..].iter_mut().zip(items) {
+                    *slot = MaybeUninit::new(*item);
+                }
+                *len += items.len();
+            }
+
+            Self::Inline(..) => {
+                self.spill(items.len());
+                self.extend_from_slice(items);
+            }
+
+            Self::Heap(vec) => vec.extend_from_slice(items),
+        }
+    }
+
+    #[inline]
+    pub fn push(&mut self, item: T) {
+        self.extend_from_slice(std::slice::from_ref(&item));
+    }
+
+    #[inline]
+    pub fn pop(&mut self) -> Option<T> {
+        match self {
+            Self::Inline(_, 0) => None,
+            Self::Inline(buf, len) => {
+                *len -= 1;
+                // SAFETY: This element was initialized (it was within the first `len` elements)
+                Some(unsafe { buf[*len].assume_init() })
+            }
+            Self::Heap(vec) => vec.pop(),
+        }
+    }
+}
+
+impl<T: Copy, const N: usize> std::ops::Deref for InlineVec<T, N> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T: Copy + std::fmt::Debug, const N: usize> std::fmt::Debug for InlineVec<T, N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.as_slice()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inline_vec() {
+        let mut vec = InlineVec::<u8, 4>::new();
+        assert!(vec.is_empty());
+        assert!(!vec.spilled());
+
+        vec.extend_from_slice(b"abc");
+        vec.push(b'd');
+        assert_eq!(&vec[..], b"abcd");
+        assert!(!vec.spilled());
+
+        vec.push(b'e');
+        assert_eq!(&vec[..], b"abcde");
+        assert!(vec.spilled());
+
+        assert_eq!(vec.pop(), Some(b'e'));
+        assert_eq!(vec.len(), 4);
+
+        let mut vec = InlineVec::<u8, 4>::new();
+        vec.push(b'a');
+        assert_eq!(vec.pop(), Some(b'a'));
+        assert_eq!(vec.pop(), None);
+
+        assert!(!InlineVec::<u8, 4>::with_capacity(4).spilled());
+        assert!(InlineVec::<u8, 4>::with_capacity(5).spilled());
+
+        // With no inline capacity, it always uses the heap
+        let mut vec = InlineVec::<u8, 0>::new();
+        vec.push(b'a');
+        assert!(vec.spilled());
+        assert_eq!(&vec[..], b"a");
+    }
+}
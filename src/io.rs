@@ -0,0 +1,198 @@
+//! Zero-copy I/O helpers for serving files (requires the `net` feature).
+//!
+//! A typical consumer of `obnth` is a web server, which opens a file beneath a directory and then
+//! copies it to a socket. [`send_file()`] does that copy inside the kernel where possible
+//! (`sendfile()` on Linux/Android, FreeBSD, and macOS), and falls back on reading and writing
+//! through a buffer elsewhere (or when the kernel refuses, for example because the destination is
+//! not a socket on FreeBSD/macOS).
+
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::os::unix::prelude::*;
+
+/// The size of the buffer used when `sendfile()` is not available.
+const COPY_BUF_SIZE: usize = 64 * 1024;
+
+/// Send the bytes in `range` of `file` to `out` (usually a `TcpStream` or `UnixStream`), and
+/// return the number of bytes that were sent.
+///
+/// This does not use or change the file offset of `file`, so it can be used concurrently on a
+/// shared file descriptor (for example, to serve several HTTP range requests from the same open
+/// file).
+///
+/// Fewer bytes than requested are sent if the end of the file is reached first. If `out` is in
+/// non-blocking mode and would block, this returns the number of bytes sent so far (or fails
+/// with `WouldBlock` if nothing could be sent); the caller should wait until `out` is writable
+/// and then call this again with the rest of the range.
+///
+/// Fails with `EINVAL` if `range.start > range.end`.
+pub fn send_file<F: AsRawFd + ?Sized>(
+    file: &fs::File,
+    out: &F,
+    range: Range<u64>,
+) -> io::Result<u64> {
+    if range.start > range.end {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+
+    let mut sent = 0;
+
+    while sent < range.end - range.start {
+        let offset = range.start + sent;
+        let remaining = range.end - offset;
+
+        let res = match sendfile_once(file.as_raw_fd(), out.as_raw_fd(), offset, remaining) {
+            Some(res) => res,
+            None => return copy_range(file, out.as_raw_fd(), offset, remaining).map(|n| sent + n),
+        };
+
+        match res {
+            Ok(0) => break,
+            Ok(n) => sent += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock && sent > 0 => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(sent)
+}
+
+/// Try to send up to `len` bytes from `offset` in `fd` to `out_fd` with `sendfile()`.
+///
+/// Returns `None` if `sendfile()` cannot be used for these file descriptors (or on this platform)
+/// and no data has been sent.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn sendfile_once(fd: RawFd, out_fd: RawFd, offset: u64, len: u64) -> Option<io::Result<u64>> {
+    // Linux never transfers more than this at once
+    let count = std::cmp::min(len, 0x7fff_f000) as usize;
+    let mut off = offset as libc::off_t;
+
+    if unsafe { libc::sendfile(out_fd, fd, &mut off, count) } >= 0 {
+        Some(Ok(off as u64 - offset))
+    } else {
+        let err = io::Error::last_os_error();
+
+        match err.raw_os_error() {
+            Some(libc::EINVAL) | Some(libc::ENOSYS) => None,
+            _ => Some(Err(err)),
+        }
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+fn sendfile_once(fd: RawFd, out_fd: RawFd, offset: u64, len: u64) -> Option<io::Result<u64>> {
+    let mut sbytes = 0;
+
+    let ret = unsafe {
+        libc::sendfile(
+            fd,
+            out_fd,
+            offset as libc::off_t,
+            len as libc::size_t,
+            std::ptr::null_mut(),
+            &mut sbytes,
+            0,
+        )
+    };
+
+    // sendfile() may send some data and then fail with EAGAIN/EINTR
+    if ret == 0 || sbytes > 0 {
+        Some(Ok(sbytes as u64))
+    } else {
+        let err = io::Error::last_os_error();
+
+        match err.raw_os_error() {
+            Some(libc::ENOTSOCK) | Some(libc::EOPNOTSUPP) | Some(libc::EINVAL) => None,
+            _ => Some(Err(err)),
+        }
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn sendfile_once(fd: RawFd, out_fd: RawFd, offset: u64, len: u64) -> Option<io::Result<u64>> {
+    let mut nbytes = std::cmp::min(len, libc::off_t::MAX as u64) as libc::off_t;
+
+    let ret = unsafe {
+        libc::sendfile(
+            fd,
+            out_fd,
+            offset as libc::off_t,
+            &mut nbytes,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+
+    // sendfile() may send some data and then fail with EAGAIN/EINTR
+    if ret == 0 || nbytes > 0 {
+        Some(Ok(nbytes as u64))
+    } else {
+        let err = io::Error::last_os_error();
+
+        match err.raw_os_error() {
+            Some(libc::ENOTSOCK) | Some(libc::EOPNOTSUPP) | Some(libc::EINVAL) => None,
+            _ => Some(Err(err)),
+        }
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "ios",
+)))]
+#[inline]
+fn sendfile_once(_fd: RawFd, _out_fd: RawFd, _offset: u64, _len: u64) -> Option<io::Result<u64>> {
+    None
+}
+
+/// Copy up to `len` bytes from `offset` in `file` to `out_fd` through a buffer.
+fn copy_range(file: &fs::File, out_fd: RawFd, offset: u64, len: u64) -> io::Result<u64> {
+    let mut buf = vec![0; std::cmp::min(len, COPY_BUF_SIZE as u64) as usize];
+    let mut sent = 0;
+
+    while sent < len {
+        let want = std::cmp::min(len - sent, buf.len() as u64) as usize;
+
+        let n = match file.read_at(&mut buf[..want], offset + sent) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        let mut written = 0;
+        while written < n {
+            match unsafe {
+                libc::write(
+                    out_fd,
+                    buf[written..n].as_ptr() as *const libc::c_void,
+                    n - written,
+                )
+            } {
+                -1 => {
+                    let err = io::Error::last_os_error();
+
+                    if err.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    } else if err.kind() == io::ErrorKind::WouldBlock && sent + written as u64 > 0 {
+                        // The rest of the data we read will be read again by the next call
+                        return Ok(sent + written as u64);
+                    } else {
+                        return Err(err);
+                    }
+                }
+                0 => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                w => written += w as usize,
+            }
+        }
+
+        sent += n as u64;
+    }
+
+    Ok(sent)
+}
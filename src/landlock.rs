@@ -0,0 +1,164 @@
+//! Landlock integration (Linux only; requires the `landlock` feature).
+//!
+//! `obnth` confines path resolution in userspace. [Landlock] lets the kernel enforce a similar
+//! restriction, so that even code that doesn't go through `obnth` (or a bug in `obnth` itself)
+//! can't access files outside the given directories.
+//!
+//! [Landlock]: https://docs.kernel.org/userspace-api/landlock.html
+
+use std::io;
+use std::os::unix::prelude::*;
+
+use crate::{sys, Dir};
+
+bitflags::bitflags! {
+    /// Filesystem access rights that can be restricted by Landlock.
+    ///
+    /// See `landlock(7)` for a description of each right.
+    pub struct AccessFs: u64 {
+        /// Execute a file.
+        const EXECUTE = 1 << 0;
+        /// Open a file with write access.
+        const WRITE_FILE = 1 << 1;
+        /// Open a file with read access.
+        const READ_FILE = 1 << 2;
+        /// Open a directory or list its contents.
+        const READ_DIR = 1 << 3;
+        /// Remove an empty directory or rename one.
+        const REMOVE_DIR = 1 << 4;
+        /// Unlink or rename a file.
+        const REMOVE_FILE = 1 << 5;
+        /// Create (or rename/link) a character device.
+        const MAKE_CHAR = 1 << 6;
+        /// Create (or rename) a directory.
+        const MAKE_DIR = 1 << 7;
+        /// Create (or rename/link) a regular file.
+        const MAKE_REG = 1 << 8;
+        /// Create (or rename/link) a UNIX domain socket.
+        const MAKE_SOCK = 1 << 9;
+        /// Create (or rename/link) a named pipe.
+        const MAKE_FIFO = 1 << 10;
+        /// Create (or rename/link) a block device.
+        const MAKE_BLOCK = 1 << 11;
+        /// Create (or rename/link) a symbolic link.
+        const MAKE_SYM = 1 << 12;
+        /// Link or rename a file from or to a different directory (Landlock ABI 2+).
+        const REFER = 1 << 13;
+        /// Truncate a file (Landlock ABI 3+).
+        const TRUNCATE = 1 << 14;
+    }
+}
+
+impl AccessFs {
+    /// Get the set of access rights that are supported by the given Landlock ABI version.
+    pub fn for_abi(abi: u32) -> Self {
+        match abi {
+            0 => Self::empty(),
+            1 => Self::all() - Self::REFER - Self::TRUNCATE,
+            2 => Self::all() - Self::TRUNCATE,
+            _ => Self::all(),
+        }
+    }
+}
+
+/// Get the version of the Landlock ABI supported by the running kernel.
+///
+/// This fails with `ENOSYS` if the kernel was built without Landlock support, or `EOPNOTSUPP` if
+/// Landlock is supported but has been disabled.
+pub fn abi_version() -> io::Result<u32> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<sys::landlock_ruleset_attr>(),
+            0usize,
+            sys::LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res as u32)
+    }
+}
+
+/// Restrict the calling thread (and any threads or processes it later creates) so that it can
+/// only access files beneath the given directories.
+///
+/// This is equivalent to `restrict_to_access(dirs, AccessFs::all())`. See
+/// [`restrict_to_access()`] for details.
+///
+/// [`restrict_to_access()`]: fn.restrict_to_access.html
+#[inline]
+pub fn restrict_to(dirs: &[&Dir]) -> io::Result<()> {
+    restrict_to_access(dirs, AccessFs::all())
+}
+
+/// Restrict the calling thread (and any threads or processes it later creates) so that it can
+/// only perform the operations in `access` on files beneath the given directories.
+///
+/// Every access right supported by the running kernel is restricted; accesses outside of `dirs`,
+/// or accesses inside `dirs` that are not listed in `access`, will fail with `EACCES`. Rights that
+/// the running kernel does not support are silently ignored.
+///
+/// This sets the "no new privileges" flag on the calling thread (see `prctl(2)`), and it cannot
+/// be undone. Note that Landlock restrictions apply per-thread, so this should usually be called
+/// before any other threads are spawned.
+///
+/// If Landlock is unavailable, this fails with `ENOSYS` or `EOPNOTSUPP` (see [`abi_version()`]).
+///
+/// [`abi_version()`]: fn.abi_version.html
+pub fn restrict_to_access(dirs: &[&Dir], access: AccessFs) -> io::Result<()> {
+    let handled = AccessFs::for_abi(abi_version()?);
+
+    let attr = sys::landlock_ruleset_attr {
+        handled_access_fs: handled.bits(),
+    };
+
+    let ruleset_fd = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const sys::landlock_ruleset_attr,
+            std::mem::size_of::<sys::landlock_ruleset_attr>(),
+            0u32,
+        )
+    };
+    if ruleset_fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Make sure it gets closed
+    let ruleset = unsafe { OwnedFd::from_raw_fd(ruleset_fd as RawFd) };
+
+    let allowed = access & handled;
+    if !allowed.is_empty() {
+        for dir in dirs {
+            let rule = sys::landlock_path_beneath_attr {
+                allowed_access: allowed.bits(),
+                parent_fd: dir.as_raw_fd(),
+            };
+
+            if unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    ruleset.as_raw_fd(),
+                    sys::LANDLOCK_RULE_PATH_BENEATH,
+                    &rule as *const sys::landlock_path_beneath_attr,
+                    0u32,
+                )
+            } < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0u32) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
@@ -58,14 +58,62 @@
 //!   `obnth` works on Linux, macOS, and the BSDs (which necessitated certain differences in the
 //!   semantics).
 
+#[cfg(not(unix))]
+compile_error!("obnth currently only supports Unix-like systems (see the README for details)");
+
+#[cfg(all(
+    any(target_os = "haiku", target_os = "redox"),
+    not(feature = "portable-fallback")
+))]
+compile_error!("obnth requires the \"portable-fallback\" feature on Haiku and Redox");
+
 mod as_path;
+mod audit;
 mod constants;
 mod dir;
+mod error;
+mod filter;
+mod inline_vec;
 mod mntid;
 mod open;
+mod safe_path;
 mod sys;
 mod util;
 
+#[cfg(target_os = "freebsd")]
+pub mod capsicum;
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+pub mod exec;
+pub mod extract;
+#[cfg(feature = "net")]
+pub mod io;
+#[cfg(all(feature = "landlock", target_os = "linux"))]
+pub mod landlock;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub mod uring;
+#[cfg(all(
+    feature = "watch",
+    any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly",
+    )
+))]
+pub mod watch;
+
 pub use as_path::*;
+pub use audit::{AuditHook, ResolveEvent};
 pub use dir::*;
+pub use error::Error;
+pub use filter::ComponentFilter;
+#[cfg(feature = "mount-id")]
+pub use mntid::{mount_id, MountId};
 pub use open::*;
+pub use safe_path::{SafePath, SafePathError, SafePathPolicy, UnicodeNormalization};
@@ -4,9 +4,23 @@ use std::io::prelude::*;
 use std::mem::MaybeUninit;
 use std::os::unix::prelude::*;
 
+/// An identifier for a mount; see [`mount_id()`](./fn.mount_id.html).
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub struct MountId(u32);
 
+impl MountId {
+    /// Get the raw mount ID.
+    ///
+    /// This can be compared to the value returned by [`Metadata::mount_id()`].
+    ///
+    /// [`Metadata::mount_id()`]: ./struct.Metadata.html#method.mount_id
+    #[allow(dead_code)]
+    #[inline]
+    pub fn as_raw(&self) -> u64 {
+        self.0 as u64
+    }
+}
+
 #[inline]
 pub fn identify_mount(fd: RawFd) -> io::Result<MountId> {
     get_mnt_id(fd).map(MountId)
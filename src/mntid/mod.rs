@@ -7,3 +7,23 @@ cfg_if::cfg_if! {
         pub use unix::{MountId, identify_mount};
     }
 }
+
+/// Identify the mount containing the file referred to by `fd`.
+///
+/// This is the same check that is used to implement [`LookupFlags::NO_XDEV`]: two files are on
+/// the same mount if and only if their `MountId`s are equal. It can be used to implement other
+/// policies (for example, allowing specific bind mounts).
+///
+/// On Linux, this is the mount ID (as shown in `/proc/self/mountinfo`), so it can distinguish
+/// bind mounts of the same filesystem. (It may require `/proc` to be mounted on kernels older than
+/// 2.6.39, or if `name_to_handle_at()` is blocked by a seccomp rule.) On other platforms
+/// (including macOS, the BSDs, and Solaris/illumos), this is the ID of the device containing the
+/// file (`st_dev`), so bind mounts (or, on Solaris/illumos, loopback mounts) of the same
+/// filesystem cannot be distinguished.
+///
+/// [`LookupFlags::NO_XDEV`]: ./struct.LookupFlags.html#associatedconstant.NO_XDEV
+#[cfg(feature = "mount-id")]
+#[inline]
+pub fn mount_id(fd: std::os::unix::io::RawFd) -> std::io::Result<MountId> {
+    identify_mount(fd)
+}
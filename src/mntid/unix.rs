@@ -1,9 +1,19 @@
 use std::io;
 use std::os::unix::prelude::*;
 
+/// An identifier for a mount; see [`mount_id()`](./fn.mount_id.html).
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub struct MountId(libc::dev_t);
 
+impl MountId {
+    /// Get the raw mount ID (on this platform, the ID of the device containing the file).
+    #[allow(dead_code)]
+    #[inline]
+    pub fn as_raw(&self) -> u64 {
+        self.0 as u64
+    }
+}
+
 #[inline]
 pub fn identify_mount(fd: RawFd) -> io::Result<MountId> {
     let st = crate::util::fstat(fd)?;
@@ -1,12 +1,14 @@
-use std::borrow::Cow;
-use std::collections::VecDeque;
-use std::ffi::{CStr, CString};
+use std::ffi::{CStr, OsStr, OsString};
 use std::fs;
 use std::io;
 use std::os::unix::prelude::*;
-use std::path::{Component, Path};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::time::Instant;
 
-use crate::{constants, util, AsPath};
+use crate::inline_vec::InlineVec;
+use crate::mntid::MountId;
+use crate::{constants, util, AsPath, AuditHook, ComponentFilter, Error, Metadata, ResolveEvent};
 
 bitflags::bitflags! {
     /// Flags that modify path loookup when opening a file/directory beneath another directory.
@@ -29,6 +31,103 @@ bitflags::bitflags! {
         /// it's blocked by a seccomp rule) then this option may require `/proc` to be mounted to
         /// work reliably.
         const NO_XDEV = 0x04;
+
+        /// Fail with `ELOOP` if any "magic links" (such as `/proc/<pid>/fd/<fd>` or
+        /// `/proc/<pid>/exe` on Linux) are encountered during path resolution.
+        ///
+        /// Magic links never allow escaping the directory: if `openat2()` is used, the kernel is
+        /// always prevented from following them (so this flag has no additional effect), and
+        /// otherwise the contents of the link are resolved like any other symlink. However, the
+        /// contents of magic links are generally not meaningful paths, so it is usually better to
+        /// reject them outright.
+        ///
+        /// This is currently only meaningful on Linux and Android; other OSes do not have magic
+        /// links.
+        const NO_MAGICLINKS = 0x08;
+
+        /// Fail with `EXDEV` if any `..` components are encountered during path resolution
+        /// (either in the given path or in the target of a symlink).
+        ///
+        /// This is stricter than the default behavior (which allows `..` components as long as
+        /// they don't escape the directory) and [`IN_ROOT`](#associatedconstant.IN_ROOT) (which
+        /// clamps them at the directory).
+        const NO_DOTDOT = 0x10;
+
+        /// Only succeed if path resolution can be completed using the kernel's caches (without
+        /// blocking on I/O); otherwise, fail with `EAGAIN`.
+        ///
+        /// This is intended for use in asynchronous executors: try opening the file with this flag
+        /// on the current thread, and if it fails with `EAGAIN`, retry without it on a thread where
+        /// blocking is acceptable. Note that this only applies to path resolution; opening the
+        /// file itself may still block (for example, opening a FIFO without `O_NONBLOCK`).
+        ///
+        /// This is currently only supported on Linux 5.12+ (with the `openat2` feature), using
+        /// `openat2()`'s `RESOLVE_CACHED`. In all other cases (including if any of the other lookup
+        /// flags or options would prevent `openat2()` from being used, or `O_CREAT`, `O_TRUNC`, or
+        /// `O_TMPFILE` are specified) opening a file with this flag always fails with `EAGAIN`,
+        /// and [`OpenBeneath::retries()`] is ignored.
+        ///
+        /// [`OpenBeneath::retries()`]: ./struct.OpenBeneath.html#method.retries
+        const CACHED_ONLY = 0x20;
+
+        /// Refuse to follow symlinks in sticky world-writable directories (such as `/tmp`)
+        /// unless the owner of the symlink matches either the owner of the directory or the
+        /// effective UID of the current process; fail with `EACCES` instead.
+        ///
+        /// This mirrors Linux's `fs.protected_symlinks` sysctl, but it is applied even if that
+        /// sysctl is disabled (or on other OSes). It is mainly useful when resolving paths in
+        /// directories that other users can create files in (for example, shared upload
+        /// directories).
+        ///
+        /// The kernel has no way to perform these checks during path resolution, so if this
+        /// flag is specified, the fast paths (e.g. `openat2()`) are first tried without following
+        /// any symlinks, and if that fails because a symlink was encountered, the path is resolved
+        /// manually (which may be slower). This flag has no effect if
+        /// [`NO_SYMLINKS`](#associatedconstant.NO_SYMLINKS) is also specified.
+        const PROTECTED_SYMLINKS = 0x40;
+
+        /// Double-check the results of the fast paths (e.g. `openat2()`).
+        ///
+        /// If a file is opened using one of the kernel's path resolution facilities, the path is
+        /// resolved again with the manual resolver (which checks for escapes by walking back up
+        /// the directory tree, and with [`NO_XDEV`](#associatedconstant.NO_XDEV), checks the
+        /// mount of every component), and the two results are compared (including their mounts).
+        /// If they don't match (or the manual resolution fails), the file opened by the fast path
+        /// is discarded, and the file is opened again using only the manual resolver.
+        ///
+        /// Mismatches are counted by [`paranoid_mismatches()`](./fn.paranoid_mismatches.html).
+        ///
+        /// This is a "belt-and-suspenders" mode for high-assurance environments; it roughly
+        /// doubles the cost of opening files. It also ensures that the manual resolver is
+        /// exercised regularly, even on systems where the fast paths are always available.
+        ///
+        /// If `O_CREAT` or `O_TRUNC` is specified, opening the file could have side effects that
+        /// can't be undone if the results don't match, so only the manual resolver is used. Note
+        /// that the comparison is skipped for `O_TMPFILE` (which does not open the file at
+        /// `path`). With [`CACHED_ONLY`](#associatedconstant.CACHED_ONLY), opening a file always
+        /// fails with `EAGAIN`, since the manual resolver may block.
+        const PARANOID = 0x80;
+
+        /// Convert each component of the path to Unicode Normalization Form C before looking it
+        /// up.
+        ///
+        /// The same name can be encoded in several ways (for example, `é` can be a single code
+        /// point or an `e` followed by a combining accent), and most filesystems compare names
+        /// byte-for-byte. Since this is applied by the resolver, opening, inspecting, and creating
+        /// files with the same lookup flags will always agree on the name, regardless of how the
+        /// caller encoded it.
+        ///
+        /// Components that are not valid UTF-8 are left unchanged, as are the targets of any
+        /// symlinks encountered during resolution.
+        ///
+        /// This requires the `unicode-normalization` feature; without it, any operation given
+        /// this flag fails with `EOPNOTSUPP`. Specifying both this flag and
+        /// [`NORMALIZE_NFD`](#associatedconstant.NORMALIZE_NFD) fails with `EINVAL`.
+        const NORMALIZE_NFC = 0x100;
+
+        /// Like [`NORMALIZE_NFC`](#associatedconstant.NORMALIZE_NFC), but convert each component
+        /// to Normalization Form D (which is what HFS+ on macOS uses).
+        const NORMALIZE_NFD = 0x200;
     }
 }
 
@@ -43,6 +142,9 @@ bitflags::bitflags! {
 /// Currently, this will only return `true` on the following platforms:
 /// - Linux/Android
 /// - FreeBSD
+/// - Solaris/illumos
+///
+/// It always returns `false` if the `portable-fallback` feature is enabled.
 ///
 /// # Unix directory permissions background
 ///
@@ -93,6 +195,16 @@ pub fn has_o_search() -> bool {
 /// 3. The file will be opened with `O_CLOEXEC|O_NOCTTY`, so its close-on-exec flag will be set and
 ///    it cannot become the process's controlling terminal.
 ///
+/// On Linux 5.6+ (with the `openat2` feature) and FreeBSD 13+ (which supports
+/// `O_RESOLVE_BENEATH`), the kernel can usually perform path resolution directly; otherwise (or if
+/// `lookup_flags` contains options the kernel can't handle), the path is resolved manually one
+/// component at a time.
+///
+/// If `path` is already nul-terminated (for example, a `&CStr`), it can be passed directly to
+/// `openat2()` (or `openat()` with `O_RESOLVE_BENEATH`) without being copied. Otherwise, path
+/// resolution copies the path once (into a single buffer shared by all of its components), rather
+/// than allocating once per component.
+///
 /// [`LookupFlags`]: ./struct.LookupFlags.html
 ///
 /// # Errors
@@ -102,12 +214,21 @@ pub fn has_o_search() -> bool {
 /// - `ELOOP` if [`LookupFlags::NO_SYMLINKS`] is given and a component of the given `path` is a
 ///   symbolic link.
 /// - `EXDEV` if any of the other conditions required by the given [`LookupFlags`] are not met.
+/// - `EINVAL` (which has an `ErrorKind` of `InvalidInput`) if `flags` contains an invalid
+///   combination of flags: `O_CREAT` with `O_DIRECTORY`, `O_TRUNC` or `O_TMPFILE` without write
+///   access, or (on Linux) `O_PATH` with write access, `O_CREAT`, `O_EXCL`, `O_TRUNC`,
+///   `O_APPEND`, or `O_TMPFILE`. This is checked before any path resolution is done.
 /// - `EAGAIN` if a race condition occurred that prevented safely resolving the path. This usually
 ///   involves checking for escapes caused by `..` components.
 ///
-///   In this case it may be desirable to retry the call, though if possible it's recommended to
-///   limit the number of retries in order to prevent DOSes (intentional or accidental) by other
-///   programs.
+///   By default, the call will be retried automatically up to [`default_retries()`] times (0
+///   unless changed with [`set_default_retries()`]) before `EAGAIN` is returned. It's recommended
+///   to keep the number of retries low in order to prevent DOSes (intentional or accidental) by
+///   other programs.
+///
+/// [`default_retries()`]: ./fn.default_retries.html
+/// [`set_default_retries()`]: ./fn.set_default_retries.html
+#[inline]
 pub fn open_beneath<P: AsPath>(
     dir_fd: RawFd,
     path: P,
@@ -115,6 +236,323 @@ pub fn open_beneath<P: AsPath>(
     mode: libc::mode_t,
     lookup_flags: LookupFlags,
 ) -> io::Result<fs::File> {
+    open_beneath_impl(
+        dir_fd,
+        &path,
+        flags,
+        mode,
+        lookup_flags,
+        &ResolveOptions::default(),
+        default_retries(),
+    )
+}
+
+static DEFAULT_RETRIES: AtomicU32 = AtomicU32::new(0);
+
+/// Get the number of times that operations will be retried by default if they fail with `EAGAIN`.
+///
+/// See [`set_default_retries()`] for more information.
+///
+/// [`set_default_retries()`]: ./fn.set_default_retries.html
+#[inline]
+pub fn default_retries() -> u32 {
+    DEFAULT_RETRIES.load(Ordering::Relaxed)
+}
+
+/// Set the number of times that operations will be retried by default if they fail with `EAGAIN`
+/// (the initial value is 0).
+///
+/// Path resolution can fail with `EAGAIN` if a race condition occurred (usually, a directory was
+/// renamed while checking for escapes caused by `..` components). This applies to
+/// [`open_beneath()`] and to every method of [`Dir`] that resolves paths; it can be overridden
+/// for individual calls with [`OpenBeneath::retries()`].
+///
+/// [`open_beneath()`]: ./fn.open_beneath.html
+/// [`Dir`]: ./struct.Dir.html
+/// [`OpenBeneath::retries()`]: ./struct.OpenBeneath.html#method.retries
+#[inline]
+pub fn set_default_retries(retries: u32) {
+    DEFAULT_RETRIES.store(retries, Ordering::Relaxed);
+}
+
+static PARANOID_MISMATCHES: AtomicUsize = AtomicUsize::new(0);
+
+/// Get the number of times that a file opened by one of the fast paths (e.g. `openat2()`) with
+/// [`LookupFlags::PARANOID`] did not match the results of the manual resolver (so it was discarded
+/// and the file was opened again with the manual resolver).
+///
+/// This should always be 0; anything else indicates either a concurrent modification of the
+/// filesystem (for example, a file being renamed) or a bug in one of the fast paths, and may be
+/// worth logging or reporting.
+///
+/// [`LookupFlags::PARANOID`]: ./struct.LookupFlags.html#associatedconstant.PARANOID
+#[inline]
+pub fn paranoid_mismatches() -> usize {
+    PARANOID_MISMATCHES.load(Ordering::Relaxed)
+}
+
+/// Check whether the directory referred to by `candidate` is `root` or one of its subdirectories
+/// (at any depth).
+///
+/// This is useful for verifying file descriptors that were obtained from elsewhere (for example,
+/// received from another process over a Unix socket) before using them. It works by walking up
+/// the directory tree from `candidate` with `..` (the same way that escapes are detected while
+/// resolving paths), so it requires search permission on every directory between `candidate` and
+/// `root` (or, on Linux, none at all).
+///
+/// Note that the result only reflects the state of the filesystem at the time of the check; if a
+/// directory is later moved out of `root`, file descriptors open to it (or to its subdirectories)
+/// will no longer be beneath `root`.
+///
+/// # Errors
+///
+/// Fails with `ENOTDIR` if either `candidate` or `root` does not refer to a directory. (To check
+/// a file, check the directory it was opened beneath instead.)
+pub fn is_beneath<C: AsRawFd + ?Sized, R: AsRawFd + ?Sized>(
+    candidate: &C,
+    root: &R,
+) -> io::Result<bool> {
+    let root_stat = util::fstat(root.as_raw_fd())?;
+    let candidate_stat = util::fstat(candidate.as_raw_fd())?;
+
+    if root_stat.st_mode & libc::S_IFMT != libc::S_IFDIR
+        || candidate_stat.st_mode & libc::S_IFMT != libc::S_IFDIR
+    {
+        return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+    }
+
+    walk_beneath(candidate.as_raw_fd(), &root_stat)
+}
+
+/// Options for `do_open_beneath()` that can't be expressed with `LookupFlags`.
+///
+/// The fast paths (`openat2()`, etc.) can't honor most of these, so `open_beneath_once()` uses
+/// `do_open_beneath()` directly if any of them are set.
+#[derive(Clone, Copy, Debug, Default)]
+struct ResolveOptions<'a> {
+    max_symlinks: Option<u16>,
+    /// With `NO_XDEV`, mounts (other than the one containing the starting directory) that may be
+    /// crossed into
+    allowed_mounts: &'a [MountId],
+    /// If this time passes, resolution is aborted with `ETIMEDOUT`
+    deadline: Option<Instant>,
+    /// Every component must be owned by this user
+    required_owner: Option<libc::uid_t>,
+    /// No component may be world-writable
+    forbid_world_writable: bool,
+    /// Every component must be allowed by this filter
+    component_filter: Option<&'a ComponentFilter>,
+    /// Called for every component that is resolved
+    audit: Option<&'a AuditHook>,
+}
+
+impl ResolveOptions<'_> {
+    /// Fail with `ETIMEDOUT` if the deadline has passed.
+    #[inline]
+    fn check_deadline(&self) -> io::Result<()> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                Err(io::Error::from_raw_os_error(libc::ETIMEDOUT))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns `true` if a policy that every component must satisfy has been set.
+    #[inline]
+    fn has_policy(&self) -> bool {
+        self.required_owner.is_some()
+            || self.forbid_world_writable
+            || self.component_filter.is_some()
+    }
+
+    /// Fail with `EPERM` if the component named `name` (described by `st`) does not satisfy the
+    /// policy.
+    fn check_policy(&self, name: &CStr, st: &libc::stat) -> io::Result<()> {
+        // The permission bits of symlinks are meaningless
+        if matches!(self.required_owner, Some(uid) if uid != st.st_uid)
+            || (self.forbid_world_writable
+                && st.st_mode & libc::S_IFMT != libc::S_IFLNK
+                && st.st_mode & libc::S_IWOTH != 0)
+            || matches!(self.component_filter, Some(filter)
+                if !filter.allows(OsStr::from_bytes(name.to_bytes()), &Metadata::new(*st)))
+        {
+            return Err(io::Error::from_raw_os_error(libc::EPERM));
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if these options prevent using any of the fast paths.
+    fn requires_manual(&self, lookup_flags: LookupFlags) -> bool {
+        (matches!(self.max_symlinks, Some(n) if n > 0)
+            && !lookup_flags.contains(LookupFlags::NO_SYMLINKS))
+            || (!self.allowed_mounts.is_empty() && lookup_flags.contains(LookupFlags::NO_XDEV))
+            || self.has_policy()
+            || self.audit.is_some()
+    }
+}
+
+fn open_beneath_impl<P: AsPath + ?Sized>(
+    dir_fd: RawFd,
+    path: &P,
+    flags: libc::c_int,
+    mode: libc::mode_t,
+    lookup_flags: LookupFlags,
+    opts: &ResolveOptions,
+    mut retries: u32,
+) -> io::Result<fs::File> {
+    check_flags(flags)?;
+
+    if let Some(path) = crate::safe_path::normalize_components(path.as_path(), lookup_flags)? {
+        // Normalize once up front (instead of in each fast path)
+        return open_beneath_impl(
+            dir_fd,
+            &path,
+            flags,
+            mode,
+            lookup_flags - (LookupFlags::NORMALIZE_NFC | LookupFlags::NORMALIZE_NFD),
+            opts,
+            retries,
+        );
+    }
+
+    loop {
+        opts.check_deadline()?;
+
+        match open_beneath_once(dir_fd, path, flags, mode, lookup_flags, opts) {
+            Err(e)
+                if e.raw_os_error() == Some(libc::EAGAIN)
+                    && retries > 0
+                    && !lookup_flags.contains(LookupFlags::CACHED_ONLY) =>
+            {
+                retries -= 1
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Reject invalid combinations of open flags before doing any work.
+///
+/// The kernel would reject (or, worse, silently ignore) most of these, but it might only do so
+/// partway through path resolution, after directories have been opened (or created, with
+/// `O_CREAT`).
+pub(crate) fn check_flags(flags: libc::c_int) -> io::Result<()> {
+    let einval = || Err(io::Error::from_raw_os_error(libc::EINVAL));
+
+    let accmode = flags & libc::O_ACCMODE;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let is_tmpfile = flags & libc::O_TMPFILE == libc::O_TMPFILE;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let is_tmpfile = false;
+
+    // O_DIRECTORY|O_CREAT is ambiguous (older kernels create a regular file and then fail)
+    if flags & libc::O_CREAT == libc::O_CREAT && flags & libc::O_DIRECTORY == libc::O_DIRECTORY {
+        return einval();
+    }
+
+    if is_tmpfile && accmode == libc::O_RDONLY {
+        return einval();
+    }
+
+    // Truncating a file that's only open for reading is unspecified by POSIX (Linux does it)
+    if flags & libc::O_TRUNC == libc::O_TRUNC && accmode == libc::O_RDONLY {
+        return einval();
+    }
+
+    // Linux ignores everything except O_DIRECTORY, O_NOFOLLOW, and O_CLOEXEC with O_PATH
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if flags & libc::O_PATH == libc::O_PATH
+        && (accmode != libc::O_RDONLY
+            || is_tmpfile
+            || flags & (libc::O_CREAT | libc::O_EXCL | libc::O_TRUNC | libc::O_APPEND) != 0)
+    {
+        return einval();
+    }
+
+    Ok(())
+}
+
+fn open_beneath_once<P: AsPath + ?Sized>(
+    dir_fd: RawFd,
+    path: &P,
+    flags: libc::c_int,
+    mode: libc::mode_t,
+    mut lookup_flags: LookupFlags,
+    opts: &ResolveOptions,
+) -> io::Result<fs::File> {
+    if opts.max_symlinks == Some(0) {
+        lookup_flags |= LookupFlags::NO_SYMLINKS;
+    }
+
+    // The fast paths can't limit the number of symlinks that are followed (or allow crossing
+    // specific mounts)
+    if opts.requires_manual(lookup_flags) {
+        if lookup_flags.contains(LookupFlags::CACHED_ONLY) {
+            return Err(io::Error::from_raw_os_error(libc::EAGAIN));
+        }
+
+        return do_open_beneath(dir_fd, path.as_path(), flags, mode, lookup_flags, opts);
+    }
+
+    if lookup_flags.contains(LookupFlags::PARANOID) {
+        lookup_flags -= LookupFlags::PARANOID;
+
+        if lookup_flags.contains(LookupFlags::CACHED_ONLY) {
+            return Err(io::Error::from_raw_os_error(libc::EAGAIN));
+        }
+
+        // Creating or truncating the file can't be undone if the results don't match, so only
+        // use the manual resolver
+        if flags & (libc::O_CREAT | libc::O_TRUNC) == 0 {
+            let file = open_beneath_once(dir_fd, path, flags, mode, lookup_flags, opts)?;
+
+            if verify_paranoid(dir_fd, path.as_path(), &file, flags, lookup_flags) {
+                return Ok(file);
+            }
+
+            PARANOID_MISMATCHES.fetch_add(1, Ordering::Relaxed);
+            drop(file);
+        }
+
+        return do_open_beneath(dir_fd, path.as_path(), flags, mode, lookup_flags, opts);
+    }
+
+    // The fast paths can't check who owns the symlinks they follow. So try them without following
+    // any symlinks, and only fall back on manual resolution if that fails because of a symlink.
+    if lookup_flags.contains(LookupFlags::PROTECTED_SYMLINKS) {
+        lookup_flags -= LookupFlags::PROTECTED_SYMLINKS;
+
+        if !lookup_flags.contains(LookupFlags::NO_SYMLINKS) {
+            match open_beneath_once(
+                dir_fd,
+                path,
+                flags,
+                mode,
+                lookup_flags | LookupFlags::NO_SYMLINKS,
+                opts,
+            ) {
+                Err(e) if matches!(e.raw_os_error(), Some(libc::ELOOP) | Some(libc::ENOTDIR)) => (),
+                res => return res,
+            }
+
+            if lookup_flags.contains(LookupFlags::CACHED_ONLY) {
+                return Err(io::Error::from_raw_os_error(libc::EAGAIN));
+            }
+
+            return do_open_beneath(
+                dir_fd,
+                path.as_path(),
+                flags,
+                mode,
+                lookup_flags | LookupFlags::PROTECTED_SYMLINKS,
+                opts,
+            );
+        }
+    }
+
     #[cfg(all(feature = "openat2", target_os = "linux"))]
     if let Some(file) =
         path.with_cstr(|s| open_beneath_openat2(dir_fd, s, flags, mode, lookup_flags))?
@@ -122,6 +560,11 @@ pub fn open_beneath<P: AsPath>(
         return Ok(file);
     }
 
+    // Only openat2() can guarantee that path resolution won't block
+    if lookup_flags.contains(LookupFlags::CACHED_ONLY) {
+        return Err(io::Error::from_raw_os_error(libc::EAGAIN));
+    }
+
     // On macOS, if the O_NOFOLLOW_ANY flag is included, translate that to NO_SYMLINKS
     #[cfg(any(target_os = "macos", target_os = "ios"))]
     let (flags, lookup_flags) = if flags & crate::sys::O_NOFOLLOW_ANY == crate::sys::O_NOFOLLOW_ANY
@@ -141,27 +584,513 @@ pub fn open_beneath<P: AsPath>(
         return Ok(file);
     }
 
-    do_open_beneath(dir_fd, path.as_path(), flags, mode, lookup_flags)
+    #[cfg(target_os = "freebsd")]
+    if let Some(file) =
+        path.with_cstr(|s| open_beneath_resolve_beneath(dir_fd, s, flags, mode, lookup_flags))?
+    {
+        return Ok(file);
+    }
+
+    #[cfg(target_os = "freebsd")]
+    if let Some(file) =
+        path.with_cstr(|s| open_beneath_capmode(dir_fd, s, flags, mode, lookup_flags))?
+    {
+        return Ok(file);
+    }
+
+    do_open_beneath(dir_fd, path.as_path(), flags, mode, lookup_flags, opts)
+}
+
+/// For `LookupFlags::PARANOID`: resolve `path` again with the manual resolver, and check whether
+/// it refers to the same file (on the same mount) as `file`.
+fn verify_paranoid(
+    dir_fd: RawFd,
+    path: &Path,
+    file: &fs::File,
+    flags: libc::c_int,
+    lookup_flags: LookupFlags,
+) -> bool {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if flags & libc::O_TMPFILE == libc::O_TMPFILE {
+        return true;
+    }
+
+    let check = match do_open_beneath(
+        dir_fd,
+        path,
+        constants::REF_OPEN_FLAGS | (flags & (libc::O_NOFOLLOW | libc::O_DIRECTORY)),
+        0,
+        lookup_flags,
+        &ResolveOptions::default(),
+    ) {
+        Ok(check) => check,
+        Err(_) => return false,
+    };
+
+    match (
+        util::fstat(file.as_raw_fd()),
+        util::fstat(check.as_raw_fd()),
+    ) {
+        (Ok(st1), Ok(st2)) if util::samestat(&st1, &st2) => (),
+        _ => return false,
+    }
+
+    !lookup_flags.contains(LookupFlags::NO_XDEV)
+        || matches!(
+            (
+                crate::mntid::identify_mount(file.as_raw_fd()),
+                crate::mntid::identify_mount(check.as_raw_fd()),
+            ),
+            (Ok(m1), Ok(m2)) if m1 == m2
+        )
+}
+
+/// A builder for opening files beneath a directory.
+///
+/// This is a more extensible version of [`open_beneath()`]; it allows specifying additional
+/// options without needing to pass every argument explicitly. For example:
+///
+/// ```no_run
+/// # use obnth::{Dir, LookupFlags, OpenBeneath};
+/// let dir = Dir::open("/srv/www")?;
+/// let file = OpenBeneath::new("index.html")
+///     .lookup_flags(LookupFlags::NO_SYMLINKS)
+///     .open_at(&dir)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// [`open_beneath()`]: ./fn.open_beneath.html
+#[derive(Clone, Debug)]
+pub struct OpenBeneath<P> {
+    path: P,
+    flags: libc::c_int,
+    mode: libc::mode_t,
+    lookup_flags: LookupFlags,
+    dir_only: bool,
+    cloexec: bool,
+    max_symlinks: Option<u16>,
+    retries: Option<u32>,
+    allowed_mounts: Vec<MountId>,
+    deadline: Option<Instant>,
+    required_owner: Option<libc::uid_t>,
+    forbid_world_writable: bool,
+    component_filter: Option<ComponentFilter>,
+    audit: Option<AuditHook>,
+}
+
+impl<P: AsPath> OpenBeneath<P> {
+    /// Create a new builder that will open the given `path`.
+    ///
+    /// By default, the file will be opened read-only, with no lookup flags.
+    #[inline]
+    pub fn new(path: P) -> Self {
+        Self {
+            path,
+            flags: libc::O_RDONLY,
+            mode: 0,
+            lookup_flags: LookupFlags::empty(),
+            dir_only: false,
+            cloexec: true,
+            max_symlinks: None,
+            retries: None,
+            allowed_mounts: Vec::new(),
+            deadline: None,
+            required_owner: None,
+            forbid_world_writable: false,
+            component_filter: None,
+            audit: None,
+        }
+    }
+
+    /// Set the flags that will be used to open the file (e.g. `libc::O_RDWR | libc::O_CREAT`).
+    ///
+    /// See [`open_beneath()`] for more information.
+    ///
+    /// [`open_beneath()`]: ./fn.open_beneath.html
+    #[inline]
+    pub fn flags(&mut self, flags: libc::c_int) -> &mut Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Set the mode with which the file will be created (if `O_CREAT` or `O_TMPFILE` is passed).
+    #[inline]
+    pub fn mode(&mut self, mode: libc::mode_t) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Set the "lookup flags" used when opening the file.
+    ///
+    /// See [`LookupFlags`] for more information.
+    ///
+    /// [`LookupFlags`]: ./struct.LookupFlags.html
+    #[inline]
+    pub fn lookup_flags(&mut self, lookup_flags: LookupFlags) -> &mut Self {
+        self.lookup_flags = lookup_flags;
+        self
+    }
+
+    /// Require the path to refer to a directory (i.e. add `O_DIRECTORY` to the flags).
+    #[inline]
+    pub fn dir_only(&mut self, dir_only: bool) -> &mut Self {
+        self.dir_only = dir_only;
+        self
+    }
+
+    /// Set whether the returned file descriptor will have its close-on-exec flag set (the
+    /// default is `true`).
+    ///
+    /// See [`OpenOptions::cloexec()`] for more information.
+    ///
+    /// [`OpenOptions::cloexec()`]: ./struct.OpenOptions.html#method.cloexec
+    #[inline]
+    pub fn cloexec(&mut self, cloexec: bool) -> &mut Self {
+        self.cloexec = cloexec;
+        self
+    }
+
+    /// Set the maximum number of symlinks that may be followed while resolving the path (or
+    /// `None` to use the system default, which is usually 40).
+    ///
+    /// If more than this many symlinks are encountered, opening the file will fail with `ELOOP`.
+    /// `Some(0)` is equivalent to passing [`LookupFlags::NO_SYMLINKS`].
+    ///
+    /// Note that on Linux, specifying a limit (other than 0) prevents `openat2()` from being used,
+    /// since it does not support custom limits.
+    ///
+    /// [`LookupFlags::NO_SYMLINKS`]: ./struct.LookupFlags.html#associatedconstant.NO_SYMLINKS
+    #[inline]
+    pub fn max_symlinks(&mut self, max_symlinks: Option<usize>) -> &mut Self {
+        self.max_symlinks = max_symlinks.map(|n| n.min(u16::MAX as usize) as u16);
+        self
+    }
+
+    /// Set the number of times that opening the file will be retried if path resolution fails
+    /// with `EAGAIN` (or `None` to use [`default_retries()`]).
+    ///
+    /// [`default_retries()`]: ./fn.default_retries.html
+    #[inline]
+    pub fn retries(&mut self, retries: Option<u32>) -> &mut Self {
+        self.retries = retries;
+        self
+    }
+
+    /// With [`LookupFlags::NO_XDEV`], allow crossing into any of the given mounts (in addition to
+    /// the one containing the starting directory).
+    ///
+    /// For example, this can be used with a web root that intentionally contains a bind-mounted
+    /// directory: pass the [`MountId`] of that directory (obtained with [`mount_id()`]). Crossing
+    /// into any other mount still fails with `EXDEV`.
+    ///
+    /// This has no effect unless `NO_XDEV` is specified. If any mounts are given, `openat2()`
+    /// cannot be used on Linux.
+    ///
+    /// [`LookupFlags::NO_XDEV`]: ./struct.LookupFlags.html#associatedconstant.NO_XDEV
+    /// [`MountId`]: ./struct.MountId.html
+    /// [`mount_id()`]: ./fn.mount_id.html
+    #[cfg(feature = "mount-id")]
+    #[inline]
+    pub fn allow_mounts(&mut self, mounts: &[MountId]) -> &mut Self {
+        self.allowed_mounts = mounts.to_vec();
+        self
+    }
+
+    /// Set a deadline for resolving the path (or `None` for no deadline).
+    ///
+    /// If the deadline passes before the file has been opened, opening it fails with
+    /// `ETIMEDOUT`. The deadline is checked before every attempt (so an attacker who keeps
+    /// renaming directories cannot force [`retries()`] to run for an unbounded amount of time),
+    /// and between components when the path is resolved manually. Individual syscalls are not
+    /// interrupted, so opening the file may take slightly longer than the deadline (for example,
+    /// if a component is on a slow network filesystem).
+    ///
+    /// [`retries()`]: #method.retries
+    #[inline]
+    pub fn deadline(&mut self, deadline: Option<Instant>) -> &mut Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Require every component of the path to be owned by the given user (or `None` to not check
+    /// the owner).
+    ///
+    /// This applies to every directory that is traversed (including ones reached through `..`
+    /// components), every symlink that is followed, and the file that is finally opened. It does
+    /// not apply to the starting directory itself; use [`DirOpenOptions`] to verify that. If a
+    /// component is owned by another user, opening the file fails with `EPERM` (and
+    /// [`open_at_detailed()`] returns [`Error::PolicyViolation`]).
+    ///
+    /// Each component is checked after it has been opened, so the checks cannot be bypassed by
+    /// replacing it. If the final component already exists, it is also checked before it is
+    /// opened. With `O_TRUNC`, the file is only truncated after it has been opened and checked,
+    /// so a file that violates the policy is never truncated. With `O_CREAT`, if the final
+    /// component doesn't exist and `uid` is not the effective UID of the current process (which
+    /// would own the new file), opening it fails with `EPERM` instead of creating it.
+    ///
+    /// Setting a policy prevents the fast paths (such as `openat2()`) from being used.
+    ///
+    /// [`DirOpenOptions`]: ./struct.DirOpenOptions.html
+    /// [`open_at_detailed()`]: #method.open_at_detailed
+    /// [`Error::PolicyViolation`]: ./enum.Error.html#variant.PolicyViolation
+    #[inline]
+    pub fn require_owner(&mut self, uid: Option<libc::uid_t>) -> &mut Self {
+        self.required_owner = uid;
+        self
+    }
+
+    /// Refuse to traverse or open world-writable components (i.e. ones with the `S_IWOTH` bit set),
+    /// failing with `EPERM` instead.
+    ///
+    /// This applies to the same components as [`require_owner()`] (except symlinks, whose
+    /// permission bits are meaningless); see there for more details. If a new file is created
+    /// with `O_CREAT`, `S_IWOTH` is removed from its mode.
+    ///
+    /// [`require_owner()`]: #method.require_owner
+    #[inline]
+    pub fn forbid_world_writable(&mut self, forbid: bool) -> &mut Self {
+        self.forbid_world_writable = forbid;
+        self
+    }
+
+    /// Reject components that are not allowed by the given filter (or `None` to not filter
+    /// components), failing with `EPERM`.
+    ///
+    /// The filter is called with the name and metadata of the same components as
+    /// [`require_owner()`] (see there for more details). This includes the components of symlink
+    /// targets, so, for example, a filter that rejects `.git` also prevents opening `link/config`
+    /// if `link` is a symlink to `.git`. Components reached through `..` are passed to the filter
+    /// with the name `..`.
+    ///
+    /// Since the filter needs the metadata of every component, it cannot be applied to a file that
+    /// doesn't exist yet: if `O_CREAT` is specified and the final component does not exist,
+    /// opening the file fails with `EPERM` instead of creating it.
+    ///
+    /// Filters only apply to files opened with `OpenBeneath`; they are not applied by methods
+    /// that only take [`LookupFlags`] (such as [`Dir::resolve()`], [`Dir::canonicalize()`], or
+    /// the batched operations in the `uring` module).
+    ///
+    /// [`require_owner()`]: #method.require_owner
+    /// [`LookupFlags`]: ./struct.LookupFlags.html
+    /// [`Dir::resolve()`]: ./struct.Dir.html#method.resolve
+    /// [`Dir::canonicalize()`]: ./struct.Dir.html#method.canonicalize
+    #[inline]
+    pub fn component_filter(&mut self, filter: Option<ComponentFilter>) -> &mut Self {
+        self.component_filter = filter;
+        self
+    }
+
+    /// Set a hook that will be called for every step of path resolution (or `None` to remove
+    /// it).
+    ///
+    /// The hook receives a [`ResolveEvent`] for every component that is resolved (including `..`
+    /// components and the components of symlink targets), recording the flags that were used,
+    /// the target of the component if it was a symlink, and whether resolving it succeeded. This
+    /// makes it possible to audit exactly what the resolver did for a given path.
+    ///
+    /// The fast paths (such as `openat2()`) resolve the whole path inside the kernel, so setting
+    /// a hook forces the path to be resolved manually (which may be slower). As a result, opening
+    /// a file with [`LookupFlags::CACHED_ONLY`] always fails with `EAGAIN` if a hook is set.
+    ///
+    /// If opening the file is retried (see [`retries()`]), the hook sees the events for every
+    /// attempt. The hook is called synchronously, in the middle of path resolution; it should
+    /// not block for long.
+    ///
+    /// [`ResolveEvent`]: ./struct.ResolveEvent.html
+    /// [`LookupFlags::CACHED_ONLY`]: ./struct.LookupFlags.html#associatedconstant.CACHED_ONLY
+    /// [`retries()`]: #method.retries
+    #[inline]
+    pub fn audit(&mut self, hook: Option<AuditHook>) -> &mut Self {
+        self.audit = hook;
+        self
+    }
+
+    #[inline]
+    fn resolve_options(&self) -> ResolveOptions {
+        ResolveOptions {
+            max_symlinks: self.max_symlinks,
+            allowed_mounts: &self.allowed_mounts,
+            deadline: self.deadline,
+            required_owner: self.required_owner,
+            forbid_world_writable: self.forbid_world_writable,
+            component_filter: self.component_filter.as_ref(),
+            audit: self.audit.as_ref(),
+        }
+    }
+
+    /// Open the file beneath the directory referred to by `dir` (for example, a [`Dir`] or a
+    /// `std::fs::File`).
+    ///
+    /// [`Dir`]: ./struct.Dir.html
+    pub fn open_at<D: AsRawFd + ?Sized>(&self, dir: &D) -> io::Result<fs::File> {
+        self.open_at_fd(dir.as_raw_fd())
+    }
+
+    /// Open the file beneath the directory referred to by `dir`, returning a structured
+    /// [`Error`] on failure.
+    ///
+    /// This behaves identically to [`open_at()`], except that if path resolution fails, the
+    /// returned [`Error`] describes why (for example, whether an `EXDEV` error was caused by an
+    /// escape attempt or by crossing a mount point), which component of the path was
+    /// responsible, and how much of the path had been resolved before that. This information is
+    /// available for any error that occurs while resolving a component (including, for example,
+    /// `EACCES`).
+    ///
+    /// Opening the file is first attempted normally (so this is just as fast as [`open_at()`] if
+    /// it succeeds). If that fails, path resolution is repeated component-by-component in order
+    /// to determine where it failed.
+    ///
+    /// [`Error`]: ./enum.Error.html
+    /// [`open_at()`]: #method.open_at
+    pub fn open_at_detailed<D: AsRawFd + ?Sized>(&self, dir: &D) -> Result<fs::File, Error> {
+        let err = match self.open_at_fd(dir.as_raw_fd()) {
+            Ok(file) => return Ok(file),
+            Err(e) => e,
+        };
+
+        let mut flags = self.flags;
+        if self.dir_only {
+            flags |= libc::O_DIRECTORY;
+        }
+
+        // The flags were invalid, so path resolution never started; or repeating it might block
+        // (or would time out immediately)
+        if check_flags(flags).is_err()
+            || self.lookup_flags.contains(LookupFlags::CACHED_ONLY)
+            || err.raw_os_error() == Some(libc::ETIMEDOUT)
+        {
+            return Err(Error::Io(err));
+        }
+
+        // The hook already saw the failed attempt; don't report the repetition
+        let opts = ResolveOptions {
+            audit: None,
+            ..self.resolve_options()
+        };
+
+        // Only resolve the path; repeating the open itself could have side effects (such as
+        // creating or truncating the file, or blocking on a FIFO)
+        let mut failure = None;
+        let res = do_open_beneath_ctx(
+            dir.as_raw_fd(),
+            self.path.as_path(),
+            constants::REF_OPEN_FLAGS | (flags & (libc::O_NOFOLLOW | libc::O_DIRECTORY)),
+            0,
+            self.lookup_flags,
+            &opts,
+            Some(&mut failure),
+        );
+
+        match (res, failure) {
+            // The failure is no longer reproducible (maybe the filesystem was changed, or it was
+            // caused by opening the file rather than resolving the path); report the original
+            // error
+            (Ok(_), _) => Err(Error::Io(err)),
+            (Err(e), _) if e.raw_os_error() != err.raw_os_error() => Err(Error::Io(err)),
+
+            (Err(e), Some(failure)) => Err(Error::with_context(
+                e,
+                failure.resolved,
+                failure.component,
+                failure.remaining,
+                failure.crossed_mount,
+                failure.policy_violation,
+            )),
+
+            (Err(e), None) => Err(Error::Io(e)),
+        }
+    }
+
+    /// Open the file beneath the directory referred to by the file descriptor `dir_fd`.
+    pub fn open_at_fd(&self, dir_fd: RawFd) -> io::Result<fs::File> {
+        let mut flags = self.flags;
+        if self.dir_only {
+            flags |= libc::O_DIRECTORY;
+        }
+
+        let file = open_beneath_impl(
+            dir_fd,
+            &self.path,
+            flags,
+            self.mode,
+            self.lookup_flags,
+            &self.resolve_options(),
+            self.retries.unwrap_or_else(default_retries),
+        )?;
+
+        if !self.cloexec {
+            util::set_cloexec(file.as_raw_fd(), false)?;
+        }
+
+        Ok(file)
+    }
 }
 
 #[cfg(all(feature = "openat2", target_os = "linux"))]
 fn open_beneath_openat2(
     dir_fd: RawFd,
     path: &CStr,
-    mut flags: libc::c_int,
+    flags: libc::c_int,
     mode: libc::mode_t,
     lookup_flags: LookupFlags,
 ) -> io::Result<Option<fs::File>> {
+    let (path, how) = match prepare_openat2(dir_fd, path, flags, mode, lookup_flags)? {
+        Some(prepared) => prepared,
+        None => return Ok(None),
+    };
+
+    finish_openat2(
+        openat2_rs::openat2_cstr(Some(dir_fd), &path, &how),
+        lookup_flags,
+    )
+}
+
+/// Check whether `openat2()` can be used to open the given `path` with the given `lookup_flags`,
+/// and if so, return the (possibly modified) path and the `open_how` structure to pass to it.
+///
+/// If this returns `None`, the caller should fall back on `do_open_beneath()`.
+#[cfg(all(feature = "openat2", target_os = "linux"))]
+pub(crate) fn prepare_openat2(
+    dir_fd: RawFd,
+    path: &CStr,
+    mut flags: libc::c_int,
+    mode: libc::mode_t,
+    lookup_flags: LookupFlags,
+) -> io::Result<Option<(std::borrow::Cow<CStr>, openat2_rs::OpenHow)>> {
     if dir_fd == libc::AT_FDCWD {
         // An actual directory must be specified
         return Err(io::Error::from_raw_os_error(libc::EBADF));
     }
 
+    // The results need to be checked by the manual resolver anyway
+    if lookup_flags.contains(LookupFlags::PARANOID) {
+        return Ok(None);
+    }
+
+    // openat2() can't check who owns the symlinks it follows
+    if lookup_flags.contains(LookupFlags::PROTECTED_SYMLINKS)
+        && !lookup_flags.contains(LookupFlags::NO_SYMLINKS)
+    {
+        return Ok(None);
+    }
+
+    if lookup_flags.contains(LookupFlags::NO_DOTDOT) {
+        if has_dotdot_component(path.to_bytes()) {
+            return Err(io::Error::from_raw_os_error(libc::EXDEV));
+        } else if !lookup_flags.contains(LookupFlags::NO_SYMLINKS) {
+            // openat2() can't reject ".." components in symlink targets
+            return Ok(None);
+        }
+    }
+
     // Before we go any further, make sure the current kernel supports openat2()
     if !openat2_rs::has_openat2_cached() {
         return Ok(None);
     }
 
+    use std::borrow::Cow;
+    use std::ffi::CString;
+
     // If there's a trailing slash, strip it and add in O_DIRECTORY
     let path: Cow<CStr> = match path.to_bytes().split_last() {
         Some((b'/', rest)) if !rest.is_empty() => {
@@ -186,9 +1115,35 @@ fn open_beneath_openat2(
     if lookup_flags.contains(LookupFlags::NO_XDEV) {
         how.resolve |= openat2_rs::ResolveFlags::NO_XDEV;
     }
+    if lookup_flags.contains(LookupFlags::CACHED_ONLY) {
+        how.resolve |= openat2_rs::ResolveFlags::CACHED;
+    }
+
+    Ok(Some((path, how)))
+}
 
-    match openat2_rs::openat2_cstr(Some(dir_fd), &path, &how) {
+/// Interpret the result of calling `openat2()` with the path and `open_how` structure returned by
+/// `prepare_openat2()`.
+///
+/// If this returns `None`, the caller should fall back on `do_open_beneath()`.
+#[cfg(all(feature = "openat2", target_os = "linux"))]
+pub(crate) fn finish_openat2(
+    res: io::Result<RawFd>,
+    lookup_flags: LookupFlags,
+) -> io::Result<Option<fs::File>> {
+    match res {
         Ok(fd) => Ok(Some(unsafe { fs::File::from_raw_fd(fd) })),
+
+        // Kernels older than 5.12 reject RESOLVE_CACHED with EINVAL. (If the error was actually
+        // caused by something else, retrying without CACHED_ONLY will reveal that.)
+        Err(e)
+            if lookup_flags.contains(LookupFlags::CACHED_ONLY)
+                && matches!(e.raw_os_error(), Some(libc::E2BIG) | Some(libc::EINVAL)) =>
+        {
+            Err(io::Error::from_raw_os_error(libc::EAGAIN))
+        }
+        Err(e) if lookup_flags.contains(LookupFlags::CACHED_ONLY) => Err(e),
+
         // E2BIG means an unsupported extension was specified.
         // EAGAIN is returned from openat2() with RESOLVE_BENEATH or RESOLVE_IN_ROOT if any file is
         // renamed on the system. Fall back on the normal method if this happens.
@@ -205,21 +1160,20 @@ fn open_beneath_nofollow_any(
     mode: libc::mode_t,
     lookup_flags: LookupFlags,
 ) -> io::Result<Option<fs::File>> {
-    // We can only handle NO_SYMLINKS (possibly together with IN_ROOT)
-    if lookup_flags & !LookupFlags::IN_ROOT != LookupFlags::NO_SYMLINKS {
+    // We can only handle NO_SYMLINKS (possibly together with IN_ROOT; NO_MAGICLINKS is implied,
+    // and NO_DOTDOT is handled by falling back below)
+    if lookup_flags & !(LookupFlags::IN_ROOT | LookupFlags::NO_MAGICLINKS | LookupFlags::NO_DOTDOT)
+        != LookupFlags::NO_SYMLINKS
+    {
         return Ok(None);
     }
 
     // We cannot handle any ".." components
-    if path
-        .to_bytes()
-        .split(|&c| c == b'/')
-        .any(|part| part == b"..")
-    {
+    if has_dotdot_component(path.to_bytes()) {
         return Ok(None);
     }
 
-    use std::sync::atomic::{AtomicU8, Ordering};
+    use std::sync::atomic::AtomicU8;
     static HAS_NOFOLLOW_ANY: AtomicU8 = AtomicU8::new(2);
     match HAS_NOFOLLOW_ANY.load(Ordering::Relaxed) {
         0 => return Ok(None),
@@ -269,108 +1223,334 @@ fn open_beneath_nofollow_any(
     util::openat(dir_fd, path, flags | crate::sys::O_NOFOLLOW_ANY, mode).map(Some)
 }
 
-fn map_component_cstring(component: Component) -> io::Result<Cow<CStr>> {
-    Ok(match component {
-        Component::RootDir => Cow::Borrowed(unsafe { CStr::from_bytes_with_nul_unchecked(b"/\0") }),
-        Component::ParentDir => {
-            Cow::Borrowed(unsafe { CStr::from_bytes_with_nul_unchecked(b"..\0") })
-        }
+#[cfg(target_os = "freebsd")]
+fn open_beneath_resolve_beneath(
+    dir_fd: RawFd,
+    path: &CStr,
+    flags: libc::c_int,
+    mode: libc::mode_t,
+    lookup_flags: LookupFlags,
+) -> io::Result<Option<fs::File>> {
+    // O_RESOLVE_BENEATH has the same semantics as our default behavior, but there are no
+    // equivalents for the other lookup flags (and there are no magic links on FreeBSD)
+    if !(lookup_flags - LookupFlags::NO_MAGICLINKS).is_empty() {
+        return Ok(None);
+    }
 
-        Component::Normal(fname) => Cow::Owned(CString::new(fname.as_bytes())?),
+    if dir_fd == libc::AT_FDCWD {
+        // An actual directory must be specified
+        return Err(io::Error::from_raw_os_error(libc::EBADF));
+    }
+
+    use std::sync::atomic::AtomicU8;
+    static HAS_RESOLVE_BENEATH: AtomicU8 = AtomicU8::new(2);
+    match HAS_RESOLVE_BENEATH.load(Ordering::Relaxed) {
+        0 => return Ok(None),
+        1 => (),
 
-        // Filtered out earlier
-        Component::CurDir => unreachable!(),
+        _ => {
+            // Older versions of FreeBSD silently ignore unknown flags, so we have to check that
+            // it's actually honored. Opening an absolute path with O_RESOLVE_BENEATH should fail
+            // with ENOTCAPABLE.
+            if matches!(
+                util::openat(
+                    libc::AT_FDCWD,
+                    unsafe { CStr::from_bytes_with_nul_unchecked(b"/\0") },
+                    crate::sys::O_RESOLVE_BENEATH | libc::O_DIRECTORY | libc::O_RDONLY,
+                    0,
+                ),
+                Err(e) if e.raw_os_error() == Some(libc::ENOTCAPABLE),
+            ) {
+                // Supported
+                HAS_RESOLVE_BENEATH.store(1, Ordering::Relaxed);
+            } else {
+                // Not supported
+                HAS_RESOLVE_BENEATH.store(0, Ordering::Relaxed);
+                return Ok(None);
+            }
+        }
+    }
 
-        // This is a Unix-only crate
-        Component::Prefix(_) => unreachable!(),
-    })
+    match util::openat(dir_fd, path, flags | crate::sys::O_RESOLVE_BENEATH, mode) {
+        Ok(f) => Ok(Some(f)),
+        // Absolute paths and lookups that escape the directory fail with ENOTCAPABLE
+        Err(e) if e.raw_os_error() == Some(libc::ENOTCAPABLE) => {
+            Err(translate_enotcapable(dir_fd, path, flags, lookup_flags))
+        }
+        Err(e) => Err(e),
+    }
 }
 
-fn split_path(
-    path: &Path,
-    mut flags: libc::c_int,
-) -> io::Result<VecDeque<(Cow<CStr>, libc::c_int)>> {
-    if path.as_os_str().is_empty() {
-        return Err(io::Error::from_raw_os_error(libc::ENOENT));
+#[cfg(target_os = "freebsd")]
+fn open_beneath_capmode(
+    dir_fd: RawFd,
+    path: &CStr,
+    flags: libc::c_int,
+    mode: libc::mode_t,
+    lookup_flags: LookupFlags,
+) -> io::Result<Option<fs::File>> {
+    // In capability mode, the kernel refuses to let lookups escape the directory, so we can let
+    // it do all the work. It has no equivalent for the other lookup flags, though (and there are
+    // no magic links on FreeBSD).
+    if !crate::capsicum::in_capability_mode()
+        || !(lookup_flags - LookupFlags::NO_MAGICLINKS).is_empty()
+    {
+        return Ok(None);
     }
 
-    if path.as_os_str().as_bytes().ends_with(b"/") || path.as_os_str().as_bytes().ends_with(b"/.") {
-        flags |= libc::O_DIRECTORY;
+    match util::openat(dir_fd, path, flags, mode) {
+        Ok(f) => Ok(Some(f)),
+        // Absolute paths and lookups that escape the directory fail with ENOTCAPABLE
+        Err(e) if e.raw_os_error() == Some(libc::ENOTCAPABLE) => {
+            Err(translate_enotcapable(dir_fd, path, flags, lookup_flags))
+        }
+        Err(e) => Err(e),
     }
+}
 
-    let mut queue = VecDeque::new();
+/// Translate an `ENOTCAPABLE` error from opening `path` beneath `dir_fd` into `EXDEV` if it was
+/// caused by the path escaping the directory.
+///
+/// FreeBSD also fails with `ENOTCAPABLE` for other capability violations (for example, if
+/// `dir_fd` lacks the rights needed to open the file), so the path is resolved again with the
+/// manual resolver to check whether it actually escapes; if it doesn't, `ENOTCAPABLE` is returned
+/// as-is.
+#[cfg(target_os = "freebsd")]
+fn translate_enotcapable(
+    dir_fd: RawFd,
+    path: &CStr,
+    flags: libc::c_int,
+    lookup_flags: LookupFlags,
+) -> io::Error {
+    match do_open_beneath(
+        dir_fd,
+        Path::new(OsStr::from_bytes(path.to_bytes())),
+        constants::REF_OPEN_FLAGS | (flags & (libc::O_NOFOLLOW | libc::O_DIRECTORY)),
+        0,
+        lookup_flags,
+        &ResolveOptions::default(),
+    ) {
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => e,
+        _ => io::Error::from_raw_os_error(libc::ENOTCAPABLE),
+    }
+}
 
-    let mut it = path
-        .components()
-        .filter(|c| !matches!(c, Component::CurDir))
-        .peekable();
-    while let Some(component) = it.next() {
-        let component_flags = if it.peek().is_some() {
-            constants::DIR_OPEN_FLAGS
-        } else {
-            flags
+#[cfg(any(
+    all(feature = "openat2", target_os = "linux"),
+    target_os = "macos",
+    target_os = "ios"
+))]
+#[inline]
+fn has_dotdot_component(path: &[u8]) -> bool {
+    path.split(|&c| c == b'/').any(|part| part == b"..")
+}
+
+/// A single component of a path that is being resolved by `do_open_beneath()`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum PathPart {
+    Root,
+    Parent,
+    /// The start and end (exclusive, not including the nul terminator) of the component in the
+    /// `PathParts` buffer.
+    Normal(usize, usize),
+}
+
+/// The queue of path components that remain to be resolved by `do_open_beneath()`.
+///
+/// Rather than allocating a `CString` for every component, all of the "normal" components are
+/// stored (nul-terminated) in a single buffer, and the queue only stores their positions. As a
+/// result, resolving a path only requires a constant number of allocations (the buffer only needs
+/// to grow if symlinks are encountered). With the `inline-paths` feature, both the buffer and the
+/// queue are stored on the stack, so short paths can be resolved without allocating at all.
+///
+/// The queue is stored in reverse order (the next component is at the end), so components can be
+/// pushed onto and popped off of the front cheaply.
+#[derive(Debug)]
+struct PathParts {
+    buf: InlineVec<u8, { constants::INLINE_PATH_BYTES }>,
+    queue: InlineVec<(PathPart, libc::c_int), { constants::INLINE_PATH_COMPONENTS }>,
+}
+
+impl PathParts {
+    fn new(path: &[u8], flags: libc::c_int) -> io::Result<Self> {
+        let mut parts = Self {
+            buf: InlineVec::with_capacity(path.len() + 1),
+            queue: InlineVec::new(),
         };
 
-        queue.push_back((map_component_cstring(component)?, component_flags));
+        parts.push_front_path(path, flags, false)?;
+
+        Ok(parts)
     }
 
-    Ok(queue)
-}
+    #[inline]
+    fn pop_front(&mut self) -> Option<(PathPart, libc::c_int)> {
+        self.queue.pop()
+    }
 
-fn split_link_path_into(
-    path: &Path,
-    mut flags: libc::c_int,
-    queue: &mut VecDeque<(Cow<CStr>, libc::c_int)>,
-) -> io::Result<()> {
-    if path.as_os_str().is_empty() {
-        return Err(io::Error::from_raw_os_error(libc::ENOENT));
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
     }
 
-    if path.as_os_str().as_bytes().ends_with(b"/") || path.as_os_str().as_bytes().ends_with(b"/.") {
-        flags |= libc::O_DIRECTORY;
+    /// Get the path formed by the components that remain in the queue.
+    fn remaining(&self) -> PathBuf {
+        let mut path = PathBuf::new();
+
+        for &(part, _) in self.queue.iter().rev() {
+            path.push(OsStr::from_bytes(self.get(part).to_bytes()));
+        }
+
+        path
     }
 
-    for (i, component) in path
-        .components()
-        .filter(|c| !matches!(c, Component::CurDir))
-        .rev()
-        .enumerate()
-    {
-        let component_flags = if i == 0 {
-            flags
-        } else {
-            constants::DIR_OPEN_FLAGS
-        };
+    fn get(&self, part: PathPart) -> &CStr {
+        match part {
+            PathPart::Root => unsafe { CStr::from_bytes_with_nul_unchecked(b"/\0") },
+            PathPart::Parent => unsafe { CStr::from_bytes_with_nul_unchecked(b"..\0") },
+            PathPart::Normal(start, end) => unsafe {
+                CStr::from_bytes_with_nul_unchecked(&self.buf[start..=end])
+            },
+        }
+    }
 
-        // We need a CString because the given `path` might go out of scope before this is used
-        // again
-        let component = Cow::Owned(map_component_cstring(component)?.into_owned());
+    fn push_normal(&mut self, component: &[u8], flags: libc::c_int) {
+        let start = self.buf.len();
+        self.buf.extend_from_slice(component);
+        self.buf.push(0);
 
-        queue.push_front((component, component_flags));
+        self.queue
+            .push((PathPart::Normal(start, start + component.len()), flags));
     }
 
-    if queue.is_empty() {
-        // We remove CurDir elements when splitting the paths. This has the consequence that if the
-        // last element in the path is a symbolic link pointing to ".", nothing will get added to
-        // `queue` with the corresponding flags, so they will not be properly honored (just opened
-        // with DIR_OPEN_FLAGS).
-        // It's an edge case, but it could happen.
+    /// Split the given `path` into components and add them to the front of the queue.
+    ///
+    /// The last component is given the specified `flags`; all of the others are given
+    /// `DIR_OPEN_FLAGS`. `is_link` should be `true` if `path` is the target of a symlink.
+    fn push_front_path(
+        &mut self,
+        path: &[u8],
+        mut flags: libc::c_int,
+        is_link: bool,
+    ) -> io::Result<()> {
+        if path.is_empty() {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        } else if path.contains(&0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "path contained an interior nul byte",
+            ));
+        }
+
+        if path.ends_with(b"/") || path.ends_with(b"/.") {
+            flags |= libc::O_DIRECTORY;
+        }
 
-        debug_assert_eq!(path, Path::new("."));
+        let old_len = self.queue.len();
+        let mut component_flags = flags;
 
-        queue.push_front((
-            Cow::Borrowed(unsafe { CStr::from_bytes_with_nul_unchecked(b".\0") }),
-            flags,
-        ));
+        // Walk through the components in reverse so we can push them onto the front of the queue
+        for component in path.rsplit(|&c| c == b'/') {
+            match component {
+                b"" | b"." => continue,
+                b".." => self.queue.push((PathPart::Parent, component_flags)),
+                _ => self.push_normal(component, component_flags),
+            }
+
+            component_flags = constants::DIR_OPEN_FLAGS;
+        }
+
+        if path[0] == b'/' {
+            self.queue.push((PathPart::Root, component_flags));
+        }
+
+        if is_link && self.queue.len() == old_len {
+            // We remove "." elements when splitting the paths. This has the consequence that if the
+            // last element in the path is a symbolic link pointing to ".", nothing will get added
+            // to the queue with the corresponding flags, so they will not be properly honored
+            // (just opened with DIR_OPEN_FLAGS).
+            // It's an edge case, but it could happen.
+            self.push_normal(b".", flags);
+        }
+
+        Ok(())
+    }
+}
+
+/// If `lookup_flags` contains `NO_MAGICLINKS`, check that a symlink found in the directory
+/// referred to by `parent_fd` is not a "magic link", and fail with `ELOOP` if it is.
+pub(crate) fn check_magiclink(parent_fd: RawFd, lookup_flags: LookupFlags) -> io::Result<()> {
+    if !lookup_flags.contains(LookupFlags::NO_MAGICLINKS) {
+        return Ok(());
+    }
+
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "linux", target_os = "android"))] {
+            const PROC_SUPER_MAGIC: libc::c_long = 0x9fa0;
+            const PROC_ROOT_INO: u64 = 1;
+
+            // All of the magic links live on procfs, in subdirectories of the root (for example,
+            // /proc/<pid> or /proc/<pid>/fd). The only symlinks in the root itself (/proc/self,
+            // /proc/mounts, etc.) are ordinary symlinks.
+            if util::fstatfs(parent_fd)?.f_type == PROC_SUPER_MAGIC as _
+                && util::fstat(parent_fd)?.st_ino as u64 != PROC_ROOT_INO
+            {
+                return Err(io::Error::from_raw_os_error(libc::ELOOP));
+            }
+        } else {
+            let _ = parent_fd;
+        }
     }
 
     Ok(())
 }
 
+/// If `lookup_flags` contains `PROTECTED_SYMLINKS`, check that a symlink found in the directory
+/// referred to by `parent_fd` may be followed, and fail with `EACCES` if it may not.
+///
+/// The symlink is identified by `name` relative to `link_fd`; if `name` is empty, `link_fd` must
+/// be a file descriptor open to the symlink itself.
+pub(crate) fn check_protected_symlink(
+    parent_fd: RawFd,
+    link_fd: RawFd,
+    name: &CStr,
+    lookup_flags: LookupFlags,
+) -> io::Result<()> {
+    if !lookup_flags.contains(LookupFlags::PROTECTED_SYMLINKS) {
+        return Ok(());
+    }
+
+    let dir_stat = util::fstat(parent_fd)?;
+    if dir_stat.st_mode & (libc::S_ISVTX | libc::S_IWOTH) != libc::S_ISVTX | libc::S_IWOTH {
+        return Ok(());
+    }
+
+    let link_stat = if name.to_bytes().is_empty() {
+        util::fstat(link_fd)?
+    } else {
+        util::fstatat(link_fd, name, libc::AT_SYMLINK_NOFOLLOW)?
+    };
+
+    if link_stat.st_uid == dir_stat.st_uid || link_stat.st_uid == unsafe { libc::geteuid() } {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(libc::EACCES))
+    }
+}
+
 fn check_beneath(base_fd: RawFd, dir_fd_stat: &libc::stat) -> io::Result<()> {
     // We need to rewind up the directory tree and make sure that we didn't escape because of
     // race conditions with "..".
+    if walk_beneath(base_fd, dir_fd_stat)? {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(libc::EAGAIN))
+    }
+}
 
+/// Walk up the directory tree from `base_fd`, and return whether the directory described by
+/// `dir_fd_stat` was found along the way.
+fn walk_beneath(base_fd: RawFd, dir_fd_stat: &libc::stat) -> io::Result<bool> {
     let mut prev_stat = unsafe { std::mem::zeroed() };
 
     let mut cur_file: Option<fs::File> = None;
@@ -382,12 +1562,12 @@ fn check_beneath(base_fd: RawFd, dir_fd_stat: &libc::stat) -> io::Result<()> {
 
         if util::samestat(&cur_stat, dir_fd_stat) {
             // We found it! We *didn't* escape.
-            return Ok(());
+            return Ok(true);
         } else if cur_file.is_some() && util::samestat(&cur_stat, &prev_stat) {
             // Trying to open ".." brought us the same directory. That means we're at "/"
             // (the REAL "/").
             // So we escaped the "beneath" directory.
-            return Err(io::Error::from_raw_os_error(libc::EAGAIN));
+            return Ok(false);
         }
 
         prev_stat = cur_stat;
@@ -402,7 +1582,43 @@ fn do_open_beneath(
     orig_flags: libc::c_int,
     mode: libc::mode_t,
     lookup_flags: LookupFlags,
+    opts: &ResolveOptions,
 ) -> io::Result<fs::File> {
+    do_open_beneath_ctx(
+        dir_fd,
+        orig_path,
+        orig_flags,
+        mode,
+        lookup_flags,
+        opts,
+        None,
+    )
+}
+
+/// Information about where path resolution failed in `do_open_beneath_ctx()`.
+#[derive(Debug)]
+struct Failure {
+    resolved: PathBuf,
+    component: OsString,
+    remaining: PathBuf,
+    crossed_mount: bool,
+    policy_violation: bool,
+}
+
+/// Like `do_open_beneath()`, but if `failure` is not `None` and resolving one of the components
+/// fails, information about the failure is stored in it.
+fn do_open_beneath_ctx(
+    dir_fd: RawFd,
+    orig_path: &Path,
+    orig_flags: libc::c_int,
+    mode: libc::mode_t,
+    lookup_flags: LookupFlags,
+    opts: &ResolveOptions,
+    failure: Option<&mut Option<Failure>>,
+) -> io::Result<fs::File> {
+    let normalized = crate::safe_path::normalize_components(orig_path, lookup_flags)?;
+    let orig_path = normalized.as_deref().unwrap_or(orig_path);
+
     let dir_fd_stat = util::fstat(dir_fd)?;
 
     if dir_fd == libc::AT_FDCWD {
@@ -419,10 +1635,27 @@ fn do_open_beneath(
         None
     };
 
-    let mut parts = split_path(orig_path, orig_flags)?;
+    // If there's a policy, the file is only truncated once it's been checked
+    let truncate = opts.has_policy() && orig_flags & libc::O_TRUNC == libc::O_TRUNC;
+    let orig_flags = if truncate {
+        orig_flags & !libc::O_TRUNC
+    } else {
+        orig_flags
+    };
+    // A newly created file must not be world-writable either
+    let mode = if opts.forbid_world_writable {
+        mode & !libc::S_IWOTH
+    } else {
+        mode
+    };
+
+    let mut parts = PathParts::new(orig_path.as_os_str().as_bytes(), orig_flags)?;
+    let mut link_buf = [0u8; libc::PATH_MAX as usize];
 
     let mut links = if lookup_flags.contains(LookupFlags::NO_SYMLINKS) {
         util::SymlinkCounter::nolinks()
+    } else if let Some(max_symlinks) = opts.max_symlinks {
+        util::SymlinkCounter::with_max(max_symlinks)
     } else {
         util::SymlinkCounter::new()
     };
@@ -430,14 +1663,15 @@ fn do_open_beneath(
     let mut cur_file: Option<fs::File> = None;
     let mut saw_parent_elem = false;
 
-    fn handle_possible_symlink(
+    // Returns the target of the symlink (stored in `link_buf`)
+    fn handle_possible_symlink<'a>(
         relfd: RawFd,
         relpath: &CStr,
         flags: libc::c_int,
         eno: libc::c_int,
         links: &mut util::SymlinkCounter,
-        parts: &mut VecDeque<(Cow<CStr>, libc::c_int)>,
-    ) -> io::Result<()> {
+        link_buf: &'a mut [u8],
+    ) -> io::Result<&'a [u8]> {
         debug_assert!(matches!(eno, libc::ELOOP | libc::ENOTDIR));
 
         // If we know it's definitely a symlink, and either a) we were given
@@ -454,7 +1688,7 @@ fn do_open_beneath(
             ));
         }
 
-        let target = match util::readlinkat(relfd, relpath) {
+        let target = match util::readlinkat_buf(relfd, relpath, link_buf) {
             // Successfully read the symlink
             Ok(t) => t,
 
@@ -487,22 +1721,23 @@ fn do_open_beneath(
             ));
         }
 
-        split_link_path_into(&target, flags, parts)?;
-
-        Ok(())
+        Ok(target)
     }
 
     fn check_mnt_id(
-        dir_mnt_id: Option<crate::mntid::MountId>,
+        dir_mnt_id: Option<MountId>,
+        allowed_mounts: &[MountId],
         prev_fd: libc::c_int,
         new_file: Option<&fs::File>,
     ) -> io::Result<()> {
         if let Some(dir_mnt_id) = dir_mnt_id {
             if let Some(new_file) = new_file.as_ref() {
-                if new_file.as_raw_fd() != prev_fd
-                    && crate::mntid::identify_mount(new_file.as_raw_fd())? != dir_mnt_id
-                {
-                    return Err(io::Error::from_raw_os_error(libc::EXDEV));
+                if new_file.as_raw_fd() != prev_fd {
+                    let mnt_id = crate::mntid::identify_mount(new_file.as_raw_fd())?;
+
+                    if mnt_id != dir_mnt_id && !allowed_mounts.contains(&mnt_id) {
+                        return Err(io::Error::from_raw_os_error(libc::EXDEV));
+                    }
                 }
             }
         }
@@ -510,278 +1745,468 @@ fn do_open_beneath(
         Ok(())
     }
 
+    let mut crossed_mount = false;
+    let mut policy_violation = false;
+    // The path (relative to `dir_fd`) that has been resolved so far; only tracked if the caller
+    // wants failure information
+    let mut resolved = failure.as_ref().map(|_| PathBuf::new());
+
     while let Some((part, flags)) = parts.pop_front() {
         // Sanity check -- `flags` can only ever be something other than DIR_OPEN_FLAGS if there
         // are no components left
         debug_assert!(flags == constants::DIR_OPEN_FLAGS || parts.is_empty());
 
-        let cur_fd = cur_file.as_ref().map(|f| f.as_raw_fd()).unwrap_or(dir_fd);
-
-        match part.to_bytes() {
-            b"/" => {
-                if !lookup_flags.contains(LookupFlags::IN_ROOT) {
-                    return Err(io::Error::from_raw_os_error(libc::EXDEV));
-                }
+        opts.check_deadline()?;
 
-                cur_file = None;
+        let cur_fd = cur_file.as_ref().map(|f| f.as_raw_fd()).unwrap_or(dir_fd);
 
-                // It's impossible for us to see `/` immediately after seeing `..`.
-                debug_assert!(!saw_parent_elem);
-            }
+        // If resolving this component fails, it may have already been added to `resolved`
+        let resolved_before = resolved.clone();
+        // If this component is a symlink, its target (only tracked if there is an audit hook)
+        let mut symlink_target = None;
 
-            b".." => {
-                if cur_file.is_none() || util::samestat(&util::fstat(cur_fd)?, &dir_fd_stat) {
+        // Resolve this component; on failure, we need to know which component it was
+        let res = (|| -> io::Result<()> {
+            match part {
+                PathPart::Root => {
                     if !lookup_flags.contains(LookupFlags::IN_ROOT) {
                         return Err(io::Error::from_raw_os_error(libc::EXDEV));
                     }
 
                     cur_file = None;
-                    saw_parent_elem = false;
-                } else {
-                    cur_file = Some(util::open_dotdot(cur_fd, flags, mode)?);
+                    if let Some(resolved) = resolved.as_mut() {
+                        *resolved = PathBuf::new();
+                    }
 
-                    saw_parent_elem = true;
+                    // It's impossible for us to see `/` immediately after seeing `..`.
+                    debug_assert!(!saw_parent_elem);
                 }
-            }
 
-            _ => {
-                if saw_parent_elem {
-                    check_beneath(cur_fd, &dir_fd_stat)?;
-                    saw_parent_elem = false;
+                PathPart::Parent => {
+                    if lookup_flags.contains(LookupFlags::NO_DOTDOT) {
+                        return Err(io::Error::from_raw_os_error(libc::EXDEV));
+                    }
+
+                    if cur_file.is_none() || util::samestat(&util::fstat(cur_fd)?, &dir_fd_stat) {
+                        if !lookup_flags.contains(LookupFlags::IN_ROOT) {
+                            return Err(io::Error::from_raw_os_error(libc::EXDEV));
+                        }
+
+                        cur_file = None;
+                        saw_parent_elem = false;
+                        if let Some(resolved) = resolved.as_mut() {
+                            *resolved = PathBuf::new();
+                        }
+                    } else {
+                        cur_file = Some(util::open_dotdot(cur_fd, flags, mode)?);
+
+                        saw_parent_elem = true;
+                        if let Some(resolved) = resolved.as_mut() {
+                            resolved.pop();
+                        }
+                    }
                 }
 
-                match util::openat(cur_fd, &part, flags | libc::O_NOFOLLOW, mode) {
-                    Ok(f) => {
-                        // On Linux (and FreeBSD 14.0+), O_PATH|O_NOFOLLOW will return a file
-                        // descriptor open to the *symlink* (though adding in O_DIRECTORY will
-                        // prevent this by only allowing a directory). Since we "add in" O_NOFOLLOW,
-                        // if O_PATH was specified and neither O_NOFOLLOW nor O_DIRECTORY was, we
-                        // might accidentally open a symlink when that isn't what the user wants.
-                        //
-                        // So let's check if it's a symlink in that case.
-
-                        #[cfg(any(target_os = "linux", target_os = "android"))]
-                        use libc::O_PATH;
-                        #[cfg(target_os = "freebsd")]
-                        const O_PATH: libc::c_int = 0x00400000;
-
-                        #[cfg(any(
-                            target_os = "linux",
-                            target_os = "android",
-                            target_os = "freebsd",
-                        ))]
-                        if flags & (O_PATH | libc::O_NOFOLLOW | libc::O_DIRECTORY) == O_PATH
-                            && f.metadata()?.file_type().is_symlink()
-                        {
-                            // It *is* a symlink.
-
-                            // Now that we have this file descriptor open to a symlink, we can pass
-                            // *that* to readlinkat() to resolve the symlink.
-                            handle_possible_symlink(
-                                f.as_raw_fd(),
-                                unsafe { CStr::from_bytes_with_nul_unchecked(b"\0") },
+                PathPart::Normal(..) => {
+                    if saw_parent_elem {
+                        check_beneath(cur_fd, &dir_fd_stat)?;
+                        saw_parent_elem = false;
+                    }
+
+                    // Check the final component against the policy *before* opening it, so that a
+                    // file that violates the policy is never created or truncated. (It is checked
+                    // again once it has been opened, in case it was replaced in between.)
+                    let mut flags = flags;
+                    if opts.has_policy() && parts.is_empty() {
+                        match util::fstatat(cur_fd, parts.get(part), libc::AT_SYMLINK_NOFOLLOW) {
+                            // Symlinks are checked below (and their targets once they're resolved)
+                            Ok(st) if st.st_mode & libc::S_IFMT == libc::S_IFLNK => (),
+
+                            Ok(st) => {
+                                if let Err(e) = opts.check_policy(parts.get(part), &st) {
+                                    policy_violation = true;
+                                    return Err(e);
+                                }
+
+                                // If it's removed after the check, don't create a new file in its
+                                // place
+                                if flags & libc::O_EXCL == 0 {
+                                    flags &= !libc::O_CREAT;
+                                }
+                            }
+
+                            // The filter can't be run on a file that doesn't exist yet, and a new
+                            // file would be owned by the current user
+                            Err(e)
+                                if e.raw_os_error() == Some(libc::ENOENT)
+                                    && flags & libc::O_CREAT == libc::O_CREAT
+                                    && (opts.component_filter.is_some()
+                                        || matches!(opts.required_owner,
+                                            Some(uid) if uid != unsafe { libc::geteuid() })) =>
+                            {
+                                policy_violation = true;
+                                return Err(io::Error::from_raw_os_error(libc::EPERM));
+                            }
+
+                            // Let openat() report any other errors
+                            Err(_) => (),
+                        }
+                    }
+
+                    match util::openat(cur_fd, parts.get(part), flags | libc::O_NOFOLLOW, mode) {
+                        Ok(f) => {
+                            // On Linux (and FreeBSD 14.0+), O_PATH|O_NOFOLLOW will return a file
+                            // descriptor open to the *symlink* (though adding in O_DIRECTORY will
+                            // prevent this by only allowing a directory). Since we "add in"
+                            // O_NOFOLLOW, if O_PATH was specified and neither O_NOFOLLOW nor
+                            // O_DIRECTORY was, we might accidentally open a symlink when that isn't
+                            // what the user wants.
+                            //
+                            // So let's check if it's a symlink in that case.
+
+                            #[cfg(any(target_os = "linux", target_os = "android"))]
+                            use libc::O_PATH;
+                            #[cfg(target_os = "freebsd")]
+                            const O_PATH: libc::c_int = 0x00400000;
+
+                            #[cfg(any(
+                                target_os = "linux",
+                                target_os = "android",
+                                target_os = "freebsd",
+                            ))]
+                            if flags & (O_PATH | libc::O_NOFOLLOW | libc::O_DIRECTORY) == O_PATH
+                                && f.metadata()?.file_type().is_symlink()
+                            {
+                                // It *is* a symlink.
+
+                                // Now that we have this file descriptor open to a symlink, we can
+                                // pass *that* to readlinkat() to resolve the symlink.
+                                let target = handle_possible_symlink(
+                                    f.as_raw_fd(),
+                                    unsafe { CStr::from_bytes_with_nul_unchecked(b"\0") },
+                                    flags,
+                                    libc::ELOOP,
+                                    &mut links,
+                                    &mut link_buf,
+                                )?;
+                                check_magiclink(cur_fd, lookup_flags)?;
+                                check_protected_symlink(
+                                    cur_fd,
+                                    f.as_raw_fd(),
+                                    unsafe { CStr::from_bytes_with_nul_unchecked(b"\0") },
+                                    lookup_flags,
+                                )?;
+                                if opts.has_policy() {
+                                    if let Err(e) = opts
+                                        .check_policy(parts.get(part), &util::fstat(f.as_raw_fd())?)
+                                    {
+                                        policy_violation = true;
+                                        return Err(e);
+                                    }
+                                }
+                                if opts.audit.is_some() {
+                                    symlink_target = Some(target.to_vec());
+                                }
+                                parts.push_front_path(target, flags, true)?;
+
+                                drop(f);
+                                // Stay where we are and skip the mount ID check
+                                return Ok(());
+                            }
+
+                            cur_file = Some(f);
+                            if let Some(resolved) = resolved.as_mut() {
+                                resolved.push(OsStr::from_bytes(parts.get(part).to_bytes()));
+                            }
+                        }
+
+                        Err(e) => {
+                            // When flags=O_DIRECTORY|O_NOFLLOW, if the last component is a
+                            // symlink then it will fail with ENOTDIR.
+                            //
+                            // Otherwise, when the last component is a symlink, most OSes return
+                            // ELOOP. However, FreeBSD returns EMLINK and NetBSD returns EFTYPE.
+
+                            let eno = e.raw_os_error().unwrap();
+
+                            #[cfg(any(target_os = "freebsd", target_os = "dragonfly"))]
+                            let eno = if eno == libc::EMLINK {
+                                libc::ELOOP
+                            } else {
+                                eno
+                            };
+
+                            #[cfg(target_os = "netbsd")]
+                            let eno = if eno == libc::EFTYPE {
+                                libc::ELOOP
+                            } else {
+                                eno
+                            };
+
+                            if eno != libc::ELOOP && eno != libc::ENOTDIR {
+                                return Err(e);
+                            }
+
+                            // It may have failed because it's a symlink.
+                            // (If eno == libc::ELOOP, it's definitely a symlink.)
+                            let target = handle_possible_symlink(
+                                cur_fd,
+                                parts.get(part),
                                 flags,
-                                libc::ELOOP,
+                                eno,
                                 &mut links,
-                                &mut parts,
+                                &mut link_buf,
                             )?;
-
-                            drop(f);
-                            // Stay where we are and skip the mount ID check
-                            continue;
+                            check_magiclink(cur_fd, lookup_flags)?;
+                            check_protected_symlink(cur_fd, cur_fd, parts.get(part), lookup_flags)?;
+                            if opts.has_policy() {
+                                if let Err(e) = opts.check_policy(
+                                    parts.get(part),
+                                    &util::fstatat(
+                                        cur_fd,
+                                        parts.get(part),
+                                        libc::AT_SYMLINK_NOFOLLOW,
+                                    )?,
+                                ) {
+                                    policy_violation = true;
+                                    return Err(e);
+                                }
+                            }
+                            if opts.audit.is_some() {
+                                symlink_target = Some(target.to_vec());
+                            }
+                            parts.push_front_path(target, flags, true)?;
                         }
-
-                        cur_file = Some(f);
                     }
+                }
+            }
 
-                    Err(e) => {
-                        // When flags=O_DIRECTORY|O_NOFLLOW, if the last component is a symlink then
-                        // it will fail with ENOTDIR.
-                        //
-                        // Otherwise, when the last component is a symlink, most OSes return ELOOP.
-                        // However, FreeBSD returns EMLINK and NetBSD returns EFTYPE.
-
-                        let eno = e.raw_os_error().unwrap();
-
-                        #[cfg(any(target_os = "freebsd", target_os = "dragonfly"))]
-                        let eno = if eno == libc::EMLINK {
-                            libc::ELOOP
-                        } else {
-                            eno
-                        };
-
-                        #[cfg(target_os = "netbsd")]
-                        let eno = if eno == libc::EFTYPE {
-                            libc::ELOOP
-                        } else {
-                            eno
-                        };
-
-                        if eno != libc::ELOOP && eno != libc::ENOTDIR {
-                            return Err(e);
-                        }
+            debug_assert_eq!(
+                lookup_flags.contains(LookupFlags::NO_XDEV),
+                dir_mnt_id.is_some()
+            );
 
-                        // It may have failed because it's a symlink.
-                        // (If eno == libc::ELOOP, it's definitely a symlink.)
-                        handle_possible_symlink(cur_fd, &part, flags, eno, &mut links, &mut parts)?;
+            if let Err(e) = check_mnt_id(dir_mnt_id, opts.allowed_mounts, cur_fd, cur_file.as_ref())
+            {
+                crossed_mount = e.raw_os_error() == Some(libc::EXDEV);
+                return Err(e);
+            }
+
+            // Check every new component that was opened
+            if let Some(new_file) = cur_file.as_ref() {
+                if opts.has_policy() && new_file.as_raw_fd() != cur_fd {
+                    if let Err(e) =
+                        opts.check_policy(parts.get(part), &util::fstat(new_file.as_raw_fd())?)
+                    {
+                        policy_violation = true;
+                        return Err(e);
                     }
                 }
             }
+
+            Ok(())
+        })();
+
+        if let Some(audit) = opts.audit {
+            audit.emit(&ResolveEvent {
+                component: OsStr::from_bytes(parts.get(part).to_bytes()),
+                flags,
+                symlink_target: symlink_target.as_deref().map(OsStr::from_bytes),
+                result: res.as_ref().map(|_| ()),
+            });
         }
 
-        debug_assert_eq!(
-            lookup_flags.contains(LookupFlags::NO_XDEV),
-            dir_mnt_id.is_some()
-        );
+        if let Err(e) = res {
+            if let Some(failure) = failure {
+                *failure = Some(Failure {
+                    resolved: resolved_before.unwrap_or_default(),
+                    component: OsStr::from_bytes(parts.get(part).to_bytes()).to_os_string(),
+                    remaining: parts.remaining(),
+                    crossed_mount,
+                    policy_violation,
+                });
+            }
 
-        check_mnt_id(dir_mnt_id, cur_fd, cur_file.as_ref())?;
+            return Err(e);
+        }
     }
 
     if saw_parent_elem {
-        check_beneath(cur_file.as_ref().unwrap().as_raw_fd(), &dir_fd_stat)?;
+        if let Err(e) = check_beneath(cur_file.as_ref().unwrap().as_raw_fd(), &dir_fd_stat) {
+            if let Some(audit) = opts.audit {
+                audit.emit(&ResolveEvent {
+                    component: OsStr::new(".."),
+                    flags: constants::DIR_OPEN_FLAGS,
+                    symlink_target: None,
+                    result: Err(&e),
+                });
+            }
+
+            if let Some(failure) = failure {
+                *failure = Some(Failure {
+                    resolved: resolved.unwrap_or_default(),
+                    component: OsString::from(".."),
+                    remaining: PathBuf::new(),
+                    crossed_mount: false,
+                    policy_violation: false,
+                });
+            }
+
+            return Err(e);
+        }
     }
 
-    if let Some(cur_file) = cur_file {
-        Ok(cur_file)
+    let file = if let Some(cur_file) = cur_file {
+        cur_file
     } else {
-        util::open_dot(dir_fd, orig_flags, mode)
+        util::open_dot(dir_fd, orig_flags, mode)?
+    };
+
+    // O_TRUNC is ignored for files other than regular files
+    if truncate && file.metadata()?.is_file() {
+        file.set_len(0)?;
     }
+
+    Ok(file)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn collect_parts(parts: &PathParts) -> Vec<(&[u8], libc::c_int)> {
+        parts
+            .queue
+            .iter()
+            .rev()
+            .map(|&(part, flags)| (parts.get(part).to_bytes(), flags))
+            .collect()
+    }
+
     #[test]
-    fn test_split_path() {
+    fn test_path_parts_new() {
+        let parts = PathParts::new(b"abc", libc::O_RDONLY).unwrap();
+        assert_eq!(collect_parts(&parts), &[(&b"abc"[..], libc::O_RDONLY)]);
+
+        let parts = PathParts::new(b"abc/def", libc::O_RDONLY).unwrap();
         assert_eq!(
-            split_path("abc".as_ref(), libc::O_RDONLY).unwrap(),
-            &[(Cow::Owned(CString::new("abc").unwrap()), libc::O_RDONLY)]
+            collect_parts(&parts),
+            &[
+                (&b"abc"[..], constants::DIR_OPEN_FLAGS),
+                (&b"def"[..], libc::O_RDONLY),
+            ]
         );
 
+        let parts = PathParts::new(b"/abc/./../def", libc::O_RDONLY).unwrap();
         assert_eq!(
-            split_path("abc/def".as_ref(), libc::O_RDONLY).unwrap(),
+            collect_parts(&parts),
             &[
-                (
-                    Cow::Owned(CString::new("abc").unwrap()),
-                    constants::DIR_OPEN_FLAGS
-                ),
-                (Cow::Owned(CString::new("def").unwrap()), libc::O_RDONLY)
+                (&b"/"[..], constants::DIR_OPEN_FLAGS),
+                (&b"abc"[..], constants::DIR_OPEN_FLAGS),
+                (&b".."[..], constants::DIR_OPEN_FLAGS),
+                (&b"def"[..], libc::O_RDONLY),
             ]
         );
 
+        let parts = PathParts::new(b"./abc/./../def/", libc::O_RDONLY).unwrap();
         assert_eq!(
-            split_path("/abc/./../def".as_ref(), libc::O_RDONLY).unwrap(),
+            collect_parts(&parts),
             &[
-                (
-                    Cow::Owned(CString::new("/").unwrap()),
-                    constants::DIR_OPEN_FLAGS
-                ),
-                (
-                    Cow::Owned(CString::new("abc").unwrap()),
-                    constants::DIR_OPEN_FLAGS
-                ),
-                (
-                    Cow::Owned(CString::new("..").unwrap()),
-                    constants::DIR_OPEN_FLAGS
-                ),
-                (Cow::Owned(CString::new("def").unwrap()), libc::O_RDONLY)
+                (&b"abc"[..], constants::DIR_OPEN_FLAGS),
+                (&b".."[..], constants::DIR_OPEN_FLAGS),
+                (&b"def"[..], libc::O_RDONLY | libc::O_DIRECTORY),
             ]
         );
 
+        let parts = PathParts::new(b"//abc//", libc::O_RDONLY).unwrap();
         assert_eq!(
-            split_path("./abc/./../def/".as_ref(), libc::O_RDONLY).unwrap(),
+            collect_parts(&parts),
             &[
-                (
-                    Cow::Owned(CString::new("abc").unwrap()),
-                    constants::DIR_OPEN_FLAGS
-                ),
-                (
-                    Cow::Owned(CString::new("..").unwrap()),
-                    constants::DIR_OPEN_FLAGS
-                ),
-                (
-                    Cow::Owned(CString::new("def").unwrap()),
-                    libc::O_RDONLY | libc::O_DIRECTORY
-                )
+                (&b"/"[..], constants::DIR_OPEN_FLAGS),
+                (&b"abc"[..], libc::O_RDONLY | libc::O_DIRECTORY),
             ]
         );
 
+        let parts = PathParts::new(b"/", libc::O_RDONLY).unwrap();
         assert_eq!(
-            split_path("".as_ref(), libc::O_RDONLY)
+            collect_parts(&parts),
+            &[(&b"/"[..], libc::O_RDONLY | libc::O_DIRECTORY)]
+        );
+
+        assert!(PathParts::new(b".", libc::O_RDONLY).unwrap().is_empty());
+
+        assert_eq!(
+            PathParts::new(b"", libc::O_RDONLY)
                 .unwrap_err()
                 .raw_os_error(),
             Some(libc::ENOENT)
         );
+        assert_eq!(
+            PathParts::new(b"a\0b", libc::O_RDONLY).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
     }
 
     #[test]
-    fn test_split_link_path_into() {
-        let mut parts = VecDeque::new();
-
-        parts.push_back((Cow::Owned(CString::new("END").unwrap()), 0));
-        split_link_path_into("abc/def".as_ref(), libc::O_RDONLY, &mut parts).unwrap();
+    fn test_path_parts_push_link() {
+        let mut parts = PathParts::new(b"END", 0).unwrap();
+        parts
+            .push_front_path(b"abc/def", libc::O_RDONLY, true)
+            .unwrap();
         assert_eq!(
-            parts,
+            collect_parts(&parts),
             &[
-                (
-                    Cow::Owned(CString::new("abc").unwrap()),
-                    constants::DIR_OPEN_FLAGS
-                ),
-                (Cow::Owned(CString::new("def").unwrap()), libc::O_RDONLY),
-                (Cow::Owned(CString::new("END").unwrap()), 0),
+                (&b"abc"[..], constants::DIR_OPEN_FLAGS),
+                (&b"def"[..], libc::O_RDONLY),
+                (&b"END"[..], 0),
             ]
         );
-        parts.clear();
 
-        parts.push_back((Cow::Owned(CString::new("END").unwrap()), 0));
-        split_link_path_into("/abc/./../def".as_ref(), libc::O_RDONLY, &mut parts).unwrap();
+        let mut parts = PathParts::new(b"END", 0).unwrap();
+        parts
+            .push_front_path(b"/abc/./../def", libc::O_RDONLY, true)
+            .unwrap();
         assert_eq!(
-            parts,
+            collect_parts(&parts),
             &[
-                (
-                    Cow::Owned(CString::new("/").unwrap()),
-                    constants::DIR_OPEN_FLAGS
-                ),
-                (
-                    Cow::Owned(CString::new("abc").unwrap()),
-                    constants::DIR_OPEN_FLAGS
-                ),
-                (
-                    Cow::Owned(CString::new("..").unwrap()),
-                    constants::DIR_OPEN_FLAGS
-                ),
-                (Cow::Owned(CString::new("def").unwrap()), libc::O_RDONLY),
-                (Cow::Owned(CString::new("END").unwrap()), 0),
+                (&b"/"[..], constants::DIR_OPEN_FLAGS),
+                (&b"abc"[..], constants::DIR_OPEN_FLAGS),
+                (&b".."[..], constants::DIR_OPEN_FLAGS),
+                (&b"def"[..], libc::O_RDONLY),
+                (&b"END"[..], 0),
             ]
         );
-        parts.clear();
 
+        let mut parts = PathParts::new(b"END", 0).unwrap();
         assert_eq!(
-            split_link_path_into("".as_ref(), libc::O_RDONLY, &mut parts)
+            parts
+                .push_front_path(b"", libc::O_RDONLY, true)
                 .unwrap_err()
                 .raw_os_error(),
             Some(libc::ENOENT)
         );
-        assert!(parts.is_empty());
+        assert_eq!(collect_parts(&parts), &[(&b"END"[..], 0)]);
 
-        parts.push_back((Cow::Owned(CString::new("END").unwrap()), 0));
-        split_link_path_into("./abc/./def/.".as_ref(), libc::O_RDONLY, &mut parts).unwrap();
+        let mut parts = PathParts::new(b"END", 0).unwrap();
+        parts
+            .push_front_path(b"./abc/./def/.", libc::O_RDONLY, true)
+            .unwrap();
         assert_eq!(
-            parts,
+            collect_parts(&parts),
             &[
-                (
-                    Cow::Owned(CString::new("abc").unwrap()),
-                    constants::DIR_OPEN_FLAGS
-                ),
-                (
-                    Cow::Owned(CString::new("def").unwrap()),
-                    libc::O_RDONLY | libc::O_DIRECTORY
-                ),
-                (Cow::Owned(CString::new("END").unwrap()), 0),
+                (&b"abc"[..], constants::DIR_OPEN_FLAGS),
+                (&b"def"[..], libc::O_RDONLY | libc::O_DIRECTORY),
+                (&b"END"[..], 0),
             ]
         );
-        parts.clear();
+
+        // Links to "." still get the correct flags
+        let mut parts = PathParts::new(b"END", 0).unwrap();
+        parts.push_front_path(b".", libc::O_RDONLY, true).unwrap();
+        assert_eq!(
+            collect_parts(&parts),
+            &[(&b"."[..], libc::O_RDONLY), (&b"END"[..], 0)]
+        );
     }
 
     #[test]
@@ -804,4 +2229,44 @@ mod tests {
             Some(libc::EAGAIN)
         );
     }
+
+    #[test]
+    fn test_verify_paranoid() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let tmpdir = tmpdir.as_ref();
+
+        fs::write(tmpdir.join("a"), b"").unwrap();
+        fs::write(tmpdir.join("b"), b"").unwrap();
+
+        let tmpdir_file = fs::File::open(tmpdir).unwrap();
+        let tmpdir_fd = tmpdir_file.as_raw_fd();
+
+        let a = fs::File::open(tmpdir.join("a")).unwrap();
+
+        for &lookup_flags in [LookupFlags::empty(), LookupFlags::NO_XDEV].iter() {
+            assert!(verify_paranoid(
+                tmpdir_fd,
+                Path::new("a"),
+                &a,
+                libc::O_RDONLY,
+                lookup_flags
+            ));
+            // A different file
+            assert!(!verify_paranoid(
+                tmpdir_fd,
+                Path::new("b"),
+                &a,
+                libc::O_RDONLY,
+                lookup_flags
+            ));
+            // Manual resolution fails
+            assert!(!verify_paranoid(
+                tmpdir_fd,
+                Path::new("../a"),
+                &a,
+                libc::O_RDONLY,
+                lookup_flags
+            ));
+        }
+    }
 }
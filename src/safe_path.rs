@@ -0,0 +1,349 @@
+use std::ffi::{CStr, CString, OsStr};
+use std::fmt;
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{AsPath, LookupFlags};
+
+/// The rules used by [`SafePath::new()`] to validate a path.
+///
+/// The [`Default`] policy is the strictest one: `..` components and absolute paths are rejected,
+/// and there are no limits on the depth or length.
+///
+/// [`SafePath::new()`]: ./struct.SafePath.html#method.new
+/// [`Default`]: #impl-Default
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct SafePathPolicy {
+    /// Whether `..` components are allowed.
+    ///
+    /// If this is `false`, they are rejected (like with `LookupFlags::NO_DOTDOT`). If it is
+    /// `true`, they are kept in the normalized path (since they can only be resolved correctly by
+    /// looking at the filesystem), but paths that lexically go above the starting directory (such
+    /// as `a/../..`) are still rejected.
+    pub allow_dotdot: bool,
+    /// Whether absolute paths are allowed.
+    ///
+    /// If this is `false`, they are rejected (as the resolver does by default). If it is `true`,
+    /// the leading slashes are stripped, so the path is interpreted relative to the starting
+    /// directory (like with `LookupFlags::IN_ROOT`).
+    pub allow_absolute: bool,
+    /// The maximum number of components in the normalized path (excluding `.` components), or 0
+    /// for no limit.
+    pub max_depth: usize,
+    /// The maximum length of the normalized path in bytes, or 0 for no limit.
+    pub max_len: usize,
+    /// The Unicode normalization form to convert each component to (or `None` to leave
+    /// components unchanged). Requires the `unicode-normalization` feature; without it,
+    /// [`SafePath::new()`] fails with [`SafePathError::NormalizationUnsupported`] if this is not
+    /// `None`.
+    ///
+    /// The same name can be encoded in several ways (for example, `é` can be a single code point
+    /// or an `e` followed by a combining accent). Some filesystems (such as HFS+ on macOS)
+    /// normalize names when they are created or looked up, while most others (including those
+    /// commonly used on Linux) compare them byte-for-byte. Normalizing untrusted paths with
+    /// `SafePath` before passing them to the [`Dir`] methods ensures that opening, inspecting,
+    /// and creating files all see the same name, regardless of how the client encoded it. (The
+    /// [`LookupFlags::NORMALIZE_NFC`] and [`LookupFlags::NORMALIZE_NFD`] lookup flags do the same
+    /// thing during path resolution.)
+    ///
+    /// Components that are not valid UTF-8 are left unchanged. Note that this only applies to
+    /// the path itself; the targets of any symlinks encountered during resolution are not
+    /// normalized.
+    ///
+    /// [`Dir`]: ./struct.Dir.html
+    /// [`LookupFlags::NORMALIZE_NFC`]: ./struct.LookupFlags.html#associatedconstant.NORMALIZE_NFC
+    /// [`LookupFlags::NORMALIZE_NFD`]: ./struct.LookupFlags.html#associatedconstant.NORMALIZE_NFD
+    /// [`SafePath::new()`]: ./struct.SafePath.html#method.new
+    /// [`SafePathError::NormalizationUnsupported`]: ./enum.SafePathError.html#variant.NormalizationUnsupported
+    pub normalization: Option<UnicodeNormalization>,
+}
+
+/// A Unicode normalization form, for use with [`SafePathPolicy::normalization`].
+///
+/// [`SafePathPolicy::normalization`]: ./struct.SafePathPolicy.html#structfield.normalization
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum UnicodeNormalization {
+    /// Normalization Form C (canonical composition), which is used by most software (and by
+    /// APFS when comparing names).
+    Nfc,
+    /// Normalization Form D (canonical decomposition), which is (a variant of) the form that HFS+
+    /// stores names in.
+    Nfd,
+}
+
+/// The reason that a path was rejected by [`SafePath::new()`].
+///
+/// This can be converted into an `io::Error` with the same error code that the resolver would
+/// fail with.
+///
+/// [`SafePath::new()`]: ./struct.SafePath.html#method.new
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum SafePathError {
+    /// The path is absolute, and [`SafePathPolicy::allow_absolute`] is `false` (`EXDEV`).
+    ///
+    /// [`SafePathPolicy::allow_absolute`]: ./struct.SafePathPolicy.html#structfield.allow_absolute
+    Absolute,
+    /// The path contains a `..` component, and [`SafePathPolicy::allow_dotdot`] is `false`
+    /// (`EXDEV`).
+    ///
+    /// [`SafePathPolicy::allow_dotdot`]: ./struct.SafePathPolicy.html#structfield.allow_dotdot
+    DotDot,
+    /// The path (lexically) escapes the starting directory (`EXDEV`).
+    Escape,
+    /// The path has more components than [`SafePathPolicy::max_depth`] (`ENAMETOOLONG`).
+    ///
+    /// [`SafePathPolicy::max_depth`]: ./struct.SafePathPolicy.html#structfield.max_depth
+    TooDeep,
+    /// The normalized path is longer than [`SafePathPolicy::max_len`] (`ENAMETOOLONG`).
+    ///
+    /// [`SafePathPolicy::max_len`]: ./struct.SafePathPolicy.html#structfield.max_len
+    TooLong,
+    /// The path contains a nul byte (`EINVAL`).
+    Nul,
+    /// [`SafePathPolicy::normalization`] was set, but the `unicode-normalization` feature is not
+    /// enabled (`EOPNOTSUPP`).
+    ///
+    /// [`SafePathPolicy::normalization`]: ./struct.SafePathPolicy.html#structfield.normalization
+    NormalizationUnsupported,
+}
+
+impl SafePathError {
+    /// Get the OS error code corresponding to this error.
+    #[inline]
+    pub fn raw_os_error(&self) -> i32 {
+        match self {
+            Self::Absolute | Self::DotDot | Self::Escape => libc::EXDEV,
+            Self::TooDeep | Self::TooLong => libc::ENAMETOOLONG,
+            Self::Nul => libc::EINVAL,
+            Self::NormalizationUnsupported => libc::EOPNOTSUPP,
+        }
+    }
+}
+
+impl fmt::Display for SafePathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Absolute => "absolute paths are not allowed",
+            Self::DotDot => "'..' components are not allowed",
+            Self::Escape => "path escapes the directory",
+            Self::TooDeep => "path has too many components",
+            Self::TooLong => "path is too long",
+            Self::Nul => "path contains a nul byte",
+            Self::NormalizationUnsupported => {
+                "Unicode normalization requires the unicode-normalization feature"
+            }
+        })
+    }
+}
+
+impl std::error::Error for SafePathError {}
+
+impl From<SafePathError> for io::Error {
+    #[inline]
+    fn from(err: SafePathError) -> Self {
+        io::Error::from_raw_os_error(err.raw_os_error())
+    }
+}
+
+/// A normalized relative path that has been validated against a [`SafePathPolicy`].
+///
+/// This performs the same checks that the resolver performs (with the corresponding lookup
+/// flags), but without accessing the filesystem. So it can be used to validate untrusted paths
+/// early (for example, when routing an HTTP request), before they are passed to [`Dir`] methods.
+/// Note that since symlinks cannot be checked without accessing the filesystem, the resolver
+/// may still reject a `SafePath` (for example, if it traverses a symlink that escapes the
+/// directory).
+///
+/// The normalized path never starts with a slash, and it contains no empty or `.` components (if
+/// nothing is left, it is `.`). Trailing slashes are removed.
+///
+/// ```
+/// # use std::path::Path;
+/// # use obnth::{SafePath, SafePathError, SafePathPolicy};
+/// let policy = SafePathPolicy::default();
+///
+/// let path = SafePath::new("a//./b/", &policy).unwrap();
+/// assert_eq!(path.as_path(), Path::new("a/b"));
+///
+/// assert_eq!(SafePath::new("../a", &policy).unwrap_err(), SafePathError::DotDot);
+/// ```
+///
+/// [`SafePathPolicy`]: ./struct.SafePathPolicy.html
+/// [`Dir`]: ./struct.Dir.html
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct SafePath {
+    path: CString,
+}
+
+impl SafePath {
+    /// Validate and normalize the given path according to `policy`.
+    pub fn new<P: AsPath>(path: P, policy: &SafePathPolicy) -> Result<Self, SafePathError> {
+        let path = path.as_path();
+
+        if path.as_os_str().as_bytes().contains(&0) {
+            return Err(SafePathError::Nul);
+        }
+
+        #[cfg(not(feature = "unicode-normalization"))]
+        if policy.normalization.is_some() {
+            return Err(SafePathError::NormalizationUnsupported);
+        }
+
+        let mut res = Vec::with_capacity(path.as_os_str().len());
+        let mut depth = 0;
+        let mut ncomponents = 0;
+
+        for component in path.components() {
+            match component {
+                Component::RootDir | Component::Prefix(_) if !policy.allow_absolute => {
+                    return Err(SafePathError::Absolute)
+                }
+                Component::RootDir | Component::Prefix(_) | Component::CurDir => continue,
+
+                Component::ParentDir if !policy.allow_dotdot => return Err(SafePathError::DotDot),
+                Component::ParentDir if depth == 0 => return Err(SafePathError::Escape),
+                Component::ParentDir => depth -= 1,
+
+                Component::Normal(_) => depth += 1,
+            }
+
+            ncomponents += 1;
+            if policy.max_depth != 0 && ncomponents > policy.max_depth {
+                return Err(SafePathError::TooDeep);
+            }
+
+            if !res.is_empty() {
+                res.push(b'/');
+            }
+            #[cfg(feature = "unicode-normalization")]
+            if let (Some(form), Component::Normal(name)) = (policy.normalization, component) {
+                push_normalized(&mut res, name, form);
+                continue;
+            }
+            res.extend_from_slice(component.as_os_str().as_bytes());
+        }
+
+        if res.is_empty() {
+            res.push(b'.');
+        }
+
+        if policy.max_len != 0 && res.len() > policy.max_len {
+            return Err(SafePathError::TooLong);
+        }
+
+        Ok(Self {
+            // We checked for nul bytes above
+            path: CString::new(res).unwrap(),
+        })
+    }
+
+    /// Get the normalized path.
+    #[inline]
+    pub fn as_path(&self) -> &Path {
+        Path::new(OsStr::from_bytes(self.path.as_bytes()))
+    }
+
+    /// Get the normalized path as a `CStr`.
+    #[inline]
+    pub fn as_c_str(&self) -> &CStr {
+        &self.path
+    }
+}
+
+/// Append `name` to `buf`, normalized to the given form (if it is valid UTF-8).
+#[cfg(feature = "unicode-normalization")]
+fn push_normalized(buf: &mut Vec<u8>, name: &OsStr, form: UnicodeNormalization) {
+    use unicode_normalization_rs::UnicodeNormalization as _;
+
+    let name = match std::str::from_utf8(name.as_bytes()) {
+        Ok(name) => name,
+        Err(_) => return buf.extend_from_slice(name.as_bytes()),
+    };
+
+    let mut tmp = [0; 4];
+    match form {
+        UnicodeNormalization::Nfc => {
+            for ch in name.nfc() {
+                buf.extend_from_slice(ch.encode_utf8(&mut tmp).as_bytes());
+            }
+        }
+        UnicodeNormalization::Nfd => {
+            for ch in name.nfd() {
+                buf.extend_from_slice(ch.encode_utf8(&mut tmp).as_bytes());
+            }
+        }
+    }
+}
+
+/// Normalize each component of `path` as requested by the `NORMALIZE_*` lookup flags.
+///
+/// Returns `None` if no normalization was requested. Otherwise, the structure of the path
+/// (leading, trailing, and repeated slashes, and `.` and `..` components) is preserved exactly,
+/// since it can affect how the path is resolved.
+pub(crate) fn normalize_components(
+    path: &Path,
+    lookup_flags: LookupFlags,
+) -> io::Result<Option<PathBuf>> {
+    let form = match (
+        lookup_flags.contains(LookupFlags::NORMALIZE_NFC),
+        lookup_flags.contains(LookupFlags::NORMALIZE_NFD),
+    ) {
+        (false, false) => return Ok(None),
+        (true, true) => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        (true, false) => UnicodeNormalization::Nfc,
+        (false, true) => UnicodeNormalization::Nfd,
+    };
+
+    #[cfg(feature = "unicode-normalization")]
+    {
+        let path = path.as_os_str().as_bytes();
+        let mut buf = Vec::with_capacity(path.len());
+
+        for (i, name) in path.split(|&ch| ch == b'/').enumerate() {
+            if i != 0 {
+                buf.push(b'/');
+            }
+            push_normalized(&mut buf, OsStr::from_bytes(name), form);
+        }
+
+        Ok(Some(PathBuf::from(std::ffi::OsString::from_vec(buf))))
+    }
+
+    #[cfg(not(feature = "unicode-normalization"))]
+    {
+        let _ = (path, form);
+        Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+    }
+}
+
+impl AsRef<Path> for SafePath {
+    #[inline]
+    fn as_ref(&self) -> &Path {
+        self.as_path()
+    }
+}
+
+impl AsPath for SafePath {
+    #[inline]
+    fn as_path(&self) -> &Path {
+        self.as_path()
+    }
+
+    #[inline]
+    fn with_cstr<T, F: FnMut(&CStr) -> io::Result<T>>(&self, mut f: F) -> io::Result<T> {
+        f(&self.path)
+    }
+}
+
+impl AsPath for &SafePath {
+    #[inline]
+    fn as_path(&self) -> &Path {
+        (*self).as_path()
+    }
+
+    #[inline]
+    fn with_cstr<T, F: FnMut(&CStr) -> io::Result<T>>(&self, mut f: F) -> io::Result<T> {
+        f(&self.path)
+    }
+}
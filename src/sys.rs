@@ -1,2 +1,174 @@
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 pub const O_NOFOLLOW_ANY: libc::c_int = 0x20000000;
+
+// FreeBSD 13+
+#[cfg(target_os = "freebsd")]
+pub const O_RESOLVE_BENEATH: libc::c_int = 0x00800000;
+
+// struct statx (and related constants) from linux/stat.h; defined here so we don't depend on the
+// version of libc in use exposing them
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct statx_timestamp {
+    pub tv_sec: i64,
+    pub tv_nsec: u32,
+    __reserved: i32,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct statx {
+    pub stx_mask: u32,
+    pub stx_blksize: u32,
+    pub stx_attributes: u64,
+    pub stx_nlink: u32,
+    pub stx_uid: u32,
+    pub stx_gid: u32,
+    pub stx_mode: u16,
+    __spare0: u16,
+    pub stx_ino: u64,
+    pub stx_size: u64,
+    pub stx_blocks: u64,
+    pub stx_attributes_mask: u64,
+    pub stx_atime: statx_timestamp,
+    pub stx_btime: statx_timestamp,
+    pub stx_ctime: statx_timestamp,
+    pub stx_mtime: statx_timestamp,
+    pub stx_rdev_major: u32,
+    pub stx_rdev_minor: u32,
+    pub stx_dev_major: u32,
+    pub stx_dev_minor: u32,
+    pub stx_mnt_id: u64,
+    __spare2: u64,
+    __spare3: [u64; 12],
+}
+
+#[cfg(target_os = "linux")]
+pub const STATX_BASIC_STATS: libc::c_uint = 0x7ff;
+#[cfg(target_os = "linux")]
+pub const STATX_BTIME: libc::c_uint = 0x800;
+#[cfg(target_os = "linux")]
+pub const STATX_MNT_ID: libc::c_uint = 0x1000;
+
+// The mask used to retrieve file metadata (see Metadata::from_statx())
+#[cfg(target_os = "linux")]
+pub const STATX_MASK: libc::c_uint = STATX_BASIC_STATS | STATX_BTIME | STATX_MNT_ID;
+
+#[cfg(target_os = "linux")]
+pub const STATX_ATTR_IMMUTABLE: u64 = 0x10;
+#[cfg(target_os = "linux")]
+pub const STATX_ATTR_APPEND: u64 = 0x20;
+
+// Landlock structures and constants from linux/landlock.h
+#[cfg(all(feature = "landlock", target_os = "linux"))]
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct landlock_ruleset_attr {
+    pub handled_access_fs: u64,
+}
+
+#[cfg(all(feature = "landlock", target_os = "linux"))]
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug)]
+pub struct landlock_path_beneath_attr {
+    pub allowed_access: u64,
+    pub parent_fd: i32,
+}
+
+#[cfg(all(feature = "landlock", target_os = "linux"))]
+pub const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1;
+#[cfg(all(feature = "landlock", target_os = "linux"))]
+pub const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+// io_uring structures and constants from linux/io_uring.h
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct io_sqring_offsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub flags: u32,
+    pub dropped: u32,
+    pub array: u32,
+    __resv1: u32,
+    __user_addr: u64,
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct io_cqring_offsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub overflow: u32,
+    pub cqes: u32,
+    pub flags: u32,
+    __resv1: u32,
+    __user_addr: u64,
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct io_uring_params {
+    pub sq_entries: u32,
+    pub cq_entries: u32,
+    pub flags: u32,
+    pub sq_thread_cpu: u32,
+    pub sq_thread_idle: u32,
+    pub features: u32,
+    pub wq_fd: u32,
+    __resv: [u32; 3],
+    pub sq_off: io_sqring_offsets,
+    pub cq_off: io_cqring_offsets,
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct io_uring_sqe {
+    pub opcode: u8,
+    pub flags: u8,
+    pub ioprio: u16,
+    pub fd: i32,
+    pub off: u64,
+    pub addr: u64,
+    pub len: u32,
+    pub op_flags: u32,
+    pub user_data: u64,
+    pub buf_index: u16,
+    pub personality: u16,
+    pub splice_fd_in: i32,
+    pub addr3: u64,
+    pub __pad2: [u64; 1],
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct io_uring_cqe {
+    pub user_data: u64,
+    pub res: i32,
+    pub flags: u32,
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub const IORING_OFF_SQ_RING: libc::off_t = 0;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub const IORING_OFF_CQ_RING: libc::off_t = 0x8000000;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub const IORING_OFF_SQES: libc::off_t = 0x10000000;
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub const IORING_ENTER_GETEVENTS: libc::c_uint = 1;
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub const IORING_OP_STATX: u8 = 21;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub const IORING_OP_OPENAT2: u8 = 28;
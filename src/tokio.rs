@@ -0,0 +1,569 @@
+//! Asynchronous wrappers for use with `tokio` (requires the `tokio` feature).
+//!
+//! Every operation runs the underlying (blocking) syscalls on tokio's blocking thread pool with
+//! `tokio::task::spawn_blocking()`, and files are returned as `tokio::fs::File`s.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+#[cfg(feature = "mount-id")]
+use crate::MountId;
+use crate::{
+    AsPath, AuditHook, ComponentFilter, Dir, FileType, LookupFlags, Metadata, OpenOptions,
+};
+
+async fn run_blocking<F, T>(f: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio_rs::task::spawn_blocking(f).await?
+}
+
+/// An asynchronous wrapper around a [`Dir`].
+///
+/// This can be cheaply cloned; all clones refer to the same directory file descriptor.
+///
+/// [`Dir`]: ../struct.Dir.html
+#[derive(Clone, Debug)]
+pub struct AsyncDir {
+    dir: Arc<Dir>,
+}
+
+impl AsyncDir {
+    /// Open the specified directory.
+    ///
+    /// See [`Dir::open()`].
+    ///
+    /// [`Dir::open()`]: ../struct.Dir.html#method.open
+    pub async fn open<P: AsPath>(path: P) -> io::Result<Self> {
+        let path = path.as_path().to_path_buf();
+        run_blocking(move || Dir::open(path)).await.map(Self::from)
+    }
+
+    /// Get a reference to the underlying [`Dir`].
+    ///
+    /// [`Dir`]: ../struct.Dir.html
+    #[inline]
+    pub fn as_dir(&self) -> &Dir {
+        &self.dir
+    }
+
+    /// Run the given closure with the underlying [`Dir`] on tokio's blocking thread pool.
+    ///
+    /// This can be used to perform operations that have no dedicated asynchronous wrapper.
+    ///
+    /// [`Dir`]: ../struct.Dir.html
+    pub async fn run<F, T>(&self, f: F) -> io::Result<T>
+    where
+        F: FnOnce(&Dir) -> io::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let dir = self.dir.clone();
+        run_blocking(move || f(&dir)).await
+    }
+
+    /// Open a subdirectory of this directory.
+    ///
+    /// See [`Dir::sub_dir()`].
+    ///
+    /// [`Dir::sub_dir()`]: ../struct.Dir.html#method.sub_dir
+    pub async fn sub_dir<P: AsPath>(&self, path: P, lookup_flags: LookupFlags) -> io::Result<Self> {
+        let path = path.as_path().to_path_buf();
+        self.run(move |dir| dir.sub_dir(path, lookup_flags))
+            .await
+            .map(Self::from)
+    }
+
+    /// Return an [`AsyncOpenOptions`] struct that can be used to open files within this
+    /// directory.
+    ///
+    /// [`AsyncOpenOptions`]: ./struct.AsyncOpenOptions.html
+    #[inline]
+    pub fn open_file(&self) -> AsyncOpenOptions {
+        AsyncOpenOptions::beneath(self.dir.clone())
+    }
+
+    /// List the contents of the specified subdirectory.
+    ///
+    /// The entire directory is read on the blocking thread pool; see [`Dir::list_dir()`].
+    ///
+    /// [`Dir::list_dir()`]: ../struct.Dir.html#method.list_dir
+    pub async fn read_dir<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<Vec<AsyncEntry>> {
+        let path = path.as_path().to_path_buf();
+
+        self.run(move |dir| {
+            dir.list_dir(path, lookup_flags)?
+                .map(|entry| {
+                    entry.map(|entry| AsyncEntry {
+                        name: entry.name().to_os_string(),
+                        ino: entry.ino(),
+                        ftype: entry.file_type(),
+                    })
+                })
+                .collect()
+        })
+        .await
+    }
+
+    /// Retrieve information on the file with the given path.
+    ///
+    /// See [`Dir::metadata()`].
+    ///
+    /// [`Dir::metadata()`]: ../struct.Dir.html#method.metadata
+    pub async fn metadata<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<Metadata> {
+        let path = path.as_path().to_path_buf();
+        self.run(move |dir| dir.metadata(path, lookup_flags)).await
+    }
+
+    /// Retrieve information on the file with the given path, following symlinks in the final
+    /// component.
+    ///
+    /// See [`Dir::metadata_follow()`].
+    ///
+    /// [`Dir::metadata_follow()`]: ../struct.Dir.html#method.metadata_follow
+    pub async fn metadata_follow<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<Metadata> {
+        let path = path.as_path().to_path_buf();
+        self.run(move |dir| dir.metadata_follow(path, lookup_flags))
+            .await
+    }
+
+    /// Create a directory within this directory.
+    ///
+    /// See [`Dir::create_dir()`].
+    ///
+    /// [`Dir::create_dir()`]: ../struct.Dir.html#method.create_dir
+    pub async fn create_dir<P: AsPath>(
+        &self,
+        path: P,
+        mode: libc::mode_t,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        let path = path.as_path().to_path_buf();
+        self.run(move |dir| dir.create_dir(path, mode, lookup_flags))
+            .await
+    }
+
+    /// Remove a subdirectory of this directory.
+    ///
+    /// See [`Dir::remove_dir()`].
+    ///
+    /// [`Dir::remove_dir()`]: ../struct.Dir.html#method.remove_dir
+    pub async fn remove_dir<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        let path = path.as_path().to_path_buf();
+        self.run(move |dir| dir.remove_dir(path, lookup_flags))
+            .await
+    }
+
+    /// Remove a file within this directory.
+    ///
+    /// See [`Dir::remove_file()`].
+    ///
+    /// [`Dir::remove_file()`]: ../struct.Dir.html#method.remove_file
+    pub async fn remove_file<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        let path = path.as_path().to_path_buf();
+        self.run(move |dir| dir.remove_file(path, lookup_flags))
+            .await
+    }
+
+    /// Read the contents of the symlink with the given path.
+    ///
+    /// See [`Dir::read_link()`].
+    ///
+    /// [`Dir::read_link()`]: ../struct.Dir.html#method.read_link
+    pub async fn read_link<P: AsPath>(
+        &self,
+        path: P,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<PathBuf> {
+        let path = path.as_path().to_path_buf();
+        self.run(move |dir| dir.read_link(path, lookup_flags)).await
+    }
+
+    /// Rename a file in this directory.
+    ///
+    /// See [`Dir::local_rename()`].
+    ///
+    /// [`Dir::local_rename()`]: ../struct.Dir.html#method.local_rename
+    pub async fn local_rename<P: AsPath, R: AsPath>(
+        &self,
+        old: P,
+        new: R,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<()> {
+        let old = old.as_path().to_path_buf();
+        let new = new.as_path().to_path_buf();
+        self.run(move |dir| dir.local_rename(old, new, lookup_flags))
+            .await
+    }
+}
+
+impl From<Dir> for AsyncDir {
+    #[inline]
+    fn from(dir: Dir) -> Self {
+        Self { dir: Arc::new(dir) }
+    }
+}
+
+/// An entry returned by [`AsyncDir::read_dir()`].
+///
+/// [`AsyncDir::read_dir()`]: ./struct.AsyncDir.html#method.read_dir
+#[derive(Clone, Debug)]
+pub struct AsyncEntry {
+    name: OsString,
+    ino: u64,
+    ftype: Option<FileType>,
+}
+
+impl AsyncEntry {
+    /// Get the name of this entry.
+    #[inline]
+    pub fn name(&self) -> &std::ffi::OsStr {
+        &self.name
+    }
+
+    /// Get this entry's inode.
+    ///
+    /// See [`Entry::ino()`] for caveats.
+    ///
+    /// [`Entry::ino()`]: ../struct.Entry.html#method.ino
+    #[inline]
+    pub fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    /// Get the entry's file type, if the OS specified it.
+    #[inline]
+    pub fn file_type(&self) -> Option<FileType> {
+        self.ftype
+    }
+}
+
+/// An asynchronous version of [`OpenOptions`].
+///
+/// An `AsyncOpenOptions` struct can be created with [`AsyncDir::open_file()`]. Unlike
+/// `OpenOptions`, it does not borrow the directory, so it can be stored or moved between tasks.
+///
+/// The options are the same as those of `OpenOptions`; see its documentation for details.
+///
+/// [`OpenOptions`]: ../struct.OpenOptions.html
+/// [`AsyncDir::open_file()`]: ./struct.AsyncDir.html#method.open_file
+#[derive(Clone, Debug)]
+pub struct AsyncOpenOptions {
+    dir: Arc<Dir>,
+    opts: OpenOptions<'static>,
+    try_cached: bool,
+}
+
+impl AsyncOpenOptions {
+    #[inline]
+    fn beneath(dir: Arc<Dir>) -> Self {
+        Self {
+            dir,
+            opts: OpenOptions::new(),
+            try_cached: false,
+        }
+    }
+
+    /// Enable the option for read access.
+    ///
+    /// See [`OpenOptions::read()`].
+    ///
+    /// [`OpenOptions::read()`]: ../struct.OpenOptions.html#method.read
+    #[inline]
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.opts.read(read);
+        self
+    }
+
+    /// Enable the option for write access.
+    ///
+    /// See [`OpenOptions::write()`].
+    ///
+    /// [`OpenOptions::write()`]: ../struct.OpenOptions.html#method.write
+    #[inline]
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.opts.write(write);
+        self
+    }
+
+    /// Create a new file if it does not exist.
+    ///
+    /// See [`OpenOptions::create()`].
+    ///
+    /// [`OpenOptions::create()`]: ../struct.OpenOptions.html#method.create
+    #[inline]
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.opts.create(create);
+        self
+    }
+
+    /// Create a new file, failing if it already exists.
+    ///
+    /// See [`OpenOptions::create_new()`].
+    ///
+    /// [`OpenOptions::create_new()`]: ../struct.OpenOptions.html#method.create_new
+    #[inline]
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.opts.create_new(create_new);
+        self
+    }
+
+    /// Enable append mode.
+    ///
+    /// See [`OpenOptions::append()`].
+    ///
+    /// [`OpenOptions::append()`]: ../struct.OpenOptions.html#method.append
+    #[inline]
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.opts.append(append);
+        self
+    }
+
+    /// If the file already exists, truncate it while opening.
+    ///
+    /// See [`OpenOptions::truncate()`].
+    ///
+    /// [`OpenOptions::truncate()`]: ../struct.OpenOptions.html#method.truncate
+    #[inline]
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.opts.truncate(truncate);
+        self
+    }
+
+    /// Linux-specific: Create an unnamed temporary file instead of opening an existing file.
+    ///
+    /// See [`OpenOptions::tmpfile()`].
+    ///
+    /// [`OpenOptions::tmpfile()`]: ../struct.OpenOptions.html#method.tmpfile
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[inline]
+    pub fn tmpfile(&mut self, tmpfile: bool) -> &mut Self {
+        self.opts.tmpfile(tmpfile);
+        self
+    }
+
+    /// Open the file in non-blocking mode (with `O_NONBLOCK`).
+    ///
+    /// See [`OpenOptions::nonblock()`].
+    ///
+    /// [`OpenOptions::nonblock()`]: ../struct.OpenOptions.html#method.nonblock
+    #[inline]
+    pub fn nonblock(&mut self, nonblock: bool) -> &mut Self {
+        self.opts.nonblock(nonblock);
+        self
+    }
+
+    /// Set whether the returned file descriptor will have its close-on-exec flag set.
+    ///
+    /// See [`OpenOptions::cloexec()`].
+    ///
+    /// [`OpenOptions::cloexec()`]: ../struct.OpenOptions.html#method.cloexec
+    #[inline]
+    pub fn cloexec(&mut self, cloexec: bool) -> &mut Self {
+        self.opts.cloexec(cloexec);
+        self
+    }
+
+    /// Set the maximum number of symlinks that may be followed while resolving the path.
+    ///
+    /// See [`OpenOptions::max_symlinks()`].
+    ///
+    /// [`OpenOptions::max_symlinks()`]: ../struct.OpenOptions.html#method.max_symlinks
+    #[inline]
+    pub fn max_symlinks(&mut self, max_symlinks: Option<usize>) -> &mut Self {
+        self.opts.max_symlinks(max_symlinks);
+        self
+    }
+
+    /// Set the number of times that opening the file will be retried if path resolution fails with
+    /// `EAGAIN`.
+    ///
+    /// See [`OpenOptions::retries()`].
+    ///
+    /// [`OpenOptions::retries()`]: ../struct.OpenOptions.html#method.retries
+    #[inline]
+    pub fn retries(&mut self, retries: Option<u32>) -> &mut Self {
+        self.opts.retries(retries);
+        self
+    }
+
+    /// With `LookupFlags::NO_XDEV`, allow crossing into any of the given mounts.
+    ///
+    /// See [`OpenOptions::allow_mounts()`].
+    ///
+    /// [`OpenOptions::allow_mounts()`]: ../struct.OpenOptions.html#method.allow_mounts
+    #[cfg(feature = "mount-id")]
+    #[inline]
+    pub fn allow_mounts(&mut self, mounts: &[MountId]) -> &mut Self {
+        self.opts.allow_mounts(mounts);
+        self
+    }
+
+    /// Set a deadline for resolving the path; if it passes, opening the file fails with
+    /// `ETIMEDOUT`.
+    ///
+    /// See [`OpenOptions::deadline()`].
+    ///
+    /// [`OpenOptions::deadline()`]: ../struct.OpenOptions.html#method.deadline
+    #[inline]
+    pub fn deadline(&mut self, deadline: Option<Instant>) -> &mut Self {
+        self.opts.deadline(deadline);
+        self
+    }
+
+    /// Require every component of the path to be owned by the given user; otherwise, opening the
+    /// file fails with `EPERM`.
+    ///
+    /// See [`OpenOptions::require_owner()`].
+    ///
+    /// [`OpenOptions::require_owner()`]: ../struct.OpenOptions.html#method.require_owner
+    #[inline]
+    pub fn require_owner(&mut self, uid: Option<libc::uid_t>) -> &mut Self {
+        self.opts.require_owner(uid);
+        self
+    }
+
+    /// Refuse to traverse or open world-writable components; opening the file fails with `EPERM`
+    /// instead.
+    ///
+    /// See [`OpenOptions::forbid_world_writable()`].
+    ///
+    /// [`OpenOptions::forbid_world_writable()`]: ../struct.OpenOptions.html#method.forbid_world_writable
+    #[inline]
+    pub fn forbid_world_writable(&mut self, forbid: bool) -> &mut Self {
+        self.opts.forbid_world_writable(forbid);
+        self
+    }
+
+    /// Reject components that are not allowed by the given filter; opening the file fails with
+    /// `EPERM` instead.
+    ///
+    /// See [`OpenOptions::component_filter()`].
+    ///
+    /// [`OpenOptions::component_filter()`]: ../struct.OpenOptions.html#method.component_filter
+    #[inline]
+    pub fn component_filter(&mut self, filter: Option<ComponentFilter>) -> &mut Self {
+        self.opts.component_filter(filter);
+        self
+    }
+
+    /// Set a hook that will be called for every step of path resolution (or `None` to remove it).
+    ///
+    /// See [`OpenOptions::audit()`].
+    ///
+    /// [`OpenOptions::audit()`]: ../struct.OpenOptions.html#method.audit
+    #[inline]
+    pub fn audit(&mut self, hook: Option<AuditHook>) -> &mut Self {
+        self.opts.audit(hook);
+        self
+    }
+
+    /// Require the opened file to be of the given type (or `None` to accept any type).
+    ///
+    /// See [`OpenOptions::expect()`].
+    ///
+    /// [`OpenOptions::expect()`]: ../struct.OpenOptions.html#method.expect
+    #[inline]
+    pub fn expect(&mut self, ftype: Option<FileType>) -> &mut Self {
+        self.opts.expect(ftype);
+        self
+    }
+
+    /// Set the mode with which the file will be opened (e.g `0o777`).
+    ///
+    /// See [`OpenOptions::mode()`].
+    ///
+    /// [`OpenOptions::mode()`]: ../struct.OpenOptions.html#method.mode
+    #[inline]
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.opts.mode(mode);
+        self
+    }
+
+    /// Pass custom flags when opening the file.
+    ///
+    /// See [`OpenOptions::custom_flags()`].
+    ///
+    /// [`OpenOptions::custom_flags()`]: ../struct.OpenOptions.html#method.custom_flags
+    #[inline]
+    pub fn custom_flags(&mut self, flags: libc::c_int) -> &mut Self {
+        self.opts.custom_flags(flags);
+        self
+    }
+
+    /// Set the "lookup flags" used when opening the file.
+    ///
+    /// See [`OpenOptions::lookup_flags()`].
+    ///
+    /// [`OpenOptions::lookup_flags()`]: ../struct.OpenOptions.html#method.lookup_flags
+    #[inline]
+    pub fn lookup_flags(&mut self, lookup_flags: LookupFlags) -> &mut Self {
+        self.opts.lookup_flags(lookup_flags);
+        self
+    }
+
+    /// Set whether to first try opening the file on the current thread, with
+    /// [`LookupFlags::CACHED_ONLY`].
+    ///
+    /// If path resolution can be completed using the kernel's caches, this avoids the overhead of
+    /// moving the operation to tokio's blocking thread pool. Otherwise (or if `CACHED_ONLY` isn't
+    /// supported), the file is opened on the blocking thread pool as usual.
+    ///
+    /// This only applies to path resolution; opening the file itself may still block (for
+    /// example, opening a FIFO without [`nonblock()`]), so only enable this if that is not a
+    /// concern.
+    ///
+    /// [`LookupFlags::CACHED_ONLY`]: ../struct.LookupFlags.html#associatedconstant.CACHED_ONLY
+    /// [`nonblock()`]: #method.nonblock
+    #[inline]
+    pub fn try_cached(&mut self, try_cached: bool) -> &mut Self {
+        self.try_cached = try_cached;
+        self
+    }
+
+    /// Open the file at `path` with the options specified by `self`.
+    pub async fn open<P: AsPath>(&self, path: P) -> io::Result<tokio_rs::fs::File> {
+        if self.try_cached {
+            let mut opts = self.opts.clone();
+            opts.lookup_flags(self.opts.get_lookup_flags() | LookupFlags::CACHED_ONLY);
+
+            match opts.open_at(&self.dir, path.as_path()) {
+                Err(e) if e.raw_os_error() == Some(libc::EAGAIN) => (),
+                res => return res.map(tokio_rs::fs::File::from_std),
+            }
+        }
+
+        let path = path.as_path().to_path_buf();
+        let dir = self.dir.clone();
+        let opts = self.opts.clone();
+
+        let file: fs::File = run_blocking(move || opts.open_at(&dir, path)).await?;
+
+        Ok(tokio_rs::fs::File::from_std(file))
+    }
+}
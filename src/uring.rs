@@ -0,0 +1,470 @@
+//! Batched operations using `io_uring` (Linux only; requires the `io-uring` feature).
+//!
+//! Opening many files beneath a directory one at a time requires at least one syscall per file.
+//! A [`Ring`] submits a whole batch of `openat2()` (and `statx()`) operations to the kernel at
+//! once, which amortizes that overhead (for example, in static file servers).
+//!
+//! The same resolution rules apply as for [`open_beneath()`]: paths that `openat2()` can't handle
+//! (for example, because of the lookup flags), or that fail in a way that requires falling back
+//! on the manual resolver, are opened normally after the batch completes. Likewise, if `io_uring`
+//! isn't available (on kernels older than 5.6, or if it's blocked by a seccomp rule), everything
+//! is opened normally.
+//!
+//! [`Ring`]: ./struct.Ring.html
+//! [`open_beneath()`]: ../fn.open_beneath.html
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{constants, open, open_beneath, sys, AsPath, Dir, LookupFlags, Metadata, Rights};
+
+/// The number of entries used by [`Ring::new()`](./struct.Ring.html#method.new).
+pub const DEFAULT_ENTRIES: u32 = 64;
+
+#[derive(Debug)]
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: RawFd, len: usize, offset: libc::off_t) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+
+        if ptr == libc::MAP_FAILED {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(Self { ptr, len })
+        }
+    }
+
+    #[inline]
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        debug_assert!(offset as usize + std::mem::size_of::<T>() <= self.len);
+        (self.ptr as *mut u8).add(offset as usize) as *mut T
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+/// An `io_uring` instance that can be used to open (or retrieve the metadata of) many files
+/// beneath a directory at once.
+///
+/// A `Ring` can be reused for any number of batches (with any number of directories).
+#[derive(Debug)]
+pub struct Ring {
+    // Field order matters: the mappings must be unmapped before the ring is closed
+    sq_ring: Mmap,
+    cq_ring: Mmap,
+    sqes: Mmap,
+    params: sys::io_uring_params,
+    fd: fs::File,
+    // Set if an error left operations in flight; the ring can't be used after that
+    poisoned: bool,
+}
+
+// The ring is only ever accessed through a `&mut Ring`
+unsafe impl Send for Ring {}
+
+impl Ring {
+    /// Create a new `Ring` with [`DEFAULT_ENTRIES`] entries.
+    ///
+    /// This fails with `ENOSYS` if `io_uring` is not supported by the kernel (or `EPERM` if it has
+    /// been disabled). However, the methods of [`Ring`] fall back on opening files normally in
+    /// that case, so it is usually better to use [`open_many()`] instead of handling that error.
+    ///
+    /// [`DEFAULT_ENTRIES`]: ./constant.DEFAULT_ENTRIES.html
+    /// [`Ring`]: ./struct.Ring.html
+    /// [`open_many()`]: ./fn.open_many.html
+    #[inline]
+    pub fn new() -> io::Result<Self> {
+        Self::with_entries(DEFAULT_ENTRIES)
+    }
+
+    /// Create a new `Ring` that can submit up to `entries` operations at once.
+    ///
+    /// The kernel rounds `entries` up to a power of two. Batches larger than that are split up
+    /// and submitted in several parts.
+    pub fn with_entries(entries: u32) -> io::Result<Self> {
+        let mut params = sys::io_uring_params::default();
+
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries,
+                &mut params as *mut sys::io_uring_params,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { fs::File::from_raw_fd(fd as RawFd) };
+
+        let sq_ring = Mmap::new(
+            fd.as_raw_fd(),
+            params.sq_off.array as usize + params.sq_entries as usize * 4,
+            sys::IORING_OFF_SQ_RING,
+        )?;
+        let cq_ring = Mmap::new(
+            fd.as_raw_fd(),
+            params.cq_off.cqes as usize
+                + params.cq_entries as usize * std::mem::size_of::<sys::io_uring_cqe>(),
+            sys::IORING_OFF_CQ_RING,
+        )?;
+        let sqes = Mmap::new(
+            fd.as_raw_fd(),
+            params.sq_entries as usize * std::mem::size_of::<sys::io_uring_sqe>(),
+            sys::IORING_OFF_SQES,
+        )?;
+
+        Ok(Self {
+            sq_ring,
+            cq_ring,
+            sqes,
+            params,
+            fd,
+            poisoned: false,
+        })
+    }
+
+    /// Open each of the given `paths` beneath `dir`, as if by calling
+    /// `dir.open_file().custom_flags(flags).mode(mode).lookup_flags(lookup_flags).open(path)`
+    /// on each one (`flags` should include the access mode, for example `libc::O_RDONLY`).
+    ///
+    /// The results are returned in the same order as the paths.
+    pub fn open_many<P: AsPath>(
+        &mut self,
+        dir: &Dir,
+        paths: &[P],
+        flags: libc::c_int,
+        mode: libc::mode_t,
+        lookup_flags: LookupFlags,
+    ) -> Vec<io::Result<fs::File>> {
+        if let Some(errs) = check_rights_many(dir, paths, crate::dir::open_rights(flags)) {
+            return errs;
+        }
+
+        let dir_fd = dir.as_raw_fd();
+
+        let mut results: Vec<Option<io::Result<fs::File>>> = self
+            .submit_opens(dir_fd, paths, flags, mode, lookup_flags)
+            .into_iter()
+            .map(|res| res.transpose())
+            .collect();
+
+        // Open everything that couldn't be handled by the ring normally
+        paths
+            .iter()
+            .zip(results.iter_mut())
+            .map(|(path, res)| match res.take() {
+                Some(res) => res,
+                None => open_beneath(dir_fd, path.as_path(), flags, mode, lookup_flags),
+            })
+            .collect()
+    }
+
+    /// Retrieve information on each of the given `paths` beneath `dir`, as if by calling
+    /// [`dir.metadata(path, lookup_flags)`](../struct.Dir.html#method.metadata) on each one.
+    ///
+    /// Each file is opened with `O_PATH` (which doesn't require any permissions on the file
+    /// itself), and then its metadata is retrieved with `statx()`; both steps are submitted
+    /// through the ring. The results are returned in the same order as the paths.
+    pub fn metadata_many<P: AsPath>(
+        &mut self,
+        dir: &Dir,
+        paths: &[P],
+        lookup_flags: LookupFlags,
+    ) -> Vec<io::Result<Metadata>> {
+        if let Some(errs) = check_rights_many(dir, paths, Rights::READ) {
+            return errs;
+        }
+
+        let files = self.submit_opens(
+            dir.as_raw_fd(),
+            paths,
+            constants::REF_OPEN_FLAGS | libc::O_NOFOLLOW,
+            0,
+            lookup_flags,
+        );
+
+        let fds: Vec<Option<RawFd>> = files
+            .iter()
+            .map(|file| match file {
+                Ok(Some(file)) => Some(file.as_raw_fd()),
+                _ => None,
+            })
+            .collect();
+
+        let mut stxs: Vec<sys::statx> = Vec::with_capacity(fds.len());
+        stxs.resize_with(fds.len(), || unsafe { std::mem::zeroed() });
+
+        let sqes: Vec<Option<sys::io_uring_sqe>> = fds
+            .iter()
+            .zip(stxs.iter_mut())
+            .map(|(fd, stx)| {
+                fd.map(|fd| sys::io_uring_sqe {
+                    opcode: sys::IORING_OP_STATX,
+                    fd,
+                    addr: b"\0".as_ptr() as u64,
+                    len: sys::STATX_MASK,
+                    op_flags: libc::AT_EMPTY_PATH as u32,
+                    off: stx as *mut sys::statx as u64,
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let res = self.submit(&sqes);
+        if self.poisoned {
+            // Some of the operations may still be in flight, and the kernel may write to the
+            // buffers later
+            std::mem::forget(stxs);
+            return paths
+                .iter()
+                .map(|path| dir.metadata(path.as_path(), lookup_flags))
+                .collect();
+        }
+
+        paths
+            .iter()
+            .zip(files)
+            .zip(res.into_iter().zip(stxs.iter()))
+            .map(|((path, file), (res, stx))| match (file, res) {
+                (Ok(Some(_)), Some(0)) => Ok(Metadata::from_statx(stx)),
+                (Ok(Some(_)), Some(eno)) if eno < 0 => Err(io::Error::from_raw_os_error(-eno)),
+                (Err(e), _) => Err(e),
+                _ => dir.metadata(path.as_path(), lookup_flags),
+            })
+            .collect()
+    }
+
+    /// Submit `openat2()` operations for each of the given `paths` through the ring.
+    ///
+    /// An `Ok(None)` result means that the path could not be opened through the ring, and it
+    /// should be opened normally.
+    fn submit_opens<P: AsPath>(
+        &mut self,
+        dir_fd: RawFd,
+        paths: &[P],
+        flags: libc::c_int,
+        mode: libc::mode_t,
+        lookup_flags: LookupFlags,
+    ) -> Vec<io::Result<Option<fs::File>>> {
+        let prepared: Vec<io::Result<Option<(CString, openat2_rs::OpenHow)>>> = paths
+            .iter()
+            .map(|path| {
+                open::check_flags(flags)?;
+
+                path.with_cstr(|s| {
+                    Ok(open::prepare_openat2(dir_fd, s, flags, mode, lookup_flags)?
+                        .map(|(path, how)| (path.into_owned(), how)))
+                })
+            })
+            .collect();
+
+        let sqes: Vec<Option<sys::io_uring_sqe>> = prepared
+            .iter()
+            .map(|prep| match prep {
+                Ok(Some((path, how))) => Some(sys::io_uring_sqe {
+                    opcode: sys::IORING_OP_OPENAT2,
+                    fd: dir_fd,
+                    addr: path.as_ptr() as u64,
+                    len: std::mem::size_of::<openat2_rs::OpenHow>() as u32,
+                    off: how as *const openat2_rs::OpenHow as u64,
+                    ..Default::default()
+                }),
+                _ => None,
+            })
+            .collect();
+
+        // The kernel copies the path and the open_how structure when the operations are
+        // submitted, so it's safe to free them afterward even if the operations are still in
+        // flight
+        let res = self.submit(&sqes);
+
+        prepared
+            .into_iter()
+            .zip(res)
+            .map(|(prep, res)| match (prep, res) {
+                (Err(e), _) => Err(e),
+                (Ok(None), _) | (Ok(Some(_)), None) => Ok(None),
+                (Ok(Some(_)), Some(res)) => open::finish_openat2(
+                    if res < 0 {
+                        Err(io::Error::from_raw_os_error(-res))
+                    } else {
+                        Ok(res)
+                    },
+                    lookup_flags,
+                ),
+            })
+            .collect()
+    }
+
+    /// Submit all of the given operations through the ring and wait for them to complete.
+    ///
+    /// The result of each operation (a file descriptor or other non-negative value on success, or
+    /// a negated error number on failure) is returned; `None` is returned for `None` entries, and
+    /// for all of the operations that couldn't be completed if submitting them fails. In that
+    /// case, if any operations are still in flight, the ring is marked as poisoned (and no
+    /// further operations will be submitted through it).
+    fn submit(&mut self, sqes: &[Option<sys::io_uring_sqe>]) -> Vec<Option<i32>> {
+        let mut results = vec![None; sqes.len()];
+        if self.poisoned {
+            return results;
+        }
+
+        let sq_entries = self.params.sq_entries as usize;
+        let sq_off = self.params.sq_off;
+        let cq_off = self.params.cq_off;
+
+        let (sq_tail, sq_mask, sq_array, sqe_base, cq_head, cq_tail, cq_mask, cqe_base) = unsafe {
+            (
+                &*self.sq_ring.at::<AtomicU32>(sq_off.tail),
+                *self.sq_ring.at::<u32>(sq_off.ring_mask),
+                self.sq_ring.at::<u32>(sq_off.array),
+                self.sqes.at::<sys::io_uring_sqe>(0),
+                &*self.cq_ring.at::<AtomicU32>(cq_off.head),
+                &*self.cq_ring.at::<AtomicU32>(cq_off.tail),
+                *self.cq_ring.at::<u32>(cq_off.ring_mask),
+                self.cq_ring.at::<sys::io_uring_cqe>(cq_off.cqes),
+            )
+        };
+
+        let pending: Vec<(usize, &sys::io_uring_sqe)> = sqes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, sqe)| sqe.as_ref().map(|sqe| (i, sqe)))
+            .collect();
+
+        // Since each chunk has at most `sq_entries` operations (and the completion queue is at
+        // least that large), and we wait for every chunk to complete before submitting the next
+        // one, neither queue can overflow.
+        for chunk in pending.chunks(sq_entries) {
+            let tail = sq_tail.load(Ordering::Relaxed);
+
+            for (i, &(index, sqe)) in chunk.iter().enumerate() {
+                let slot = tail.wrapping_add(i as u32) & sq_mask;
+
+                unsafe {
+                    let entry = sqe_base.add(slot as usize);
+                    entry.write(*sqe);
+                    (*entry).user_data = index as u64;
+                    sq_array.add(slot as usize).write(slot);
+                }
+            }
+
+            sq_tail.store(tail.wrapping_add(chunk.len() as u32), Ordering::Release);
+
+            let mut submitted = 0;
+            let mut completed = 0;
+
+            while completed < chunk.len() {
+                let res = unsafe {
+                    libc::syscall(
+                        libc::SYS_io_uring_enter,
+                        self.fd.as_raw_fd(),
+                        (chunk.len() - submitted) as libc::c_uint,
+                        1 as libc::c_uint,
+                        sys::IORING_ENTER_GETEVENTS,
+                        std::ptr::null::<libc::sigset_t>(),
+                        0usize,
+                    )
+                };
+
+                if res < 0 {
+                    match io::Error::last_os_error().raw_os_error() {
+                        Some(libc::EINTR) => continue,
+                        _ => {
+                            // Either nothing was submitted (so we can keep going, and just fall
+                            // back on the normal methods), or some operations are still in flight
+                            // and we don't know when they'll complete
+                            if submitted == 0 {
+                                // Un-submit them
+                                sq_tail.store(tail, Ordering::Release);
+                            }
+                            self.poisoned = submitted > completed;
+                            return results;
+                        }
+                    }
+                }
+                submitted += res as usize;
+
+                let mut head = cq_head.load(Ordering::Relaxed);
+                let end = cq_tail.load(Ordering::Acquire);
+
+                while head != end {
+                    let cqe = unsafe { cqe_base.add((head & cq_mask) as usize).read() };
+                    results[cqe.user_data as usize] = Some(cqe.res);
+                    head = head.wrapping_add(1);
+                    completed += 1;
+                }
+
+                cq_head.store(head, Ordering::Release);
+            }
+        }
+
+        results
+    }
+}
+
+/// Open each of the given `paths` beneath `dir`, submitting the operations through a new
+/// [`Ring`] if possible.
+///
+/// See [`Ring::open_many()`] for more details. If `io_uring` is not available, this simply opens
+/// each path in turn.
+///
+/// [`Ring`]: ./struct.Ring.html
+/// [`Ring::open_many()`]: ./struct.Ring.html#method.open_many
+pub fn open_many<P: AsPath>(
+    dir: &Dir,
+    paths: &[P],
+    flags: libc::c_int,
+    mode: libc::mode_t,
+    lookup_flags: LookupFlags,
+) -> Vec<io::Result<fs::File>> {
+    match Ring::with_entries(paths.len().clamp(1, DEFAULT_ENTRIES as usize) as u32) {
+        Ok(mut ring) => ring.open_many(dir, paths, flags, mode, lookup_flags),
+        Err(_) => paths
+            .iter()
+            .map(|path| {
+                dir.check_rights(crate::dir::open_rights(flags))?;
+                open_beneath(dir.as_raw_fd(), path.as_path(), flags, mode, lookup_flags)
+            })
+            .collect(),
+    }
+}
+
+/// If `dir` is missing any of the `needed` rights, return an error for each of the `paths` (so
+/// that the results still line up with the paths).
+fn check_rights_many<P, T>(dir: &Dir, paths: &[P], needed: Rights) -> Option<Vec<io::Result<T>>> {
+    let missing = needed - dir.rights();
+
+    if missing.is_empty() {
+        None
+    } else {
+        Some(
+            paths
+                .iter()
+                .map(|_| Err(crate::Error::MissingRights(missing).into()))
+                .collect(),
+        )
+    }
+}
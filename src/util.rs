@@ -1,11 +1,12 @@
-use std::ffi::{CStr, OsStr, OsString};
+use std::ffi::{CStr, CString, OsStr};
 use std::fs;
 use std::io;
 use std::mem::MaybeUninit;
 use std::os::unix::prelude::*;
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[cfg(any(target_os = "linux", target_os = "dragonfly"))]
+#[cfg(any(target_os = "linux", target_os = "dragonfly", target_os = "redox"))]
 pub use libc::__errno_location as errno_ptr;
 
 #[cfg(any(target_os = "freebsd", target_os = "macos"))]
@@ -14,6 +15,12 @@ pub use libc::__error as errno_ptr;
 #[cfg(any(target_os = "android", target_os = "netbsd", target_os = "openbsd"))]
 pub use libc::__errno as errno_ptr;
 
+#[cfg(any(target_os = "solaris", target_os = "illumos"))]
+pub use libc::___errno as errno_ptr;
+
+#[cfg(target_os = "haiku")]
+pub use libc::_errnop as errno_ptr;
+
 #[derive(Debug)]
 pub struct SymlinkCounter {
     max: u16,
@@ -38,6 +45,11 @@ impl SymlinkCounter {
         Self { max: 0, cur: 0 }
     }
 
+    #[inline]
+    pub fn with_max(max: u16) -> Self {
+        Self { max, cur: 0 }
+    }
+
     #[inline]
     pub fn exhausted(&self) -> bool {
         self.cur >= self.max
@@ -80,6 +92,31 @@ pub fn renameat2(
     }
 }
 
+#[cfg(target_os = "macos")]
+#[inline]
+pub fn renameatx_np(
+    old_dfd: RawFd,
+    old_path: &CStr,
+    new_dfd: RawFd,
+    new_path: &CStr,
+    flags: libc::c_uint,
+) -> io::Result<()> {
+    if unsafe {
+        libc::renameatx_np(
+            old_dfd,
+            old_path.as_ptr(),
+            new_dfd,
+            new_path.as_ptr(),
+            flags,
+        )
+    } < 0
+    {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
 #[inline]
 pub fn fstat(fd: RawFd) -> io::Result<libc::stat> {
     let mut stat = MaybeUninit::uninit();
@@ -102,6 +139,80 @@ pub fn fstatat(fd: RawFd, path: &CStr, flags: libc::c_int) -> io::Result<libc::s
     }
 }
 
+pub fn fallocate(fd: RawFd, offset: u64, len: u64) -> io::Result<()> {
+    use std::convert::TryInto;
+
+    let offset: libc::off_t = offset
+        .try_into()
+        .map_err(|_| io::Error::from_raw_os_error(libc::EFBIG))?;
+    let len: libc::off_t = len
+        .try_into()
+        .map_err(|_| io::Error::from_raw_os_error(libc::EFBIG))?;
+
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "linux", target_os = "android"))] {
+            loop {
+                if unsafe { libc::fallocate(fd, 0, offset, len) } == 0 {
+                    return Ok(());
+                }
+
+                let err = io::Error::last_os_error();
+                if err.raw_os_error() != Some(libc::EINTR) {
+                    return Err(err);
+                }
+            }
+        } else if #[cfg(target_os = "freebsd")] {
+            // posix_fallocate() returns the error number instead of setting errno
+            match unsafe { libc::posix_fallocate(fd, offset, len) } {
+                0 => Ok(()),
+                // Returned if the filesystem doesn't support it
+                libc::EINVAL => Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP)),
+                eno => Err(io::Error::from_raw_os_error(eno)),
+            }
+        } else {
+            let _ = (fd, offset, len);
+            Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+        }
+    }
+}
+
+#[inline]
+pub fn faccessat(fd: RawFd, path: &CStr, mode: libc::c_int, flags: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::faccessat(fd, path.as_ptr(), mode, flags) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos"
+))]
+#[inline]
+pub fn fstatfs(fd: RawFd) -> io::Result<libc::statfs> {
+    let mut buf = MaybeUninit::uninit();
+
+    if unsafe { libc::fstatfs(fd, buf.as_mut_ptr()) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(unsafe { buf.assume_init() })
+    }
+}
+
+#[inline]
+pub fn fstatvfs(fd: RawFd) -> io::Result<libc::statvfs> {
+    let mut buf = MaybeUninit::uninit();
+
+    if unsafe { libc::fstatvfs(fd, buf.as_mut_ptr()) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(unsafe { buf.assume_init() })
+    }
+}
+
 #[inline]
 pub fn samestat(st1: &libc::stat, st2: &libc::stat) -> bool {
     st1.st_ino == st2.st_ino && st1.st_dev == st2.st_dev
@@ -118,6 +229,64 @@ pub fn dup(fd: RawFd) -> io::Result<RawFd> {
     }
 }
 
+pub fn set_cloexec(fd: RawFd, cloexec: bool) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let new_flags = if cloexec {
+        flags | libc::FD_CLOEXEC
+    } else {
+        flags & !libc::FD_CLOEXEC
+    };
+
+    if new_flags != flags && unsafe { libc::fcntl(fd, libc::F_SETFD, new_flags) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+pub fn set_nonblock(fd: RawFd, nonblock: bool) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let new_flags = if nonblock {
+        flags | libc::O_NONBLOCK
+    } else {
+        flags & !libc::O_NONBLOCK
+    };
+
+    if new_flags != flags && unsafe { libc::fcntl(fd, libc::F_SETFL, new_flags) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos"
+))]
+#[inline]
+pub fn lseek(fd: RawFd, offset: i64, whence: libc::c_int) -> io::Result<i64> {
+    #[cfg(target_os = "linux")]
+    let res = unsafe { libc::lseek64(fd, offset, whence) };
+    #[cfg(not(target_os = "linux"))]
+    let res = unsafe { libc::lseek(fd, offset as libc::off_t, whence) } as i64;
+
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res)
+    }
+}
+
 #[inline]
 pub fn openat_raw(
     dir_fd: RawFd,
@@ -153,7 +322,13 @@ pub fn openat(
 
 pub fn readlinkat(dir_fd: RawFd, path: &CStr) -> io::Result<PathBuf> {
     let mut buf = [0u8; libc::PATH_MAX as usize];
+    let target = readlinkat_buf(dir_fd, path, &mut buf)?;
+    Ok(PathBuf::from(OsStr::from_bytes(target)))
+}
 
+// Read the target of the given symlink into `buf`, returning the portion that was filled. `buf`
+// should be at least PATH_MAX bytes long.
+pub fn readlinkat_buf<'a>(dir_fd: RawFd, path: &CStr, buf: &'a mut [u8]) -> io::Result<&'a [u8]> {
     match unsafe {
         libc::readlinkat(
             dir_fd,
@@ -190,7 +365,7 @@ pub fn readlinkat(dir_fd: RawFd, path: &CStr) -> io::Result<PathBuf> {
                 }
             }
 
-            Ok(PathBuf::from(OsString::from_vec(buf[..len].into())))
+            Ok(&buf[..len])
         }
     }
 }
@@ -204,6 +379,31 @@ pub fn mkdirat(dir_fd: RawFd, path: &CStr, mode: libc::mode_t) -> io::Result<()>
     }
 }
 
+#[inline]
+pub fn mknodat(dir_fd: RawFd, path: &CStr, mode: libc::mode_t, dev: libc::dev_t) -> io::Result<()> {
+    if unsafe { libc::mknodat(dir_fd, path.as_ptr(), mode, dev) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[inline]
+pub fn mkfifoat(dir_fd: RawFd, path: &CStr, mode: libc::mode_t) -> io::Result<()> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "android")] {
+            // Android's libc doesn't expose mkfifoat()
+            mknodat(dir_fd, path, libc::S_IFIFO | (mode & !libc::S_IFMT), 0)
+        } else {
+            if unsafe { libc::mkfifoat(dir_fd, path.as_ptr(), mode) } < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
 #[inline]
 pub fn unlinkat(dir_fd: RawFd, path: &CStr, dir: bool) -> io::Result<()> {
     if unsafe {
@@ -253,6 +453,30 @@ pub fn linkat(
     }
 }
 
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn link_tmpfile(fd: RawFd, new_dfd: RawFd, new_path: &CStr) -> io::Result<()> {
+    // This requires CAP_DAC_READ_SEARCH; if we don't have it, it will fail with ENOENT
+    match linkat(
+        fd,
+        unsafe { CStr::from_bytes_with_nul_unchecked(b"\0") },
+        new_dfd,
+        new_path,
+        libc::AT_EMPTY_PATH,
+    ) {
+        Err(e) if matches!(e.raw_os_error(), Some(libc::ENOENT) | Some(libc::EPERM)) => (),
+        res => return res,
+    }
+
+    // Fall back on linking the magic link in /proc (see open(2))
+    linkat(
+        libc::AT_FDCWD,
+        &proc_fd_path(fd),
+        new_dfd,
+        new_path,
+        libc::AT_SYMLINK_FOLLOW,
+    )
+}
+
 #[inline]
 pub fn renameat(
     old_dfd: RawFd,
@@ -267,6 +491,377 @@ pub fn renameat(
     }
 }
 
+#[inline]
+pub fn fchmod(fd: RawFd, mode: libc::mode_t) -> io::Result<()> {
+    if unsafe { libc::fchmod(fd, mode) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[inline]
+pub fn fchmodat(
+    dir_fd: RawFd,
+    path: &CStr,
+    mode: libc::mode_t,
+    flags: libc::c_int,
+) -> io::Result<()> {
+    if unsafe { libc::fchmodat(dir_fd, path.as_ptr(), mode, flags) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[inline]
+pub fn fchownat(
+    dir_fd: RawFd,
+    path: &CStr,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    flags: libc::c_int,
+) -> io::Result<()> {
+    if unsafe { libc::fchownat(dir_fd, path.as_ptr(), uid, gid, flags) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+// Like fchown(), but on Linux this also works for O_PATH file descriptors
+#[inline]
+pub fn fchown_any(fd: RawFd, uid: libc::uid_t, gid: libc::gid_t) -> io::Result<()> {
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "linux", target_os = "android"))] {
+            fchownat(
+                fd,
+                unsafe { CStr::from_bytes_with_nul_unchecked(b"\0") },
+                uid,
+                gid,
+                libc::AT_EMPTY_PATH,
+            )
+        } else {
+            if unsafe { libc::fchown(fd, uid, gid) } < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Build a `timespec` from the seconds/nanoseconds fields of a `struct stat`.
+#[inline]
+pub fn timespec_from_stat(sec: libc::time_t, nsec: libc::c_long) -> libc::timespec {
+    let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+    ts.tv_sec = sec;
+    ts.tv_nsec = nsec as _;
+    ts
+}
+
+pub fn timespec_from_systime(time: Option<SystemTime>) -> io::Result<libc::timespec> {
+    use std::convert::TryInto;
+
+    let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+
+    let time = match time {
+        Some(time) => time,
+        None => {
+            ts.tv_nsec = libc::UTIME_OMIT as _;
+            return Ok(ts);
+        }
+    };
+
+    let (secs, nsecs) = match time.duration_since(UNIX_EPOCH) {
+        Ok(dur) => (dur.as_secs() as i128, dur.subsec_nanos()),
+
+        // Before the epoch
+        Err(e) => {
+            let dur = e.duration();
+
+            match dur.subsec_nanos() {
+                0 => (-(dur.as_secs() as i128), 0),
+                nsecs => (-(dur.as_secs() as i128) - 1, 1_000_000_000 - nsecs),
+            }
+        }
+    };
+
+    ts.tv_sec = secs
+        .try_into()
+        .map_err(|_| io::Error::from_raw_os_error(libc::EOVERFLOW))?;
+    ts.tv_nsec = nsecs as _;
+
+    Ok(ts)
+}
+
+#[inline]
+pub fn utimensat(
+    dir_fd: RawFd,
+    path: &CStr,
+    times: &[libc::timespec; 2],
+    flags: libc::c_int,
+) -> io::Result<()> {
+    if unsafe { libc::utimensat(dir_fd, path.as_ptr(), times.as_ptr(), flags) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[inline]
+pub fn futimens(fd: RawFd, times: &[libc::timespec; 2]) -> io::Result<()> {
+    if unsafe { libc::futimens(fd, times.as_ptr()) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+// Like futimens(), but on Linux this also works for O_PATH file descriptors (which futimens()
+// rejects with EBADF)
+pub fn futimens_any(fd: RawFd, times: &[libc::timespec; 2]) -> io::Result<()> {
+    match futimens(fd, times) {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Err(e) if e.raw_os_error() == Some(libc::EBADF) && fd >= 0 => {
+            match utimensat(libc::AT_FDCWD, &proc_fd_path(fd), times, 0) {
+                // The file descriptor isn't open
+                Err(e2) if e2.raw_os_error() == Some(libc::ENOENT) => Err(e),
+                res => res,
+            }
+        }
+
+        res => res,
+    }
+}
+
+#[inline]
+pub fn fsync(fd: RawFd) -> io::Result<()> {
+    if unsafe { libc::fsync(fd) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+pub fn fsync_dir_any(fd: RawFd) -> io::Result<()> {
+    match fsync(fd) {
+        // Directory file descriptors opened with O_PATH (Linux) or O_EXEC (FreeBSD) can't be
+        // fsync()ed directly, so reopen it for reading
+        Err(e) if e.raw_os_error() == Some(libc::EBADF) && fd >= 0 => {
+            let file = open_dot(fd, libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+            fsync(file.as_raw_fd())
+        }
+
+        res => res,
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[inline]
+pub fn proc_fd_path(fd: RawFd) -> CString {
+    CString::new(format!("/proc/self/fd/{}", fd)).unwrap()
+}
+
+// Like fchmod(), but on Linux this also works for O_PATH file descriptors (which fchmod() rejects
+// with EBADF)
+pub fn fchmod_any(fd: RawFd, mode: libc::mode_t) -> io::Result<()> {
+    match fchmod(fd, mode) {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Err(e) if e.raw_os_error() == Some(libc::EBADF) && fd >= 0 => {
+            // chmod() the magic link in /proc, which refers to the exact file the descriptor is
+            // open to
+            match fchmodat(libc::AT_FDCWD, &proc_fd_path(fd), mode, 0) {
+                // The file descriptor isn't open
+                Err(e2) if e2.raw_os_error() == Some(libc::ENOENT) => Err(e),
+                res => res,
+            }
+        }
+
+        res => res,
+    }
+}
+
+// The extended attribute functions below accept any file descriptor. The f*xattr() functions fail
+// with EBADF on O_PATH file descriptors on Linux, so there we retry through /proc/self/fd (like
+// fchmod_any() does).
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+#[inline]
+fn xattr_res(res: libc::ssize_t) -> io::Result<usize> {
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res as usize)
+    }
+}
+
+// Call `f`, and if it fails with EBADF, call `proc_f` with the magic link in /proc that refers to
+// the file descriptor
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn xattr_any<F, G>(fd: RawFd, f: F, proc_f: G) -> io::Result<usize>
+where
+    F: FnOnce() -> libc::ssize_t,
+    G: FnOnce(&CStr) -> libc::ssize_t,
+{
+    match xattr_res(f()) {
+        Err(e) if e.raw_os_error() == Some(libc::EBADF) && fd >= 0 => {
+            match xattr_res(proc_f(&proc_fd_path(fd))) {
+                // The file descriptor isn't open
+                Err(e2) if e2.raw_os_error() == Some(libc::ENOENT) => Err(e),
+                res => res,
+            }
+        }
+
+        res => res,
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+pub fn fgetxattr_any(fd: RawFd, name: &CStr, buf: &mut [u8]) -> io::Result<usize> {
+    let (ptr, len) = (buf.as_mut_ptr() as *mut libc::c_void, buf.len());
+
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "macos")] {
+            xattr_res(unsafe { libc::fgetxattr(fd, name.as_ptr(), ptr, len, 0, 0) })
+        } else {
+            xattr_any(
+                fd,
+                || unsafe { libc::fgetxattr(fd, name.as_ptr(), ptr, len) },
+                |path| unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), ptr, len) },
+            )
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+pub fn fsetxattr_any(fd: RawFd, name: &CStr, value: &[u8]) -> io::Result<()> {
+    let (ptr, len) = (value.as_ptr() as *const libc::c_void, value.len());
+
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "macos")] {
+            xattr_res(unsafe { libc::fsetxattr(fd, name.as_ptr(), ptr, len, 0, 0) } as _)
+                .map(drop)
+        } else {
+            xattr_any(
+                fd,
+                || unsafe { libc::fsetxattr(fd, name.as_ptr(), ptr, len, 0) } as _,
+                |path| unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), ptr, len, 0) } as _,
+            )
+            .map(drop)
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+pub fn flistxattr_any(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    let (ptr, len) = (buf.as_mut_ptr() as *mut libc::c_char, buf.len());
+
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "macos")] {
+            xattr_res(unsafe { libc::flistxattr(fd, ptr, len, 0) })
+        } else {
+            xattr_any(
+                fd,
+                || unsafe { libc::flistxattr(fd, ptr, len) },
+                |path| unsafe { libc::listxattr(path.as_ptr(), ptr, len) },
+            )
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+pub fn fremovexattr_any(fd: RawFd, name: &CStr) -> io::Result<()> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "macos")] {
+            xattr_res(unsafe { libc::fremovexattr(fd, name.as_ptr(), 0) } as _).map(drop)
+        } else {
+            xattr_any(
+                fd,
+                || unsafe { libc::fremovexattr(fd, name.as_ptr()) } as _,
+                |path| unsafe { libc::removexattr(path.as_ptr(), name.as_ptr()) } as _,
+            )
+            .map(drop)
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[inline]
+pub fn ficlone(dest_fd: RawFd, src_fd: RawFd) -> io::Result<()> {
+    if unsafe { libc::ioctl(dest_fd, libc::FICLONE, src_fd) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "macos")]
+#[inline]
+pub fn fclonefileat(src_fd: RawFd, dst_dir_fd: RawFd, dst: &CStr) -> io::Result<()> {
+    if unsafe { libc::fclonefileat(src_fd, dst_dir_fd, dst.as_ptr(), 0) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+#[inline]
+pub fn copy_file_range(fd_in: RawFd, fd_out: RawFd, len: usize) -> io::Result<usize> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            // Not all libcs we support provide a wrapper, so call the syscall directly
+            let n = unsafe {
+                libc::syscall(
+                    libc::SYS_copy_file_range,
+                    fd_in,
+                    std::ptr::null_mut::<libc::loff_t>(),
+                    fd_out,
+                    std::ptr::null_mut::<libc::loff_t>(),
+                    len,
+                    0,
+                )
+            };
+        } else {
+            let n = unsafe {
+                libc::copy_file_range(
+                    fd_in,
+                    std::ptr::null_mut(),
+                    fd_out,
+                    std::ptr::null_mut(),
+                    len,
+                    0,
+                )
+            };
+        }
+    }
+
+    if n < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(n as usize)
+    }
+}
+
+pub fn random_u64() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    // RandomState is seeded randomly (and its keys change every time it's created), so this is
+    // unpredictable enough without pulling in an RNG
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(dur) = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        hasher.write_u128(dur.as_nanos());
+    }
+
+    hasher.finish()
+}
+
+pub fn temp_name() -> CString {
+    CString::new(format!(".tmp{:016x}", random_u64())).unwrap()
+}
+
 #[inline]
 pub fn open_dot(dir_fd: RawFd, flags: libc::c_int, mode: libc::mode_t) -> io::Result<fs::File> {
     openat(
@@ -461,6 +1056,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_timespec_from_systime() {
+        use std::time::Duration;
+
+        let ts = timespec_from_systime(None).unwrap();
+        assert_eq!(ts.tv_nsec, libc::UTIME_OMIT as _);
+
+        let ts = timespec_from_systime(Some(UNIX_EPOCH)).unwrap();
+        assert_eq!((ts.tv_sec, ts.tv_nsec), (0, 0));
+
+        let ts = timespec_from_systime(Some(UNIX_EPOCH + Duration::new(10, 5))).unwrap();
+        assert_eq!((ts.tv_sec, ts.tv_nsec), (10, 5));
+
+        let ts = timespec_from_systime(Some(UNIX_EPOCH - Duration::new(10, 0))).unwrap();
+        assert_eq!((ts.tv_sec, ts.tv_nsec), (-10, 0));
+
+        let ts = timespec_from_systime(Some(UNIX_EPOCH - Duration::new(10, 5))).unwrap();
+        assert_eq!((ts.tv_sec, ts.tv_nsec), (-11, 999_999_995));
+    }
+
+    #[test]
+    fn test_temp_name() {
+        let name = temp_name();
+        assert!(name.to_bytes().starts_with(b".tmp"));
+        assert_eq!(name.to_bytes().len(), 20);
+        assert_ne!(name, temp_name());
+    }
+
     #[test]
     fn test_errno_ptr() {
         for eno in [0, libc::EEXIST].iter().copied() {
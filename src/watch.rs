@@ -0,0 +1,414 @@
+//! Watching files for changes (requires the `watch` feature).
+//!
+//! On Linux and Android this is backed by inotify, and on macOS and the BSDs by kqueue's
+//! `EVFILT_VNODE` filter. In both cases, files are looked up with the same safe resolution used by
+//! [`Dir::open_file()`], and the watch is registered through the resulting file descriptor rather
+//! than through a path, so a watch can never be placed on a file outside the directory.
+//!
+//! [`Dir::open_file()`]: ../struct.Dir.html#method.open_file
+
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::prelude::*;
+
+use crate::{open_beneath, AsPath, Dir, LookupFlags, Rights};
+
+bitflags::bitflags! {
+    /// The kinds of changes that can be watched for.
+    pub struct WatchMask: u32 {
+        /// The file was modified (or, for a directory, entries were created, removed, or renamed
+        /// within it).
+        const MODIFY = 0x01;
+        /// The file's metadata (permissions, ownership, timestamps, etc.) changed.
+        const ATTRIB = 0x02;
+        /// The file itself was deleted.
+        const DELETE = 0x04;
+        /// The file itself was renamed.
+        const RENAME = 0x08;
+    }
+}
+
+/// Identifies a watch registered with a [`Watcher`].
+///
+/// [`Watcher`]: ./struct.Watcher.html
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct WatchId(RawFd);
+
+/// An event reported by a [`Watcher`].
+///
+/// [`Watcher`]: ./struct.Watcher.html
+#[derive(Clone, Debug)]
+pub struct Event {
+    id: WatchId,
+    mask: WatchMask,
+    name: Option<OsString>,
+}
+
+impl Event {
+    /// Get the ID of the watch that this event is associated with.
+    #[inline]
+    pub fn id(&self) -> WatchId {
+        self.id
+    }
+
+    /// Get the kinds of changes that occurred.
+    #[inline]
+    pub fn mask(&self) -> WatchMask {
+        self.mask
+    }
+
+    /// If the watched file is a directory and the event refers to an entry within it, get the name
+    /// of that entry.
+    ///
+    /// This is only ever provided on Linux/Android; kqueue does not report it.
+    #[inline]
+    pub fn name(&self) -> Option<&OsString> {
+        self.name.as_ref()
+    }
+}
+
+/// A set of watches on files, and a stream of events for those files.
+///
+/// The underlying file descriptor (inotify instance or kqueue) is available through `AsRawFd`, so
+/// it can be registered with `poll()` or an event loop.
+#[derive(Debug)]
+pub struct Watcher {
+    fd: RawFd,
+    // With kqueue, the watched files need to be kept open
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    files: std::collections::HashMap<RawFd, std::fs::File>,
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(any(target_os = "linux", target_os = "android"))] {
+        const OPEN_FLAGS: libc::c_int = libc::O_PATH;
+    } else if #[cfg(any(target_os = "macos", target_os = "ios"))] {
+        // O_NONBLOCK so that watching a FIFO doesn't block until a writer opens it
+        const OPEN_FLAGS: libc::c_int = libc::O_EVTONLY | libc::O_NONBLOCK;
+    } else {
+        const OPEN_FLAGS: libc::c_int = libc::O_RDONLY | libc::O_NONBLOCK;
+    }
+}
+
+impl Watcher {
+    /// Create a new `Watcher` with no watches.
+    pub fn new() -> io::Result<Self> {
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "android"))] {
+                let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+            } else {
+                let fd = unsafe { libc::kqueue() };
+            }
+        }
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "android"))] {
+                Ok(Self { fd })
+            } else {
+                let watcher = Self {
+                    fd,
+                    files: std::collections::HashMap::new(),
+                };
+                // kqueue() doesn't accept flags
+                crate::util::set_cloexec(fd, true)?;
+                Ok(watcher)
+            }
+        }
+    }
+
+    /// Watch the file at `path` (within `dir`) for the changes specified by `mask`.
+    ///
+    /// Symlinks are resolved within `dir` (subject to `lookup_flags`), and the file that is
+    /// finally found is watched.
+    pub fn add<P: AsPath>(
+        &mut self,
+        dir: &Dir,
+        path: P,
+        mask: WatchMask,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<WatchId> {
+        dir.check_rights(Rights::READ)?;
+
+        let file = open_beneath(dir.as_raw_fd(), path, OPEN_FLAGS, 0, lookup_flags)?;
+
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "android"))] {
+                // inotify only accepts paths, but the magic link in /proc/self/fd refers directly
+                // to the file we opened
+                let path = crate::util::proc_fd_path(file.as_raw_fd());
+                let wd =
+                    unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), inotify_mask(mask)) };
+
+                if wd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                Ok(WatchId(wd))
+            } else {
+                let fd = file.as_raw_fd();
+
+                let mut kev: libc::kevent = unsafe { std::mem::zeroed() };
+                kev.ident = fd as _;
+                kev.filter = libc::EVFILT_VNODE as _;
+                kev.flags = (libc::EV_ADD | libc::EV_CLEAR) as _;
+                kev.fflags = kqueue_fflags(mask) as _;
+
+                if unsafe {
+                    libc::kevent(self.fd, &kev, 1, std::ptr::null_mut(), 0, std::ptr::null())
+                } < 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+
+                self.files.insert(fd, file);
+                Ok(WatchId(fd))
+            }
+        }
+    }
+
+    /// Remove the watch with the given ID.
+    pub fn remove(&mut self, id: WatchId) -> io::Result<()> {
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "android"))] {
+                if unsafe { libc::inotify_rm_watch(self.fd, id.0) } < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            } else {
+                // Closing the file automatically removes it from the kqueue
+                if self.files.remove(&id.0).is_none() {
+                    return Err(io::Error::from_raw_os_error(libc::EINVAL));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wait for events on any of the watched files, and return them.
+    ///
+    /// This blocks until at least one event is available (unless the file descriptor has been
+    /// made non-blocking, in which case it may fail with `EAGAIN`).
+    pub fn read_events(&mut self) -> io::Result<Vec<Event>> {
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "android"))] {
+                self.read_events_inotify()
+            } else {
+                self.read_events_kqueue()
+            }
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn read_events_inotify(&mut self) -> io::Result<Vec<Event>> {
+        const HEADER_SIZE: usize = std::mem::size_of::<libc::inotify_event>();
+
+        let mut buf = vec![0u8; 4096 + libc::PATH_MAX as usize];
+
+        let n = loop {
+            let n = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut _, buf.len()) };
+            if n >= 0 {
+                break n as usize;
+            }
+
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EINTR) {
+                return Err(err);
+            }
+        };
+
+        let mut events = Vec::new();
+        let mut pos = 0;
+
+        while pos + HEADER_SIZE <= n {
+            let ev: libc::inotify_event =
+                unsafe { std::ptr::read_unaligned(buf[pos..].as_ptr() as *const _) };
+
+            let name_bytes = &buf[pos + HEADER_SIZE..pos + HEADER_SIZE + ev.len as usize];
+            pos += HEADER_SIZE + ev.len as usize;
+
+            let mut mask = WatchMask::empty();
+            if ev.mask
+                & (libc::IN_MODIFY
+                    | libc::IN_CREATE
+                    | libc::IN_DELETE
+                    | libc::IN_MOVED_FROM
+                    | libc::IN_MOVED_TO)
+                != 0
+            {
+                mask |= WatchMask::MODIFY;
+            }
+            if ev.mask & libc::IN_ATTRIB != 0 {
+                mask |= WatchMask::ATTRIB;
+            }
+            if ev.mask & libc::IN_DELETE_SELF != 0 {
+                mask |= WatchMask::DELETE;
+            }
+            if ev.mask & libc::IN_MOVE_SELF != 0 {
+                mask |= WatchMask::RENAME;
+            }
+
+            // For example, IN_IGNORED
+            if mask.is_empty() {
+                continue;
+            }
+
+            // The name is padded with nul bytes
+            let name_len = name_bytes
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(name_bytes.len());
+
+            events.push(Event {
+                id: WatchId(ev.wd),
+                mask,
+                name: if name_len > 0 {
+                    Some(OsString::from_vec(name_bytes[..name_len].to_vec()))
+                } else {
+                    None
+                },
+            });
+        }
+
+        Ok(events)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn read_events_kqueue(&mut self) -> io::Result<Vec<Event>> {
+        let mut kevs: [libc::kevent; 32] = unsafe { std::mem::zeroed() };
+
+        let n = loop {
+            let n = unsafe {
+                libc::kevent(
+                    self.fd,
+                    std::ptr::null(),
+                    0,
+                    kevs.as_mut_ptr(),
+                    kevs.len() as _,
+                    std::ptr::null(),
+                )
+            };
+            if n >= 0 {
+                break n as usize;
+            }
+
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EINTR) {
+                return Err(err);
+            }
+        };
+
+        let mut events = Vec::new();
+
+        for kev in kevs[..n].iter() {
+            let fflags = kev.fflags as u32;
+
+            let mut mask = WatchMask::empty();
+            if fflags & (libc::NOTE_WRITE | libc::NOTE_EXTEND) as u32 != 0 {
+                mask |= WatchMask::MODIFY;
+            }
+            if fflags & libc::NOTE_ATTRIB as u32 != 0 {
+                mask |= WatchMask::ATTRIB;
+            }
+            if fflags & libc::NOTE_DELETE as u32 != 0 {
+                mask |= WatchMask::DELETE;
+            }
+            if fflags & libc::NOTE_RENAME as u32 != 0 {
+                mask |= WatchMask::RENAME;
+            }
+
+            if !mask.is_empty() {
+                events.push(Event {
+                    id: WatchId(kev.ident as RawFd),
+                    mask,
+                    name: None,
+                });
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn inotify_mask(mask: WatchMask) -> u32 {
+    let mut res = 0;
+
+    if mask.contains(WatchMask::MODIFY) {
+        res |= libc::IN_MODIFY
+            | libc::IN_CREATE
+            | libc::IN_DELETE
+            | libc::IN_MOVED_FROM
+            | libc::IN_MOVED_TO;
+    }
+    if mask.contains(WatchMask::ATTRIB) {
+        res |= libc::IN_ATTRIB;
+    }
+    if mask.contains(WatchMask::DELETE) {
+        res |= libc::IN_DELETE_SELF;
+    }
+    if mask.contains(WatchMask::RENAME) {
+        res |= libc::IN_MOVE_SELF;
+    }
+
+    res
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn kqueue_fflags(mask: WatchMask) -> u32 {
+    let mut res = 0;
+
+    if mask.contains(WatchMask::MODIFY) {
+        res |= (libc::NOTE_WRITE | libc::NOTE_EXTEND) as u32;
+    }
+    if mask.contains(WatchMask::ATTRIB) {
+        res |= libc::NOTE_ATTRIB as u32;
+    }
+    if mask.contains(WatchMask::DELETE) {
+        res |= libc::NOTE_DELETE as u32;
+    }
+    if mask.contains(WatchMask::RENAME) {
+        res |= libc::NOTE_RENAME as u32;
+    }
+
+    res
+}
+
+impl AsRawFd for Watcher {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for Watcher {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+impl Dir {
+    /// Watch the file at `path` within this directory for the changes specified by `mask`.
+    ///
+    /// This is a shortcut for creating a new [`Watcher`] and calling [`Watcher::add()`]; more
+    /// watches can be added to the returned `Watcher` later.
+    ///
+    /// [`Watcher`]: ./watch/struct.Watcher.html
+    /// [`Watcher::add()`]: ./watch/struct.Watcher.html#method.add
+    pub fn watch<P: AsPath>(
+        &self,
+        path: P,
+        mask: WatchMask,
+        lookup_flags: LookupFlags,
+    ) -> io::Result<Watcher> {
+        let mut watcher = Watcher::new()?;
+        watcher.add(self, path, mask, lookup_flags)?;
+        Ok(watcher)
+    }
+}
@@ -1,8 +1,10 @@
+use std::ffi::OsStr;
 use std::fs;
 use std::io;
 use std::os::unix::prelude::*;
+use std::path::Path;
 
-use obnth::{open_beneath, LookupFlags};
+use obnth::{open_beneath, resolve_beneath, LookupFlags, OpenBeneath};
 
 fn same_file_meta(f1: &fs::File, m2: &fs::Metadata) -> io::Result<bool> {
     let m1 = f1.metadata()?;
@@ -225,6 +227,50 @@ fn test_open_beneath_error() {
     check_err!("a/../..", libc::O_RDONLY, libc::EXDEV);
     check_err!("a/sub/../../..", libc::O_RDONLY, libc::EXDEV);
 
+    // NO_DOTDOT rejects ".." components, even if they wouldn't escape
+    std::os::unix::fs::symlink("../b", tmpdir.join("a/sub/up")).unwrap();
+    check_err!(
+        "a/../a/b",
+        libc::O_RDONLY,
+        LookupFlags::NO_DOTDOT,
+        libc::EXDEV
+    );
+    check_err!(
+        "a/sub/..",
+        libc::O_RDONLY,
+        LookupFlags::NO_DOTDOT | LookupFlags::IN_ROOT,
+        libc::EXDEV
+    );
+    check_err!(
+        "a/sub/..",
+        libc::O_RDONLY,
+        LookupFlags::NO_DOTDOT | LookupFlags::NO_SYMLINKS,
+        libc::EXDEV
+    );
+    check_err!(
+        "a/sub/up",
+        libc::O_RDONLY,
+        LookupFlags::NO_DOTDOT,
+        libc::EXDEV
+    );
+    open_beneath(
+        tmpdir_fd,
+        "a/sub/up",
+        libc::O_RDONLY,
+        0,
+        LookupFlags::empty(),
+    )
+    .unwrap();
+    open_beneath(tmpdir_fd, "c", libc::O_RDONLY, 0, LookupFlags::NO_DOTDOT).unwrap();
+    open_beneath(
+        tmpdir_fd,
+        "a/./b",
+        libc::O_RDONLY,
+        0,
+        LookupFlags::NO_DOTDOT | LookupFlags::NO_SYMLINKS,
+    )
+    .unwrap();
+
     check_err!("a/h", libc::O_WRONLY, libc::EISDIR);
     check_err!("e", libc::O_WRONLY, libc::EISDIR);
 }
@@ -277,3 +323,1062 @@ fn test_open_beneath_execute() {
     std::fs::set_permissions(tmpdir.join("a"), fs::Permissions::from_mode(0o755)).unwrap();
     res.unwrap();
 }
+
+#[test]
+fn test_has_o_search() {
+    assert_eq!(
+        obnth::has_o_search(),
+        cfg!(all(
+            any(
+                target_os = "linux",
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "solaris",
+                target_os = "illumos",
+            ),
+            not(feature = "portable-fallback"),
+        ))
+    );
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn test_open_beneath_magiclinks() {
+    let proc_self = match fs::File::open("/proc/self") {
+        Ok(f) => f,
+        // /proc isn't mounted
+        Err(_) => return,
+    };
+    let proc_root = fs::File::open("/proc").unwrap();
+
+    let fd_path = format!("fd/{}", proc_root.as_raw_fd());
+
+    for path in [fd_path.as_str(), "exe", "cwd"].iter() {
+        assert_eq!(
+            open_beneath(
+                proc_self.as_raw_fd(),
+                *path,
+                libc::O_RDONLY,
+                0,
+                LookupFlags::NO_MAGICLINKS,
+            )
+            .unwrap_err()
+            .raw_os_error(),
+            Some(libc::ELOOP)
+        );
+    }
+
+    // Ordinary symlinks in /proc are still allowed
+    open_beneath(
+        proc_root.as_raw_fd(),
+        "self/status",
+        libc::O_RDONLY,
+        0,
+        LookupFlags::NO_MAGICLINKS,
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_open_beneath_builder() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = tmpdir.as_ref();
+
+    let tmpdir_file = fs::File::open(tmpdir).unwrap();
+
+    fs::create_dir(tmpdir.join("a")).unwrap();
+    fs::write(tmpdir.join("a/b"), b"abc").unwrap();
+    std::os::unix::fs::symlink("a/b", tmpdir.join("c")).unwrap();
+
+    let f = OpenBeneath::new("c").open_at(&tmpdir_file).unwrap();
+    assert!(same_file_meta(&f, &tmpdir.join("a/b").metadata().unwrap()).unwrap());
+    let fd_flags = unsafe { libc::fcntl(f.as_raw_fd(), libc::F_GETFD) };
+    assert_eq!(fd_flags & libc::FD_CLOEXEC, libc::FD_CLOEXEC);
+
+    let f = OpenBeneath::new(std::ffi::CString::new("a").unwrap())
+        .dir_only(true)
+        .cloexec(false)
+        .open_at_fd(tmpdir_file.as_raw_fd())
+        .unwrap();
+    assert!(same_file_meta(&f, &tmpdir.join("a").metadata().unwrap()).unwrap());
+    let fd_flags = unsafe { libc::fcntl(f.as_raw_fd(), libc::F_GETFD) };
+    assert_eq!(fd_flags & libc::FD_CLOEXEC, 0);
+
+    OpenBeneath::new("a/new")
+        .flags(libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL)
+        .mode(0o600)
+        .open_at(&tmpdir_file)
+        .unwrap();
+    assert_eq!(
+        tmpdir
+            .join("a/new")
+            .metadata()
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777,
+        0o600
+    );
+
+    macro_rules! check_err {
+        ($builder:expr, $eno:expr) => {
+            assert_eq!(
+                $builder.open_at(&tmpdir_file).unwrap_err().raw_os_error(),
+                Some($eno)
+            );
+        };
+    }
+
+    check_err!(OpenBeneath::new("a/b").dir_only(true), libc::ENOTDIR);
+    check_err!(
+        OpenBeneath::new("c").lookup_flags(LookupFlags::NO_SYMLINKS),
+        libc::ELOOP
+    );
+    check_err!(OpenBeneath::new("../a"), libc::EXDEV);
+}
+
+#[test]
+fn test_open_beneath_max_symlinks() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = tmpdir.as_ref();
+
+    let tmpdir_file = fs::File::open(tmpdir).unwrap();
+
+    fs::write(tmpdir.join("file"), b"").unwrap();
+    // link3 -> link2 -> link1 -> file
+    std::os::unix::fs::symlink("file", tmpdir.join("link1")).unwrap();
+    std::os::unix::fs::symlink("link1", tmpdir.join("link2")).unwrap();
+    std::os::unix::fs::symlink("link2", tmpdir.join("link3")).unwrap();
+
+    for &(path, nlinks) in [("file", 0), ("link1", 1), ("link2", 2), ("link3", 3)].iter() {
+        for max in 0..5 {
+            let res = OpenBeneath::new(path)
+                .max_symlinks(Some(max))
+                .open_at(&tmpdir_file);
+
+            if nlinks <= max {
+                assert!(
+                    same_file_meta(&res.unwrap(), &tmpdir.join("file").metadata().unwrap())
+                        .unwrap()
+                );
+            } else {
+                assert_eq!(res.unwrap_err().raw_os_error(), Some(libc::ELOOP));
+            }
+        }
+
+        OpenBeneath::new(path)
+            .max_symlinks(None)
+            .open_at(&tmpdir_file)
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_resolve_beneath() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.path();
+
+    fs::create_dir_all(tmpdir_path.join("a/b")).unwrap();
+    fs::write(tmpdir_path.join("a/b/file"), b"").unwrap();
+    std::os::unix::fs::symlink("a/b", tmpdir_path.join("link")).unwrap();
+    std::os::unix::fs::symlink("/a", tmpdir_path.join("abslink")).unwrap();
+    std::os::unix::fs::symlink("file", tmpdir_path.join("a/b/flink")).unwrap();
+
+    let tmpdir_file = fs::File::open(tmpdir_path).unwrap();
+    let fd = tmpdir_file.as_raw_fd();
+
+    let res = resolve_beneath(fd, "link/../b/./flink", LookupFlags::empty()).unwrap();
+    assert_eq!(res.path(), Path::new("a/b/flink"));
+    assert_eq!(res.file_name(), Some(OsStr::new("flink")));
+    assert_eq!(res.parent_components(), ["a", "b"]);
+    assert_eq!(
+        res.parent().self_metadata().unwrap().ino(),
+        tmpdir_path.join("a/b").metadata().unwrap().ino()
+    );
+
+    // The final component doesn't have to exist
+    let res = resolve_beneath(fd, "link/noexist", LookupFlags::empty()).unwrap();
+    assert_eq!(res.path(), Path::new("a/b/noexist"));
+
+    // No final component
+    let res = resolve_beneath(fd, "link/..", LookupFlags::empty()).unwrap();
+    assert_eq!(res.path(), Path::new("a"));
+    assert_eq!(res.file_name(), None);
+    let res = resolve_beneath(fd, ".", LookupFlags::empty()).unwrap();
+    assert_eq!(res.path(), Path::new("."));
+    assert!(res.parent_components().is_empty());
+
+    // A trailing slash means the final component must be a directory
+    let res = resolve_beneath(fd, "link/", LookupFlags::empty()).unwrap();
+    assert_eq!(res.path(), Path::new("a/b"));
+    assert_eq!(res.file_name(), None);
+
+    let res = resolve_beneath(fd, "abslink/b/file", LookupFlags::IN_ROOT).unwrap();
+    assert_eq!(res.path(), Path::new("a/b/file"));
+
+    for (path, lookup_flags, eno) in [
+        ("abslink/b", LookupFlags::empty(), libc::EXDEV),
+        ("../a", LookupFlags::empty(), libc::EXDEV),
+        ("link/x", LookupFlags::NO_SYMLINKS, libc::ELOOP),
+        ("noexist/x", LookupFlags::empty(), libc::ENOENT),
+        ("a/b/file/x", LookupFlags::empty(), libc::ENOTDIR),
+        ("", LookupFlags::empty(), libc::ENOENT),
+        // Always resolved manually, which may block
+        ("a/b", LookupFlags::CACHED_ONLY, libc::EAGAIN),
+    ] {
+        assert_eq!(
+            resolve_beneath(fd, path, lookup_flags)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno),
+            "{:?}",
+            path
+        );
+    }
+
+    let file = fs::File::open(tmpdir_path.join("a/b/file")).unwrap();
+    assert_eq!(
+        resolve_beneath(file.as_raw_fd(), "x", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+}
+
+#[test]
+fn test_open_beneath_cached_only() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = tmpdir.as_ref();
+    let tmpdir_file = fs::File::open(tmpdir).unwrap();
+
+    fs::create_dir(tmpdir.join("a")).unwrap();
+    fs::write(tmpdir.join("a/b"), b"").unwrap();
+    // Make sure it's in the cache
+    fs::metadata(tmpdir.join("a/b")).unwrap();
+
+    // Either it succeeds or it's not supported
+    match OpenBeneath::new("a/b")
+        .lookup_flags(LookupFlags::CACHED_ONLY)
+        .open_at(&tmpdir_file)
+    {
+        Ok(f) => assert!(same_file_meta(&f, &fs::metadata(tmpdir.join("a/b")).unwrap()).unwrap()),
+        Err(e) => assert_eq!(e.raw_os_error(), Some(libc::EAGAIN)),
+    }
+
+    // Escape attempts are still rejected (if the kernel is able to tell)
+    let eno = OpenBeneath::new("a/../..")
+        .lookup_flags(LookupFlags::CACHED_ONLY)
+        .open_at(&tmpdir_file)
+        .unwrap_err()
+        .raw_os_error();
+    assert!(
+        matches!(eno, Some(libc::EXDEV) | Some(libc::EAGAIN)),
+        "{:?}",
+        eno
+    );
+
+    // Options that prevent using openat2() always fail with EAGAIN (even with retries)
+    for opts in [
+        OpenBeneath::new("a/b")
+            .lookup_flags(LookupFlags::CACHED_ONLY)
+            .max_symlinks(Some(3))
+            .retries(Some(2))
+            .clone(),
+        OpenBeneath::new("a/b")
+            .lookup_flags(LookupFlags::CACHED_ONLY | LookupFlags::NO_DOTDOT)
+            .clone(),
+        OpenBeneath::new("a/c")
+            .lookup_flags(LookupFlags::CACHED_ONLY)
+            .flags(libc::O_WRONLY | libc::O_CREAT)
+            .clone(),
+    ]
+    .iter()
+    {
+        assert_eq!(
+            opts.open_at(&tmpdir_file).unwrap_err().raw_os_error(),
+            Some(libc::EAGAIN)
+        );
+    }
+    assert!(!tmpdir.join("a/c").exists());
+
+    // The detailed errors don't repeat resolution (which might block)
+    assert!(matches!(
+        OpenBeneath::new("a/b")
+            .lookup_flags(LookupFlags::CACHED_ONLY)
+            .max_symlinks(Some(3))
+            .open_at_detailed(&tmpdir_file)
+            .unwrap_err(),
+        obnth::Error::Io(_)
+    ));
+}
+
+#[test]
+fn test_open_beneath_invalid_flags() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = tmpdir.as_ref();
+
+    let tmpdir_file = fs::File::open(tmpdir).unwrap();
+    let tmpdir_fd = tmpdir_file.as_raw_fd();
+
+    fs::create_dir(tmpdir.join("a")).unwrap();
+    fs::write(tmpdir.join("a/b"), b"abc").unwrap();
+
+    #[allow(unused_mut)]
+    let mut bad_flags = vec![
+        libc::O_WRONLY | libc::O_CREAT | libc::O_DIRECTORY,
+        libc::O_RDONLY | libc::O_CREAT | libc::O_DIRECTORY,
+        libc::O_RDONLY | libc::O_TRUNC,
+    ];
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    bad_flags.extend_from_slice(&[
+        libc::O_RDONLY | libc::O_TMPFILE,
+        libc::O_WRONLY | libc::O_CREAT | libc::O_TMPFILE,
+        libc::O_PATH | libc::O_WRONLY,
+        libc::O_PATH | libc::O_RDWR,
+        libc::O_PATH | libc::O_CREAT,
+        libc::O_PATH | libc::O_TRUNC,
+        libc::O_PATH | libc::O_APPEND,
+    ]);
+
+    for &flags in bad_flags.iter() {
+        for path in ["a/b", "a/c", "a/c/d", "../x"] {
+            let err =
+                open_beneath(tmpdir_fd, path, flags, 0o666, LookupFlags::empty()).unwrap_err();
+            assert_eq!(
+                err.raw_os_error(),
+                Some(libc::EINVAL),
+                "{:#o} {}",
+                flags,
+                path
+            );
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    // Nothing was created or truncated
+    assert!(!tmpdir.join("a/c").exists());
+    assert_eq!(fs::read(tmpdir.join("a/b")).unwrap(), b"abc");
+
+    // Valid combinations still work
+    open_beneath(
+        tmpdir_fd,
+        "a",
+        libc::O_RDONLY | libc::O_DIRECTORY,
+        0,
+        LookupFlags::empty(),
+    )
+    .unwrap();
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    open_beneath(
+        tmpdir_fd,
+        "a",
+        libc::O_PATH | libc::O_DIRECTORY | libc::O_NOFOLLOW,
+        0,
+        LookupFlags::empty(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_open_beneath_detailed_errors() {
+    use obnth::Error;
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = tmpdir.as_ref();
+    let tmpdir_file = fs::File::open(tmpdir).unwrap();
+
+    fs::create_dir(tmpdir.join("a")).unwrap();
+    fs::write(tmpdir.join("a/b"), b"").unwrap();
+    std::os::unix::fs::symlink("../..", tmpdir.join("a/up")).unwrap();
+    std::os::unix::fs::symlink("b", tmpdir.join("a/link")).unwrap();
+
+    OpenBeneath::new("a/b")
+        .open_at_detailed(&tmpdir_file)
+        .unwrap();
+
+    match OpenBeneath::new("a/../../x/y")
+        .open_at_detailed(&tmpdir_file)
+        .unwrap_err()
+    {
+        Error::EscapeAttempt {
+            resolved,
+            component,
+            remaining,
+        } => {
+            assert_eq!(resolved, Path::new(""));
+            assert_eq!(component, "..");
+            assert_eq!(remaining, Path::new("x/y"));
+        }
+        e => panic!("{:?}", e),
+    }
+
+    let err = OpenBeneath::new("a/up/c")
+        .open_at_detailed(&tmpdir_file)
+        .unwrap_err();
+    assert!(matches!(err, Error::EscapeAttempt { .. }), "{:?}", err);
+    assert!(err.is_rejection());
+    assert_eq!(err.raw_os_error(), Some(libc::EXDEV));
+    assert_eq!(err.resolved(), Some(Path::new("")));
+    assert_eq!(err.component(), Some(OsStr::new("..")));
+    assert_eq!(err.remaining(), Some(Path::new("c")));
+
+    let err = OpenBeneath::new("/a")
+        .open_at_detailed(&tmpdir_file)
+        .unwrap_err();
+    assert!(matches!(err, Error::EscapeAttempt { .. }), "{:?}", err);
+    assert_eq!(err.component(), Some(OsStr::new("/")));
+    assert_eq!(err.remaining(), Some(Path::new("a")));
+
+    let err = OpenBeneath::new("a/link")
+        .lookup_flags(LookupFlags::NO_SYMLINKS)
+        .open_at_detailed(&tmpdir_file)
+        .unwrap_err();
+    assert!(matches!(err, Error::SymlinkForbidden { .. }), "{:?}", err);
+    assert!(err.is_rejection());
+    assert_eq!(err.resolved(), Some(Path::new("a")));
+    assert_eq!(err.component(), Some(OsStr::new("link")));
+    assert_eq!(err.remaining(), Some(Path::new("")));
+
+    let err = OpenBeneath::new("a/noexist/c")
+        .open_at_detailed(&tmpdir_file)
+        .unwrap_err();
+    assert!(matches!(err, Error::NotFound { .. }), "{:?}", err);
+    assert!(!err.is_rejection());
+    assert_eq!(err.resolved(), Some(Path::new("a")));
+    assert_eq!(err.component(), Some(OsStr::new("noexist")));
+    assert_eq!(err.remaining(), Some(Path::new("c")));
+
+    let err = OpenBeneath::new("a/b/c")
+        .open_at_detailed(&tmpdir_file)
+        .unwrap_err();
+    assert!(matches!(err, Error::Other { .. }), "{:?}", err);
+    assert_eq!(err.raw_os_error(), Some(libc::ENOTDIR));
+    assert_eq!(err.resolved(), Some(Path::new("a")));
+    assert_eq!(err.component(), Some(OsStr::new("b")));
+    assert_eq!(err.remaining(), Some(Path::new("c")));
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "{} (at \"b\" in \"a\")",
+            io::Error::from_raw_os_error(libc::ENOTDIR)
+        )
+    );
+
+    // The detailed errors are determined by resolving the path only
+    let err = OpenBeneath::new("a/noexist/c")
+        .flags(libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC)
+        .mode(0o600)
+        .open_at_detailed(&tmpdir_file)
+        .unwrap_err();
+    assert!(matches!(err, Error::NotFound { .. }), "{:?}", err);
+    assert_eq!(err.component(), Some(OsStr::new("noexist")));
+    assert!(!tmpdir.join("a/noexist").exists());
+
+    // If the file can't be opened with the given flags, the original error is returned
+    let err = OpenBeneath::new("a")
+        .flags(libc::O_WRONLY)
+        .open_at_detailed(&tmpdir_file)
+        .unwrap_err();
+    assert!(matches!(err, Error::Io(_)), "{:?}", err);
+    assert_eq!(err.raw_os_error(), Some(libc::EISDIR));
+
+    // Converting back to an io::Error preserves the error number
+    let err: io::Error = OpenBeneath::new("../x")
+        .open_at_detailed(&tmpdir_file)
+        .unwrap_err()
+        .into();
+    assert_eq!(err.raw_os_error(), Some(libc::EXDEV));
+
+    #[cfg(target_os = "linux")]
+    {
+        let root = fs::File::open("/").unwrap();
+        let err = OpenBeneath::new("proc/self")
+            .lookup_flags(LookupFlags::NO_XDEV)
+            .open_at_detailed(&root)
+            .unwrap_err();
+        assert!(matches!(err, Error::CrossesMount { .. }), "{:?}", err);
+        assert_eq!(err.resolved(), Some(Path::new("")));
+        assert_eq!(err.component(), Some(OsStr::new("proc")));
+        assert_eq!(err.remaining(), Some(Path::new("self")));
+    }
+}
+
+#[test]
+fn test_open_beneath_deadline() {
+    use std::time::{Duration, Instant};
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = tmpdir.as_ref();
+    let tmpdir_file = fs::File::open(tmpdir).unwrap();
+
+    fs::create_dir(tmpdir.join("a")).unwrap();
+    fs::write(tmpdir.join("a/b"), b"").unwrap();
+
+    let past = Instant::now();
+    let future = Instant::now() + Duration::from_secs(3600);
+
+    // Both with the fast paths and with manual resolution
+    for &max_symlinks in [None, Some(10)].iter() {
+        let mut opts = OpenBeneath::new("a/../a/b");
+        opts.max_symlinks(max_symlinks);
+
+        opts.deadline(Some(future)).open_at(&tmpdir_file).unwrap();
+
+        assert_eq!(
+            opts.deadline(Some(past))
+                .open_at(&tmpdir_file)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ETIMEDOUT)
+        );
+
+        // Resolution isn't repeated to find the failing component
+        match opts.open_at_detailed(&tmpdir_file).unwrap_err() {
+            obnth::Error::Io(e) => assert_eq!(e.raw_os_error(), Some(libc::ETIMEDOUT)),
+            e => panic!("{:?}", e),
+        }
+    }
+}
+
+#[test]
+fn test_open_beneath_deadline_mid_resolution() {
+    use std::time::{Duration, Instant};
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = tmpdir.as_ref();
+    let tmpdir_file = fs::File::open(tmpdir).unwrap();
+
+    fs::create_dir_all(tmpdir.join("a/b/c/d/e/f")).unwrap();
+    fs::write(tmpdir.join("a/b/c/d/e/f/file"), b"").unwrap();
+
+    // The audit hook forces manual resolution, and slows down every component so the deadline
+    // passes partway through
+    let mut opts = OpenBeneath::new("a/b/c/d/e/f/file");
+    opts.audit(Some(obnth::AuditHook::new(|_| {
+        std::thread::sleep(Duration::from_millis(50))
+    })));
+
+    opts.open_at(&tmpdir_file).unwrap();
+
+    assert_eq!(
+        opts.deadline(Some(Instant::now() + Duration::from_millis(120)))
+            .open_at(&tmpdir_file)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ETIMEDOUT)
+    );
+}
+
+#[test]
+fn test_open_beneath_protected_symlinks() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = tmpdir.as_ref();
+    let tmpdir_file = fs::File::open(tmpdir).unwrap();
+
+    fs::create_dir(tmpdir.join("sticky")).unwrap();
+    fs::set_permissions(tmpdir.join("sticky"), fs::Permissions::from_mode(0o1777)).unwrap();
+    fs::write(tmpdir.join("file"), b"").unwrap();
+    std::os::unix::fs::symlink("../file", tmpdir.join("sticky/own")).unwrap();
+    std::os::unix::fs::symlink("../file", tmpdir.join("sticky/other")).unwrap();
+
+    // Only root can create a symlink owned by another user
+    let is_root = unsafe { libc::geteuid() } == 0;
+    if is_root {
+        let path = std::ffi::CString::new(tmpdir.join("sticky/other").into_os_string().into_vec())
+            .unwrap();
+        assert_eq!(unsafe { libc::lchown(path.as_ptr(), 4321, 4321) }, 0);
+    }
+
+    for &max_symlinks in [None, Some(10)].iter() {
+        let open = |path: &str, flags: libc::c_int, lookup_flags: LookupFlags| {
+            OpenBeneath::new(path)
+                .flags(flags)
+                .lookup_flags(lookup_flags)
+                .max_symlinks(max_symlinks)
+                .open_at(&tmpdir_file)
+        };
+
+        // Without the flag, both symlinks can be followed
+        open("sticky/own", 0, LookupFlags::empty()).unwrap();
+        open("sticky/other", 0, LookupFlags::empty()).unwrap();
+
+        let lflags = LookupFlags::PROTECTED_SYMLINKS;
+        open("sticky/own", 0, lflags).unwrap();
+        open("sticky/../sticky/own", 0, lflags).unwrap();
+        // Not a symlink
+        open("sticky/../file", 0, lflags).unwrap();
+
+        if is_root {
+            for &path in ["sticky/other", "sticky/../sticky/other", "./sticky/other"].iter() {
+                assert_eq!(
+                    open(path, 0, lflags).unwrap_err().raw_os_error(),
+                    Some(libc::EACCES)
+                );
+            }
+
+            // resolve_beneath() applies the same checks to the symlinks it follows
+            assert_eq!(
+                resolve_beneath(tmpdir_file.as_raw_fd(), "sticky/other/", lflags)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some(libc::EACCES)
+            );
+            resolve_beneath(tmpdir_file.as_raw_fd(), "sticky/other", lflags).unwrap();
+
+            // With O_NOFOLLOW, the symlink isn't followed, so it fails with ELOOP as usual
+            assert_eq!(
+                open("sticky/other", libc::O_NOFOLLOW, lflags)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some(libc::ELOOP)
+            );
+
+            // The checks only apply in sticky world-writable directories
+            fs::set_permissions(tmpdir.join("sticky"), fs::Permissions::from_mode(0o777)).unwrap();
+            open("sticky/other", 0, lflags).unwrap();
+            fs::set_permissions(tmpdir.join("sticky"), fs::Permissions::from_mode(0o1777)).unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_open_beneath_paranoid() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = tmpdir.as_ref();
+    let tmpdir_file = fs::File::open(tmpdir).unwrap();
+
+    fs::create_dir_all(tmpdir.join("a/b")).unwrap();
+    fs::write(tmpdir.join("a/file"), b"").unwrap();
+    std::os::unix::fs::symlink("b/../file", tmpdir.join("a/link")).unwrap();
+    std::os::unix::fs::symlink("/", tmpdir.join("a/escape")).unwrap();
+
+    let lflags = LookupFlags::PARANOID;
+    let open = |path: &str, flags: libc::c_int, lookup_flags: LookupFlags| {
+        open_beneath(tmpdir_file.as_raw_fd(), path, flags, 0o600, lookup_flags)
+    };
+
+    for &(path, flags, same_path) in [
+        ("a/file", libc::O_RDONLY, "a/file"),
+        ("a/link", libc::O_RDONLY, "a/file"),
+        ("a/b/../link", libc::O_RDONLY, "a/file"),
+        ("a/b", libc::O_RDONLY | libc::O_DIRECTORY, "a/b"),
+        ("a/b/", libc::O_RDONLY, "a/b"),
+        ("a/new", libc::O_WRONLY | libc::O_CREAT, "a/new"),
+    ]
+    .iter()
+    {
+        for &extra in [LookupFlags::empty(), LookupFlags::NO_XDEV].iter() {
+            let f = open(path, flags, lflags | extra).unwrap();
+            assert!(
+                same_file_meta(&f, &fs::symlink_metadata(tmpdir.join(same_path)).unwrap()).unwrap()
+            );
+        }
+    }
+
+    // Files created with O_EXCL are only opened once
+    open(
+        "a/excl",
+        libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL,
+        lflags,
+    )
+    .unwrap();
+
+    // Nothing was modified concurrently, so the results always matched
+    assert_eq!(obnth::paranoid_mismatches(), 0);
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let f = open("a/link", libc::O_PATH | libc::O_NOFOLLOW, lflags).unwrap();
+        assert!(same_file_meta(&f, &fs::symlink_metadata(tmpdir.join("a/link")).unwrap()).unwrap());
+    }
+
+    for &(path, lookup_flags, eno) in [
+        ("a/escape/etc", lflags, libc::EXDEV),
+        ("../file", lflags, libc::EXDEV),
+        ("a/noexist", lflags, libc::ENOENT),
+        ("a/link", lflags | LookupFlags::NO_SYMLINKS, libc::ELOOP),
+        ("a/file", lflags | LookupFlags::CACHED_ONLY, libc::EAGAIN),
+    ]
+    .iter()
+    {
+        assert_eq!(
+            open(path, libc::O_RDONLY, lookup_flags)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+    }
+}
+
+#[test]
+fn test_open_beneath_component_policy() {
+    use obnth::Error;
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = tmpdir.as_ref();
+    let tmpdir_file = fs::File::open(tmpdir).unwrap();
+
+    let uid = unsafe { libc::geteuid() };
+
+    fs::create_dir(tmpdir.join("a")).unwrap();
+    fs::set_permissions(tmpdir.join("a"), fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(tmpdir.join("a/file"), b"").unwrap();
+    fs::set_permissions(tmpdir.join("a/file"), fs::Permissions::from_mode(0o644)).unwrap();
+    fs::create_dir(tmpdir.join("a/ww")).unwrap();
+    fs::set_permissions(tmpdir.join("a/ww"), fs::Permissions::from_mode(0o777)).unwrap();
+    fs::write(tmpdir.join("a/ww/file"), b"").unwrap();
+    fs::set_permissions(tmpdir.join("a/ww/file"), fs::Permissions::from_mode(0o644)).unwrap();
+    std::os::unix::fs::symlink("file", tmpdir.join("a/link")).unwrap();
+
+    // Only root can create files owned by another user
+    let is_root = uid == 0;
+    if is_root {
+        let path =
+            std::ffi::CString::new(tmpdir.join("a/link").into_os_string().into_vec()).unwrap();
+        assert_eq!(unsafe { libc::lchown(path.as_ptr(), 4321, 4321) }, 0);
+    }
+
+    // The owner check
+    OpenBeneath::new("a/file")
+        .require_owner(Some(uid))
+        .open_at(&tmpdir_file)
+        .unwrap();
+    OpenBeneath::new("a/ww/../file")
+        .require_owner(Some(uid))
+        .open_at(&tmpdir_file)
+        .unwrap();
+
+    let err = OpenBeneath::new("a/file")
+        .require_owner(Some(uid + 1))
+        .open_at_detailed(&tmpdir_file)
+        .unwrap_err();
+    assert!(matches!(err, Error::PolicyViolation { .. }), "{:?}", err);
+    assert!(err.is_rejection());
+    assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+    assert_eq!(err.resolved(), Some(Path::new("")));
+    assert_eq!(err.component(), Some(OsStr::new("a")));
+    assert_eq!(err.remaining(), Some(Path::new("file")));
+
+    if is_root {
+        let err = OpenBeneath::new("a/link")
+            .require_owner(Some(uid))
+            .open_at_detailed(&tmpdir_file)
+            .unwrap_err();
+        assert!(matches!(err, Error::PolicyViolation { .. }), "{:?}", err);
+        assert_eq!(err.resolved(), Some(Path::new("a")));
+        assert_eq!(err.component(), Some(OsStr::new("link")));
+    }
+
+    // The world-writable check
+    OpenBeneath::new("a/file")
+        .forbid_world_writable(true)
+        .open_at(&tmpdir_file)
+        .unwrap();
+    // Symlinks are exempt
+    OpenBeneath::new("a/link")
+        .forbid_world_writable(true)
+        .open_at(&tmpdir_file)
+        .unwrap();
+
+    for &path in ["a/ww/file", "a/ww", "a/ww/../file"].iter() {
+        let err = OpenBeneath::new(path)
+            .forbid_world_writable(true)
+            .open_at_detailed(&tmpdir_file)
+            .unwrap_err();
+        assert!(matches!(err, Error::PolicyViolation { .. }), "{:?}", err);
+        assert_eq!(err.resolved(), Some(Path::new("a")));
+        assert_eq!(err.component(), Some(OsStr::new("ww")));
+    }
+
+    // Files are only truncated after they've been checked
+    fs::write(tmpdir.join("a/wwfile"), b"data").unwrap();
+    fs::set_permissions(tmpdir.join("a/wwfile"), fs::Permissions::from_mode(0o666)).unwrap();
+    assert_eq!(
+        OpenBeneath::new("a/wwfile")
+            .flags(libc::O_WRONLY | libc::O_TRUNC)
+            .forbid_world_writable(true)
+            .open_at(&tmpdir_file)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EPERM)
+    );
+    assert_eq!(fs::metadata(tmpdir.join("a/wwfile")).unwrap().len(), 4);
+    fs::set_permissions(tmpdir.join("a/wwfile"), fs::Permissions::from_mode(0o644)).unwrap();
+    OpenBeneath::new("a/wwfile")
+        .flags(libc::O_WRONLY | libc::O_TRUNC)
+        .forbid_world_writable(true)
+        .open_at(&tmpdir_file)
+        .unwrap();
+    assert_eq!(fs::metadata(tmpdir.join("a/wwfile")).unwrap().len(), 0);
+
+    // New files are created without S_IWOTH
+    let file = OpenBeneath::new("a/new")
+        .flags(libc::O_WRONLY | libc::O_CREAT)
+        .mode(0o666)
+        .forbid_world_writable(true)
+        .open_at(&tmpdir_file)
+        .unwrap();
+    assert_eq!(file.metadata().unwrap().mode() & 0o002, 0);
+
+    // New files would be owned by the current user
+    let a_file = fs::File::open(tmpdir.join("a")).unwrap();
+    assert_eq!(
+        OpenBeneath::new("new2")
+            .flags(libc::O_WRONLY | libc::O_CREAT)
+            .mode(0o600)
+            .require_owner(Some(uid + 1))
+            .open_at(&a_file)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EPERM)
+    );
+    assert!(!tmpdir.join("a/new2").exists());
+    OpenBeneath::new("new2")
+        .flags(libc::O_WRONLY | libc::O_CREAT)
+        .mode(0o600)
+        .require_owner(Some(uid))
+        .open_at(&a_file)
+        .unwrap();
+
+    // The starting directory isn't checked
+    fs::set_permissions(tmpdir, fs::Permissions::from_mode(0o777)).unwrap();
+    OpenBeneath::new("a/file")
+        .forbid_world_writable(true)
+        .open_at(&tmpdir_file)
+        .unwrap();
+    fs::set_permissions(tmpdir, fs::Permissions::from_mode(0o700)).unwrap();
+}
+
+#[test]
+fn test_is_beneath() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = tmpdir.as_ref();
+
+    fs::create_dir_all(tmpdir.join("a/b/c")).unwrap();
+    fs::create_dir(tmpdir.join("d")).unwrap();
+    fs::write(tmpdir.join("a/file"), b"").unwrap();
+
+    let root = obnth::Dir::open(tmpdir.join("a")).unwrap();
+    let sub = root.sub_dir("b/c", LookupFlags::empty()).unwrap();
+    let other = obnth::Dir::open(tmpdir.join("d")).unwrap();
+    let parent = obnth::Dir::open(tmpdir).unwrap();
+
+    assert!(obnth::is_beneath(&root, &root).unwrap());
+    assert!(obnth::is_beneath(&sub, &root).unwrap());
+    assert!(!obnth::is_beneath(&root, &sub).unwrap());
+    assert!(!obnth::is_beneath(&other, &root).unwrap());
+    assert!(!obnth::is_beneath(&parent, &root).unwrap());
+    assert!(obnth::is_beneath(&root, &parent).unwrap());
+
+    // Works with any kind of file descriptor
+    let sub_file = fs::File::open(tmpdir.join("a/b")).unwrap();
+    assert!(obnth::is_beneath(&sub_file, &root).unwrap());
+
+    // Moving a directory out changes the result
+    fs::rename(tmpdir.join("a/b"), tmpdir.join("d/b")).unwrap();
+    assert!(!obnth::is_beneath(&sub, &root).unwrap());
+    assert!(obnth::is_beneath(&sub, &other).unwrap());
+
+    let file = fs::File::open(tmpdir.join("a/file")).unwrap();
+    assert_eq!(
+        obnth::is_beneath(&file, &root).unwrap_err().raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+    assert_eq!(
+        obnth::is_beneath(&root, &file).unwrap_err().raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+}
+
+#[test]
+fn test_open_beneath_audit() {
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = tmpdir.as_ref();
+    let tmpdir_file = fs::File::open(tmpdir).unwrap();
+
+    fs::create_dir(tmpdir.join("a")).unwrap();
+    fs::write(tmpdir.join("a/b"), b"").unwrap();
+    std::os::unix::fs::symlink("a/b", tmpdir.join("link")).unwrap();
+
+    type Event = (PathBuf, Option<PathBuf>, Option<i32>);
+    let events: Arc<Mutex<Vec<Event>>> = Arc::new(Mutex::new(Vec::new()));
+    let hook = {
+        let events = events.clone();
+        obnth::AuditHook::new(move |ev| {
+            events.lock().unwrap().push((
+                PathBuf::from(ev.component()),
+                ev.symlink_target().map(PathBuf::from),
+                ev.result().err().map(|e| e.raw_os_error().unwrap()),
+            ))
+        })
+    };
+    let take = || std::mem::take(&mut *events.lock().unwrap());
+    let ev = |c: &str, t: Option<&str>, e: Option<i32>| (PathBuf::from(c), t.map(PathBuf::from), e);
+
+    let mut opts = OpenBeneath::new("a/../link");
+    opts.audit(Some(hook.clone()));
+
+    let file = opts.open_at(&tmpdir_file).unwrap();
+    assert!(same_file_meta(&file, &fs::metadata(tmpdir.join("a/b")).unwrap()).unwrap());
+    assert_eq!(
+        take(),
+        vec![
+            ev("a", None, None),
+            ev("..", None, None),
+            ev("link", Some("a/b"), None),
+            ev("a", None, None),
+            ev("b", None, None),
+        ]
+    );
+
+    // Failures are reported (once, even with open_at_detailed())
+    opts.lookup_flags(LookupFlags::NO_SYMLINKS);
+    assert_eq!(
+        opts.open_at_detailed(&tmpdir_file)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ELOOP)
+    );
+    assert_eq!(
+        take(),
+        vec![
+            ev("a", None, None),
+            ev("..", None, None),
+            ev("link", None, Some(libc::ELOOP)),
+        ]
+    );
+
+    let mut opts = OpenBeneath::new("a/../..");
+    opts.audit(Some(hook.clone()));
+    assert_eq!(
+        opts.open_at(&tmpdir_file).unwrap_err().raw_os_error(),
+        Some(libc::EXDEV)
+    );
+    assert_eq!(
+        take(),
+        vec![
+            ev("a", None, None),
+            ev("..", None, None),
+            ev("..", None, Some(libc::EXDEV)),
+        ]
+    );
+
+    // The fast paths can't report anything
+    opts.lookup_flags(LookupFlags::CACHED_ONLY);
+    assert_eq!(
+        opts.open_at(&tmpdir_file).unwrap_err().raw_os_error(),
+        Some(libc::EAGAIN)
+    );
+    assert_eq!(take(), vec![]);
+
+    // Through Dir::open_file()
+    let dir = obnth::Dir::open(tmpdir).unwrap();
+    dir.open_file()
+        .read(true)
+        .audit(Some(hook))
+        .open("a/b")
+        .unwrap();
+    assert_eq!(take(), vec![ev("a", None, None), ev("b", None, None)]);
+}
+
+#[test]
+fn test_open_beneath_component_filter() {
+    use obnth::{ComponentFilter, Error};
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = tmpdir.as_ref();
+    let tmpdir_file = fs::File::open(tmpdir).unwrap();
+
+    fs::create_dir_all(tmpdir.join("a/.git")).unwrap();
+    fs::write(tmpdir.join("a/.git/config"), b"").unwrap();
+    fs::write(tmpdir.join("a/file"), b"").unwrap();
+    fs::write(tmpdir.join("a/big"), [0; 100]).unwrap();
+    std::os::unix::fs::symlink(".git", tmpdir.join("a/link")).unwrap();
+
+    let deny_git = ComponentFilter::deny_names(&[".git"]);
+
+    OpenBeneath::new("a/file")
+        .component_filter(Some(deny_git.clone()))
+        .open_at(&tmpdir_file)
+        .unwrap();
+
+    // Also applies to symlink-expanded components
+    for &(path, resolved, component) in [
+        ("a/.git/config", "a", ".git"),
+        ("a/link/config", "a", ".git"),
+        ("a/link/../file", "a", ".git"),
+    ]
+    .iter()
+    {
+        let err = OpenBeneath::new(path)
+            .component_filter(Some(deny_git.clone()))
+            .open_at_detailed(&tmpdir_file)
+            .unwrap_err();
+        assert!(matches!(err, Error::PolicyViolation { .. }), "{:?}", err);
+        assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+        assert_eq!(err.resolved(), Some(Path::new(resolved)));
+        assert_eq!(err.component(), Some(OsStr::new(component)));
+    }
+
+    // Filtering by metadata
+    let small_only = ComponentFilter::new(|_, meta| meta.is_dir() || meta.len() < 10);
+    OpenBeneath::new("a/file")
+        .component_filter(Some(small_only.clone()))
+        .open_at(&tmpdir_file)
+        .unwrap();
+    assert_eq!(
+        OpenBeneath::new("a/big")
+            .component_filter(Some(small_only.clone()))
+            .open_at(&tmpdir_file)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EPERM)
+    );
+
+    // The filter runs before the file is truncated
+    assert_eq!(
+        OpenBeneath::new("a/big")
+            .flags(libc::O_WRONLY | libc::O_TRUNC)
+            .component_filter(Some(small_only.clone()))
+            .open_at(&tmpdir_file)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EPERM)
+    );
+    assert_eq!(fs::metadata(tmpdir.join("a/big")).unwrap().len(), 100);
+
+    // Existing files can be opened with O_CREAT, but new ones can't be created (since the filter
+    // can't be run on them)
+    OpenBeneath::new("a/file")
+        .flags(libc::O_WRONLY | libc::O_CREAT)
+        .mode(0o600)
+        .component_filter(Some(small_only.clone()))
+        .open_at(&tmpdir_file)
+        .unwrap();
+    assert_eq!(
+        OpenBeneath::new("a/new")
+            .flags(libc::O_WRONLY | libc::O_CREAT)
+            .mode(0o600)
+            .component_filter(Some(small_only.clone()))
+            .open_at(&tmpdir_file)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EPERM)
+    );
+    assert!(!tmpdir.join("a/new").exists());
+
+    // Through Dir::open_file()
+    let dir = obnth::Dir::open(tmpdir).unwrap();
+    assert_eq!(
+        dir.open_file()
+            .read(true)
+            .component_filter(Some(deny_git))
+            .open("a/link/config")
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EPERM)
+    );
+}
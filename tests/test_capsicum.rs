@@ -0,0 +1,31 @@
+#![cfg(target_os = "freebsd")]
+
+use obnth::capsicum;
+use obnth::{Dir, LookupFlags};
+
+#[test]
+fn test_limit_rights() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = Dir::open(tmpdir.path()).unwrap();
+
+    tmpdir.write("file", b"abc", LookupFlags::empty()).unwrap();
+
+    capsicum::limit_rights(&tmpdir, capsicum::DIR_READ_RIGHTS).unwrap();
+
+    assert_eq!(tmpdir.read("file", LookupFlags::empty()).unwrap(), b"abc");
+    assert_eq!(
+        tmpdir
+            .write("file", b"def", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOTCAPABLE)
+    );
+
+    // Rights can't be added back
+    assert_eq!(
+        capsicum::limit_rights(&tmpdir, &[libc::CAP_LOOKUP, libc::CAP_WRITE])
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOTCAPABLE)
+    );
+}
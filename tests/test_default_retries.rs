@@ -0,0 +1,15 @@
+// The default retry count is process-global, so this is kept in its own test binary (where no
+// other tests can run concurrently and observe the modified value).
+
+use obnth::{default_retries, set_default_retries};
+
+#[test]
+fn test_default_retries() {
+    assert_eq!(default_retries(), 0);
+
+    set_default_retries(3);
+    assert_eq!(default_retries(), 3);
+
+    set_default_retries(0);
+    assert_eq!(default_retries(), 0);
+}
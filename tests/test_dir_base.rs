@@ -2,7 +2,7 @@ use std::fs;
 use std::os::unix::prelude::*;
 use std::path::Path;
 
-use obnth::{Dir, LookupFlags, Metadata};
+use obnth::{Dir, LockType, LookupFlags, Metadata};
 
 fn same_meta(m1: &Metadata, m2: &Metadata) -> bool {
     m1.ino() == m2.ino() && m1.dev() == m2.dev()
@@ -16,6 +16,68 @@ fn test_parent() {
     assert!(Dir::open("/").unwrap().parent().unwrap().is_none());
 }
 
+#[test]
+fn test_open_parent() {
+    use std::ffi::OsStr;
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    fs::create_dir_all(tmpdir_path.join("a/b")).unwrap();
+    std::os::unix::fs::symlink("a", tmpdir_path.join("link")).unwrap();
+
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+    let a_meta = tmpdir.metadata("a", LookupFlags::empty()).unwrap();
+
+    let (parent, name) = tmpdir
+        .open_parent("a/NOEXIST", LookupFlags::empty())
+        .unwrap();
+    assert!(same_meta(&parent.self_metadata().unwrap(), &a_meta));
+    assert_eq!(name.as_deref(), Some(OsStr::new("NOEXIST")));
+
+    // Symlinks are followed in all but the last component
+    let (parent, name) = tmpdir.open_parent("link/b", LookupFlags::empty()).unwrap();
+    assert!(same_meta(&parent.self_metadata().unwrap(), &a_meta));
+    assert_eq!(name.as_deref(), Some(OsStr::new("b")));
+
+    let (parent, name) = tmpdir.open_parent("link", LookupFlags::empty()).unwrap();
+    assert!(same_meta(
+        &parent.self_metadata().unwrap(),
+        &tmpdir.self_metadata().unwrap()
+    ));
+    assert_eq!(name.as_deref(), Some(OsStr::new("link")));
+
+    // Paths that refer to a directory have no name
+    let (parent, name) = tmpdir.open_parent("a/b/..", LookupFlags::empty()).unwrap();
+    assert!(same_meta(&parent.self_metadata().unwrap(), &a_meta));
+    assert_eq!(name, None);
+
+    let (parent, name) = tmpdir.open_parent("link/", LookupFlags::empty()).unwrap();
+    assert!(same_meta(&parent.self_metadata().unwrap(), &a_meta));
+    assert_eq!(name, None);
+
+    let (parent, name) = tmpdir.open_parent(".", LookupFlags::empty()).unwrap();
+    assert!(same_meta(
+        &parent.self_metadata().unwrap(),
+        &tmpdir.self_metadata().unwrap()
+    ));
+    assert_eq!(name, None);
+
+    assert_eq!(
+        tmpdir
+            .open_parent("../a", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
+    assert_eq!(
+        tmpdir
+            .open_parent("link/b", LookupFlags::NO_SYMLINKS)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ELOOP)
+    );
+}
+
 #[test]
 fn test_into_from_raw_fd() {
     let temp_dir = Dir::open(std::env::temp_dir()).unwrap();
@@ -27,6 +89,179 @@ fn test_into_from_raw_fd() {
     assert!(same_meta(&meta1, &meta2));
 }
 
+#[test]
+fn test_owned_fd_conversions() {
+    use std::convert::TryFrom;
+    use std::os::unix::io::{AsFd, OwnedFd};
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    fs::write(tmpdir_path.join("file"), b"").unwrap();
+
+    let dir = Dir::open(tmpdir_path).unwrap();
+    let meta = dir.self_metadata().unwrap();
+    assert_eq!(dir.as_fd().as_raw_fd(), dir.as_raw_fd());
+
+    let fd = OwnedFd::from(dir);
+    let dir = Dir::try_from(fd).unwrap();
+    assert!(same_meta(&dir.self_metadata().unwrap(), &meta));
+
+    let dir = Dir::try_from(fs::File::open(tmpdir_path).unwrap()).unwrap();
+    assert!(same_meta(&dir.self_metadata().unwrap(), &meta));
+
+    let file = fs::File::open(tmpdir_path.join("file")).unwrap();
+    assert_eq!(
+        Dir::try_from(OwnedFd::from(file))
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+    let file = fs::File::open(tmpdir_path.join("file")).unwrap();
+    assert_eq!(
+        Dir::try_from(file).unwrap_err().raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+}
+
+#[test]
+fn test_send_recv_dir() {
+    use std::io::Write;
+    use std::os::unix::net::{UnixDatagram, UnixStream};
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    fs::write(tmpdir_path.join("file"), b"").unwrap();
+
+    let dir = Dir::open(tmpdir_path).unwrap();
+    let meta = dir.self_metadata().unwrap();
+
+    let (a, b) = UnixStream::pair().unwrap();
+    dir.send_over(&a).unwrap();
+    dir.send_over(&a).unwrap();
+    let dir2 = Dir::recv_from(&b).unwrap();
+    let dir3 = Dir::recv_from(&b).unwrap();
+    assert_ne!(dir2.as_raw_fd(), dir.as_raw_fd());
+    assert!(same_meta(&dir2.self_metadata().unwrap(), &meta));
+    assert!(same_meta(&dir3.self_metadata().unwrap(), &meta));
+    assert_ne!(
+        unsafe { libc::fcntl(dir2.as_raw_fd(), libc::F_GETFD) } & libc::FD_CLOEXEC,
+        0
+    );
+    dir2.open_file().read(true).open("file").unwrap();
+
+    let (a, b) = UnixDatagram::pair().unwrap();
+    dir.send_over(&a).unwrap();
+    let dir2 = Dir::recv_from(&b).unwrap();
+    assert!(same_meta(&dir2.self_metadata().unwrap(), &meta));
+
+    // Not a directory
+    let (a, b) = UnixStream::pair().unwrap();
+    let file = unsafe {
+        Dir::from_raw_fd(
+            fs::File::open(tmpdir_path.join("file"))
+                .unwrap()
+                .into_raw_fd(),
+        )
+    };
+    file.send_over(&a).unwrap();
+    assert_eq!(
+        Dir::recv_from(&b).unwrap_err().raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+
+    // No file descriptor
+    (&a).write_all(b"D").unwrap();
+    assert_eq!(
+        Dir::recv_from(&b).unwrap_err().raw_os_error(),
+        Some(libc::EBADMSG)
+    );
+
+    // Connection closed
+    drop(a);
+    assert_eq!(
+        Dir::recv_from(&b).unwrap_err().kind(),
+        std::io::ErrorKind::UnexpectedEof
+    );
+}
+
+#[test]
+fn test_diagnostics() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref().canonicalize().unwrap();
+    fs::create_dir(tmpdir_path.join("a")).unwrap();
+
+    let dir = Dir::open(tmpdir_path.join("a")).unwrap();
+    let meta = dir.self_metadata().unwrap();
+
+    let diag = dir.diagnostics().unwrap();
+    assert_eq!(diag.fd(), dir.as_raw_fd());
+    assert_eq!(diag.path(), Some(tmpdir_path.join("a").as_path()));
+    assert_eq!(diag.dev(), meta.dev());
+    assert_eq!(diag.ino(), meta.ino());
+    assert_eq!(diag.flags() & libc::O_ACCMODE, libc::O_RDONLY);
+
+    let s = diag.to_string();
+    assert!(s.starts_with(&format!(
+        "fd {} (path {:?}",
+        dir.as_raw_fd(),
+        tmpdir_path.join("a")
+    )));
+    assert!(s.contains(&format!("ino {}", meta.ino())));
+
+    // The path is best-effort
+    fs::remove_dir(tmpdir_path.join("a")).unwrap();
+    let diag = dir.diagnostics().unwrap();
+    assert_eq!(diag.path(), None);
+    assert_eq!(diag.ino(), meta.ino());
+    assert!(diag.to_string().contains("path unknown"));
+}
+
+#[test]
+fn test_open_dir_beneath_from_file() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    fs::create_dir(tmpdir_path.join("a")).unwrap();
+    fs::write(tmpdir_path.join("a/file"), b"").unwrap();
+    tmpdir.symlink("link", "a", LookupFlags::empty()).unwrap();
+
+    let meta = tmpdir.metadata("a", LookupFlags::empty()).unwrap();
+
+    let subdir = tmpdir
+        .open_dir_beneath("link", libc::O_RDONLY, LookupFlags::empty())
+        .unwrap();
+    assert!(same_meta(&subdir.self_metadata().unwrap(), &meta));
+    assert_eq!(subdir.list_self().unwrap().count(), 1);
+
+    macro_rules! check_err {
+        ($path:expr, $lookup_flags:expr, $eno:expr) => {
+            assert_eq!(
+                tmpdir
+                    .open_dir_beneath($path, libc::O_RDONLY, $lookup_flags)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some($eno)
+            )
+        };
+    }
+
+    check_err!("a/file", LookupFlags::empty(), libc::ENOTDIR);
+    check_err!("link", LookupFlags::NO_SYMLINKS, libc::ELOOP);
+    check_err!("..", LookupFlags::empty(), libc::EXDEV);
+
+    let subdir = Dir::from_file(fs::File::open(tmpdir_path.join("a")).unwrap()).unwrap();
+    assert!(same_meta(&subdir.self_metadata().unwrap(), &meta));
+    subdir.open_file().read(true).open("file").unwrap();
+
+    assert_eq!(
+        Dir::from_file(fs::File::open(tmpdir_path.join("a/file")).unwrap())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+}
+
 #[test]
 fn test_create_remove_dir() {
     let tmpdir = tempfile::tempdir().unwrap();
@@ -118,6 +353,73 @@ fn test_create_remove_dir() {
     tmpdir.remove_dir("dir", LookupFlags::empty()).unwrap();
 }
 
+#[test]
+fn test_create_dir_all() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir
+        .create_dir_all("a/b/c", 0o777, LookupFlags::empty())
+        .unwrap();
+    assert!(tmpdir_path.join("a/b/c").is_dir());
+
+    // Already exists
+    tmpdir
+        .create_dir_all("a/b/c", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .create_dir_all("a/b/", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .create_dir_all(".", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .create_dir_all("/", 0o777, LookupFlags::IN_ROOT)
+        .unwrap();
+
+    // Symlinks are followed, but can't escape
+    tmpdir.symlink("link", "a/b", LookupFlags::empty()).unwrap();
+    tmpdir
+        .create_dir_all("link/d/e", 0o777, LookupFlags::empty())
+        .unwrap();
+    assert!(tmpdir_path.join("a/b/d/e").is_dir());
+
+    tmpdir
+        .create_dir_all("/x/y", 0o777, LookupFlags::IN_ROOT)
+        .unwrap();
+    assert!(tmpdir_path.join("x/y").is_dir());
+
+    fs::File::create(tmpdir_path.join("file")).unwrap();
+    tmpdir
+        .symlink("link-escape", "..", LookupFlags::empty())
+        .unwrap();
+
+    macro_rules! check_err {
+        ($path:expr, $lookup_flags:expr, $eno:expr) => {
+            assert_eq!(
+                tmpdir
+                    .create_dir_all($path, 0o777, $lookup_flags)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some($eno)
+            )
+        };
+
+        ($path:expr, $eno:expr) => {
+            check_err!($path, LookupFlags::empty(), $eno)
+        };
+    }
+
+    check_err!("", libc::ENOENT);
+    check_err!("file", libc::EEXIST);
+    check_err!("file/a", libc::ENOTDIR);
+    check_err!("/a", libc::EXDEV);
+    check_err!("../a", libc::EXDEV);
+    check_err!("link-escape/a", libc::EXDEV);
+    check_err!("link/f", LookupFlags::NO_SYMLINKS, libc::ELOOP);
+}
+
 #[test]
 fn test_open_file_lookup_flags() {
     let tmpdir = tempfile::tempdir().unwrap();
@@ -163,545 +465,3135 @@ fn test_open_file_lookup_flags() {
 }
 
 #[test]
-fn test_remove_file() {
+fn test_open_file_nonblock_cloexec() {
     let tmpdir = tempfile::tempdir().unwrap();
     let tmpdir_path = tmpdir.as_ref();
     let tmpdir = Dir::open(tmpdir_path).unwrap();
 
-    fs::File::create(&tmpdir_path.join("file")).unwrap();
-
-    tmpdir
-        .create_dir("dir", 0o777, LookupFlags::empty())
-        .unwrap();
-
-    fs::File::create(&tmpdir_path.join("dir/subfile")).unwrap();
+    fn get_fd_flags(file: &fs::File) -> (libc::c_int, libc::c_int) {
+        let fl = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) };
+        let fd = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFD) };
+        assert!(fl >= 0 && fd >= 0);
+        (fl, fd)
+    }
 
+    tmpdir.mkfifo("fifo", 0o600, LookupFlags::empty()).unwrap();
     tmpdir
-        .symlink("link", "file", LookupFlags::empty())
+        .symlink("link", "fifo", LookupFlags::empty())
         .unwrap();
 
-    tmpdir
-        .symlink("link-noexist", "NOEXIST", LookupFlags::empty())
+    // Opening a FIFO with no writers doesn't block
+    let file = tmpdir
+        .open_file()
+        .read(true)
+        .nonblock(true)
+        .open("link")
         .unwrap();
+    let (fl, fd) = get_fd_flags(&file);
+    assert_eq!(fl & libc::O_NONBLOCK, libc::O_NONBLOCK);
+    assert_eq!(fd & libc::FD_CLOEXEC, libc::FD_CLOEXEC);
 
-    macro_rules! check_err {
-        ($path:expr, $lookup_flags:expr, $($enos:pat)|+) => {{
-            let eno = tmpdir
-                .remove_file($path, $lookup_flags)
-                .unwrap_err()
-                .raw_os_error()
-                .unwrap();
-
-            assert!(matches!(eno, $($enos)|+), "{}", eno);
-        }};
-
-        ($path:expr, $($enos:pat)|+) => {
-            check_err!($path, LookupFlags::empty(), $($enos)|+)
-        };
-    }
-
-    check_err!(".", libc::EISDIR | libc::EPERM);
-    check_err!("/", LookupFlags::IN_ROOT, libc::EISDIR | libc::EPERM);
-    check_err!("..", LookupFlags::IN_ROOT, libc::EISDIR | libc::EPERM);
-    check_err!("dir", libc::EISDIR | libc::EPERM);
-    check_err!("dir/subfile/..", libc::ENOTDIR);
-
-    // Trying to unlink() the symlinks will succeed
-    tmpdir.remove_file("link", LookupFlags::empty()).unwrap();
-    tmpdir
-        .remove_file("link-noexist", LookupFlags::empty())
+    // Now there's a reader, so opening it for writing succeeds
+    let file = tmpdir
+        .open_file()
+        .write(true)
+        .nonblock(true)
+        .cloexec(false)
+        .open("fifo")
         .unwrap();
+    let (fl, fd) = get_fd_flags(&file);
+    assert_eq!(fl & libc::O_NONBLOCK, libc::O_NONBLOCK);
+    assert_eq!(fd & libc::FD_CLOEXEC, 0);
+    drop(file);
 
-    // But it leaves the original file in place
-    tmpdir
-        .remove_file("dir/subfile", LookupFlags::empty())
+    fs::write(tmpdir_path.join("file"), b"").unwrap();
+    let file = tmpdir
+        .open_file()
+        .read(true)
+        .cloexec(false)
+        .open("file")
         .unwrap();
-    tmpdir.remove_file("file", LookupFlags::empty()).unwrap();
+    let (fl, fd) = get_fd_flags(&file);
+    assert_eq!(fl & libc::O_NONBLOCK, 0);
+    assert_eq!(fd & libc::FD_CLOEXEC, 0);
 }
 
 #[test]
-fn test_symlinks() {
+fn test_open_file_expect() {
+    use obnth::FileType;
+
+    fn assert_unexpected(err: std::io::Error, exp: FileType, fnd: FileType) {
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        match err.get_ref().unwrap().downcast_ref::<obnth::Error>() {
+            Some(obnth::Error::UnexpectedFileType { expected, found }) => {
+                assert_eq!(*expected, exp);
+                assert_eq!(*found, fnd);
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
     let tmpdir = tempfile::tempdir().unwrap();
     let tmpdir_path = tmpdir.as_ref();
     let tmpdir = Dir::open(tmpdir_path).unwrap();
 
-    fs::File::create(&tmpdir_path.join("file")).unwrap();
-
     tmpdir
-        .symlink("link", "target", LookupFlags::empty())
+        .create_dir("dir", 0o777, LookupFlags::empty())
         .unwrap();
-
+    tmpdir.write("file", b"data", LookupFlags::empty()).unwrap();
+    tmpdir.mkfifo("fifo", 0o600, LookupFlags::empty()).unwrap();
     tmpdir
-        .symlink("link-exist", "dir", LookupFlags::empty())
+        .symlink("link", "fifo", LookupFlags::empty())
+        .unwrap();
+
+    let file = tmpdir
+        .open_file()
+        .read(true)
+        .expect(Some(FileType::File))
+        .open("file")
         .unwrap();
+    // O_NONBLOCK was cleared afterward
+    let fl = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) };
+    assert_eq!(fl & libc::O_NONBLOCK, 0);
 
     tmpdir
-        .create_dir("dir", 0o777, LookupFlags::empty())
+        .open_file()
+        .read(true)
+        .expect(Some(FileType::Directory))
+        .open("dir")
         .unwrap();
 
+    assert_unexpected(
+        tmpdir
+            .open_file()
+            .read(true)
+            .expect(Some(FileType::File))
+            .open("dir")
+            .unwrap_err(),
+        FileType::File,
+        FileType::Directory,
+    );
+
+    // Opening a FIFO where a regular file is expected fails instead of blocking
+    assert_unexpected(
+        tmpdir
+            .open_file()
+            .read(true)
+            .expect(Some(FileType::File))
+            .open("link")
+            .unwrap_err(),
+        FileType::File,
+        FileType::Fifo,
+    );
+
+    // But FIFOs can be opened if they're expected
     tmpdir
-        .symlink("dir/sublink", "subtarget", LookupFlags::empty())
+        .open_file()
+        .read(true)
+        .nonblock(true)
+        .expect(Some(FileType::Fifo))
+        .open("fifo")
         .unwrap();
+}
 
-    macro_rules! check_err {
-        ($path:expr, $lookup_flags:expr, $eno:expr) => {{
-            assert_eq!(
-                tmpdir
-                    .symlink($path, "target", $lookup_flags)
-                    .unwrap_err()
-                    .raw_os_error(),
-                Some($eno)
-            );
-        }};
+#[test]
+fn test_dir_open_with() {
+    use obnth::DirOpenOptions;
 
-        ($path:expr, $eno:expr) => {
-            check_err!($path, LookupFlags::empty(), $eno)
-        };
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+
+    fs::create_dir(tmpdir_path.join("dir")).unwrap();
+    fs::set_permissions(tmpdir_path.join("dir"), fs::Permissions::from_mode(0o755)).unwrap();
+    std::os::unix::fs::symlink("dir", tmpdir_path.join("link")).unwrap();
+
+    let meta = fs::metadata(tmpdir_path.join("dir")).unwrap();
+
+    let open_err = |path: &str, opts: &DirOpenOptions| {
+        Dir::open_with(tmpdir_path.join(path), opts)
+            .unwrap_err()
+            .raw_os_error()
+            .unwrap()
+    };
+
+    // No checks
+    Dir::open_with(tmpdir_path.join("link"), &DirOpenOptions::new()).unwrap();
+
+    let mut opts = DirOpenOptions::new();
+    opts.nofollow(true);
+    Dir::open_with(tmpdir_path.join("dir"), &opts).unwrap();
+    // Only the final component is checked
+    Dir::open_with(tmpdir_path.join("link/."), &opts).unwrap();
+    let eno = open_err("link", &opts);
+    assert!(eno == libc::ELOOP || eno == libc::ENOTDIR, "{}", eno);
+
+    let mut opts = DirOpenOptions::new();
+    opts.owner(Some(meta.uid())).group(Some(meta.gid()));
+    opts.forbid_mode(0o022);
+    let dir = opts.open(tmpdir_path.join("link")).unwrap();
+    assert_eq!(dir.self_metadata().unwrap().ino(), meta.ino());
+
+    opts.forbid_mode(0o005);
+    assert_eq!(open_err("dir", &opts), libc::EPERM);
+    opts.forbid_mode(0).owner(Some(meta.uid() + 1));
+    assert_eq!(open_err("dir", &opts), libc::EPERM);
+    opts.owner(None).group(Some(meta.gid() + 1));
+    assert_eq!(open_err("dir", &opts), libc::EPERM);
+
+    let mut opts = DirOpenOptions::new();
+    opts.mountpoint(true);
+    assert_eq!(open_err("dir", &opts), libc::EPERM);
+    Dir::open_with("/", &opts).unwrap();
+    #[cfg(target_os = "linux")]
+    if fs::metadata("/proc/self").is_ok() {
+        Dir::open_with("/proc", &opts).unwrap();
     }
+}
 
-    check_err!(".", libc::EEXIST);
-    check_err!("/", LookupFlags::IN_ROOT, libc::EEXIST);
-    check_err!("..", LookupFlags::IN_ROOT, libc::EEXIST);
-    check_err!("dir", libc::EEXIST);
-    check_err!("dir/sublink/..", libc::ENOENT);
-    check_err!("link", libc::EEXIST);
-    check_err!("link/", libc::EEXIST);
-    check_err!("link-exist", libc::EEXIST);
-    check_err!("link-exist/", libc::EEXIST);
+#[test]
+fn test_dir_resolve_only() {
+    use obnth::DirOpenOptions;
+
+    fn assert_resolve_only(err: std::io::Error) {
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(matches!(
+            err.get_ref().unwrap().downcast_ref::<obnth::Error>(),
+            Some(obnth::Error::ResolveOnly)
+        ));
+    }
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    fs::create_dir_all(tmpdir_path.join("a/b")).unwrap();
+    fs::write(tmpdir_path.join("a/file"), b"data").unwrap();
 
+    let dir = DirOpenOptions::new()
+        .resolve_only(true)
+        .open(tmpdir_path)
+        .unwrap();
+    assert!(dir.is_resolve_only());
+
+    // Known names can still be opened
+    assert_eq!(dir.read("a/file", LookupFlags::empty()).unwrap(), b"data");
+    dir.metadata("a/b", LookupFlags::empty()).unwrap();
+
+    // But nothing can be listed
+    assert_resolve_only(dir.list_self().unwrap_err());
+    assert_resolve_only(dir.list_dir("a", LookupFlags::empty()).unwrap_err());
+    assert_resolve_only(dir.walk().walk(".").unwrap().next().unwrap().unwrap_err());
+    assert_resolve_only(dir.remove_dir_all("a", LookupFlags::empty()).unwrap_err());
+    assert!(tmpdir_path.join("a/b").exists());
+
+    // Derived directories inherit the mode
+    let sub = dir.sub_dir("a", LookupFlags::empty()).unwrap();
+    assert!(sub.is_resolve_only());
+    assert_resolve_only(sub.list_self().unwrap_err());
+    assert!(dir.try_clone().unwrap().is_resolve_only());
+    assert!(sub.parent_unchecked().unwrap().is_resolve_only());
+
+    // Path recovery still works
     assert_eq!(
-        tmpdir.read_link("link", LookupFlags::empty()).unwrap(),
-        Path::new("target"),
+        sub.recover_path().unwrap(),
+        tmpdir_path.join("a").canonicalize().unwrap()
     );
+
+    // Ordinary directories can be converted
+    let dir = Dir::open(tmpdir_path).unwrap();
+    assert!(!dir.is_resolve_only());
+    dir.list_self().unwrap();
+    let dir = dir.into_resolve_only();
+    assert_resolve_only(dir.list_self().unwrap_err());
+}
+
+#[test]
+fn test_read_only_dir() {
+    use std::io::Write;
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    fs::create_dir(tmpdir_path.join("a")).unwrap();
+    fs::write(tmpdir_path.join("a/file"), b"data").unwrap();
+    std::os::unix::fs::symlink("file", tmpdir_path.join("a/link")).unwrap();
+
+    let dir = Dir::open(tmpdir_path).unwrap().into_read_only();
+
+    assert_eq!(dir.read("a/file", LookupFlags::empty()).unwrap(), b"data");
     assert_eq!(
-        tmpdir.read_link("/link", LookupFlags::IN_ROOT).unwrap(),
-        Path::new("target"),
+        dir.read_to_string("a/link", LookupFlags::empty()).unwrap(),
+        "data"
     );
-
     assert_eq!(
-        tmpdir
-            .read_link("dir/../link", LookupFlags::IN_ROOT)
-            .unwrap(),
-        Path::new("target"),
+        dir.read_link("a/link", LookupFlags::empty()).unwrap(),
+        Path::new("file")
     );
+    assert!(dir.is_file("a/link", LookupFlags::empty()).unwrap());
+    assert!(dir.is_dir("a", LookupFlags::empty()).unwrap());
+    assert!(!dir.exists("b", LookupFlags::empty()).unwrap());
+    assert_eq!(dir.list_dir("a", LookupFlags::empty()).unwrap().count(), 2);
 
+    // Files are opened read-only
+    let mut file = dir.open_file("a/file", LookupFlags::empty()).unwrap();
     assert_eq!(
-        tmpdir
-            .read_link("dir/sublink", LookupFlags::empty())
-            .unwrap(),
-        Path::new("subtarget"),
+        file.write(b"x").unwrap_err().raw_os_error(),
+        Some(libc::EBADF)
     );
 
-    macro_rules! check_err {
-        ($path:expr, $lookup_flags:expr, $eno:expr) => {{
-            assert_eq!(
-                tmpdir
-                    .read_link($path, $lookup_flags)
-                    .unwrap_err()
-                    .raw_os_error(),
-                Some($eno)
-            );
-        }};
-
-        ($path:expr, $eno:expr) => {
-            check_err!($path, LookupFlags::empty(), $eno)
-        };
-    }
+    // Subdirectories are read-only too
+    let sub = dir.sub_dir("a", LookupFlags::empty()).unwrap();
+    assert_eq!(sub.read("file", LookupFlags::empty()).unwrap(), b"data");
+    assert_eq!(
+        sub.read("../a/file", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
 
-    check_err!(".", libc::EINVAL);
-    check_err!("/", LookupFlags::IN_ROOT, libc::EINVAL);
-    check_err!("..", LookupFlags::IN_ROOT, libc::EINVAL);
-    check_err!("dir", libc::EINVAL);
-    check_err!("file", libc::EINVAL);
-    check_err!("dir/sublink/..", libc::ENOENT);
+    assert_eq!(
+        dir.open_file("a/../..", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
 }
 
 #[test]
-fn test_change_cwd_to() {
-    // No-op... unfortunately we can't test much more without messing up other threads
-    Dir::open(".").unwrap().change_cwd_to().unwrap();
+fn test_dir_restrict() {
+    use obnth::Rights;
+
+    fn assert_missing(err: std::io::Error, rights: Rights) {
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        match err.get_ref().unwrap().downcast_ref::<obnth::Error>() {
+            Some(obnth::Error::MissingRights(missing)) => assert_eq!(*missing, rights),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
 
-    assert_eq!(
-        unsafe { Dir::from_raw_fd(-1) }
-            .change_cwd_to()
-            .unwrap_err()
-            .raw_os_error(),
-        Some(libc::EBADF)
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    fs::create_dir(tmpdir_path.join("a")).unwrap();
+    fs::write(tmpdir_path.join("a/file"), b"data").unwrap();
+
+    let mut dir = Dir::open(tmpdir_path).unwrap();
+    assert_eq!(dir.rights(), Rights::all());
+    dir.restrict(Rights::READ);
+    assert_eq!(dir.rights(), Rights::READ);
+
+    // Reading still works
+    assert_eq!(dir.read("a/file", LookupFlags::empty()).unwrap(), b"data");
+    assert_eq!(dir.list_dir("a", LookupFlags::empty()).unwrap().count(), 1);
+    dir.metadata("a/file", LookupFlags::empty()).unwrap();
+
+    // But nothing can be modified
+    assert_missing(
+        dir.open_file().write(true).open("a/file").unwrap_err(),
+        Rights::WRITE,
+    );
+    assert_missing(
+        dir.write("a/new", b"", LookupFlags::empty()).unwrap_err(),
+        Rights::WRITE | Rights::CREATE,
+    );
+    assert_missing(
+        dir.create_dir("b", 0o777, LookupFlags::empty())
+            .unwrap_err(),
+        Rights::CREATE,
+    );
+    assert_missing(
+        dir.remove_file("a/file", LookupFlags::empty()).unwrap_err(),
+        Rights::DELETE,
+    );
+    assert_missing(
+        dir.set_permissions(
+            "a/file",
+            fs::Permissions::from_mode(0o600),
+            LookupFlags::empty(),
+        )
+        .unwrap_err(),
+        Rights::SETATTR,
+    );
+    assert!(!tmpdir_path.join("a/new").exists());
+    assert!(!tmpdir_path.join("b").exists());
+    assert!(tmpdir_path.join("a/file").exists());
+
+    let full = Dir::open(tmpdir_path).unwrap();
+    assert_missing(
+        obnth::rename(&dir, "a/file", &full, "file", LookupFlags::empty()).unwrap_err(),
+        Rights::RENAME,
+    );
+    assert!(tmpdir_path.join("a/file").exists());
+
+    // Replacing a file by renaming over it requires DELETE
+    let mut nodelete = Dir::open(tmpdir_path).unwrap();
+    nodelete.restrict(Rights::READ | Rights::WRITE | Rights::CREATE);
+    assert_missing(
+        nodelete
+            .write_atomic("a/file", b"new", 0o666, LookupFlags::empty())
+            .unwrap_err(),
+        Rights::DELETE,
+    );
+    assert_missing(
+        nodelete
+            .replace_file("a/file", b"new", LookupFlags::empty())
+            .unwrap_err(),
+        Rights::DELETE,
+    );
+    assert_missing(
+        nodelete
+            .symlink_replace("a/file", "x", LookupFlags::empty())
+            .unwrap_err(),
+        Rights::DELETE,
+    );
+    assert_missing(
+        nodelete
+            .tempfile("tmp", "", 0o600)
+            .unwrap()
+            .persist("a/file", LookupFlags::empty())
+            .unwrap_err(),
+        Rights::DELETE,
+    );
+    assert_eq!(fs::read(tmpdir_path.join("a/file")).unwrap(), b"data");
+    assert_eq!(fs::read_dir(tmpdir_path.join("a")).unwrap().count(), 1);
+    assert_eq!(fs::read_dir(tmpdir_path).unwrap().count(), 1);
+
+    // Syncing requires READ
+    let mut noread = Dir::open(tmpdir_path).unwrap();
+    noread.restrict(Rights::empty());
+    assert_missing(noread.sync_all().unwrap_err(), Rights::READ);
+    assert_missing(
+        noread
+            .sync_parent("a/file", LookupFlags::empty())
+            .unwrap_err(),
+        Rights::READ,
     );
 
+    // Derived directories inherit the rights, and rights can't be added back
+    let mut sub = dir.sub_dir("a", LookupFlags::empty()).unwrap();
+    assert_eq!(sub.rights(), Rights::READ);
+    sub.restrict(Rights::all());
+    assert_eq!(sub.rights(), Rights::READ);
+    assert_eq!(dir.try_clone().unwrap().rights(), Rights::READ);
+    assert_eq!(sub.parent().unwrap().unwrap().rights(), Rights::READ);
     assert_eq!(
-        unsafe {
-            Dir::from_raw_fd(
-                fs::File::open(std::env::current_exe().unwrap())
-                    .unwrap()
-                    .into_raw_fd(),
-            )
-        }
-        .change_cwd_to()
-        .unwrap_err()
-        .raw_os_error(),
-        Some(libc::ENOTDIR)
+        dir.resolve("a/x", LookupFlags::empty())
+            .unwrap()
+            .into_parent()
+            .rights(),
+        Rights::READ
+    );
+    sub.restrict(Rights::empty());
+    assert_missing(
+        sub.resolve("x", LookupFlags::empty()).unwrap_err(),
+        Rights::READ,
+    );
+    assert_missing(
+        sub.read("file", LookupFlags::empty()).unwrap_err(),
+        Rights::READ,
     );
 }
 
+#[cfg(target_os = "linux")]
 #[test]
-fn test_hardlink() {
+fn test_open_file_custom_flags() {
     let tmpdir = tempfile::tempdir().unwrap();
     let tmpdir_path = tmpdir.as_ref();
     let tmpdir = Dir::open(tmpdir_path).unwrap();
 
+    tmpdir.create_dir("a", 0o777, LookupFlags::empty()).unwrap();
     tmpdir
-        .open_file()
-        .write(true)
-        .create_new(true)
-        .open("a")
-        .unwrap();
-
-    tmpdir
-        .create_dir("dir", 0o777, LookupFlags::empty())
+        .create_dir("a/b", 0o777, LookupFlags::empty())
         .unwrap();
+    tmpdir.write("a/b/file", b"", LookupFlags::empty()).unwrap();
+    tmpdir.symlink("link", "a/b", LookupFlags::empty()).unwrap();
     tmpdir
-        .create_dir("dir2", 0o777, LookupFlags::empty())
+        .symlink("a/flink", "b/file", LookupFlags::empty())
         .unwrap();
 
-    tmpdir.symlink("link", "a", LookupFlags::empty()).unwrap();
+    for &custom in [libc::O_NOATIME, libc::O_DIRECT, libc::O_SYNC, libc::O_DSYNC].iter() {
+        for &path in ["a/b/file", "link/file", "a/flink", "a/../link/../flink"].iter() {
+            // The default resolution method, and the fallback resolver (which is forced by
+            // setting a symlink limit)
+            for &max_symlinks in [None, Some(10)].iter() {
+                let file = match tmpdir
+                    .open_file()
+                    .read(true)
+                    .custom_flags(custom)
+                    .max_symlinks(max_symlinks)
+                    .open(path)
+                {
+                    Ok(file) => file,
+                    // Not all filesystems support O_DIRECT
+                    Err(e)
+                        if custom == libc::O_DIRECT && e.raw_os_error() == Some(libc::EINVAL) =>
+                    {
+                        continue
+                    }
+                    Err(e) => panic!("{} {:o}: {}", path, custom, e),
+                };
+
+                let fl = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) };
+                assert!(fl >= 0);
+                assert_eq!(fl & custom, custom, "{} {:o}", path, custom);
+            }
+        }
+    }
+}
 
-    tmpdir
-        .symlink("link-noexist", "NOEXIST", LookupFlags::empty())
+#[test]
+fn test_remove_dir_all() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+
+    fs::create_dir(tmpdir_path.join("outside")).unwrap();
+    fs::write(tmpdir_path.join("outside/file"), b"").unwrap();
+
+    fs::create_dir(tmpdir_path.join("root")).unwrap();
+    let root = Dir::open(tmpdir_path.join("root")).unwrap();
+
+    root.create_dir("a", 0o777, LookupFlags::empty()).unwrap();
+    root.create_dir("a/b", 0o777, LookupFlags::empty()).unwrap();
+    root.create_dir("a/b/c", 0o777, LookupFlags::empty())
+        .unwrap();
+    root.create_dir("a/empty", 0o777, LookupFlags::empty())
         .unwrap();
+    fs::write(tmpdir_path.join("root/a/file"), b"").unwrap();
+    fs::write(tmpdir_path.join("root/a/b/c/file"), b"").unwrap();
+    root.symlink("a/b/outside", "../../../../outside", LookupFlags::empty())
+        .unwrap();
+    root.symlink(
+        "a/b/c/outside-file",
+        "../../../../outside/file",
+        LookupFlags::empty(),
+    )
+    .unwrap();
+    root.symlink("link", "a", LookupFlags::empty()).unwrap();
 
-    let a_meta = tmpdir.metadata("a", LookupFlags::empty()).unwrap();
+    macro_rules! check_err {
+        ($path:expr, $lookup_flags:expr, $eno:expr) => {
+            assert_eq!(
+                root.remove_dir_all($path, $lookup_flags)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some($eno)
+            )
+        };
 
-    obnth::hardlink(&tmpdir, "a", &tmpdir, "dir/a", LookupFlags::empty()).unwrap();
+        ($path:expr, $eno:expr) => {
+            check_err!($path, LookupFlags::empty(), $eno)
+        };
+    }
 
-    assert!(same_meta(
-        &a_meta,
-        &tmpdir.metadata("a", LookupFlags::empty()).unwrap()
-    ));
-    assert!(same_meta(
-        &a_meta,
-        &tmpdir.metadata("dir/a", LookupFlags::empty()).unwrap()
-    ));
+    check_err!(".", libc::EBUSY);
+    check_err!("/", LookupFlags::IN_ROOT, libc::EBUSY);
+    check_err!("a/b/..", libc::EBUSY);
+    check_err!("..", libc::EXDEV);
+    check_err!("NOEXIST", libc::ENOENT);
 
+    // Removing a symlink removes the link, not the target
+    root.remove_dir_all("link", LookupFlags::empty()).unwrap();
+    assert!(root.metadata("a", LookupFlags::empty()).unwrap().is_dir());
     assert_eq!(
-        obnth::hardlink(&tmpdir, "dir", &tmpdir, "dir3", LookupFlags::empty())
+        root.metadata("link", LookupFlags::empty())
             .unwrap_err()
             .raw_os_error(),
-        Some(libc::EPERM)
+        Some(libc::ENOENT)
     );
 
+    root.remove_dir_all("a", LookupFlags::empty()).unwrap();
     assert_eq!(
-        obnth::hardlink(&tmpdir, "dir/..", &tmpdir, "dir2", LookupFlags::empty())
+        root.metadata("a", LookupFlags::empty())
             .unwrap_err()
             .raw_os_error(),
-        Some(libc::EPERM)
+        Some(libc::ENOENT)
     );
+    assert_eq!(root.list_self().unwrap().count(), 0);
 
-    assert_eq!(
-        obnth::hardlink(&tmpdir, "dir", &tmpdir, "dir2/.", LookupFlags::empty())
-            .unwrap_err()
-            .raw_os_error(),
-        Some(libc::EEXIST)
-    );
+    // Nothing outside was touched
+    assert!(tmpdir_path.join("outside/file").exists());
+}
+
+#[test]
+fn test_recreate_dir() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    fs::create_dir_all(tmpdir_path.join("a/b")).unwrap();
+    fs::write(tmpdir_path.join("a/b/file"), b"").unwrap();
+    fs::create_dir(tmpdir_path.join("empty")).unwrap();
+    fs::write(tmpdir_path.join("file"), b"").unwrap();
+    tmpdir.symlink("link", "a", LookupFlags::empty()).unwrap();
 
+    // Missing directories are simply created
+    tmpdir
+        .recreate_dir("new", 0o700, false, LookupFlags::empty())
+        .unwrap();
+    assert!(tmpdir.is_dir("new", LookupFlags::empty()).unwrap());
+
+    // (Inode numbers may be reused, so check the permissions instead)
+    fs::set_permissions(tmpdir_path.join("empty"), fs::Permissions::from_mode(0o711)).unwrap();
+    tmpdir
+        .recreate_dir("empty", 0o700, false, LookupFlags::empty())
+        .unwrap();
     assert_eq!(
-        obnth::hardlink(&tmpdir, "a", &tmpdir, "link", LookupFlags::empty())
-            .unwrap_err()
-            .raw_os_error(),
-        Some(libc::EEXIST)
+        fs::metadata(tmpdir_path.join("empty"))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777,
+        0o700
     );
 
+    // Non-empty directories are only removed if `recursive` is true
     assert_eq!(
-        obnth::hardlink(&tmpdir, "a", &tmpdir, "link/", LookupFlags::empty())
+        tmpdir
+            .recreate_dir("a", 0o777, false, LookupFlags::empty())
             .unwrap_err()
             .raw_os_error(),
-        Some(libc::EEXIST)
+        Some(libc::ENOTEMPTY)
     );
-
+    assert!(tmpdir_path.join("a/b/file").exists());
+    fs::write(tmpdir_path.join("a/marker"), b"").unwrap();
+    tmpdir
+        .recreate_dir("a", 0o777, true, LookupFlags::empty())
+        .unwrap();
+    assert!(!tmpdir_path.join("a/marker").exists());
     assert_eq!(
-        obnth::hardlink(&tmpdir, "a", &tmpdir, "link-noexist", LookupFlags::empty())
-            .unwrap_err()
-            .raw_os_error(),
-        Some(libc::EEXIST)
+        tmpdir.list_dir("a", LookupFlags::empty()).unwrap().count(),
+        0
     );
 
+    macro_rules! check_err {
+        ($path:expr, $lookup_flags:expr, $eno:expr) => {
+            assert_eq!(
+                tmpdir
+                    .recreate_dir($path, 0o777, true, $lookup_flags)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some($eno)
+            )
+        };
+
+        ($path:expr, $eno:expr) => {
+            check_err!($path, LookupFlags::empty(), $eno)
+        };
+    }
+
+    check_err!(".", libc::EBUSY);
+    check_err!("/", LookupFlags::IN_ROOT, libc::EBUSY);
+    check_err!("..", libc::EXDEV);
+    check_err!("NOEXIST/a", libc::ENOENT);
+    // Non-directories (including symlinks to directories) are left alone
+    check_err!("file", libc::ENOTDIR);
+    check_err!("link", libc::ENOTDIR);
+    assert!(tmpdir_path.join("file").exists());
     assert_eq!(
-        obnth::hardlink(&tmpdir, "a", &tmpdir, "link-noexist/", LookupFlags::empty())
-            .unwrap_err()
-            .raw_os_error(),
-        Some(libc::EEXIST)
+        tmpdir
+            .metadata("link", LookupFlags::empty())
+            .unwrap()
+            .file_type(),
+        obnth::FileType::Symlink
     );
 }
 
 #[test]
-fn test_rename() {
+fn test_write_atomic() {
     let tmpdir = tempfile::tempdir().unwrap();
     let tmpdir_path = tmpdir.as_ref();
     let tmpdir = Dir::open(tmpdir_path).unwrap();
 
-    tmpdir
-        .open_file()
-        .write(true)
-        .create_new(true)
-        .open("a")
-        .unwrap();
-
     tmpdir
         .create_dir("dir", 0o777, LookupFlags::empty())
         .unwrap();
+    fs::write(tmpdir_path.join("target"), b"target").unwrap();
     tmpdir
-        .create_dir("dir2", 0o777, LookupFlags::empty())
-        .unwrap();
-
-    tmpdir.symlink("link", "a", LookupFlags::empty()).unwrap();
-
-    tmpdir
-        .symlink("link-noexist", "NOEXIST", LookupFlags::empty())
+        .symlink("dir/link", "../target", LookupFlags::empty())
         .unwrap();
 
-    let a_meta = tmpdir.metadata("a", LookupFlags::empty()).unwrap();
-    let dir_meta = tmpdir.metadata("dir", LookupFlags::empty()).unwrap();
-
     tmpdir
-        .local_rename("a", "dir/a", LookupFlags::empty())
+        .write_atomic("dir/file", b"abc", 0o600, LookupFlags::empty())
         .unwrap();
-
-    assert!(same_meta(
-        &a_meta,
-        &tmpdir.metadata("dir/a", LookupFlags::empty()).unwrap()
-    ));
-
-    assert_eq!(
-        tmpdir
-            .local_rename("NOEXIST", "dir2", LookupFlags::empty())
-            .unwrap_err()
-            .raw_os_error(),
-        Some(libc::ENOENT)
-    );
-
+    assert_eq!(fs::read(tmpdir_path.join("dir/file")).unwrap(), b"abc");
     assert_eq!(
         tmpdir
-            .local_rename("dir/..", "dir2", LookupFlags::empty())
-            .unwrap_err()
-            .raw_os_error(),
-        Some(libc::EBUSY)
+            .metadata("dir/file", LookupFlags::empty())
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777,
+        0o600
     );
 
-    assert_eq!(
-        tmpdir
-            .local_rename("dir", "dir2/.", LookupFlags::empty())
-            .unwrap_err()
-            .raw_os_error(),
-        Some(libc::EBUSY)
-    );
+    let old_meta = tmpdir.metadata("dir/file", LookupFlags::empty()).unwrap();
+    tmpdir
+        .write_atomic("dir/file", "defg", 0o600, LookupFlags::empty())
+        .unwrap();
+    assert_eq!(fs::read(tmpdir_path.join("dir/file")).unwrap(), b"defg");
+    // It was replaced, not overwritten
+    assert!(!same_meta(
+        &old_meta,
+        &tmpdir.metadata("dir/file", LookupFlags::empty()).unwrap()
+    ));
 
+    // Symlinks are replaced, not followed
     tmpdir
-        .local_rename("dir", "dir2", LookupFlags::empty())
+        .write_atomic("dir/link", b"link", 0o666, LookupFlags::empty())
         .unwrap();
+    assert!(tmpdir
+        .metadata("dir/link", LookupFlags::empty())
+        .unwrap()
+        .is_file());
+    assert_eq!(fs::read(tmpdir_path.join("target")).unwrap(), b"target");
 
-    assert!(same_meta(
-        &dir_meta,
-        &tmpdir.metadata("dir2", LookupFlags::empty()).unwrap()
-    ));
+    // No temporary files were left behind
+    assert_eq!(fs::read_dir(tmpdir_path.join("dir")).unwrap().count(), 2);
 
-    assert_eq!(
-        obnth::rename(&tmpdir, "dir2", &tmpdir, "link", LookupFlags::empty())
+    macro_rules! check_err {
+        ($path:expr, $lookup_flags:expr, $eno:expr) => {
+            assert_eq!(
+                tmpdir
+                    .write_atomic($path, b"", 0o666, $lookup_flags)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some($eno)
+            )
+        };
+
+        ($path:expr, $eno:expr) => {
+            check_err!($path, LookupFlags::empty(), $eno)
+        };
+    }
+
+    check_err!(".", libc::EISDIR);
+    check_err!("dir/..", libc::EISDIR);
+    check_err!("/", LookupFlags::IN_ROOT, libc::EISDIR);
+    check_err!("../file", libc::EXDEV);
+    check_err!("NOEXIST/file", libc::ENOENT);
+    check_err!("dir", libc::EISDIR);
+    assert_eq!(fs::read_dir(tmpdir_path.join("dir")).unwrap().count(), 2);
+}
+
+#[test]
+fn test_replace_file() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+    fs::write(tmpdir_path.join("dir/file"), b"abc").unwrap();
+    fs::set_permissions(
+        tmpdir_path.join("dir/file"),
+        fs::Permissions::from_mode(0o640),
+    )
+    .unwrap();
+    tmpdir
+        .symlink("dir/link", "file", LookupFlags::empty())
+        .unwrap();
+
+    let old_meta = tmpdir.metadata("dir/file", LookupFlags::empty()).unwrap();
+    tmpdir
+        .replace_file("dir/file", b"defg", LookupFlags::empty())
+        .unwrap();
+    assert_eq!(fs::read(tmpdir_path.join("dir/file")).unwrap(), b"defg");
+
+    // It was replaced, not overwritten, and the permissions were kept
+    let new_meta = tmpdir.metadata("dir/file", LookupFlags::empty()).unwrap();
+    assert!(!same_meta(&old_meta, &new_meta));
+    assert_eq!(new_meta.permissions().mode() & 0o777, 0o640);
+
+    // Except for the setuid and setgid bits
+    fs::set_permissions(
+        tmpdir_path.join("dir/file"),
+        fs::Permissions::from_mode(0o6750),
+    )
+    .unwrap();
+    tmpdir
+        .replace_file("dir/file", b"", LookupFlags::empty())
+        .unwrap();
+    assert_eq!(
+        fs::metadata(tmpdir_path.join("dir/file"))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o7777,
+        0o750
+    );
+
+    macro_rules! check_err {
+        ($path:expr, $lookup_flags:expr, $eno:expr) => {
+            assert_eq!(
+                tmpdir
+                    .replace_file($path, b"", $lookup_flags)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some($eno)
+            )
+        };
+
+        ($path:expr, $eno:expr) => {
+            check_err!($path, LookupFlags::empty(), $eno)
+        };
+    }
+
+    check_err!(".", libc::EISDIR);
+    check_err!("/", LookupFlags::IN_ROOT, libc::EISDIR);
+    check_err!("../file", libc::EXDEV);
+    check_err!("dir", libc::EISDIR);
+    check_err!("dir/NOEXIST", libc::ENOENT);
+    check_err!("dir/link", libc::EINVAL);
+
+    assert_eq!(fs::read(tmpdir_path.join("dir/file")).unwrap(), b"defg");
+    // No temporary files were left behind
+    assert_eq!(fs::read_dir(tmpdir_path.join("dir")).unwrap().count(), 2);
+}
+
+#[test]
+fn test_sync() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir.sync_all().unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .write_atomic("dir/file", b"abc", 0o666, LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .sync_parent("dir/file", LookupFlags::empty())
+        .unwrap();
+    tmpdir.sync_parent("dir", LookupFlags::empty()).unwrap();
+    tmpdir.sync_parent(".", LookupFlags::empty()).unwrap();
+    // The file itself doesn't need to exist
+    tmpdir
+        .sync_parent("dir/noexist", LookupFlags::empty())
+        .unwrap();
+
+    macro_rules! check_err {
+        ($path:expr, $eno:expr) => {
+            assert_eq!(
+                tmpdir
+                    .sync_parent($path, LookupFlags::empty())
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some($eno)
+            )
+        };
+    }
+
+    check_err!("noexist/file", libc::ENOENT);
+    check_err!("dir/file/a", libc::ENOTDIR);
+    check_err!("../file", libc::EXDEV);
+}
+
+#[test]
+fn test_dir_builder() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    let uid = unsafe { libc::getuid() };
+    let gid = unsafe { libc::getgid() };
+
+    tmpdir.dir_builder().mode(0o700).create("a").unwrap();
+    assert_eq!(
+        tmpdir.metadata("a", LookupFlags::empty()).unwrap().mode() & 0o777,
+        0o700
+    );
+    assert_eq!(
+        tmpdir.dir_builder().create("a").unwrap_err().raw_os_error(),
+        Some(libc::EEXIST)
+    );
+    assert_eq!(
+        tmpdir
+            .dir_builder()
+            .create("b/c")
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOENT)
+    );
+
+    tmpdir
+        .dir_builder()
+        .recursive(true)
+        .mode(0o750)
+        .owner(Some(uid))
+        .group(Some(gid))
+        .create("b/c/d")
+        .unwrap();
+    for path in ["b", "b/c", "b/c/d"] {
+        let meta = tmpdir.metadata(path, LookupFlags::empty()).unwrap();
+        assert!(meta.is_dir());
+        assert_eq!(meta.mode() & 0o770, 0o750);
+        assert_eq!(meta.uid(), uid);
+        assert_eq!(meta.gid(), gid);
+    }
+    // Already exists
+    tmpdir
+        .dir_builder()
+        .recursive(true)
+        .create("b/c/d")
+        .unwrap();
+
+    // Symlinks are resolved inside the directory
+    tmpdir.symlink("link", "/b", LookupFlags::empty()).unwrap();
+    tmpdir
+        .dir_builder()
+        .recursive(true)
+        .lookup_flags(LookupFlags::IN_ROOT)
+        .create("link/e/f")
+        .unwrap();
+    assert!(tmpdir_path.join("b/e/f").is_dir());
+
+    for (path, eno) in [
+        ("link/g", libc::EXDEV),
+        ("../g", libc::EXDEV),
+        ("/g", libc::EXDEV),
+    ] {
+        assert_eq!(
+            tmpdir
+                .dir_builder()
+                .recursive(true)
+                .create(path)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+    }
+}
+
+#[test]
+fn test_lock_file() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = Dir::open(tmpdir.as_ref()).unwrap();
+
+    tmpdir.write("file", b"", LookupFlags::empty()).unwrap();
+
+    let is_wouldblock = |e: std::io::Error| e.raw_os_error() == Some(libc::EWOULDBLOCK);
+
+    let lock = tmpdir
+        .lock_file("file", LockType::Exclusive, LookupFlags::empty())
+        .unwrap();
+    assert!(lock.file().metadata().unwrap().is_file());
+    assert!(is_wouldblock(
+        tmpdir
+            .try_lock_file("file", LockType::Shared, LookupFlags::empty())
+            .unwrap_err()
+    ));
+    lock.unlock().unwrap();
+
+    let lock1 = tmpdir
+        .try_lock_file("file", LockType::Shared, LookupFlags::empty())
+        .unwrap();
+    let lock2 = tmpdir
+        .try_lock_file("file", LockType::Shared, LookupFlags::empty())
+        .unwrap();
+    assert!(is_wouldblock(
+        tmpdir
+            .try_lock_file("file", LockType::Exclusive, LookupFlags::empty())
+            .unwrap_err()
+    ));
+    drop(lock1);
+    drop(lock2);
+
+    tmpdir
+        .try_lock_file("file", LockType::Exclusive, LookupFlags::empty())
+        .unwrap();
+
+    // Only regular files can be locked, and FIFOs don't block
+    tmpdir.mkfifo("fifo", 0o600, LookupFlags::empty()).unwrap();
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+    for &(path, eno) in [
+        ("noexist", libc::ENOENT),
+        ("fifo", libc::EINVAL),
+        ("dir", libc::EISDIR),
+    ]
+    .iter()
+    {
+        assert_eq!(
+            tmpdir
+                .lock_file(path, LockType::Shared, LookupFlags::empty())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+        assert_eq!(
+            tmpdir
+                .try_lock_file(path, LockType::Shared, LookupFlags::empty())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+    }
+}
+
+#[test]
+fn test_read_write() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    fs::create_dir(tmpdir_path.join("dir")).unwrap();
+    fs::write(tmpdir_path.join("target"), b"target").unwrap();
+    tmpdir
+        .symlink("dir/link", "../target", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("dir/escape", "../..", LookupFlags::empty())
+        .unwrap();
+
+    tmpdir
+        .write("dir/file", b"abc", LookupFlags::empty())
+        .unwrap();
+    assert_eq!(fs::read(tmpdir_path.join("dir/file")).unwrap(), b"abc");
+    assert_eq!(
+        tmpdir.read("dir/file", LookupFlags::empty()).unwrap(),
+        b"abc"
+    );
+
+    // Existing files are truncated
+    tmpdir.write("dir/file", "d", LookupFlags::empty()).unwrap();
+    assert_eq!(
+        tmpdir
+            .read_to_string("dir/file", LookupFlags::empty())
+            .unwrap(),
+        "d"
+    );
+
+    // Symlinks are followed (unless NO_SYMLINKS is passed)
+    assert_eq!(
+        tmpdir
+            .read_to_string("dir/link", LookupFlags::empty())
+            .unwrap(),
+        "target"
+    );
+    tmpdir
+        .write("dir/link", b"new", LookupFlags::empty())
+        .unwrap();
+    assert_eq!(fs::read(tmpdir_path.join("target")).unwrap(), b"new");
+    assert_eq!(
+        tmpdir
+            .read("dir/link", LookupFlags::NO_SYMLINKS)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ELOOP)
+    );
+
+    fs::write(tmpdir_path.join("dir/invalid"), b"\xff").unwrap();
+    assert_eq!(
+        tmpdir
+            .read_to_string("dir/invalid", LookupFlags::empty())
+            .unwrap_err()
+            .kind(),
+        std::io::ErrorKind::InvalidData
+    );
+
+    macro_rules! check_err {
+        ($path:expr, $eno:expr) => {
+            assert_eq!(
+                tmpdir
+                    .read($path, LookupFlags::empty())
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some($eno)
+            );
+            assert_eq!(
+                tmpdir
+                    .write($path, b"", LookupFlags::empty())
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some($eno)
+            );
+        };
+    }
+
+    check_err!("../file", libc::EXDEV);
+    check_err!("dir/escape/file", libc::EXDEV);
+    check_err!("NOEXIST/file", libc::ENOENT);
+    check_err!("dir", libc::EISDIR);
+}
+
+#[test]
+fn test_open_with_metadata() {
+    use std::io::Read;
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    fs::write(tmpdir_path.join("file"), b"abc").unwrap();
+    std::os::unix::fs::symlink("file", tmpdir_path.join("link")).unwrap();
+
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    let (mut file, meta) = tmpdir
+        .open_file()
+        .read(true)
+        .open_with_metadata("link")
+        .unwrap();
+    let file_meta = tmpdir.metadata("file", LookupFlags::empty()).unwrap();
+    assert!(same_meta(&meta, &file_meta));
+    assert!(meta.is_file());
+    assert_eq!(meta.len(), 3);
+
+    // The metadata describes the file that was opened, even if it's replaced afterward
+    fs::write(tmpdir_path.join("file2"), b"defgh").unwrap();
+    fs::rename(tmpdir_path.join("file2"), tmpdir_path.join("file")).unwrap();
+    let mut buf = String::new();
+    file.read_to_string(&mut buf).unwrap();
+    assert_eq!(buf, "abc");
+    assert_eq!(meta.len(), buf.len() as u64);
+
+    let mut opts = obnth::OpenOptions::new();
+    opts.read(true);
+    let (_, meta) = tmpdir.open_with_metadata("file", &opts).unwrap();
+    assert_eq!(meta.len(), 5);
+
+    // Unbound options
+    assert_eq!(
+        opts.open_with_metadata("file").unwrap_err().raw_os_error(),
+        Some(libc::EBADF)
+    );
+    assert_eq!(
+        tmpdir
+            .open_with_metadata("../file", &opts)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
+}
+
+#[test]
+fn test_open_for_serving() {
+    use std::io::Read;
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    fs::create_dir(tmpdir_path.join("dir")).unwrap();
+    fs::write(tmpdir_path.join("small"), b"<html>").unwrap();
+    fs::write(tmpdir_path.join("empty"), b"").unwrap();
+    let data: Vec<u8> = (0..2000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(tmpdir_path.join("large"), &data).unwrap();
+
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+    tmpdir.mkfifo("fifo", 0o600, LookupFlags::empty()).unwrap();
+
+    let served = tmpdir
+        .open_for_serving("small", LookupFlags::empty())
+        .unwrap();
+    assert_eq!(served.head(), b"<html>");
+    assert_eq!(served.metadata().len(), 6);
+
+    let served = tmpdir
+        .open_for_serving("empty", LookupFlags::empty())
+        .unwrap();
+    assert_eq!(served.head(), b"");
+
+    let served = tmpdir
+        .open_for_serving("large", LookupFlags::empty())
+        .unwrap();
+    assert_eq!(served.head(), &data[..obnth::SNIFF_LEN]);
+    assert_eq!(served.metadata().len(), data.len() as u64);
+
+    // The file offset is still at the start
+    let (mut file, meta, head) = served.into_parts();
+    assert_eq!(head.len(), obnth::SNIFF_LEN);
+    assert!(meta.is_file());
+    // O_NONBLOCK was cleared afterward
+    let fl = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) };
+    assert_eq!(fl & libc::O_NONBLOCK, 0);
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, data);
+
+    for &(path, eno) in [
+        ("dir", libc::EISDIR),
+        ("fifo", libc::EINVAL),
+        ("../small", libc::EXDEV),
+        ("noexist", libc::ENOENT),
+    ]
+    .iter()
+    {
+        assert_eq!(
+            tmpdir
+                .open_for_serving(path, LookupFlags::empty())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+    }
+}
+
+#[test]
+fn test_read_range() {
+    use std::io::{Seek, Write};
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    fs::create_dir(tmpdir_path.join("dir")).unwrap();
+    let data: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(tmpdir_path.join("file"), &data).unwrap();
+
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    let read = |offset, len| {
+        tmpdir
+            .read_range("file", offset, len, LookupFlags::empty())
+            .unwrap()
+    };
+    assert_eq!(read(0, 10), &data[..10]);
+    assert_eq!(read(100, 40_000), &data[100..40_100]);
+    assert_eq!(read(49_990, 100), &data[49_990..]);
+    assert_eq!(read(0, 100_000), data);
+    assert_eq!(read(50_000, 10), b"");
+    assert_eq!(read(100_000, 10), b"");
+    assert_eq!(read(10, 0), b"");
+
+    assert_eq!(
+        tmpdir
+            .read_range("dir", 0, 10, LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EISDIR)
+    );
+    tmpdir.mkfifo("fifo", 0o600, LookupFlags::empty()).unwrap();
+    assert_eq!(
+        tmpdir
+            .read_range("fifo", 0, 10, LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EINVAL)
+    );
+    assert_eq!(
+        tmpdir
+            .read_range("../file", 0, 10, LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
+
+    // The file offset is unchanged, and data appended after opening is read
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .append(true)
+        .open(tmpdir_path.join("file"))
+        .unwrap();
+    assert_eq!(
+        obnth::read_file_range(&file, 49_000, 10).unwrap(),
+        &data[49_000..49_010]
+    );
+    let mut file2 = fs::OpenOptions::new()
+        .append(true)
+        .open(tmpdir_path.join("file"))
+        .unwrap();
+    file2.write_all(&[1; 20_000]).unwrap();
+    let buf = obnth::read_file_range(&file, 45_000, 30_000).unwrap();
+    assert_eq!(buf.len(), 25_000);
+    assert_eq!(&buf[..5000], &data[45_000..]);
+    assert!(buf[5000..].iter().all(|&b| b == 1));
+    assert_eq!(file.stream_position().unwrap(), 0);
+}
+
+#[test]
+fn test_hash_file() {
+    use std::io::Write;
+
+    // A simple FNV-1a hasher, to check that the contents are streamed correctly
+    struct Fnv(u64, usize);
+
+    impl Write for Fnv {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            for &b in buf {
+                self.0 = (self.0 ^ b as u64).wrapping_mul(0x100000001b3);
+            }
+            self.1 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    fs::create_dir(tmpdir_path.join("dir")).unwrap();
+    std::os::unix::fs::symlink("/", tmpdir_path.join("dir/escape")).unwrap();
+
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(tmpdir_path.join("dir/file"), &data).unwrap();
+
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+    tmpdir.mkfifo("fifo", 0o600, LookupFlags::empty()).unwrap();
+
+    let mut expected = Fnv(0xcbf29ce484222325, 0);
+    expected.write_all(&data).unwrap();
+
+    let mut hasher = Fnv(0xcbf29ce484222325, 0);
+    assert_eq!(
+        tmpdir
+            .hash_file("dir/file", &mut hasher, LookupFlags::empty())
+            .unwrap(),
+        data.len() as u64
+    );
+    assert_eq!(hasher.0, expected.0);
+    assert_eq!(hasher.1, data.len());
+
+    let mut buf = Vec::new();
+    tmpdir
+        .hash_file("dir/file", &mut buf, LookupFlags::empty())
+        .unwrap();
+    assert_eq!(buf, data);
+
+    for &(path, eno) in [
+        ("../file", libc::EXDEV),
+        ("dir/escape/file", libc::EXDEV),
+        ("NOEXIST/file", libc::ENOENT),
+        ("dir", libc::EISDIR),
+        ("fifo", libc::EINVAL),
+    ]
+    .iter()
+    {
+        assert_eq!(
+            tmpdir
+                .hash_file(path, &mut Vec::new(), LookupFlags::empty())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+    }
+}
+
+#[test]
+fn test_remove_file() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    fs::File::create(&tmpdir_path.join("file")).unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+
+    fs::File::create(&tmpdir_path.join("dir/subfile")).unwrap();
+
+    tmpdir
+        .symlink("link", "file", LookupFlags::empty())
+        .unwrap();
+
+    tmpdir
+        .symlink("link-noexist", "NOEXIST", LookupFlags::empty())
+        .unwrap();
+
+    macro_rules! check_err {
+        ($path:expr, $lookup_flags:expr, $($enos:pat)|+) => {{
+            let eno = tmpdir
+                .remove_file($path, $lookup_flags)
+                .unwrap_err()
+                .raw_os_error()
+                .unwrap();
+
+            assert!(matches!(eno, $($enos)|+), "{}", eno);
+        }};
+
+        ($path:expr, $($enos:pat)|+) => {
+            check_err!($path, LookupFlags::empty(), $($enos)|+)
+        };
+    }
+
+    check_err!(".", libc::EISDIR | libc::EPERM);
+    check_err!("/", LookupFlags::IN_ROOT, libc::EISDIR | libc::EPERM);
+    check_err!("..", LookupFlags::IN_ROOT, libc::EISDIR | libc::EPERM);
+    check_err!("dir", libc::EISDIR | libc::EPERM);
+    check_err!("dir/subfile/..", libc::ENOTDIR);
+
+    // Trying to unlink() the symlinks will succeed
+    tmpdir.remove_file("link", LookupFlags::empty()).unwrap();
+    tmpdir
+        .remove_file("link-noexist", LookupFlags::empty())
+        .unwrap();
+
+    // But it leaves the original file in place
+    tmpdir
+        .remove_file("dir/subfile", LookupFlags::empty())
+        .unwrap();
+    tmpdir.remove_file("file", LookupFlags::empty()).unwrap();
+}
+
+#[test]
+fn test_symlinks() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    fs::File::create(&tmpdir_path.join("file")).unwrap();
+
+    tmpdir
+        .symlink("link", "target", LookupFlags::empty())
+        .unwrap();
+
+    tmpdir
+        .symlink("link-exist", "dir", LookupFlags::empty())
+        .unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+
+    tmpdir
+        .symlink("dir/sublink", "subtarget", LookupFlags::empty())
+        .unwrap();
+
+    macro_rules! check_err {
+        ($path:expr, $lookup_flags:expr, $eno:expr) => {{
+            assert_eq!(
+                tmpdir
+                    .symlink($path, "target", $lookup_flags)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some($eno)
+            );
+        }};
+
+        ($path:expr, $eno:expr) => {
+            check_err!($path, LookupFlags::empty(), $eno)
+        };
+    }
+
+    check_err!(".", libc::EEXIST);
+    check_err!("/", LookupFlags::IN_ROOT, libc::EEXIST);
+    check_err!("..", LookupFlags::IN_ROOT, libc::EEXIST);
+    check_err!("dir", libc::EEXIST);
+    check_err!("dir/sublink/..", libc::ENOENT);
+    check_err!("link", libc::EEXIST);
+    check_err!("link/", libc::EEXIST);
+    check_err!("link-exist", libc::EEXIST);
+    check_err!("link-exist/", libc::EEXIST);
+
+    assert_eq!(
+        tmpdir.read_link("link", LookupFlags::empty()).unwrap(),
+        Path::new("target"),
+    );
+    assert_eq!(
+        tmpdir.read_link("/link", LookupFlags::IN_ROOT).unwrap(),
+        Path::new("target"),
+    );
+
+    assert_eq!(
+        tmpdir
+            .read_link("dir/../link", LookupFlags::IN_ROOT)
+            .unwrap(),
+        Path::new("target"),
+    );
+
+    assert_eq!(
+        tmpdir
+            .read_link("dir/sublink", LookupFlags::empty())
+            .unwrap(),
+        Path::new("subtarget"),
+    );
+
+    macro_rules! check_err {
+        ($path:expr, $lookup_flags:expr, $eno:expr) => {{
+            assert_eq!(
+                tmpdir
+                    .read_link($path, $lookup_flags)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some($eno)
+            );
+        }};
+
+        ($path:expr, $eno:expr) => {
+            check_err!($path, LookupFlags::empty(), $eno)
+        };
+    }
+
+    check_err!(".", libc::EINVAL);
+    check_err!("/", LookupFlags::IN_ROOT, libc::EINVAL);
+    check_err!("..", LookupFlags::IN_ROOT, libc::EINVAL);
+    check_err!("dir", libc::EINVAL);
+    check_err!("file", libc::EINVAL);
+    check_err!("dir/sublink/..", libc::ENOENT);
+}
+
+#[test]
+fn test_symlink_replace() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir
+        .create_dir("releases", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .create_dir("releases/1", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .create_dir("releases/2", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir.write("file", b"", LookupFlags::empty()).unwrap();
+
+    // Creates it if it doesn't exist
+    tmpdir
+        .symlink_replace("current", "releases/1", LookupFlags::empty())
+        .unwrap();
+    assert_eq!(
+        tmpdir.read_link("current", LookupFlags::empty()).unwrap(),
+        Path::new("releases/1")
+    );
+
+    // Replaces the symlink (rather than following it into releases/1)
+    tmpdir
+        .symlink_replace("current", "releases/2", LookupFlags::empty())
+        .unwrap();
+    assert_eq!(
+        tmpdir.read_link("current", LookupFlags::empty()).unwrap(),
+        Path::new("releases/2")
+    );
+    assert!(tmpdir
+        .list_dir("releases/1", LookupFlags::empty())
+        .unwrap()
+        .next()
+        .is_none());
+
+    // Regular files can be replaced too
+    tmpdir
+        .symlink_replace("file", "releases", LookupFlags::empty())
+        .unwrap();
+    assert_eq!(
+        tmpdir.read_link("file", LookupFlags::empty()).unwrap(),
+        Path::new("releases")
+    );
+
+    // But not directories
+    assert_eq!(
+        tmpdir
+            .symlink_replace("releases/1", "2", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EISDIR)
+    );
+    assert_eq!(
+        tmpdir
+            .symlink_replace(".", "2", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EISDIR)
+    );
+
+    // Lookup flags are honored for the parent directory
+    assert_eq!(
+        tmpdir
+            .symlink_replace("current/x", "1", LookupFlags::NO_SYMLINKS)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ELOOP)
+    );
+    assert_eq!(
+        tmpdir
+            .symlink_replace("../x", "1", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
+
+    // No temporary files were left behind
+    let mut names = tmpdir
+        .list_self()
+        .unwrap()
+        .map(|e| e.unwrap().name().to_os_string())
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    assert_eq!(names, ["current", "file", "releases"]);
+}
+
+#[test]
+fn test_mkfifo_mknod() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("link", "target", LookupFlags::empty())
+        .unwrap();
+
+    tmpdir.mkfifo("fifo", 0o600, LookupFlags::empty()).unwrap();
+    tmpdir
+        .mkfifo("dir/../dir/fifo", 0o600, LookupFlags::empty())
+        .unwrap();
+    assert!(fs::metadata(tmpdir_path.join("fifo"))
+        .unwrap()
+        .file_type()
+        .is_fifo());
+    assert!(fs::metadata(tmpdir_path.join("dir/fifo"))
+        .unwrap()
+        .file_type()
+        .is_fifo());
+
+    // Regular files can be created with mknod() without special privileges
+    tmpdir
+        .mknod("file", libc::S_IFREG | 0o600, 0, LookupFlags::empty())
+        .unwrap();
+    assert!(fs::metadata(tmpdir_path.join("file"))
+        .unwrap()
+        .file_type()
+        .is_file());
+
+    macro_rules! check_err {
+        ($path:expr, $lookup_flags:expr, $eno:expr) => {{
+            assert_eq!(
+                tmpdir
+                    .mkfifo($path, 0o600, $lookup_flags)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some($eno)
+            );
+            assert_eq!(
+                tmpdir
+                    .mknod($path, libc::S_IFIFO | 0o600, 0, $lookup_flags)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some($eno)
+            );
+        }};
+
+        ($path:expr, $eno:expr) => {
+            check_err!($path, LookupFlags::empty(), $eno)
+        };
+    }
+
+    check_err!(".", libc::EEXIST);
+    check_err!("/", LookupFlags::IN_ROOT, libc::EEXIST);
+    check_err!("dir", libc::EEXIST);
+    check_err!("fifo", libc::EEXIST);
+    check_err!("link", libc::EEXIST);
+    check_err!("noexist/fifo", libc::ENOENT);
+    check_err!("../fifo", libc::EXDEV);
+}
+
+#[test]
+fn test_change_cwd_to() {
+    // No-op... unfortunately we can't test much more without messing up other threads
+    Dir::open(".").unwrap().change_cwd_to().unwrap();
+
+    assert_eq!(
+        unsafe { Dir::from_raw_fd(-1) }
+            .change_cwd_to()
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EBADF)
+    );
+
+    assert_eq!(
+        unsafe {
+            Dir::from_raw_fd(
+                fs::File::open(std::env::current_exe().unwrap())
+                    .unwrap()
+                    .into_raw_fd(),
+            )
+        }
+        .change_cwd_to()
+        .unwrap_err()
+        .raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+}
+
+#[test]
+fn test_hardlink() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir
+        .open_file()
+        .write(true)
+        .create_new(true)
+        .open("a")
+        .unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .create_dir("dir2", 0o777, LookupFlags::empty())
+        .unwrap();
+
+    tmpdir.symlink("link", "a", LookupFlags::empty()).unwrap();
+
+    tmpdir
+        .symlink("link-noexist", "NOEXIST", LookupFlags::empty())
+        .unwrap();
+
+    let a_meta = tmpdir.metadata("a", LookupFlags::empty()).unwrap();
+
+    obnth::hardlink(&tmpdir, "a", &tmpdir, "dir/a", LookupFlags::empty()).unwrap();
+
+    assert!(same_meta(
+        &a_meta,
+        &tmpdir.metadata("a", LookupFlags::empty()).unwrap()
+    ));
+    assert!(same_meta(
+        &a_meta,
+        &tmpdir.metadata("dir/a", LookupFlags::empty()).unwrap()
+    ));
+
+    assert_eq!(
+        obnth::hardlink(&tmpdir, "dir", &tmpdir, "dir3", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EPERM)
+    );
+
+    assert_eq!(
+        obnth::hardlink(&tmpdir, "dir/..", &tmpdir, "dir2", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EPERM)
+    );
+
+    assert_eq!(
+        obnth::hardlink(&tmpdir, "dir", &tmpdir, "dir2/.", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EEXIST)
+    );
+
+    assert_eq!(
+        obnth::hardlink(&tmpdir, "a", &tmpdir, "link", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EEXIST)
+    );
+
+    assert_eq!(
+        obnth::hardlink(&tmpdir, "a", &tmpdir, "link/", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EEXIST)
+    );
+
+    assert_eq!(
+        obnth::hardlink(&tmpdir, "a", &tmpdir, "link-noexist", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EEXIST)
+    );
+
+    assert_eq!(
+        obnth::hardlink(&tmpdir, "a", &tmpdir, "link-noexist/", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EEXIST)
+    );
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn test_tmpfile() {
+    use std::io::prelude::*;
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+    fs::File::create(tmpdir_path.join("dir/exists")).unwrap();
+
+    let mut file = match tmpdir
+        .open_file()
+        .write(true)
+        .tmpfile(true)
+        .mode(0o600)
+        .open("dir")
+    {
+        Ok(f) => f,
+        // Not supported by this filesystem
+        Err(e)
+            if matches!(
+                e.raw_os_error(),
+                Some(libc::EOPNOTSUPP) | Some(libc::EISDIR)
+            ) =>
+        {
+            return
+        }
+        Err(e) => panic!("{}", e),
+    };
+
+    file.write_all(b"abc").unwrap();
+
+    // It's anonymous
+    assert_eq!(fs::read_dir(tmpdir_path.join("dir")).unwrap().count(), 1);
+
+    assert_eq!(
+        tmpdir
+            .link_tmpfile(&file, "dir/exists", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EEXIST)
+    );
+    assert_eq!(
+        tmpdir
+            .link_tmpfile(&file, "dir/.", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EEXIST)
+    );
+    assert_eq!(
+        tmpdir
+            .link_tmpfile(&file, "../file", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
+
+    tmpdir
+        .link_tmpfile(&file, "dir/file", LookupFlags::empty())
+        .unwrap();
+    assert_eq!(fs::read(tmpdir_path.join("dir/file")).unwrap(), b"abc");
+    assert_eq!(
+        tmpdir
+            .metadata("dir/file", LookupFlags::empty())
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777,
+        0o600
+    );
+
+    // Can't create tmpfiles "in" a file, or open them read-only
+    assert_eq!(
+        tmpdir
+            .open_file()
+            .write(true)
+            .tmpfile(true)
+            .open("dir/file")
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+    assert_eq!(
+        tmpdir
+            .open_file()
+            .read(true)
+            .tmpfile(true)
+            .open("dir")
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EINVAL)
+    );
+}
+
+#[test]
+fn test_copy() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    fs::write(tmpdir_path.join("a"), b"abcdef").unwrap();
+    fs::set_permissions(tmpdir_path.join("a"), fs::Permissions::from_mode(0o640)).unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir.symlink("link", "a", LookupFlags::empty()).unwrap();
+    tmpdir
+        .symlink("link-escape", "../a", LookupFlags::empty())
+        .unwrap();
+
+    assert_eq!(
+        obnth::copy(&tmpdir, "a", &tmpdir, "dir/b", LookupFlags::empty()).unwrap(),
+        6
+    );
+    assert_eq!(fs::read(tmpdir_path.join("dir/b")).unwrap(), b"abcdef");
+    assert_eq!(
+        tmpdir
+            .metadata("dir/b", LookupFlags::empty())
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777,
+        0o640
+    );
+
+    // Overwriting an existing file truncates it
+    fs::write(tmpdir_path.join("c"), b"0123456789").unwrap();
+    assert_eq!(
+        obnth::copy(&tmpdir, "link", &tmpdir, "c", LookupFlags::empty()).unwrap(),
+        6
+    );
+    assert_eq!(fs::read(tmpdir_path.join("c")).unwrap(), b"abcdef");
+
+    // Empty files
+    fs::write(tmpdir_path.join("empty"), b"").unwrap();
+    assert_eq!(
+        obnth::copy(&tmpdir, "empty", &tmpdir, "empty2", LookupFlags::empty()).unwrap(),
+        0
+    );
+    assert_eq!(fs::read(tmpdir_path.join("empty2")).unwrap(), b"");
+
+    assert_eq!(
+        obnth::copy(&tmpdir, "dir", &tmpdir, "dir2", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EINVAL)
+    );
+    assert_eq!(
+        obnth::copy(&tmpdir, "NOEXIST", &tmpdir, "d", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOENT)
+    );
+    assert_eq!(
+        obnth::copy(&tmpdir, "link-escape", &tmpdir, "d", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
+    assert_eq!(
+        obnth::copy(&tmpdir, "link", &tmpdir, "d", LookupFlags::NO_SYMLINKS)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ELOOP)
+    );
+}
+
+#[test]
+fn test_rename() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir
+        .open_file()
+        .write(true)
+        .create_new(true)
+        .open("a")
+        .unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .create_dir("dir2", 0o777, LookupFlags::empty())
+        .unwrap();
+
+    tmpdir.symlink("link", "a", LookupFlags::empty()).unwrap();
+
+    tmpdir
+        .symlink("link-noexist", "NOEXIST", LookupFlags::empty())
+        .unwrap();
+
+    let a_meta = tmpdir.metadata("a", LookupFlags::empty()).unwrap();
+    let dir_meta = tmpdir.metadata("dir", LookupFlags::empty()).unwrap();
+
+    tmpdir
+        .local_rename("a", "dir/a", LookupFlags::empty())
+        .unwrap();
+
+    assert!(same_meta(
+        &a_meta,
+        &tmpdir.metadata("dir/a", LookupFlags::empty()).unwrap()
+    ));
+
+    assert_eq!(
+        tmpdir
+            .local_rename("NOEXIST", "dir2", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOENT)
+    );
+
+    assert_eq!(
+        tmpdir
+            .local_rename("dir/..", "dir2", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EBUSY)
+    );
+
+    assert_eq!(
+        tmpdir
+            .local_rename("dir", "dir2/.", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EBUSY)
+    );
+
+    tmpdir
+        .local_rename("dir", "dir2", LookupFlags::empty())
+        .unwrap();
+
+    assert!(same_meta(
+        &dir_meta,
+        &tmpdir.metadata("dir2", LookupFlags::empty()).unwrap()
+    ));
+
+    assert_eq!(
+        obnth::rename(&tmpdir, "dir2", &tmpdir, "link", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+
+    assert_eq!(
+        obnth::rename(&tmpdir, "dir2", &tmpdir, "link/", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+
+    assert_eq!(
+        obnth::rename(
+            &tmpdir,
+            "dir2",
+            &tmpdir,
+            "link-noexist",
+            LookupFlags::empty()
+        )
+        .unwrap_err()
+        .raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+
+    assert_eq!(
+        obnth::rename(
+            &tmpdir,
+            "dir2",
+            &tmpdir,
+            "link-noexist/",
+            LookupFlags::empty()
+        )
+        .unwrap_err()
+        .raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_rename2() {
+    use obnth::{rename2, Rename2Flags};
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir
+        .open_file()
+        .write(true)
+        .create_new(true)
+        .open("a")
+        .unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .create_dir("dir2", 0o777, LookupFlags::empty())
+        .unwrap();
+
+    tmpdir.symlink("link", "a", LookupFlags::empty()).unwrap();
+
+    tmpdir
+        .symlink("link-noexist", "NOEXIST", LookupFlags::empty())
+        .unwrap();
+
+    let a_meta = tmpdir.metadata("a", LookupFlags::empty()).unwrap();
+    let dir_meta = tmpdir.metadata("dir", LookupFlags::empty()).unwrap();
+
+    rename2(
+        &tmpdir,
+        "a",
+        &tmpdir,
+        "dir/a",
+        Rename2Flags::empty(),
+        LookupFlags::empty(),
+    )
+    .unwrap();
+
+    assert!(same_meta(
+        &a_meta,
+        &tmpdir.metadata("dir/a", LookupFlags::empty()).unwrap()
+    ));
+
+    assert_eq!(
+        rename2(
+            &tmpdir,
+            "NOEXIST",
+            &tmpdir,
+            "dir2",
+            Rename2Flags::empty(),
+            LookupFlags::empty()
+        )
+        .unwrap_err()
+        .raw_os_error(),
+        Some(libc::ENOENT)
+    );
+
+    assert_eq!(
+        rename2(
+            &tmpdir,
+            "dir/..",
+            &tmpdir,
+            "dir2",
+            Rename2Flags::empty(),
+            LookupFlags::empty()
+        )
+        .unwrap_err()
+        .raw_os_error(),
+        Some(libc::EBUSY)
+    );
+
+    assert_eq!(
+        rename2(
+            &tmpdir,
+            "dir",
+            &tmpdir,
+            "dir2/.",
+            Rename2Flags::empty(),
+            LookupFlags::empty()
+        )
+        .unwrap_err()
+        .raw_os_error(),
+        Some(libc::EBUSY)
+    );
+
+    rename2(
+        &tmpdir,
+        "dir",
+        &tmpdir,
+        "dir2",
+        Rename2Flags::empty(),
+        LookupFlags::empty(),
+    )
+    .unwrap();
+
+    assert!(same_meta(
+        &dir_meta,
+        &tmpdir.metadata("dir2", LookupFlags::empty()).unwrap()
+    ));
+
+    assert_eq!(
+        rename2(
+            &tmpdir,
+            "dir2",
+            &tmpdir,
+            "link",
+            Rename2Flags::empty(),
+            LookupFlags::empty()
+        )
+        .unwrap_err()
+        .raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+
+    assert_eq!(
+        rename2(
+            &tmpdir,
+            "dir2",
+            &tmpdir,
+            "link/",
+            Rename2Flags::empty(),
+            LookupFlags::empty()
+        )
+        .unwrap_err()
+        .raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+
+    assert_eq!(
+        rename2(
+            &tmpdir,
+            "dir2",
+            &tmpdir,
+            "link-noexist",
+            Rename2Flags::empty(),
+            LookupFlags::empty(),
+        )
+        .unwrap_err()
+        .raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+
+    assert_eq!(
+        rename2(
+            &tmpdir,
+            "dir2",
+            &tmpdir,
+            "link-noexist/",
+            Rename2Flags::empty(),
+            LookupFlags::empty(),
+        )
+        .unwrap_err()
+        .raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+}
+
+#[test]
+fn test_canonicalize() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    fs::create_dir_all(tmpdir_path.join("a/b/c")).unwrap();
+    fs::write(tmpdir_path.join("a/b/file"), b"").unwrap();
+    tmpdir
+        .symlink("link-b", "a/b", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("a/b/c/link-up", "../..", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("a/link-file", "b/file", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("link-abs", "/a/b", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("link-escape", "..", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("link-loop", "link-loop", LookupFlags::empty())
+        .unwrap();
+
+    macro_rules! check_ok {
+        ($path:expr, $lookup_flags:expr, $expected:expr) => {{
+            assert_eq!(
+                tmpdir.canonicalize($path, $lookup_flags).unwrap(),
+                Path::new($expected),
+                "{}",
+                $path
+            );
+        }};
+
+        ($path:expr, $expected:expr) => {
+            check_ok!($path, LookupFlags::empty(), $expected)
+        };
+    }
+
+    check_ok!(".", ".");
+    check_ok!("a", "a");
+    check_ok!("a/", "a");
+    check_ok!("./a/./b/", "a/b");
+    check_ok!("a/b/..", "a");
+    check_ok!("a/b/../..", ".");
+    check_ok!("link-b", "a/b");
+    check_ok!("link-b/c", "a/b/c");
+    check_ok!("link-b/..", "a");
+    check_ok!("link-b/c/link-up", "a");
+    check_ok!("link-b/c/link-up/link-file", "a/b/file");
+    check_ok!("a/link-file", "a/b/file");
+    check_ok!("..", LookupFlags::IN_ROOT, ".");
+    check_ok!("/a/../..", LookupFlags::IN_ROOT, ".");
+    check_ok!("link-abs", LookupFlags::IN_ROOT, "a/b");
+    check_ok!("link-escape/a", LookupFlags::IN_ROOT, "a");
+    check_ok!("a/b", LookupFlags::NO_SYMLINKS, "a/b");
+
+    macro_rules! check_err {
+        ($path:expr, $lookup_flags:expr, $eno:expr) => {{
+            assert_eq!(
+                tmpdir
+                    .canonicalize($path, $lookup_flags)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some($eno),
+                "{}",
+                $path
+            );
+        }};
+
+        ($path:expr, $eno:expr) => {
+            check_err!($path, LookupFlags::empty(), $eno)
+        };
+    }
+
+    check_err!("", libc::ENOENT);
+    check_err!("noexist", libc::ENOENT);
+    check_err!("a/noexist/..", libc::ENOENT);
+    check_err!("a/b/file/", libc::ENOTDIR);
+    check_err!("a/b/file/.", libc::ENOTDIR);
+    check_err!("a/b/file/..", libc::ENOTDIR);
+    check_err!("..", libc::EXDEV);
+    check_err!("a/../..", libc::EXDEV);
+    check_err!("/a", libc::EXDEV);
+    check_err!("link-abs", libc::EXDEV);
+    check_err!("link-escape", libc::EXDEV);
+    check_err!("link-loop", libc::ELOOP);
+    check_err!("link-b", LookupFlags::NO_SYMLINKS, libc::ELOOP);
+    check_err!("a/..", LookupFlags::NO_DOTDOT, libc::EXDEV);
+    check_err!(
+        "link-b/c/link-up/link-file",
+        LookupFlags::NO_DOTDOT,
+        libc::EXDEV
+    );
+
+    // The resolved paths match what open_beneath() resolves
+    for &path in ["link-b/c/link-up/link-file", "a/link-file", "link-b/c"].iter() {
+        let canon = tmpdir.canonicalize(path, LookupFlags::empty()).unwrap();
+        assert!(same_meta(
+            &tmpdir.metadata(&canon, LookupFlags::NO_SYMLINKS).unwrap(),
+            &tmpdir.metadata_follow(path, LookupFlags::empty()).unwrap(),
+        ));
+    }
+}
+
+#[test]
+fn test_open_handle() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir.write("file", b"abc", LookupFlags::empty()).unwrap();
+    tmpdir
+        .symlink("link", "file", LookupFlags::empty())
+        .unwrap();
+
+    let handle = tmpdir.open_handle("link", LookupFlags::empty()).unwrap();
+    assert!(same_meta(
+        &handle.metadata().unwrap(),
+        &tmpdir.metadata("file", LookupFlags::empty()).unwrap()
+    ));
+
+    let mut file = handle.reopen(libc::O_RDONLY).unwrap();
+    let mut buf = String::new();
+    std::io::Read::read_to_string(&mut file, &mut buf).unwrap();
+    assert_eq!(buf, "abc");
+
+    let mut file = handle.reopen(libc::O_WRONLY | libc::O_APPEND).unwrap();
+    std::io::Write::write_all(&mut file, b"def").unwrap();
+    assert_eq!(fs::read(tmpdir_path.join("file")).unwrap(), b"abcdef");
+
+    assert_eq!(
+        handle
+            .reopen(libc::O_RDWR | libc::O_CREAT)
             .unwrap_err()
             .raw_os_error(),
-        Some(libc::ENOTDIR)
+        Some(libc::EINVAL)
+    );
+
+    for (path, eno) in [("noexist", libc::ENOENT), ("../file", libc::EXDEV)] {
+        assert_eq!(
+            tmpdir
+                .open_handle(path, LookupFlags::empty())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+    }
+}
+
+#[test]
+fn test_tempfile() {
+    use std::io::Write;
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    let mut temp = tmpdir.tempfile("pre-", ".txt", 0o600).unwrap();
+    let name = temp.name().to_os_string();
+    assert!(name.to_str().unwrap().starts_with("pre-"));
+    assert!(name.to_str().unwrap().ends_with(".txt"));
+    assert_eq!(
+        fs::symlink_metadata(tmpdir_path.join(&name))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777,
+        0o600
+    );
+    temp.file_mut().write_all(b"abc").unwrap();
+    drop(temp);
+    assert!(!tmpdir_path.join(&name).exists());
+
+    let temp = tmpdir.tempfile("", "", 0o600).unwrap();
+    let name = temp.name().to_os_string();
+    let (_, kept) = temp.keep();
+    assert_eq!(kept, name);
+    assert!(tmpdir_path.join(&name).exists());
+
+    tmpdir
+        .create_dir("sub", 0o777, LookupFlags::empty())
+        .unwrap();
+    let mut temp = tmpdir.tempfile(".tmp", "", 0o644).unwrap();
+    let name = temp.name().to_os_string();
+    temp.file_mut().write_all(b"def").unwrap();
+    temp.persist("sub/dest", LookupFlags::empty()).unwrap();
+    assert!(!tmpdir_path.join(&name).exists());
+    assert_eq!(fs::read(tmpdir_path.join("sub/dest")).unwrap(), b"def");
+
+    let temp = tmpdir.tempfile("", "", 0o600).unwrap();
+    let name = temp.name().to_os_string();
+    assert_eq!(
+        temp.persist("../dest", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
+    assert!(!tmpdir_path.join(&name).exists());
+
+    assert_eq!(
+        tmpdir
+            .tempfile("a/b", "", 0o600)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EINVAL)
+    );
+}
+
+#[test]
+fn test_tempdir_in() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    let temp = tmpdir.tempdir_in("work-").unwrap();
+    let name = temp.name().to_os_string();
+    assert!(name.to_str().unwrap().starts_with("work-"));
+    assert_eq!(
+        fs::metadata(tmpdir_path.join(&name))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777,
+        0o700
+    );
+
+    temp.dir()
+        .create_dir("a", 0o777, LookupFlags::empty())
+        .unwrap();
+    temp.dir()
+        .write("a/file", b"abc", LookupFlags::empty())
+        .unwrap();
+    drop(temp);
+    assert!(!tmpdir_path.join(&name).exists());
+
+    let temp = tmpdir.tempdir_in("").unwrap();
+    let name = temp.keep();
+    assert!(tmpdir_path.join(&name).is_dir());
+
+    assert_eq!(
+        tmpdir.tempdir_in("a/").unwrap_err().raw_os_error(),
+        Some(libc::EINVAL)
+    );
+}
+
+#[test]
+fn test_access() {
+    use obnth::AccessMode;
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o755, LookupFlags::empty())
+        .unwrap();
+    tmpdir.write("dir/file", b"", LookupFlags::empty()).unwrap();
+    tmpdir
+        .symlink("link", "dir/file", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("escape", "../x", LookupFlags::empty())
+        .unwrap();
+    fs::set_permissions(
+        tmpdir_path.join("dir/file"),
+        fs::Permissions::from_mode(0o644),
+    )
+    .unwrap();
+
+    for path in [".", "dir", "dir/", "dir/file", "link", "dir/../link"] {
+        tmpdir
+            .access(path, AccessMode::empty(), LookupFlags::empty())
+            .unwrap();
+        tmpdir
+            .access(path, AccessMode::READ, LookupFlags::empty())
+            .unwrap();
+    }
+
+    tmpdir
+        .access("dir", AccessMode::EXEC, LookupFlags::empty())
+        .unwrap();
+
+    // root can execute files if any execute bit is set, but not if none are
+    for path in ["dir/file", "link"] {
+        assert_eq!(
+            tmpdir
+                .access(path, AccessMode::EXEC, LookupFlags::empty())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EACCES)
+        );
+    }
+
+    for (path, eno) in [
+        ("noexist", libc::ENOENT),
+        ("dir/file/", libc::ENOTDIR),
+        ("escape", libc::EXDEV),
+        ("..", libc::EXDEV),
+    ] {
+        assert_eq!(
+            tmpdir
+                .access(path, AccessMode::empty(), LookupFlags::empty())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno),
+            "{}",
+            path
+        );
+    }
+}
+
+#[test]
+fn test_truncate_allocate() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir
+        .write("file", b"abcdef", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("link", "file", LookupFlags::empty())
+        .unwrap();
+
+    tmpdir.truncate("link", 3, LookupFlags::empty()).unwrap();
+    assert_eq!(fs::read(tmpdir_path.join("file")).unwrap(), b"abc");
+    tmpdir.truncate("file", 5, LookupFlags::empty()).unwrap();
+    assert_eq!(fs::read(tmpdir_path.join("file")).unwrap(), b"abc\0\0");
+
+    match tmpdir.allocate("file", 0, 4096, LookupFlags::empty()) {
+        Ok(()) => {
+            let meta = fs::metadata(tmpdir_path.join("file")).unwrap();
+            assert_eq!(meta.len(), 4096);
+            assert!(meta.blocks() * 512 >= 4096);
+        }
+        Err(e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => (),
+        Err(e) => panic!("{}", e),
+    }
+
+    tmpdir.mkfifo("fifo", 0o600, LookupFlags::empty()).unwrap();
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+
+    for (path, eno) in [
+        ("noexist", libc::ENOENT),
+        ("../file", libc::EXDEV),
+        ("fifo", libc::ENXIO),
+        ("dir", libc::EISDIR),
+    ] {
+        assert_eq!(
+            tmpdir
+                .truncate(path, 0, LookupFlags::empty())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+        assert_eq!(
+            tmpdir
+                .allocate(path, 0, 1, LookupFlags::empty())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+    }
+
+    assert_eq!(
+        tmpdir
+            .allocate("file", u64::MAX, 1, LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EFBIG)
+    );
+}
+
+#[test]
+fn test_filesystem_info() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    let info = tmpdir.filesystem_info().unwrap();
+    assert!(info.block_size() > 0);
+    assert!(info.blocks() >= info.blocks_free());
+    assert!(info.blocks_free() >= info.blocks_available());
+    assert_eq!(
+        info.bytes_available(),
+        info.blocks_available() * info.block_size()
+    );
+    assert!(info.files() >= info.files_free());
+    assert!(info.name_max() >= 14);
+    assert!(!info.is_readonly());
+
+    #[cfg(target_os = "linux")]
+    {
+        let info = Dir::open("/proc").unwrap().filesystem_info().unwrap();
+        assert_eq!(info.fs_type(), 0x9fa0);
+        assert!(info.is_noexec().is_some());
+    }
+}
+
+#[test]
+fn test_rename_noreplace_exchange() {
+    use obnth::{rename_exchange, rename_noreplace};
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir
+        .create_dir("sub", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir.write("a", b"a", LookupFlags::empty()).unwrap();
+    tmpdir.write("b", b"b", LookupFlags::empty()).unwrap();
+
+    assert_eq!(
+        rename_noreplace(&tmpdir, "a", &tmpdir, "b", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EEXIST)
     );
+    assert_eq!(fs::read(tmpdir_path.join("a")).unwrap(), b"a");
+    assert_eq!(fs::read(tmpdir_path.join("b")).unwrap(), b"b");
+
+    rename_noreplace(&tmpdir, "a", &tmpdir, "sub/c", LookupFlags::empty()).unwrap();
+    assert!(!tmpdir_path.join("a").exists());
+    assert_eq!(fs::read(tmpdir_path.join("sub/c")).unwrap(), b"a");
+
+    for (old, new, eno) in [
+        ("noexist", "x", libc::ENOENT),
+        ("b", "../x", libc::EXDEV),
+        ("..", "x", libc::EXDEV),
+        (".", "x", libc::EBUSY),
+    ] {
+        assert_eq!(
+            rename_noreplace(&tmpdir, old, &tmpdir, new, LookupFlags::empty())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+    }
+
+    match rename_exchange(&tmpdir, "b", &tmpdir, "sub/c", LookupFlags::empty()) {
+        Ok(()) => {
+            assert_eq!(fs::read(tmpdir_path.join("b")).unwrap(), b"a");
+            assert_eq!(fs::read(tmpdir_path.join("sub/c")).unwrap(), b"b");
+
+            assert_eq!(
+                rename_exchange(&tmpdir, "b", &tmpdir, "noexist", LookupFlags::empty())
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some(libc::ENOENT)
+            );
+        }
+        Err(e) => assert_eq!(e.raw_os_error(), Some(libc::EOPNOTSUPP)),
+    }
+}
+
+#[test]
+fn test_copy_options_reflink() {
+    use obnth::{CopyOptions, Reflink};
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir.write("a", b"abcdef", LookupFlags::empty()).unwrap();
+    tmpdir
+        .write("b", b"old contents", LookupFlags::empty())
+        .unwrap();
+    tmpdir.symlink("link", "a", LookupFlags::empty()).unwrap();
+
+    for reflink in [Reflink::Auto, Reflink::Never] {
+        assert_eq!(
+            CopyOptions::new()
+                .reflink(reflink)
+                .copy(&tmpdir, "link", &tmpdir, "b")
+                .unwrap(),
+            6
+        );
+        assert_eq!(fs::read(tmpdir_path.join("b")).unwrap(), b"abcdef");
+        tmpdir.remove_file("b", LookupFlags::empty()).unwrap();
+    }
+
+    // Reflinks may or may not be supported by the filesystem
+    tmpdir.write("c", b"old", LookupFlags::empty()).unwrap();
+    match CopyOptions::new()
+        .reflink(Reflink::Always)
+        .copy(&tmpdir, "a", &tmpdir, "c")
+    {
+        Ok(n) => {
+            assert_eq!(n, 6);
+            assert_eq!(fs::read(tmpdir_path.join("c")).unwrap(), b"abcdef");
+        }
+        Err(e) => {
+            assert!(
+                matches!(
+                    e.raw_os_error(),
+                    Some(libc::EOPNOTSUPP)
+                        | Some(libc::EXDEV)
+                        | Some(libc::EINVAL)
+                        | Some(libc::ENOTTY)
+                ),
+                "{}",
+                e
+            );
+            // The "new" file is left untouched
+            assert_eq!(fs::read(tmpdir_path.join("c")).unwrap(), b"old");
+        }
+    }
 
     assert_eq!(
-        obnth::rename(&tmpdir, "dir2", &tmpdir, "link/", LookupFlags::empty())
+        CopyOptions::new()
+            .lookup_flags(LookupFlags::NO_SYMLINKS)
+            .copy(&tmpdir, "link", &tmpdir, "d")
             .unwrap_err()
             .raw_os_error(),
-        Some(libc::ENOTDIR)
+        Some(libc::ELOOP)
     );
+}
+
+#[test]
+fn test_copy_options_sparse() {
+    use obnth::CopyOptions;
+    use std::io::{Seek, SeekFrom, Write};
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    const SIZE: u64 = 16 * 1024 * 1024;
+
+    let mut file = fs::File::create(tmpdir_path.join("sparse")).unwrap();
+    file.write_all(b"start").unwrap();
+    file.seek(SeekFrom::Start(SIZE / 2)).unwrap();
+    file.write_all(b"middle").unwrap();
+    file.set_len(SIZE).unwrap();
+    drop(file);
 
     assert_eq!(
-        obnth::rename(
-            &tmpdir,
-            "dir2",
-            &tmpdir,
-            "link-noexist",
-            LookupFlags::empty()
-        )
-        .unwrap_err()
-        .raw_os_error(),
-        Some(libc::ENOTDIR)
+        CopyOptions::new()
+            .sparse(true)
+            .copy(&tmpdir, "sparse", &tmpdir, "copy")
+            .unwrap(),
+        SIZE
+    );
+    assert_eq!(
+        fs::read(tmpdir_path.join("copy")).unwrap(),
+        fs::read(tmpdir_path.join("sparse")).unwrap()
     );
 
+    let meta = fs::metadata(tmpdir_path.join("copy")).unwrap();
+    assert_eq!(meta.len(), SIZE);
+    // If the filesystem supports holes, the copy shouldn't have been fully allocated
+    if fs::metadata(tmpdir_path.join("sparse")).unwrap().blocks() * 512 < SIZE {
+        assert!(meta.blocks() * 512 < SIZE);
+    }
+
+    // Non-sparse files are copied normally
+    tmpdir.write("a", b"abc", LookupFlags::empty()).unwrap();
     assert_eq!(
-        obnth::rename(
-            &tmpdir,
-            "dir2",
-            &tmpdir,
-            "link-noexist/",
-            LookupFlags::empty()
-        )
-        .unwrap_err()
-        .raw_os_error(),
-        Some(libc::ENOTDIR)
+        CopyOptions::new()
+            .sparse(true)
+            .copy(&tmpdir, "a", &tmpdir, "b")
+            .unwrap(),
+        3
     );
+    assert_eq!(fs::read(tmpdir_path.join("b")).unwrap(), b"abc");
 }
 
-#[cfg(target_os = "linux")]
 #[test]
-fn test_rename2() {
-    use obnth::{rename2, Rename2Flags};
+fn test_move_file() {
+    use obnth::{move_file, CopyOptions};
+    use std::time::{Duration, SystemTime};
 
     let tmpdir = tempfile::tempdir().unwrap();
     let tmpdir_path = tmpdir.as_ref();
     let tmpdir = Dir::open(tmpdir_path).unwrap();
 
+    let opts = CopyOptions::new();
+
+    // On the same filesystem, it's just a rename
+    tmpdir.write("a", b"abc", LookupFlags::empty()).unwrap();
+    let old_meta = tmpdir.metadata("a", LookupFlags::empty()).unwrap();
+    move_file(&tmpdir, "a", &tmpdir, "b", &opts).unwrap();
+    assert!(same_meta(
+        &old_meta,
+        &tmpdir.metadata("b", LookupFlags::empty()).unwrap()
+    ));
+    assert!(!tmpdir.exists("a", LookupFlags::empty()).unwrap());
+
+    assert_eq!(
+        move_file(&tmpdir, "b", &tmpdir, "../b", &opts)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
+    assert!(tmpdir.exists("b", LookupFlags::empty()).unwrap());
+
+    // Find a directory on another filesystem
+    let other = match tempfile::tempdir_in("/dev/shm") {
+        Ok(other) => other,
+        Err(_) => return,
+    };
+    if fs::metadata(other.as_ref()).unwrap().dev() == old_meta.dev() {
+        return;
+    }
+    let other_path = other.as_ref();
+    let other = Dir::open(other_path).unwrap();
+
+    let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
     tmpdir
-        .open_file()
-        .write(true)
-        .create_new(true)
-        .open("a")
+        .set_times("b", None, Some(mtime), LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .set_permissions("b", fs::Permissions::from_mode(0o640), LookupFlags::empty())
+        .unwrap();
+    fs::write(other_path.join("existing"), b"old").unwrap();
+
+    move_file(&tmpdir, "b", &other, "existing", &opts).unwrap();
+    assert!(!tmpdir.exists("b", LookupFlags::empty()).unwrap());
+    assert_eq!(fs::read(other_path.join("existing")).unwrap(), b"abc");
+    let meta = fs::metadata(other_path.join("existing")).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o7777, 0o640);
+    assert_eq!(meta.modified().unwrap(), mtime);
+    // No temporary files were left behind
+    assert_eq!(fs::read_dir(other_path).unwrap().count(), 1);
+
+    // Symlinks and directories can't be moved across filesystems
+    tmpdir
+        .symlink("link", "existing", LookupFlags::empty())
         .unwrap();
-
     tmpdir
         .create_dir("dir", 0o777, LookupFlags::empty())
         .unwrap();
+    for &name in ["link", "dir"].iter() {
+        assert_eq!(
+            move_file(&tmpdir, name, &other, name, &opts)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EXDEV)
+        );
+        assert!(tmpdir.metadata(name, LookupFlags::empty()).is_ok());
+    }
+    assert_eq!(fs::read_dir(other_path).unwrap().count(), 1);
+}
+
+#[test]
+fn test_metadata_many() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir.create_dir("a", 0o777, LookupFlags::empty()).unwrap();
     tmpdir
-        .create_dir("dir2", 0o777, LookupFlags::empty())
+        .create_dir("a/b", 0o777, LookupFlags::empty())
         .unwrap();
+    tmpdir.write("a/b/c", b"", LookupFlags::empty()).unwrap();
+    tmpdir.write("a/b/d", b"", LookupFlags::empty()).unwrap();
+    tmpdir.write("file", b"", LookupFlags::empty()).unwrap();
+    tmpdir.symlink("link", "a/b", LookupFlags::empty()).unwrap();
+    tmpdir.symlink("a/b/e", "c", LookupFlags::empty()).unwrap();
+
+    let paths = [
+        "a/b/c",
+        "a/b/d",
+        "a/b//e",
+        "a/b/.",
+        "a/b/",
+        "a/b/..",
+        "link/c",
+        "link",
+        "file",
+        ".",
+        "a/noexist",
+        "a/b/c/",
+        "../x",
+        "a/../../x",
+        "/file",
+    ];
+
+    let results = tmpdir.metadata_many(paths.iter().copied(), LookupFlags::empty());
+    assert_eq!(results.len(), paths.len());
+
+    for (path, res) in paths.iter().zip(results) {
+        match (res, tmpdir.metadata(*path, LookupFlags::empty())) {
+            (Ok(m1), Ok(m2)) => {
+                assert!(same_meta(&m1, &m2), "{}", path);
+                assert_eq!(m1.file_type(), m2.file_type(), "{}", path);
+            }
+            (Err(e1), Err(e2)) => assert_eq!(e1.raw_os_error(), e2.raw_os_error(), "{}", path),
+            (r1, r2) => panic!("{}: {:?} != {:?}", path, r1, r2),
+        }
+    }
+}
 
-    tmpdir.symlink("link", "a", LookupFlags::empty()).unwrap();
+#[test]
+fn test_open_many() {
+    use obnth::OpenOptions;
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
 
+    tmpdir.create_dir("a", 0o777, LookupFlags::empty()).unwrap();
     tmpdir
-        .symlink("link-noexist", "NOEXIST", LookupFlags::empty())
+        .create_dir("a/b", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir.write("a/b/c", b"c", LookupFlags::empty()).unwrap();
+    tmpdir.write("file", b"file", LookupFlags::empty()).unwrap();
+    tmpdir
+        .symlink("a/b/up", "../../file", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("a/b/abs", "/file", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("a/b/out", "../../..", LookupFlags::empty())
         .unwrap();
 
-    let a_meta = tmpdir.metadata("a", LookupFlags::empty()).unwrap();
-    let dir_meta = tmpdir.metadata("dir", LookupFlags::empty()).unwrap();
+    let mut read = OpenOptions::new();
+    read.read(true);
+    let mut create = OpenOptions::new();
+    create.write(true).create_new(true);
+    let mut in_root = OpenOptions::new();
+    in_root.read(true).lookup_flags(LookupFlags::IN_ROOT);
+    let mut no_symlinks = OpenOptions::new();
+    no_symlinks
+        .read(true)
+        .lookup_flags(LookupFlags::NO_SYMLINKS);
+
+    let entries = [
+        ("a/b/c", read.clone()),
+        ("a/b/new", create.clone()),
+        ("a/b/up", read.clone()),
+        ("a/b/abs", in_root.clone()),
+        ("a/b/abs", read.clone()),
+        ("a/b/out", read.clone()),
+        ("a/b/up", no_symlinks.clone()),
+        ("a/b/c/", read.clone()),
+        ("a/b/new", create.clone()),
+        ("file", read.clone()),
+        ("a/noexist/c", read.clone()),
+        ("../file", read.clone()),
+    ];
+
+    let results = tmpdir.open_many(&entries);
+    assert_eq!(results.len(), entries.len());
+
+    let contents = |res: &std::io::Result<fs::File>| {
+        let mut buf = Vec::new();
+        std::io::Read::read_to_end(&mut res.as_ref().unwrap(), &mut buf).unwrap();
+        buf
+    };
+    let eno = |res: &std::io::Result<fs::File>| res.as_ref().unwrap_err().raw_os_error();
+
+    assert_eq!(contents(&results[0]), b"c");
+    assert!(results[1].is_ok());
+    assert!(tmpdir_path.join("a/b/new").exists());
+    assert_eq!(contents(&results[2]), b"file");
+    assert_eq!(contents(&results[3]), b"file");
+    assert_eq!(eno(&results[4]), Some(libc::EXDEV));
+    assert_eq!(eno(&results[5]), Some(libc::EXDEV));
+    assert_eq!(eno(&results[6]), Some(libc::ELOOP));
+    assert_eq!(eno(&results[7]), Some(libc::ENOTDIR));
+    assert_eq!(eno(&results[8]), Some(libc::EEXIST));
+    assert_eq!(contents(&results[9]), b"file");
+    assert_eq!(eno(&results[10]), Some(libc::ENOENT));
+    assert_eq!(eno(&results[11]), Some(libc::EXDEV));
+}
 
-    rename2(
-        &tmpdir,
-        "a",
-        &tmpdir,
-        "dir/a",
-        Rename2Flags::empty(),
-        LookupFlags::empty(),
-    )
-    .unwrap();
+#[test]
+fn test_cached_dir() {
+    use obnth::CachedDir;
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let cached = CachedDir::new(Dir::open(tmpdir_path).unwrap());
+    let dir = cached.dir();
 
+    dir.create_dir("static", 0o777, LookupFlags::empty())
+        .unwrap();
+    dir.create_dir("static/css", 0o777, LookupFlags::empty())
+        .unwrap();
+    dir.write("static/css/a.css", b"a", LookupFlags::empty())
+        .unwrap();
+    dir.write("static/css/b.css", b"b", LookupFlags::empty())
+        .unwrap();
+    dir.write("top", b"top", LookupFlags::empty()).unwrap();
+    dir.symlink("static/css/up", "../../top", LookupFlags::empty())
+        .unwrap();
+
+    let read = |path: &str| {
+        let mut file = cached
+            .open(path, libc::O_RDONLY, 0, LookupFlags::empty())
+            .unwrap();
+        let mut buf = String::new();
+        std::io::Read::read_to_string(&mut file, &mut buf).unwrap();
+        buf
+    };
+
+    assert_eq!(read("static/css/a.css"), "a");
+    assert_eq!(read("static/css/b.css"), "b");
+    assert_eq!(read("static/css/up"), "top");
+    assert!(cached
+        .metadata("static/css/a.css", LookupFlags::empty())
+        .unwrap()
+        .is_file());
     assert!(same_meta(
-        &a_meta,
-        &tmpdir.metadata("dir/a", LookupFlags::empty()).unwrap()
+        &cached
+            .sub_dir("static/css", LookupFlags::empty())
+            .unwrap()
+            .self_metadata()
+            .unwrap(),
+        &dir.metadata("static/css", LookupFlags::empty()).unwrap()
     ));
 
-    assert_eq!(
-        rename2(
-            &tmpdir,
-            "NOEXIST",
-            &tmpdir,
-            "dir2",
-            Rename2Flags::empty(),
-            LookupFlags::empty()
-        )
-        .unwrap_err()
-        .raw_os_error(),
-        Some(libc::ENOENT)
-    );
+    // Replace the cached directory
+    fs::rename(tmpdir_path.join("static"), tmpdir_path.join("static.old")).unwrap();
+    dir.create_dir("static", 0o777, LookupFlags::empty())
+        .unwrap();
+    dir.create_dir("static/css", 0o777, LookupFlags::empty())
+        .unwrap();
+    dir.write("static/css/a.css", b"new", LookupFlags::empty())
+        .unwrap();
+    assert_eq!(read("static/css/a.css"), "new");
+
+    // Swap in a symlink that escapes
+    fs::remove_dir_all(tmpdir_path.join("static")).unwrap();
+    fs::create_dir(tmpdir_path.join("static")).unwrap();
+    std::os::unix::fs::symlink("/", tmpdir_path.join("static/css")).unwrap();
+    for path in ["static/css/a.css", "static/css/etc/passwd", "../x"] {
+        assert_eq!(
+            cached
+                .open(path, libc::O_RDONLY, 0, LookupFlags::empty())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EXDEV)
+        );
+    }
 
-    assert_eq!(
-        rename2(
-            &tmpdir,
-            "dir/..",
-            &tmpdir,
-            "dir2",
-            Rename2Flags::empty(),
-            LookupFlags::empty()
+    // Cached directories are revalidated with the same lookup flags
+    fs::remove_file(tmpdir_path.join("static/css")).unwrap();
+    dir.create_dir("static/css", 0o777, LookupFlags::empty())
+        .unwrap();
+    dir.write("static/css/a.css", b"a", LookupFlags::empty())
+        .unwrap();
+    cached
+        .open(
+            "static/css/a.css",
+            libc::O_RDONLY,
+            0,
+            LookupFlags::NO_SYMLINKS,
         )
-        .unwrap_err()
-        .raw_os_error(),
-        Some(libc::EBUSY)
-    );
-
+        .unwrap();
+    fs::rename(
+        tmpdir_path.join("static/css"),
+        tmpdir_path.join("static/css2"),
+    )
+    .unwrap();
+    std::os::unix::fs::symlink("css2", tmpdir_path.join("static/css")).unwrap();
     assert_eq!(
-        rename2(
-            &tmpdir,
-            "dir",
-            &tmpdir,
-            "dir2/.",
-            Rename2Flags::empty(),
-            LookupFlags::empty()
-        )
-        .unwrap_err()
-        .raw_os_error(),
-        Some(libc::EBUSY)
+        cached
+            .open(
+                "static/css/a.css",
+                libc::O_RDONLY,
+                0,
+                LookupFlags::NO_SYMLINKS
+            )
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ELOOP)
     );
+    assert_eq!(read("static/css/a.css"), "a");
 
-    rename2(
-        &tmpdir,
-        "dir",
-        &tmpdir,
-        "dir2",
-        Rename2Flags::empty(),
-        LookupFlags::empty(),
-    )
-    .unwrap();
+    cached.clear();
+    let _dir: Dir = cached.into_inner();
+}
+
+#[test]
+fn test_clone() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = Dir::open(tmpdir.as_ref()).unwrap();
 
+    #[allow(clippy::redundant_clone)]
+    let cloned = tmpdir.clone();
+    assert_ne!(cloned.as_raw_fd(), tmpdir.as_raw_fd());
     assert!(same_meta(
-        &dir_meta,
-        &tmpdir.metadata("dir2", LookupFlags::empty()).unwrap()
+        &cloned.self_metadata().unwrap(),
+        &tmpdir.self_metadata().unwrap()
     ));
 
-    assert_eq!(
-        rename2(
-            &tmpdir,
-            "dir2",
-            &tmpdir,
-            "link",
-            Rename2Flags::empty(),
-            LookupFlags::empty()
-        )
-        .unwrap_err()
-        .raw_os_error(),
-        Some(libc::ENOTDIR)
-    );
+    let flags = unsafe { libc::fcntl(cloned.as_raw_fd(), libc::F_GETFD) };
+    assert_ne!(flags & libc::FD_CLOEXEC, 0);
 
-    assert_eq!(
-        rename2(
-            &tmpdir,
-            "dir2",
-            &tmpdir,
-            "link/",
-            Rename2Flags::empty(),
-            LookupFlags::empty()
-        )
-        .unwrap_err()
-        .raw_os_error(),
-        Some(libc::ENOTDIR)
-    );
+    let handle = std::thread::spawn(move || {
+        cloned.write("file", b"abc", LookupFlags::empty()).unwrap();
+    });
+    handle.join().unwrap();
+    assert_eq!(tmpdir.read("file", LookupFlags::empty()).unwrap(), b"abc");
+}
+
+#[test]
+fn test_open_options_unbound() {
+    use obnth::OpenOptions;
+
+    let tmpdir1 = tempfile::tempdir().unwrap();
+    let tmpdir2 = tempfile::tempdir().unwrap();
+    let dir1 = Dir::open(tmpdir1.as_ref()).unwrap();
+    let dir2 = Dir::open(tmpdir2.as_ref()).unwrap();
+
+    let mut create = OpenOptions::new();
+    create.write(true).create_new(true).mode(0o600);
+
+    let mut read = OpenOptions::default();
+    read.read(true).lookup_flags(LookupFlags::NO_SYMLINKS);
+
+    for dir in [&dir1, &dir2] {
+        std::io::Write::write_all(&mut create.open_at(dir, "file").unwrap(), b"abc").unwrap();
+        assert_eq!(
+            create.open_at(dir, "file").unwrap_err().raw_os_error(),
+            Some(libc::EEXIST)
+        );
+
+        dir.symlink("link", "file", LookupFlags::empty()).unwrap();
+        let mut buf = String::new();
+        std::io::Read::read_to_string(&mut read.open_at(dir, "file").unwrap(), &mut buf).unwrap();
+        assert_eq!(buf, "abc");
+        assert_eq!(
+            read.open_at(dir, "link").unwrap_err().raw_os_error(),
+            Some(libc::ELOOP)
+        );
+        assert_eq!(
+            read.open_at(dir, "../file").unwrap_err().raw_os_error(),
+            Some(libc::EXDEV)
+        );
+    }
 
+    // Not bound to a directory
     assert_eq!(
-        rename2(
-            &tmpdir,
-            "dir2",
-            &tmpdir,
-            "link-noexist",
-            Rename2Flags::empty(),
-            LookupFlags::empty(),
-        )
-        .unwrap_err()
-        .raw_os_error(),
-        Some(libc::ENOTDIR)
+        read.open("file").unwrap_err().raw_os_error(),
+        Some(libc::EBADF)
     );
 
+    // Bound options can be used with other directories too
+    let bound = dir1.open_file().read(true).clone();
+    assert!(bound.open("file").is_ok());
+    dir2.remove_file("file", LookupFlags::empty()).unwrap();
     assert_eq!(
-        rename2(
-            &tmpdir,
-            "dir2",
-            &tmpdir,
-            "link-noexist/",
-            Rename2Flags::empty(),
-            LookupFlags::empty(),
-        )
-        .unwrap_err()
-        .raw_os_error(),
-        Some(libc::ENOTDIR)
+        bound.open_at(&dir2, "file").unwrap_err().raw_os_error(),
+        Some(libc::ENOENT)
     );
 }
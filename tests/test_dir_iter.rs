@@ -1,9 +1,10 @@
-use std::ffi::CString;
+use std::ffi::{CString, OsStr};
 use std::io;
 use std::os::unix::net::UnixListener;
 use std::os::unix::prelude::*;
+use std::path::Path;
 
-use obnth::{Dir, Entry, FileType, LookupFlags};
+use obnth::{Dir, Entry, FileType, ListEntry, LookupFlags, ReadDirIter};
 
 fn check_entries_match(entries_a: &[Entry], entries_b: &[Entry]) {
     assert_eq!(entries_a.len(), entries_b.len());
@@ -138,3 +139,330 @@ fn test_dir_iter_seek() {
     reader.seek(end_pos);
     assert!(reader.next().is_none());
 }
+
+#[test]
+fn test_entry_open() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = Dir::open(tmpdir.as_ref()).unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .write("dir/file", b"abc", LookupFlags::empty())
+        .unwrap();
+    tmpdir.write("file", b"def", LookupFlags::empty()).unwrap();
+    tmpdir
+        .symlink("link", "file", LookupFlags::empty())
+        .unwrap();
+
+    for entry in tmpdir.list_self().unwrap() {
+        let entry = entry.unwrap();
+
+        match entry.name().as_bytes() {
+            b"dir" => {
+                let subdir = entry.open_dir_in(&tmpdir).unwrap();
+                assert_eq!(subdir.read("file", LookupFlags::empty()).unwrap(), b"abc");
+
+                assert!(entry
+                    .open_in(&tmpdir, tmpdir.open_file().read(true))
+                    .unwrap()
+                    .metadata()
+                    .unwrap()
+                    .is_dir());
+            }
+
+            b"file" => {
+                let mut file = entry
+                    .open_in(&tmpdir, tmpdir.open_file().read(true))
+                    .unwrap();
+                let mut buf = Vec::new();
+                io::Read::read_to_end(&mut file, &mut buf).unwrap();
+                assert_eq!(buf, b"def");
+
+                assert_eq!(
+                    entry.open_dir_in(&tmpdir).unwrap_err().raw_os_error(),
+                    Some(libc::ENOTDIR)
+                );
+                assert_eq!(
+                    entry.read_link_in(&tmpdir).unwrap_err().raw_os_error(),
+                    Some(libc::EINVAL)
+                );
+            }
+
+            b"link" => {
+                assert_eq!(entry.read_link_in(&tmpdir).unwrap(), Path::new("file"));
+                assert_eq!(
+                    entry
+                        .open_in(&tmpdir, tmpdir.open_file().read(true))
+                        .unwrap_err()
+                        .raw_os_error(),
+                    Some(libc::ELOOP)
+                );
+                assert_eq!(
+                    entry.open_dir_in(&tmpdir).unwrap_err().raw_os_error(),
+                    Some(libc::ENOTDIR)
+                );
+            }
+
+            name => panic!("Unexpected entry {:?}", name),
+        }
+    }
+}
+
+#[test]
+fn test_entry_replaced() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = Dir::open(tmpdir.as_ref()).unwrap();
+
+    tmpdir.write("file", b"abc", LookupFlags::empty()).unwrap();
+
+    let entry = tmpdir.list_self().unwrap().next().unwrap().unwrap();
+    assert_eq!(entry.name(), "file");
+
+    // Keep the old file around so its inode can't be reused
+    tmpdir
+        .local_rename("file", "old", LookupFlags::empty())
+        .unwrap();
+    tmpdir.write("file", b"def", LookupFlags::empty()).unwrap();
+
+    assert_eq!(
+        entry.metadata_in(&tmpdir).unwrap_err().raw_os_error(),
+        Some(libc::ESTALE)
+    );
+    assert_eq!(
+        entry
+            .open_in(&tmpdir, tmpdir.open_file().read(true))
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ESTALE)
+    );
+    assert_eq!(
+        entry.read_link_in(&tmpdir).unwrap_err().raw_os_error(),
+        Some(libc::ESTALE)
+    );
+}
+
+#[test]
+fn test_list_options() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = Dir::open(tmpdir.as_ref()).unwrap();
+
+    tmpdir
+        .create_dir("sub", 0o777, LookupFlags::empty())
+        .unwrap();
+    for name in ["sub/c", "sub/a", "sub/.hidden", "sub/b"] {
+        tmpdir.write(name, b"", LookupFlags::empty()).unwrap();
+    }
+    tmpdir
+        .create_dir("sub/d", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir.symlink("sub/e", "a", LookupFlags::empty()).unwrap();
+
+    let names = |entries: Vec<ListEntry>| {
+        entries
+            .iter()
+            .map(|e| e.name().to_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        names(
+            tmpdir
+                .list_options()
+                .sort_by_name(true)
+                .list("sub")
+                .unwrap()
+        ),
+        [".hidden", "a", "b", "c", "d", "e"]
+    );
+    assert_eq!(
+        names(
+            tmpdir
+                .list_options()
+                .sort_by_name(true)
+                .include_hidden(false)
+                .list("sub")
+                .unwrap()
+        ),
+        ["a", "b", "c", "d", "e"]
+    );
+    assert_eq!(
+        names(
+            tmpdir
+                .list_options()
+                .sort_by_name(true)
+                .file_types(&[FileType::Directory, FileType::Symlink])
+                .list("sub")
+                .unwrap()
+        ),
+        ["d", "e"]
+    );
+
+    let entries = tmpdir
+        .sub_dir("sub", LookupFlags::empty())
+        .unwrap()
+        .list_options()
+        .sort_by_name(true)
+        .with_metadata(true)
+        .list_self()
+        .unwrap();
+    assert_eq!(entries.len(), 6);
+    for entry in entries {
+        let meta = entry.metadata().unwrap();
+        assert_eq!(meta.ino(), entry.entry().ino());
+        assert_eq!(Some(meta.file_type()), entry.entry().file_type());
+    }
+
+    assert!(tmpdir
+        .list_options()
+        .list("sub")
+        .unwrap()
+        .iter()
+        .all(|e| e.metadata().is_none()));
+}
+
+#[test]
+fn test_dir_iter_many() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = Dir::open(tmpdir.as_ref()).unwrap();
+
+    // Enough entries that they can't all be returned by a single getdents64() call on Linux
+    let mut names = (0..2000)
+        .map(|i| format!("file-with-a-fairly-long-name-{}", i))
+        .collect::<Vec<_>>();
+    for name in names.iter() {
+        tmpdir.write(name, b"", LookupFlags::empty()).unwrap();
+    }
+    names.sort_unstable();
+
+    let mut reader = tmpdir.list_self().unwrap();
+    let mut first = Vec::new();
+    for entry in reader.by_ref().take(1000) {
+        first.push(entry.unwrap().name().to_str().unwrap().to_string());
+    }
+    let pos = reader.tell();
+    let rest = reader
+        .by_ref()
+        .map(|e| e.unwrap().name().to_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+
+    let mut all = first.clone();
+    all.extend(rest.iter().cloned());
+    all.sort_unstable();
+    assert_eq!(all, names);
+
+    // Seeking back yields the same entries
+    reader.seek(pos);
+    let rest2 = reader
+        .by_ref()
+        .map(|e| e.unwrap().name().to_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(rest, rest2);
+
+    reader.rewind();
+    assert_eq!(reader.count(), 2000);
+}
+
+#[test]
+fn test_non_utf8_names() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = Dir::open(tmpdir.as_ref()).unwrap();
+
+    let dir_name = OsStr::from_bytes(b"dir-\x80");
+    let file_name = OsStr::from_bytes(b"\xff\xfe-file");
+    let link_name = OsStr::from_bytes(b"link-\xc3");
+
+    // Every API passes the names through unchanged
+    tmpdir
+        .create_dir(dir_name, 0o777, LookupFlags::empty())
+        .unwrap();
+    let subdir = tmpdir.sub_dir(dir_name, LookupFlags::empty()).unwrap();
+    subdir
+        .write(file_name, b"abc", LookupFlags::empty())
+        .unwrap();
+    subdir
+        .symlink(link_name, file_name, LookupFlags::empty())
+        .unwrap();
+
+    let path = Path::new(dir_name).join(link_name);
+    assert_eq!(tmpdir.read(&path, LookupFlags::empty()).unwrap(), b"abc");
+    assert_eq!(
+        tmpdir.read_link(&path, LookupFlags::empty()).unwrap(),
+        Path::new(file_name)
+    );
+    assert_eq!(
+        tmpdir.read_link_bytes(&path, LookupFlags::empty()).unwrap(),
+        b"\xff\xfe-file"
+    );
+    assert_eq!(
+        tmpdir
+            .read_link_bytes(dir_name, LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EINVAL)
+    );
+
+    let mut names = subdir
+        .list_self()
+        .unwrap()
+        .map(|e| {
+            let e = e.unwrap();
+            assert_eq!(e.name().as_bytes(), e.name_bytes());
+            assert_eq!(e.name_cstr().to_bytes(), e.name_bytes());
+            e.name_bytes().to_vec()
+        })
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    assert_eq!(names, [b"link-\xc3".as_ref(), b"\xff\xfe-file".as_ref()]);
+
+    // Iterating without copying the names
+    let mut reader = subdir.list_self().unwrap();
+    let mut raw_names = Vec::new();
+    while let Some(entry) = reader.next_raw() {
+        let entry = entry.unwrap();
+        let owned = entry.to_entry();
+        assert_eq!(owned.name(), entry.name());
+        assert_eq!(owned.name_cstr(), entry.name_cstr());
+        assert_eq!(owned.ino(), entry.ino());
+        assert_eq!(owned.file_type(), entry.file_type());
+        raw_names.push(entry.name_bytes().to_vec());
+    }
+    raw_names.sort_unstable();
+    assert_eq!(raw_names, names);
+
+    tmpdir
+        .remove_dir_all(dir_name, LookupFlags::empty())
+        .unwrap();
+    assert_eq!(tmpdir.list_self().unwrap().count(), 0);
+}
+
+#[test]
+fn test_send_sync() {
+    fn assert_send<T: Send>() {}
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<Dir>();
+    assert_send_sync::<Entry>();
+    assert_send_sync::<ListEntry>();
+    assert_send::<ReadDirIter>();
+    assert_send::<obnth::WalkIter>();
+    assert_send_sync::<obnth::WalkEntry>();
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = Dir::open(tmpdir.as_ref()).unwrap();
+    tmpdir.write("a", b"", LookupFlags::empty()).unwrap();
+    tmpdir.write("b", b"", LookupFlags::empty()).unwrap();
+
+    // List the directory on one thread and process the entries on another
+    let reader = tmpdir.list_self().unwrap();
+    let mut names = std::thread::spawn(move || reader.map(|e| e.unwrap()).collect::<Vec<Entry>>())
+        .join()
+        .unwrap()
+        .into_iter()
+        .map(|e| std::thread::spawn(move || e.name().to_os_string()))
+        .map(|t| t.join().unwrap())
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    assert_eq!(names, ["a", "b"]);
+}
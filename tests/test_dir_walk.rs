@@ -0,0 +1,429 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use obnth::{Dir, FileType, LookupFlags, WalkEntry, WalkErrorPolicy, WalkOrder};
+
+fn setup_tree() -> (tempfile::TempDir, Dir) {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.path();
+
+    fs::create_dir_all(tmpdir_path.join("a/b/c")).unwrap();
+    fs::create_dir(tmpdir_path.join("d")).unwrap();
+    fs::write(tmpdir_path.join("file"), b"").unwrap();
+    fs::write(tmpdir_path.join("a/file"), b"").unwrap();
+    fs::write(tmpdir_path.join("a/b/c/file"), b"").unwrap();
+
+    let dir = Dir::open(tmpdir_path).unwrap();
+
+    (tmpdir, dir)
+}
+
+fn sorted_paths(entries: &[WalkEntry]) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = entries.iter().map(|e| e.path().to_path_buf()).collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn test_walk_basic() {
+    let (_tmpdir, dir) = setup_tree();
+
+    let expected: Vec<PathBuf> = ["a", "a/b", "a/b/c", "a/b/c/file", "a/file", "d", "file"]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+
+    for &order in [WalkOrder::DepthFirst, WalkOrder::BreadthFirst].iter() {
+        let entries = dir
+            .walk()
+            .order(order)
+            .walk(".")
+            .unwrap()
+            .collect::<io::Result<Vec<WalkEntry>>>()
+            .unwrap();
+
+        assert_eq!(sorted_paths(&entries), expected);
+
+        for entry in entries.iter() {
+            assert_eq!(entry.depth(), entry.path().components().count());
+            assert_eq!(entry.file_name(), entry.path().file_name().unwrap());
+
+            let meta = entry.metadata().unwrap();
+            assert_eq!(entry.file_type(), meta.file_type());
+            assert_eq!(
+                meta.ino(),
+                entry
+                    .parent_dir()
+                    .metadata(entry.file_name(), LookupFlags::empty())
+                    .unwrap()
+                    .ino()
+            );
+        }
+
+        match order {
+            // Each directory is immediately followed by its contents
+            WalkOrder::DepthFirst => {
+                for (i, entry) in entries.iter().enumerate() {
+                    if entry.file_type() == FileType::Directory && i + 1 < entries.len() {
+                        let next = &entries[i + 1];
+                        if next.depth() > entry.depth() {
+                            assert!(next.path().starts_with(entry.path()));
+                        }
+                    }
+                }
+            }
+
+            WalkOrder::BreadthFirst => {
+                for pair in entries.windows(2) {
+                    assert!(pair[0].depth() <= pair[1].depth());
+                }
+            }
+        }
+    }
+
+    // Starting from a subdirectory
+    let entries = dir
+        .walk()
+        .walk("a/b")
+        .unwrap()
+        .collect::<io::Result<Vec<WalkEntry>>>()
+        .unwrap();
+    assert_eq!(
+        sorted_paths(&entries),
+        vec![PathBuf::from("c"), PathBuf::from("c/file")]
+    );
+
+    assert_eq!(
+        dir.walk().walk("..").unwrap_err().raw_os_error(),
+        Some(libc::EXDEV)
+    );
+    assert_eq!(
+        dir.walk().walk("file").unwrap_err().raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+}
+
+#[test]
+fn test_walk_max_depth() {
+    let (_tmpdir, dir) = setup_tree();
+
+    let entries = dir
+        .walk()
+        .max_depth(Some(1))
+        .walk(".")
+        .unwrap()
+        .collect::<io::Result<Vec<WalkEntry>>>()
+        .unwrap();
+    assert_eq!(
+        sorted_paths(&entries),
+        vec![
+            PathBuf::from("a"),
+            PathBuf::from("d"),
+            PathBuf::from("file")
+        ]
+    );
+
+    let entries = dir
+        .walk()
+        .max_depth(Some(2))
+        .walk(".")
+        .unwrap()
+        .collect::<io::Result<Vec<WalkEntry>>>()
+        .unwrap();
+    assert_eq!(entries.len(), 5);
+    assert!(entries.iter().all(|e| e.depth() <= 2));
+
+    assert_eq!(dir.walk().max_depth(Some(0)).walk(".").unwrap().count(), 0);
+}
+
+#[test]
+fn test_walk_symlinks() {
+    let (tmpdir, dir) = setup_tree();
+
+    dir.symlink("d/link-a", "../a/b", LookupFlags::empty())
+        .unwrap();
+    dir.symlink("d/link-file", "../file", LookupFlags::empty())
+        .unwrap();
+    dir.symlink("d/link-dangling", "noexist", LookupFlags::empty())
+        .unwrap();
+
+    // Not followed by default
+    let entries = dir
+        .walk()
+        .walk("d")
+        .unwrap()
+        .collect::<io::Result<Vec<WalkEntry>>>()
+        .unwrap();
+    assert_eq!(entries.len(), 3);
+    assert!(entries.iter().all(|e| e.file_type() == FileType::Symlink));
+
+    let entries = dir
+        .walk()
+        .follow_symlinks(true)
+        .walk("d")
+        .unwrap()
+        .collect::<io::Result<Vec<WalkEntry>>>()
+        .unwrap();
+    assert_eq!(
+        sorted_paths(&entries),
+        [
+            "link-a",
+            "link-a/c",
+            "link-a/c/file",
+            "link-dangling",
+            "link-file",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<PathBuf>>()
+    );
+
+    // Symlinks can't escape the directory
+    dir.symlink("d/link-escape", "../..", LookupFlags::empty())
+        .unwrap();
+    let results: Vec<io::Result<WalkEntry>> = dir
+        .walk()
+        .follow_symlinks(true)
+        .walk("d")
+        .unwrap()
+        .collect();
+    assert_eq!(
+        results
+            .iter()
+            .filter_map(|r| r.as_ref().err())
+            .map(|e| e.raw_os_error())
+            .collect::<Vec<_>>(),
+        vec![Some(libc::EXDEV)]
+    );
+
+    // ...unless IN_ROOT is passed, in which case they're resolved as if the directory was the
+    // root directory
+    let entries = dir
+        .walk()
+        .follow_symlinks(true)
+        .lookup_flags(LookupFlags::IN_ROOT)
+        .max_depth(Some(2))
+        .walk("d")
+        .unwrap()
+        .collect::<io::Result<Vec<WalkEntry>>>()
+        .unwrap();
+    assert!(entries
+        .iter()
+        .any(|e| e.path() == Path::new("link-escape/file")));
+    fs::remove_file(tmpdir.path().join("d/link-escape")).unwrap();
+
+    // Symlink loops are detected
+    dir.symlink("a/b/c/loop", "../..", LookupFlags::empty())
+        .unwrap();
+    let results: Vec<io::Result<WalkEntry>> = dir
+        .walk()
+        .follow_symlinks(true)
+        .walk("a")
+        .unwrap()
+        .collect();
+    assert_eq!(
+        results
+            .iter()
+            .filter_map(|r| r.as_ref().err())
+            .map(|e| e.raw_os_error())
+            .collect::<Vec<_>>(),
+        vec![Some(libc::ELOOP)]
+    );
+
+    // Errors can be skipped...
+    let entries = dir
+        .walk()
+        .follow_symlinks(true)
+        .error_policy(WalkErrorPolicy::Skip)
+        .walk("a")
+        .unwrap()
+        .collect::<io::Result<Vec<WalkEntry>>>()
+        .unwrap();
+    assert_eq!(
+        sorted_paths(&entries),
+        ["b", "b/c", "b/c/file", "b/c/loop", "file"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<PathBuf>>()
+    );
+
+    // ...or they can stop the walk
+    let mut walker = dir
+        .walk()
+        .follow_symlinks(true)
+        .error_policy(WalkErrorPolicy::Abort)
+        .order(WalkOrder::BreadthFirst)
+        .walk("a/b/c")
+        .unwrap();
+    let mut results = Vec::new();
+    for res in walker.by_ref() {
+        results.push(res.map(|e| e.path().to_path_buf()));
+    }
+    assert_eq!(
+        results.last().unwrap().as_ref().unwrap_err().raw_os_error(),
+        Some(libc::ELOOP)
+    );
+    assert!(walker.next().is_none());
+}
+
+#[test]
+fn test_glob() {
+    let (tmpdir, dir) = setup_tree();
+    let tmpdir_path = tmpdir.path();
+
+    fs::write(tmpdir_path.join("a/index.html"), b"").unwrap();
+    fs::write(tmpdir_path.join("a/b/page.html"), b"").unwrap();
+    fs::write(tmpdir_path.join("a/b/.hidden.html"), b"").unwrap();
+    fs::create_dir(tmpdir_path.join("a/.git")).unwrap();
+    fs::write(tmpdir_path.join("a/.git/x.html"), b"").unwrap();
+    fs::write(tmpdir_path.join("d/a1"), b"").unwrap();
+    fs::write(tmpdir_path.join("d/a2"), b"").unwrap();
+    fs::write(tmpdir_path.join("d/b1"), b"").unwrap();
+    dir.symlink("d/link", "../a", LookupFlags::empty()).unwrap();
+
+    fn glob(dir: &Dir, pattern: &str, lookup_flags: LookupFlags) -> Vec<PathBuf> {
+        sorted_paths(
+            &dir.glob(pattern, lookup_flags)
+                .unwrap()
+                .collect::<io::Result<Vec<WalkEntry>>>()
+                .unwrap(),
+        )
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    assert_eq!(
+        glob(&dir, "*", LookupFlags::empty()),
+        paths(&["a", "d", "file"])
+    );
+    assert_eq!(
+        glob(&dir, "d/a?", LookupFlags::empty()),
+        paths(&["d/a1", "d/a2"])
+    );
+    assert_eq!(
+        glob(&dir, "d/[!a]*", LookupFlags::empty()),
+        paths(&["d/b1", "d/link"])
+    );
+    assert_eq!(
+        glob(&dir, "*/*.html", LookupFlags::empty()),
+        paths(&["a/index.html"])
+    );
+    assert_eq!(
+        glob(&dir, "**/*.html", LookupFlags::empty()),
+        paths(&["a/b/page.html", "a/index.html"])
+    );
+    assert_eq!(
+        glob(&dir, "a/**/.*.html", LookupFlags::empty()),
+        paths(&["a/b/.hidden.html"])
+    );
+    assert_eq!(
+        glob(&dir, "a/.*/*", LookupFlags::empty()),
+        paths(&["a/.git/x.html"])
+    );
+
+    // Literal paths only match if they exist
+    assert_eq!(
+        glob(&dir, "a/file", LookupFlags::empty()),
+        paths(&["a/file"])
+    );
+    assert_eq!(glob(&dir, "a/noexist", LookupFlags::empty()), paths(&[]));
+    assert_eq!(glob(&dir, "noexist/*", LookupFlags::empty()), paths(&[]));
+
+    // Symlinks in the leading components are followed, but the matched part never descends
+    // into symlinks
+    assert_eq!(
+        glob(&dir, "d/link/*.html", LookupFlags::empty()),
+        paths(&["d/link/index.html"])
+    );
+    assert_eq!(glob(&dir, "d/*/*.html", LookupFlags::empty()), paths(&[]));
+    assert_eq!(
+        dir.glob("d/link/*.html", LookupFlags::NO_SYMLINKS)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ELOOP)
+    );
+
+    // Matched entries can be used to open the file safely
+    for entry in dir.glob("**/*.html", LookupFlags::empty()).unwrap() {
+        let entry = entry.unwrap();
+        entry
+            .parent_dir()
+            .open_file()
+            .read(true)
+            .lookup_flags(LookupFlags::NO_SYMLINKS)
+            .open(entry.file_name())
+            .unwrap();
+    }
+
+    assert_eq!(
+        dir.glob("../*", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
+    assert_eq!(
+        dir.glob("/*", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
+    assert_eq!(glob(&dir, "/d/b*", LookupFlags::IN_ROOT), paths(&["/d/b1"]));
+    assert_eq!(
+        dir.glob("", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EINVAL)
+    );
+}
+
+#[test]
+fn test_disk_usage() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir
+        .create_dir_all("a/b", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .write("a/f1", [0; 100], LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .write("a/b/f2", [0; 50], LookupFlags::empty())
+        .unwrap();
+    obnth::hardlink(&tmpdir, "a/f1", &tmpdir, "a/b/f3", LookupFlags::empty()).unwrap();
+    tmpdir
+        .symlink("a/link", "f1", LookupFlags::empty())
+        .unwrap();
+
+    let dir_size = |path: &str| tmpdir_path.join(path).symlink_metadata().unwrap().len();
+    let base_size = dir_size("a") + dir_size("a/b") + 150 + "f1".len() as u64;
+
+    let usage = tmpdir.disk_usage("a", false, LookupFlags::empty()).unwrap();
+    assert_eq!(usage.dirs(), 2);
+    assert_eq!(usage.files(), 4);
+    assert_eq!(usage.apparent_size(), base_size + 100);
+    assert!(usage.disk_size() > 0);
+
+    let usage = tmpdir.disk_usage("a", true, LookupFlags::empty()).unwrap();
+    assert_eq!(usage.dirs(), 2);
+    assert_eq!(usage.files(), 3);
+    assert_eq!(usage.apparent_size(), base_size);
+
+    let usage = tmpdir
+        .disk_usage("a/b", true, LookupFlags::empty())
+        .unwrap();
+    assert_eq!(usage.files(), 2);
+    assert_eq!(usage.apparent_size(), dir_size("a/b") + 150);
+
+    assert_eq!(
+        tmpdir
+            .disk_usage("../a", false, LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
+}
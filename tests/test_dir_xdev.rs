@@ -72,3 +72,98 @@ fn test_open_beneath_xdev() {
         check_err!("proc/self", libc::O_RDONLY, libc::EXDEV);
     }
 }
+
+#[cfg(feature = "mount-id")]
+#[test]
+fn test_mount_id() {
+    let root = fs::File::open("/").unwrap();
+    let root_id = obnth::mount_id(root.as_raw_fd()).unwrap();
+    assert_eq!(obnth::mount_id(root.as_raw_fd()).unwrap(), root_id);
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_file = fs::File::open(tmpdir.path()).unwrap();
+    let tmpfile = fs::File::create(tmpdir.path().join("file")).unwrap();
+    assert_eq!(
+        obnth::mount_id(tmpdir_file.as_raw_fd()).unwrap(),
+        obnth::mount_id(tmpfile.as_raw_fd()).unwrap()
+    );
+
+    #[cfg(target_os = "linux")]
+    {
+        let proc = fs::File::open("/proc").unwrap();
+        let proc_id = obnth::mount_id(proc.as_raw_fd()).unwrap();
+        assert_ne!(proc_id, root_id);
+
+        let dir = obnth::Dir::open("/").unwrap();
+        if let Some(id) = dir
+            .metadata("proc", LookupFlags::empty())
+            .unwrap()
+            .mount_id()
+        {
+            assert_eq!(id, proc_id.as_raw());
+        }
+    }
+
+    assert_eq!(
+        obnth::mount_id(-1).unwrap_err().raw_os_error(),
+        Some(libc::EBADF)
+    );
+}
+
+#[cfg(all(feature = "mount-id", target_os = "linux"))]
+#[test]
+fn test_open_beneath_allow_mounts() {
+    let root = fs::File::open("/").unwrap();
+    let proc_id = obnth::mount_id(fs::File::open("/proc").unwrap().as_raw_fd()).unwrap();
+
+    let mut opts = obnth::OpenBeneath::new("proc/self/status");
+    opts.lookup_flags(LookupFlags::NO_XDEV);
+
+    assert_eq!(
+        opts.open_at(&root).unwrap_err().raw_os_error(),
+        Some(libc::EXDEV)
+    );
+
+    opts.allow_mounts(&[proc_id]);
+    opts.open_at(&root).unwrap();
+
+    // Other mounts are still rejected
+    let mut opts = obnth::OpenBeneath::new("sys/kernel");
+    opts.lookup_flags(LookupFlags::NO_XDEV)
+        .allow_mounts(&[proc_id]);
+    assert_eq!(
+        opts.open_at(&root).unwrap_err().raw_os_error(),
+        Some(libc::EXDEV)
+    );
+
+    // Without NO_XDEV, the list has no effect
+    obnth::OpenBeneath::new("sys/kernel")
+        .allow_mounts(&[proc_id])
+        .open_at(&root)
+        .unwrap();
+}
+
+#[cfg(all(feature = "mount-id", target_os = "linux"))]
+#[test]
+fn test_open_options_allow_mounts() {
+    let root = obnth::Dir::open("/").unwrap();
+    let proc_id = obnth::mount_id(fs::File::open("/proc").unwrap().as_raw_fd()).unwrap();
+
+    let mut opts = root.open_file();
+    opts.read(true).lookup_flags(LookupFlags::NO_XDEV);
+    assert_eq!(
+        opts.open("proc/self/status").unwrap_err().raw_os_error(),
+        Some(libc::EXDEV)
+    );
+
+    opts.allow_mounts(&[proc_id]);
+    opts.open("proc/self/status").unwrap();
+    assert_eq!(
+        opts.open("sys/kernel").unwrap_err().raw_os_error(),
+        Some(libc::EXDEV)
+    );
+
+    // open_many() resolves the parent directories the same way
+    let results = root.open_many(&[("proc/self/status", opts.clone())]);
+    assert!(results[0].is_ok());
+}
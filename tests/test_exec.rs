@@ -0,0 +1,41 @@
+#![cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+
+use std::os::unix::prelude::*;
+use std::process::Command;
+
+use obnth::exec::Executable;
+use obnth::{Dir, LookupFlags};
+
+#[test]
+fn test_executable_exec() {
+    let root = Dir::open("/").unwrap();
+
+    let exe = Executable::open_beneath(&root, "bin/false", LookupFlags::empty()).unwrap();
+    assert!(exe.metadata().unwrap().is_file());
+
+    // If the exec succeeds, the child runs `false` instead of `true`
+    let status = unsafe {
+        Command::new("true")
+            .pre_exec(move || Err(exe.exec(&["false"], &[] as &[&str])))
+            .status()
+            .unwrap()
+    };
+    assert_eq!(status.code(), Some(1));
+
+    let exe = Executable::open_beneath(&root, "bin", LookupFlags::empty()).unwrap();
+    assert_eq!(
+        exe.exec(&["bin"], &[] as &[&str]).raw_os_error(),
+        Some(libc::EACCES)
+    );
+    assert_eq!(
+        exe.exec(&["a\0b"], &[] as &[&str]).kind(),
+        std::io::ErrorKind::InvalidInput
+    );
+
+    assert_eq!(
+        Executable::open_beneath(&root, "bin/noexist", LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOENT)
+    );
+}
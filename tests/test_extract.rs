@@ -0,0 +1,242 @@
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use obnth::extract::{EntryKind, ExtractEntry, ExtractOptions, PathPolicy, SymlinkPolicy};
+use obnth::{Dir, LookupFlags};
+
+type Entry = ExtractEntry<&'static [u8]>;
+
+fn file(path: &str, contents: &'static [u8]) -> Entry {
+    Entry::new(path, EntryKind::File(contents), 0o644)
+}
+
+fn symlink(path: &str, target: &str) -> Entry {
+    Entry::new(path, EntryKind::Symlink(target.into()), 0o777)
+}
+
+fn extract_one(opts: &ExtractOptions, dir: &Dir, entry: Entry) -> Option<i32> {
+    opts.extract_entry(dir, entry)
+        .err()
+        .map(|e| e.raw_os_error().unwrap())
+}
+
+#[test]
+fn test_extract_basic() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let dir = Dir::open(tmpdir_path).unwrap();
+
+    let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+    let entries = vec![
+        Entry::new("a", EntryKind::Directory, 0o555).mtime(mtime),
+        file("a/b/file", b"abc").mtime(mtime),
+        Entry::new("a/exec", EntryKind::File(&b""[..]), 0o4755),
+        symlink("a/link", "b/file"),
+        symlink("a/b/uplink", "../../a"),
+    ];
+    ExtractOptions::new()
+        .extract(&dir, entries.into_iter().map(Ok))
+        .unwrap();
+
+    assert_eq!(fs::read(tmpdir_path.join("a/b/file")).unwrap(), b"abc");
+    assert_eq!(fs::read(tmpdir_path.join("a/link")).unwrap(), b"abc");
+    assert_eq!(
+        fs::read_link(tmpdir_path.join("a/b/uplink")).unwrap(),
+        Path::new("../../a")
+    );
+
+    let meta = fs::metadata(tmpdir_path.join("a/b/file")).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o7777, 0o644);
+    assert_eq!(meta.modified().unwrap(), mtime);
+
+    // Set-ID bits are masked out by default
+    let meta = fs::metadata(tmpdir_path.join("a/exec")).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o7777, 0o755);
+
+    // Directory permissions and times are applied at the end
+    let meta = fs::metadata(tmpdir_path.join("a")).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o7777, 0o555);
+    assert_eq!(meta.modified().unwrap(), mtime);
+
+    fs::set_permissions(tmpdir_path.join("a"), fs::Permissions::from_mode(0o755)).unwrap();
+
+    // Errors from the entry iterator are propagated
+    let entries = vec![
+        Ok(file("c", b"")),
+        Err(io::Error::from_raw_os_error(libc::EIO)),
+    ];
+    assert_eq!(
+        ExtractOptions::new()
+            .extract(&dir, entries)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EIO)
+    );
+    assert!(tmpdir_path.join("c").exists());
+}
+
+#[test]
+fn test_extract_path_policy() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    fs::create_dir(tmpdir_path.join("dest")).unwrap();
+    let dir = Dir::open(tmpdir_path.join("dest")).unwrap();
+
+    let mut opts = ExtractOptions::new();
+
+    for &path in ["../file", "/file", "a/../../file", "a/.."].iter() {
+        assert_eq!(extract_one(&opts, &dir, file(path, b"")), Some(libc::EXDEV));
+    }
+    assert_eq!(fs::read_dir(tmpdir_path).unwrap().count(), 1);
+    assert_eq!(fs::read_dir(tmpdir_path.join("dest")).unwrap().count(), 0);
+
+    opts.path_policy(PathPolicy::Clamp);
+    assert_eq!(extract_one(&opts, &dir, file("../../file", b"1")), None);
+    assert_eq!(extract_one(&opts, &dir, file("/a/../b", b"2")), None);
+    assert_eq!(fs::read(tmpdir_path.join("dest/file")).unwrap(), b"1");
+    assert_eq!(fs::read(tmpdir_path.join("dest/b")).unwrap(), b"2");
+    assert!(!tmpdir_path.join("file").exists());
+
+    // Only directories can refer to the destination directory itself
+    assert_eq!(
+        extract_one(&opts, &dir, file("a/..", b"")),
+        Some(libc::EISDIR)
+    );
+    opts.extract_entry(&dir, Entry::new("/", EntryKind::Directory, 0o755))
+        .unwrap();
+}
+
+#[test]
+fn test_extract_symlinks() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    fs::create_dir(tmpdir_path.join("dest")).unwrap();
+    let dir = Dir::open(tmpdir_path.join("dest")).unwrap();
+
+    let mut opts = ExtractOptions::new();
+
+    // Escaping targets are rejected by default (including `..` after another component, since
+    // that component may be a symlink created by an earlier entry)
+    for &target in ["/etc", "..", "a/../../x", "a/../x"].iter() {
+        assert_eq!(
+            extract_one(&opts, &dir, symlink("link", target)),
+            Some(libc::EXDEV)
+        );
+    }
+
+    // Writing through a symlink created by an earlier entry fails (the symlink isn't treated as
+    // an existing parent directory)
+    opts.symlink_policy(SymlinkPolicy::Allow);
+    assert_eq!(extract_one(&opts, &dir, symlink("escape", "..")), None);
+    assert_eq!(
+        extract_one(&opts, &dir, file("escape/file", b"")),
+        Some(libc::EEXIST)
+    );
+    assert_eq!(
+        extract_one(&opts, &dir, file("escape", b"")),
+        Some(libc::EEXIST)
+    );
+    assert!(!tmpdir_path.join("file").exists());
+
+    // If symlinks are followed, entries can be extracted through ones that stay inside the
+    // directory, but not through ones that escape
+    opts.lookup_flags(LookupFlags::empty());
+    assert_eq!(extract_one(&opts, &dir, symlink("inside", "subdir")), None);
+    fs::create_dir(tmpdir_path.join("dest/subdir")).unwrap();
+    assert_eq!(extract_one(&opts, &dir, file("inside/file", b"")), None);
+    assert!(tmpdir_path.join("dest/subdir/file").exists());
+    assert_eq!(
+        extract_one(&opts, &dir, file("escape/file", b"")),
+        Some(libc::EEXIST)
+    );
+    assert_eq!(
+        extract_one(&opts, &dir, file("escape/../file", b"")),
+        Some(libc::EXDEV)
+    );
+    assert!(!tmpdir_path.join("file").exists());
+
+    // With symlinks followed, contained targets are checked against where the symlink actually
+    // ends up
+    opts.symlink_policy(SymlinkPolicy::Contained);
+    assert_eq!(extract_one(&opts, &dir, symlink("here", ".")), None);
+    assert_eq!(
+        extract_one(&opts, &dir, symlink("here/l", "..")),
+        Some(libc::EXDEV)
+    );
+    assert!(!tmpdir_path.join("dest/l").exists());
+    assert_eq!(
+        extract_one(&opts, &dir, symlink("inside/l", "../here")),
+        None
+    );
+    assert_eq!(
+        fs::read_link(tmpdir_path.join("dest/subdir/l")).unwrap(),
+        Path::new("../here")
+    );
+
+    opts.symlink_policy(SymlinkPolicy::Reject);
+    assert_eq!(
+        extract_one(&opts, &dir, symlink("link", "file")),
+        Some(libc::EPERM)
+    );
+}
+
+#[test]
+fn test_extract_overwrite() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    fs::create_dir(tmpdir_path.join("dest")).unwrap();
+    fs::write(tmpdir_path.join("outside"), b"outside").unwrap();
+    let dir = Dir::open(tmpdir_path.join("dest")).unwrap();
+
+    let mut opts = ExtractOptions::new();
+    opts.symlink_policy(SymlinkPolicy::Allow);
+
+    assert_eq!(extract_one(&opts, &dir, file("file", b"1")), None);
+    assert_eq!(
+        extract_one(&opts, &dir, file("file", b"2")),
+        Some(libc::EEXIST)
+    );
+    assert_eq!(
+        extract_one(&opts, &dir, symlink("link", "../outside")),
+        None
+    );
+
+    opts.overwrite(true);
+    assert_eq!(extract_one(&opts, &dir, file("file", b"2")), None);
+    assert_eq!(fs::read(tmpdir_path.join("dest/file")).unwrap(), b"2");
+
+    // Existing symlinks are replaced, not written through
+    assert_eq!(extract_one(&opts, &dir, file("link", b"link")), None);
+    assert_eq!(fs::read(tmpdir_path.join("dest/link")).unwrap(), b"link");
+    assert_eq!(fs::read(tmpdir_path.join("outside")).unwrap(), b"outside");
+
+    assert_eq!(extract_one(&opts, &dir, symlink("file", "link")), None);
+    assert_eq!(
+        fs::read_link(tmpdir_path.join("dest/file")).unwrap(),
+        Path::new("link")
+    );
+
+    // Directories are merged, but never replaced
+    fs::create_dir(tmpdir_path.join("dest/subdir")).unwrap();
+    fs::write(tmpdir_path.join("dest/subdir/file"), b"").unwrap();
+    opts.extract_entry(&dir, Entry::new("subdir", EntryKind::Directory, 0o755))
+        .unwrap();
+    assert!(tmpdir_path.join("dest/subdir/file").exists());
+    assert_eq!(
+        extract_one(&opts, &dir, file("subdir", b"")),
+        Some(libc::EISDIR)
+    );
+    assert_eq!(
+        extract_one(&opts, &dir, symlink("subdir", "file")),
+        Some(libc::EISDIR)
+    );
+
+    // But files can be replaced by directories
+    opts.extract_entry(&dir, Entry::new("link", EntryKind::Directory, 0o755))
+        .unwrap();
+    assert!(tmpdir_path.join("dest/link").is_dir());
+}
@@ -90,3 +90,561 @@ fn test_file_meta_basic() {
     assert!(!fifo_meta.is_dir());
     assert!(same_meta(&fifo_meta, &fifo_meta2));
 }
+
+#[test]
+fn test_file_meta_follow() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+    fs::write(tmpdir_path.join("dir/file"), b"abc").unwrap();
+
+    tmpdir
+        .symlink("link", "dir/file", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("dir/link", "../dir", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("link-escape", "..", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("link-abs", "/dir/file", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("link-noexist", "noexist", LookupFlags::empty())
+        .unwrap();
+
+    let file_meta = tmpdir
+        .metadata_follow("link", LookupFlags::empty())
+        .unwrap();
+    assert_eq!(file_meta.file_type(), FileType::File);
+    assert_eq!(file_meta.len(), 3);
+    assert!(same_meta(
+        &file_meta,
+        &tmpdir_path.join("dir/file").metadata().unwrap()
+    ));
+
+    // Not a symlink
+    assert!(same_meta(
+        &tmpdir
+            .metadata_follow("dir/file", LookupFlags::empty())
+            .unwrap(),
+        &tmpdir_path.join("dir/file").metadata().unwrap()
+    ));
+
+    let dir_meta = tmpdir
+        .metadata_follow("dir/link", LookupFlags::empty())
+        .unwrap();
+    assert_eq!(dir_meta.file_type(), FileType::Directory);
+    assert!(same_meta(
+        &dir_meta,
+        &tmpdir_path.join("dir").metadata().unwrap()
+    ));
+
+    assert!(same_meta(
+        &tmpdir
+            .metadata_follow("link-abs", LookupFlags::IN_ROOT)
+            .unwrap(),
+        &tmpdir_path.join("dir/file").metadata().unwrap()
+    ));
+
+    macro_rules! check_err {
+        ($path:expr, $lookup_flags:expr, $eno:expr) => {{
+            assert_eq!(
+                tmpdir
+                    .metadata_follow($path, $lookup_flags)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some($eno)
+            );
+        }};
+
+        ($path:expr, $eno:expr) => {
+            check_err!($path, LookupFlags::empty(), $eno)
+        };
+    }
+
+    check_err!("link-escape", libc::EXDEV);
+    check_err!("link-abs", libc::EXDEV);
+    check_err!("link-noexist", libc::ENOENT);
+    check_err!("link", LookupFlags::NO_SYMLINKS, libc::ELOOP);
+}
+
+#[test]
+fn test_exists() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+    fs::write(tmpdir_path.join("file"), b"").unwrap();
+    tmpdir
+        .symlink("link-file", "file", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("link-dir", "dir", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("link-noexist", "noexist", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("link-escape", "..", LookupFlags::empty())
+        .unwrap();
+
+    for &(path, exists, is_file, is_dir) in [
+        (".", true, false, true),
+        ("dir", true, false, true),
+        ("file", true, true, false),
+        ("link-file", true, true, false),
+        ("link-dir", true, false, true),
+        ("link-noexist", false, false, false),
+        ("noexist", false, false, false),
+        ("dir/noexist", false, false, false),
+    ]
+    .iter()
+    {
+        assert_eq!(
+            tmpdir.exists(path, LookupFlags::empty()).unwrap(),
+            exists,
+            "{}",
+            path
+        );
+        assert_eq!(
+            tmpdir.is_file(path, LookupFlags::empty()).unwrap(),
+            is_file,
+            "{}",
+            path
+        );
+        assert_eq!(
+            tmpdir.is_dir(path, LookupFlags::empty()).unwrap(),
+            is_dir,
+            "{}",
+            path
+        );
+    }
+
+    for &(path, lookup_flags, eno) in [
+        ("..", LookupFlags::empty(), libc::EXDEV),
+        ("link-escape", LookupFlags::empty(), libc::EXDEV),
+        ("link-file", LookupFlags::NO_SYMLINKS, libc::ELOOP),
+        ("file/file", LookupFlags::empty(), libc::ENOTDIR),
+    ]
+    .iter()
+    {
+        assert_eq!(
+            tmpdir
+                .exists(path, lookup_flags)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+        assert_eq!(
+            tmpdir
+                .is_file(path, lookup_flags)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+        assert_eq!(
+            tmpdir
+                .is_dir(path, lookup_flags)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+    }
+}
+
+#[test]
+fn test_file_meta_fields() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    fs::write(tmpdir_path.join("file"), b"abcdef").unwrap();
+    fs::hard_link(tmpdir_path.join("file"), tmpdir_path.join("file2")).unwrap();
+
+    let meta = tmpdir.metadata("file", LookupFlags::empty()).unwrap();
+    let meta2 = tmpdir_path.join("file").metadata().unwrap();
+
+    assert_eq!(meta.mode(), meta2.mode());
+    assert_eq!(meta.nlink(), 2);
+    assert_eq!(meta.nlink(), meta2.nlink());
+    assert_eq!(meta.uid(), meta2.uid());
+    assert_eq!(meta.gid(), meta2.gid());
+    assert_eq!(meta.rdev(), meta2.rdev());
+    assert_eq!(meta.blksize(), meta2.blksize());
+    assert_eq!(meta.blocks(), meta2.blocks());
+
+    assert_eq!(meta.accessed(), meta2.accessed().unwrap());
+    assert_eq!(meta.modified(), meta2.modified().unwrap());
+    assert_eq!(
+        meta.changed(),
+        UNIX_EPOCH + Duration::new(meta2.ctime() as u64, meta2.ctime_nsec() as u32)
+    );
+
+    // Timestamps before the epoch
+    let t = UNIX_EPOCH - Duration::new(100, 250_000_000);
+    tmpdir
+        .set_times("file", Some(t), Some(t), LookupFlags::empty())
+        .unwrap();
+    let meta = tmpdir.metadata("file", LookupFlags::empty()).unwrap();
+    assert_eq!(meta.accessed(), t);
+    assert_eq!(meta.modified(), t);
+}
+
+#[test]
+fn test_set_permissions() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o755, LookupFlags::empty())
+        .unwrap();
+    fs::write(tmpdir_path.join("dir/file"), b"").unwrap();
+    fs::set_permissions(
+        tmpdir_path.join("dir/file"),
+        fs::Permissions::from_mode(0o644),
+    )
+    .unwrap();
+    tmpdir
+        .symlink("link", "dir/file", LookupFlags::empty())
+        .unwrap();
+
+    fn get_mode(dir: &Dir, path: &str) -> u32 {
+        dir.metadata(path, LookupFlags::empty())
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o7777
+    }
+
+    tmpdir
+        .set_permissions(
+            "dir/file",
+            fs::Permissions::from_mode(0o600),
+            LookupFlags::empty(),
+        )
+        .unwrap();
+    assert_eq!(get_mode(&tmpdir, "dir/file"), 0o600);
+
+    tmpdir
+        .set_permissions(
+            "dir",
+            fs::Permissions::from_mode(0o700),
+            LookupFlags::empty(),
+        )
+        .unwrap();
+    assert_eq!(get_mode(&tmpdir, "dir"), 0o700);
+
+    tmpdir
+        .set_permissions(
+            "dir/.",
+            fs::Permissions::from_mode(0o750),
+            LookupFlags::empty(),
+        )
+        .unwrap();
+    assert_eq!(get_mode(&tmpdir, "dir"), 0o750);
+
+    // The symlink is not followed
+    let _ = tmpdir.set_permissions(
+        "link",
+        fs::Permissions::from_mode(0o777),
+        LookupFlags::empty(),
+    );
+    assert_eq!(get_mode(&tmpdir, "dir/file"), 0o600);
+
+    assert_eq!(
+        tmpdir
+            .set_permissions(
+                "..",
+                fs::Permissions::from_mode(0o777),
+                LookupFlags::empty()
+            )
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
+    assert_eq!(
+        tmpdir
+            .set_permissions(
+                "NOEXIST",
+                fs::Permissions::from_mode(0o777),
+                LookupFlags::empty()
+            )
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOENT)
+    );
+
+    let subdir = tmpdir.sub_dir("dir", LookupFlags::empty()).unwrap();
+    subdir
+        .set_self_permissions(fs::Permissions::from_mode(0o711))
+        .unwrap();
+    assert_eq!(get_mode(&tmpdir, "dir"), 0o711);
+    assert_eq!(
+        subdir.self_metadata().unwrap().permissions().mode() & 0o7777,
+        0o711
+    );
+}
+
+#[test]
+fn test_chown() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    fs::write(tmpdir_path.join("file"), b"").unwrap();
+    tmpdir
+        .symlink("link", "file", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("link-escape", "..", LookupFlags::empty())
+        .unwrap();
+
+    let uid = unsafe { libc::geteuid() };
+    let gid = unsafe { libc::getegid() };
+
+    // No-op changes should always succeed
+    tmpdir
+        .chown("file", Some(uid), Some(gid), LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .chown("link", None, Some(gid), LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .lchown("link", Some(uid), None, LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .lchown(".", None, None, LookupFlags::empty())
+        .unwrap();
+    tmpdir.set_self_owner(Some(uid), Some(gid)).unwrap();
+    tmpdir.self_chown(Some(uid), Some(gid)).unwrap();
+
+    assert_eq!(
+        tmpdir
+            .chown("link-escape", Some(uid), None, LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
+    assert_eq!(
+        tmpdir
+            .chown("link", Some(uid), None, LookupFlags::NO_SYMLINKS)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ELOOP)
+    );
+    assert_eq!(
+        tmpdir
+            .lchown("NOEXIST", Some(uid), None, LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOENT)
+    );
+
+    if uid == 0 {
+        let file_meta = || tmpdir.metadata("file", LookupFlags::empty()).unwrap();
+        let link_meta = || tmpdir.metadata("link", LookupFlags::empty()).unwrap();
+
+        tmpdir
+            .chown("link", Some(1234), Some(5678), LookupFlags::empty())
+            .unwrap();
+        assert_eq!(file_meta().stat().st_uid, 1234);
+        assert_eq!(file_meta().stat().st_gid, 5678);
+        assert_eq!(link_meta().stat().st_uid, 0);
+
+        tmpdir
+            .lchown("link", Some(4321), None, LookupFlags::empty())
+            .unwrap();
+        assert_eq!(file_meta().stat().st_uid, 1234);
+        assert_eq!(link_meta().stat().st_uid, 4321);
+        assert_eq!(link_meta().stat().st_gid, gid);
+
+        tmpdir.self_chown(Some(1234), None).unwrap();
+        assert_eq!(tmpdir.self_metadata().unwrap().stat().st_uid, 1234);
+    }
+}
+
+#[test]
+fn test_set_times() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    fs::write(tmpdir_path.join("file"), b"").unwrap();
+    tmpdir
+        .symlink("link", "file", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("link-escape", "..", LookupFlags::empty())
+        .unwrap();
+
+    fn get_times(dir: &Dir, path: &str) -> (i64, i64) {
+        let meta = dir.metadata(path, LookupFlags::empty()).unwrap();
+        (meta.stat().st_atime as i64, meta.stat().st_mtime as i64)
+    }
+
+    let t1 = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let t2 = UNIX_EPOCH + Duration::from_secs(2_000_000);
+    let t3 = UNIX_EPOCH + Duration::from_secs(3_000_000);
+
+    tmpdir
+        .set_times("file", Some(t1), Some(t2), LookupFlags::empty())
+        .unwrap();
+    assert_eq!(get_times(&tmpdir, "file"), (1_000_000, 2_000_000));
+
+    // Following the symlink
+    tmpdir
+        .set_times("link", None, Some(t3), LookupFlags::empty())
+        .unwrap();
+    assert_eq!(get_times(&tmpdir, "file"), (1_000_000, 3_000_000));
+
+    // Not following the symlink
+    tmpdir
+        .set_times_nofollow("link", Some(t2), Some(t1), LookupFlags::empty())
+        .unwrap();
+    assert_eq!(get_times(&tmpdir, "file"), (1_000_000, 3_000_000));
+    assert_eq!(get_times(&tmpdir, "link"), (2_000_000, 1_000_000));
+
+    tmpdir
+        .set_times_nofollow(".", Some(t1), Some(t1), LookupFlags::empty())
+        .unwrap();
+    assert_eq!(get_times(&tmpdir, "."), (1_000_000, 1_000_000));
+
+    tmpdir.set_self_times(None, Some(t2)).unwrap();
+    assert_eq!(get_times(&tmpdir, "."), (1_000_000, 2_000_000));
+
+    let file = tmpdir.open_file().write(true).open("file").unwrap();
+    obnth::set_file_times(&file, Some(t3), None).unwrap();
+    assert_eq!(get_times(&tmpdir, "file"), (3_000_000, 3_000_000));
+
+    assert_eq!(
+        tmpdir
+            .set_times("link-escape", Some(t1), None, LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
+    assert_eq!(
+        tmpdir
+            .set_times_nofollow("NOEXIST", Some(t1), None, LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOENT)
+    );
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+#[test]
+fn test_xattr() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    fs::write(tmpdir_path.join("file"), b"").unwrap();
+    tmpdir
+        .symlink("link", "file", LookupFlags::empty())
+        .unwrap();
+    tmpdir
+        .symlink("link-escape", "..", LookupFlags::empty())
+        .unwrap();
+
+    match tmpdir.set_xattr("file", "user.test", b"abc", LookupFlags::empty()) {
+        Ok(()) => (),
+        // The filesystem doesn't support user extended attributes
+        Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return,
+        Err(e) => panic!("{}", e),
+    }
+
+    assert_eq!(
+        tmpdir
+            .get_xattr("file", "user.test", LookupFlags::empty())
+            .unwrap(),
+        b"abc"
+    );
+    assert_eq!(
+        tmpdir
+            .get_xattr("link", "user.test", LookupFlags::empty())
+            .unwrap(),
+        b"abc"
+    );
+    assert!(tmpdir
+        .list_xattr("link", LookupFlags::empty())
+        .unwrap()
+        .contains(&"user.test".into()));
+
+    tmpdir
+        .set_xattr("link", "user.test", b"", LookupFlags::empty())
+        .unwrap();
+    assert_eq!(
+        tmpdir
+            .get_xattr("file", "user.test", LookupFlags::empty())
+            .unwrap(),
+        b""
+    );
+
+    tmpdir
+        .remove_xattr("file", "user.test", LookupFlags::empty())
+        .unwrap();
+    assert!(!tmpdir
+        .list_xattr("file", LookupFlags::empty())
+        .unwrap()
+        .contains(&"user.test".into()));
+
+    for &path in ["link-escape", "NOEXIST"].iter() {
+        let eno = if path == "NOEXIST" {
+            libc::ENOENT
+        } else {
+            libc::EXDEV
+        };
+
+        assert_eq!(
+            tmpdir
+                .get_xattr(path, "user.test", LookupFlags::empty())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+        assert_eq!(
+            tmpdir
+                .list_xattr(path, LookupFlags::empty())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_file_meta_statx() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+
+    fs::write(tmpdir_path.join("file"), b"").unwrap();
+
+    let meta = tmpdir.metadata("file", LookupFlags::empty()).unwrap();
+    let meta2 = tmpdir_path.join("file").metadata().unwrap();
+    assert!(same_meta(&meta, &meta2));
+    assert_eq!(meta.created(), meta2.created().ok());
+
+    let self_meta = tmpdir.self_metadata().unwrap();
+    assert!(same_meta(&self_meta, &tmpdir_path.metadata().unwrap()));
+    assert_eq!(meta.mount_id(), self_meta.mount_id());
+
+    assert_ne!(meta.is_immutable(), Some(true));
+    assert_ne!(meta.is_append_only(), Some(true));
+}
@@ -29,17 +29,14 @@ fn check_file_type(path: &Path, ftype: FileType) {
     // Then iterate through the parent directory and make sure the file type matches
     let fname = path.file_name().unwrap();
     let mut found = false;
-    for entry in Dir::open(path.parent().unwrap())
-        .unwrap()
-        .list_self()
-        .unwrap()
-    {
+    let parent = Dir::open(path.parent().unwrap()).unwrap();
+    for entry in parent.list_self().unwrap() {
         let entry = entry.unwrap();
         if fname == entry.name() {
             if let Some(entry_ftype) = entry.file_type() {
                 assert_eq!(entry_ftype, ftype);
             }
-            assert_eq!(entry.metadata().unwrap().file_type(), ftype);
+            assert_eq!(entry.metadata_in(&parent).unwrap().file_type(), ftype);
             found = true;
             break;
         }
@@ -0,0 +1,112 @@
+#![cfg(feature = "net")]
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::UnixStream;
+
+use obnth::io::send_file;
+
+fn make_file(len: usize) -> (tempfile::TempDir, fs::File, Vec<u8>) {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+    fs::write(tmpdir.path().join("file"), &data).unwrap();
+    let file = fs::File::open(tmpdir.path().join("file")).unwrap();
+    (tmpdir, file, data)
+}
+
+#[test]
+fn test_send_file_unix() {
+    let (_tmpdir, file, data) = make_file(100_000);
+
+    let (a, mut b) = UnixStream::pair().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        b.read_to_end(&mut buf).unwrap();
+        buf
+    });
+
+    assert_eq!(send_file(&file, &a, 0..100_000).unwrap(), 100_000);
+    assert_eq!(send_file(&file, &a, 10..20).unwrap(), 10);
+    // Past the end of the file
+    assert_eq!(send_file(&file, &a, 99_990..200_000).unwrap(), 10);
+    assert_eq!(send_file(&file, &a, 200_000..300_000).unwrap(), 0);
+    assert_eq!(send_file(&file, &a, 5..5).unwrap(), 0);
+    drop(a);
+
+    let mut expected = data.clone();
+    expected.extend_from_slice(&data[10..20]);
+    expected.extend_from_slice(&data[99_990..]);
+    assert_eq!(reader.join().unwrap(), expected);
+
+    // The file offset is unchanged
+    let mut file = file;
+    assert_eq!(file.stream_position().unwrap(), 0);
+}
+
+#[test]
+fn test_send_file_tcp() {
+    let (_tmpdir, file, data) = make_file(300_000);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let reader = std::thread::spawn(move || {
+        let (mut conn, _) = listener.accept().unwrap();
+        let mut buf = Vec::new();
+        conn.read_to_end(&mut buf).unwrap();
+        buf
+    });
+
+    let conn = TcpStream::connect(addr).unwrap();
+    assert_eq!(send_file(&file, &conn, 1000..250_000).unwrap(), 249_000);
+    drop(conn);
+
+    assert_eq!(reader.join().unwrap(), &data[1000..250_000]);
+}
+
+#[test]
+fn test_send_file_regular() {
+    let (tmpdir, file, data) = make_file(10_000);
+
+    let mut out = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(tmpdir.path().join("out"))
+        .unwrap();
+    assert_eq!(send_file(&file, &out, 100..5000).unwrap(), 4900);
+
+    let mut buf = Vec::new();
+    out.seek(SeekFrom::Start(0)).unwrap();
+    out.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, &data[100..5000]);
+}
+
+#[test]
+fn test_send_file_nonblocking() {
+    let (_tmpdir, file, _) = make_file(10_000_000);
+
+    let (a, _b) = UnixStream::pair().unwrap();
+    a.set_nonblocking(true).unwrap();
+
+    // The socket buffer fills up before the whole file can be sent
+    let n = send_file(&file, &a, 0..10_000_000).unwrap();
+    assert!(n > 0 && n < 10_000_000);
+    assert_eq!(
+        send_file(&file, &a, n..10_000_000).unwrap_err().kind(),
+        std::io::ErrorKind::WouldBlock
+    );
+}
+
+#[test]
+fn test_send_file_error() {
+    let (_tmpdir, file, _) = make_file(10);
+
+    let (a, _b) = UnixStream::pair().unwrap();
+    #[allow(clippy::reversed_empty_ranges)]
+    let range = 5..4;
+    assert_eq!(
+        send_file(&file, &a, range).unwrap_err().raw_os_error(),
+        Some(libc::EINVAL)
+    );
+}
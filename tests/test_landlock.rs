@@ -0,0 +1,56 @@
+#![cfg(all(feature = "landlock", target_os = "linux"))]
+
+use std::fs;
+
+use obnth::landlock::{self, AccessFs};
+use obnth::{Dir, LookupFlags};
+
+#[test]
+fn test_access_for_abi() {
+    assert_eq!(AccessFs::for_abi(0), AccessFs::empty());
+    assert!(!AccessFs::for_abi(1).contains(AccessFs::REFER));
+    assert!(AccessFs::for_abi(2).contains(AccessFs::REFER));
+    assert!(!AccessFs::for_abi(2).contains(AccessFs::TRUNCATE));
+    assert_eq!(AccessFs::for_abi(3), AccessFs::all());
+}
+
+#[test]
+fn test_restrict_to() {
+    match landlock::abi_version() {
+        Ok(_) => (),
+        Err(e)
+            if matches!(
+                e.raw_os_error(),
+                Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP)
+            ) =>
+        {
+            return
+        }
+        Err(e) => panic!("{}", e),
+    }
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.path().to_path_buf();
+
+    // Landlock restrictions are per-thread, so do this in a separate thread to avoid affecting
+    // the other tests
+    std::thread::spawn(move || {
+        let tmpdir = Dir::open(&tmpdir_path).unwrap();
+        let root = Dir::open("/").unwrap();
+
+        landlock::restrict_to(&[&tmpdir]).unwrap();
+
+        tmpdir.write("file", b"abc", LookupFlags::empty()).unwrap();
+        assert_eq!(tmpdir.read("file", LookupFlags::empty()).unwrap(), b"abc");
+        assert_eq!(fs::read(tmpdir_path.join("file")).unwrap(), b"abc");
+
+        for res in [
+            fs::read("/etc/passwd"),
+            root.read("etc/passwd", LookupFlags::empty()),
+        ] {
+            assert_eq!(res.unwrap_err().raw_os_error(), Some(libc::EACCES));
+        }
+    })
+    .join()
+    .unwrap();
+}
@@ -0,0 +1,60 @@
+#![cfg(feature = "mmap")]
+
+use std::fs;
+
+use obnth::{Dir, LookupFlags, MmapOptions};
+
+#[test]
+fn test_mmap_file() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    fs::create_dir(tmpdir_path.join("dir")).unwrap();
+    fs::write(tmpdir_path.join("empty"), b"").unwrap();
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(tmpdir_path.join("file"), &data).unwrap();
+    std::os::unix::fs::symlink("file", tmpdir_path.join("link")).unwrap();
+
+    let tmpdir = Dir::open(tmpdir_path).unwrap();
+    tmpdir.mkfifo("fifo", 0o600, LookupFlags::empty()).unwrap();
+
+    // Nothing else modifies these files while they're mapped
+    let map = unsafe { tmpdir.mmap_file("file", &MmapOptions::new()) }.unwrap();
+    assert_eq!(&map[..], &data[..]);
+    assert_eq!(map.metadata().len(), data.len() as u64);
+
+    let map = unsafe { tmpdir.mmap_file("link", MmapOptions::new().populate(true)) }.unwrap();
+    assert_eq!(&map[..], &data[..]);
+
+    // The mapping can be shared between threads
+    let map = std::sync::Arc::new(map);
+    let map2 = map.clone();
+    assert_eq!(
+        std::thread::spawn(move || map2.iter().map(|&b| b as u64).sum::<u64>())
+            .join()
+            .unwrap(),
+        data.iter().map(|&b| b as u64).sum::<u64>()
+    );
+
+    let map = unsafe { tmpdir.mmap_file("empty", &MmapOptions::new()) }.unwrap();
+    assert!(map.is_empty());
+
+    let check_err = |path, opts: &MmapOptions, eno| {
+        assert_eq!(
+            unsafe { tmpdir.mmap_file(path, opts) }
+                .unwrap_err()
+                .raw_os_error(),
+            Some(eno)
+        );
+    };
+
+    check_err("dir", &MmapOptions::new(), libc::EISDIR);
+    check_err("fifo", &MmapOptions::new(), libc::EINVAL);
+    check_err("../file", &MmapOptions::new(), libc::EXDEV);
+    check_err(
+        "link",
+        MmapOptions::new().lookup_flags(LookupFlags::NO_SYMLINKS),
+        libc::ELOOP,
+    );
+    check_err("file", MmapOptions::new().max_len(Some(1000)), libc::EFBIG);
+    unsafe { tmpdir.mmap_file("file", MmapOptions::new().max_len(Some(100_000))) }.unwrap();
+}
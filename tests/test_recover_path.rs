@@ -81,3 +81,106 @@ fn test_recover_path_tmp() {
         temp_dir.canonicalize().unwrap(),
     );
 }
+
+#[test]
+fn test_recover_file_path() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+
+    fs::create_dir_all(tmpdir_path.join("root/a/b")).unwrap();
+    fs::write(tmpdir_path.join("root/a/b/file"), b"").unwrap();
+    fs::write(tmpdir_path.join("outside"), b"").unwrap();
+
+    let root = Dir::open(tmpdir_path.join("root")).unwrap();
+
+    let file = root.open_file().read(true).open("a/b/file").unwrap();
+    assert_eq!(
+        obnth::recover_file_path(&file, &root).unwrap(),
+        Path::new("a/b/file")
+    );
+
+    // Relative to a subdirectory
+    let sub = root.sub_dir("a", obnth::LookupFlags::empty()).unwrap();
+    assert_eq!(
+        obnth::recover_file_path(&file, &sub).unwrap(),
+        Path::new("b/file")
+    );
+
+    // Directories work too
+    let file_b = fs::File::open(tmpdir_path.join("root/a/b")).unwrap();
+    assert_eq!(
+        obnth::recover_file_path(&file_b, &root).unwrap(),
+        Path::new("a/b")
+    );
+    let file_root = fs::File::open(tmpdir_path.join("root")).unwrap();
+    assert_eq!(
+        obnth::recover_file_path(&file_root, &root).unwrap(),
+        Path::new(".")
+    );
+
+    // Renaming is reflected
+    fs::rename(tmpdir_path.join("root/a/b"), tmpdir_path.join("root/c")).unwrap();
+    assert_eq!(
+        obnth::recover_file_path(&file, &root).unwrap(),
+        Path::new("c/file")
+    );
+
+    // Files outside the directory can't be found
+    let outside = fs::File::open(tmpdir_path.join("outside")).unwrap();
+    assert_eq!(
+        obnth::recover_file_path(&outside, &root)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOENT)
+    );
+    assert_eq!(
+        obnth::recover_file_path(&file, &sub)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOENT)
+    );
+
+    // Neither can deleted files
+    fs::remove_file(tmpdir_path.join("root/c/file")).unwrap();
+    assert_eq!(
+        obnth::recover_file_path(&file, &root)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOENT)
+    );
+}
+
+#[test]
+fn test_recover_path_from() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+
+    fs::create_dir_all(tmpdir_path.join("root/a/b/c")).unwrap();
+    fs::create_dir(tmpdir_path.join("other")).unwrap();
+
+    let root = Dir::open(tmpdir_path.join("root")).unwrap();
+    let a = Dir::open(tmpdir_path.join("root/a")).unwrap();
+    let c = Dir::open(tmpdir_path.join("root/a/b/c")).unwrap();
+    let other = Dir::open(tmpdir_path.join("other")).unwrap();
+
+    assert_eq!(c.recover_path_from(&root).unwrap(), Path::new("a/b/c"));
+    assert_eq!(c.recover_path_from(&a).unwrap(), Path::new("b/c"));
+    assert_eq!(a.recover_path_from(&root).unwrap(), Path::new("a"));
+    assert_eq!(root.recover_path_from(&root).unwrap(), Path::new("."));
+
+    // Not beneath the ancestor
+    for (dir, ancestor) in [(&root, &a), (&root, &c), (&other, &root), (&c, &other)].iter() {
+        assert_eq!(
+            dir.recover_path_from(ancestor).unwrap_err().raw_os_error(),
+            Some(libc::ENOENT)
+        );
+    }
+
+    // Renames are reflected
+    fs::rename(tmpdir_path.join("root/a/b"), tmpdir_path.join("other/d")).unwrap();
+    assert_eq!(c.recover_path_from(&other).unwrap(), Path::new("d/c"));
+    assert_eq!(
+        c.recover_path_from(&root).unwrap_err().raw_os_error(),
+        Some(libc::ENOENT)
+    );
+}
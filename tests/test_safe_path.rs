@@ -0,0 +1,319 @@
+use std::fs;
+use std::path::Path;
+
+use obnth::{Dir, LookupFlags, SafePath, SafePathError, SafePathPolicy};
+
+#[test]
+fn test_safe_path_normalize() {
+    let policy = SafePathPolicy {
+        allow_dotdot: true,
+        allow_absolute: true,
+        ..Default::default()
+    };
+
+    for &(path, expected) in [
+        ("a", "a"),
+        ("a/b/", "a/b"),
+        ("./a//b/.", "a/b"),
+        ("", "."),
+        (".", "."),
+        ("/", "."),
+        ("//a/./b", "a/b"),
+        ("a/../b", "a/../b"),
+        ("a/b/../..", "a/b/../.."),
+    ]
+    .iter()
+    {
+        assert_eq!(
+            SafePath::new(path, &policy).unwrap().as_path(),
+            Path::new(expected),
+            "{:?}",
+            path
+        );
+    }
+
+    assert_eq!(
+        SafePath::new("a/../..", &policy).unwrap_err(),
+        SafePathError::Escape
+    );
+    assert_eq!(
+        SafePath::new("/..", &policy).unwrap_err(),
+        SafePathError::Escape
+    );
+    assert_eq!(
+        SafePath::new("a\0b", &policy).unwrap_err(),
+        SafePathError::Nul
+    );
+}
+
+#[test]
+fn test_safe_path_policy() {
+    let policy = SafePathPolicy::default();
+
+    assert_eq!(
+        SafePath::new("/a", &policy).unwrap_err(),
+        SafePathError::Absolute
+    );
+    assert_eq!(
+        SafePath::new("a/../b", &policy).unwrap_err(),
+        SafePathError::DotDot
+    );
+
+    let policy = SafePathPolicy {
+        max_depth: 2,
+        max_len: 5,
+        ..Default::default()
+    };
+    SafePath::new("./a/./b/", &policy).unwrap();
+    assert_eq!(
+        SafePath::new("a/b/c", &policy).unwrap_err(),
+        SafePathError::TooDeep
+    );
+    SafePath::new("ab/cd", &policy).unwrap();
+    assert_eq!(
+        SafePath::new("abc/de/", &policy).unwrap_err(),
+        SafePathError::TooLong
+    );
+
+    let err: std::io::Error = SafePathError::TooLong.into();
+    assert_eq!(err.raw_os_error(), Some(libc::ENAMETOOLONG));
+    assert_eq!(
+        SafePathError::DotDot.to_string(),
+        "'..' components are not allowed"
+    );
+}
+
+#[test]
+fn test_safe_path_matches_resolver() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.as_ref();
+    fs::create_dir(tmpdir_path.join("a")).unwrap();
+    fs::write(tmpdir_path.join("a/file"), b"").unwrap();
+    let dir = Dir::open(tmpdir_path).unwrap();
+
+    for &(allow_dotdot, allow_absolute, lookup_flags) in [
+        (false, false, LookupFlags::NO_DOTDOT),
+        (true, false, LookupFlags::empty()),
+        (true, true, LookupFlags::IN_ROOT),
+    ]
+    .iter()
+    {
+        let policy = SafePathPolicy {
+            allow_dotdot,
+            allow_absolute,
+            ..Default::default()
+        };
+
+        for &path in [
+            "a/file",
+            "/a/file",
+            "a/../a/file",
+            "../a/file",
+            "a/../../a/file",
+        ]
+        .iter()
+        {
+            let safe = SafePath::new(path, &policy);
+            let res = dir
+                .open_file()
+                .read(true)
+                .lookup_flags(lookup_flags)
+                .open(path);
+
+            match safe {
+                Ok(safe) => {
+                    // The resolver accepts the path (and the normalized path)
+                    res.unwrap();
+                    dir.open_file()
+                        .read(true)
+                        .lookup_flags(lookup_flags)
+                        .open(&safe)
+                        .unwrap();
+                }
+                Err(SafePathError::Escape) if allow_absolute => {
+                    // IN_ROOT clamps the path instead
+                    res.unwrap();
+                }
+                Err(e) => {
+                    assert_eq!(res.unwrap_err().raw_os_error(), Some(e.raw_os_error()))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "unicode-normalization"))]
+#[test]
+fn test_safe_path_unicode_normalization_unsupported() {
+    let policy = SafePathPolicy {
+        normalization: Some(obnth::UnicodeNormalization::Nfc),
+        ..Default::default()
+    };
+    let err = SafePath::new("a", &policy).unwrap_err();
+    assert_eq!(err, SafePathError::NormalizationUnsupported);
+    assert_eq!(err.raw_os_error(), libc::EOPNOTSUPP);
+}
+
+#[cfg(feature = "unicode-normalization")]
+#[test]
+fn test_safe_path_unicode_normalization() {
+    use std::ffi::OsStr;
+    use std::os::unix::prelude::*;
+
+    use obnth::UnicodeNormalization;
+
+    let composed = "caf\u{e9}/r\u{e9}sum\u{e9}";
+    let decomposed = "cafe\u{301}/re\u{301}sume\u{301}";
+
+    let nfc = SafePathPolicy {
+        normalization: Some(UnicodeNormalization::Nfc),
+        ..Default::default()
+    };
+    let nfd = SafePathPolicy {
+        normalization: Some(UnicodeNormalization::Nfd),
+        ..Default::default()
+    };
+
+    for &path in [composed, decomposed].iter() {
+        assert_eq!(
+            SafePath::new(path, &nfc).unwrap().as_path(),
+            Path::new(composed)
+        );
+        assert_eq!(
+            SafePath::new(path, &nfd).unwrap().as_path(),
+            Path::new(decomposed)
+        );
+        // Not normalized by default
+        assert_eq!(
+            SafePath::new(path, &SafePathPolicy::default())
+                .unwrap()
+                .as_path(),
+            Path::new(path)
+        );
+    }
+
+    // The length limit applies to the normalized path
+    let policy = SafePathPolicy {
+        max_len: composed.len(),
+        ..nfd
+    };
+    assert_eq!(
+        SafePath::new(composed, &policy).unwrap_err(),
+        SafePathError::TooLong
+    );
+
+    // Components that aren't valid UTF-8 are left alone
+    let invalid = OsStr::from_bytes(b"a\xff/cafe\xcc\x81");
+    assert_eq!(
+        SafePath::new(Path::new(invalid), &nfc)
+            .unwrap()
+            .as_path()
+            .as_os_str()
+            .as_bytes(),
+        &b"a\xff/caf\xc3\xa9"[..]
+    );
+
+    // Opening, inspecting, and creating files agree on the name
+    let tmpdir = tempfile::tempdir().unwrap();
+    let dir = Dir::open(tmpdir.as_ref()).unwrap();
+    dir.create_dir(
+        SafePath::new("caf\u{e9}", &nfc).unwrap(),
+        0o755,
+        LookupFlags::empty(),
+    )
+    .unwrap();
+    let path = SafePath::new(decomposed, &nfc).unwrap();
+    dir.open_file()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .unwrap();
+    assert!(dir.metadata(&path, LookupFlags::empty()).unwrap().is_file());
+    assert!(fs::metadata(tmpdir.as_ref().join(composed))
+        .unwrap()
+        .is_file());
+}
+
+#[cfg(not(feature = "unicode-normalization"))]
+#[test]
+fn test_lookup_flags_unicode_normalization_unsupported() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let dir = Dir::open(tmpdir.as_ref()).unwrap();
+
+    for &flags in [LookupFlags::NORMALIZE_NFC, LookupFlags::NORMALIZE_NFD].iter() {
+        assert_eq!(
+            dir.create_dir("a", 0o755, flags)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EOPNOTSUPP)
+        );
+        assert_eq!(
+            dir.sub_dir(".", flags).unwrap_err().raw_os_error(),
+            Some(libc::EOPNOTSUPP)
+        );
+    }
+}
+
+#[cfg(feature = "unicode-normalization")]
+#[test]
+fn test_lookup_flags_unicode_normalization() {
+    let composed = "caf\u{e9}/r\u{e9}sum\u{e9}";
+    let decomposed = "cafe\u{301}/re\u{301}sume\u{301}";
+
+    let tmpdir = tempfile::tempdir().unwrap();
+    let dir = Dir::open(tmpdir.as_ref()).unwrap();
+
+    // Creating, opening, and inspecting files agree on the name, whichever way it is encoded
+    dir.create_dir("cafe\u{301}", 0o755, LookupFlags::NORMALIZE_NFC)
+        .unwrap();
+    dir.open_file()
+        .write(true)
+        .create_new(true)
+        .lookup_flags(LookupFlags::NORMALIZE_NFC)
+        .open(decomposed)
+        .unwrap();
+    assert!(fs::metadata(tmpdir.as_ref().join(composed))
+        .unwrap()
+        .is_file());
+
+    for &path in [composed, decomposed].iter() {
+        assert!(dir
+            .metadata(path, LookupFlags::NORMALIZE_NFC)
+            .unwrap()
+            .is_file());
+        dir.open_file()
+            .lookup_flags(LookupFlags::NORMALIZE_NFC)
+            .open(path)
+            .unwrap();
+        assert_eq!(
+            dir.canonicalize(path, LookupFlags::NORMALIZE_NFC).unwrap(),
+            Path::new(composed)
+        );
+    }
+
+    // Without the flag, the decomposed name doesn't match
+    assert_eq!(
+        dir.metadata(decomposed, LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOENT)
+    );
+
+    // NFD finds a decomposed name
+    dir.create_dir("r\u{e9}sum\u{e9}", 0o755, LookupFlags::NORMALIZE_NFD)
+        .unwrap();
+    assert!(fs::metadata(tmpdir.as_ref().join("re\u{301}sume\u{301}"))
+        .unwrap()
+        .is_dir());
+
+    // Only one normalization form can be requested
+    assert_eq!(
+        dir.metadata(
+            composed,
+            LookupFlags::NORMALIZE_NFC | LookupFlags::NORMALIZE_NFD
+        )
+        .unwrap_err()
+        .raw_os_error(),
+        Some(libc::EINVAL)
+    );
+}
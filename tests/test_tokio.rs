@@ -0,0 +1,105 @@
+#![cfg(feature = "tokio")]
+
+use std::fs;
+use std::time::Instant;
+
+use obnth::tokio::AsyncDir;
+use obnth::{FileType, LookupFlags};
+
+#[test]
+fn test_async_dir() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir_path = tmpdir.path();
+
+    fs::create_dir(tmpdir_path.join("a")).unwrap();
+    fs::write(tmpdir_path.join("a/file"), b"abc").unwrap();
+
+    let rt = tokio_rs::runtime::Runtime::new().unwrap();
+
+    rt.block_on(async {
+        let dir = AsyncDir::open(tmpdir_path).await.unwrap();
+
+        let meta = dir.metadata("a/file", LookupFlags::empty()).await.unwrap();
+        assert_eq!(meta.file_type(), FileType::File);
+        assert_eq!(meta.len(), 3);
+
+        let file = dir.open_file().read(true).open("a/file").await.unwrap();
+        assert_eq!(file.metadata().await.unwrap().len(), 3);
+
+        let file = dir
+            .open_file()
+            .read(true)
+            .try_cached(true)
+            .open("a/file")
+            .await
+            .unwrap();
+        assert_eq!(file.metadata().await.unwrap().len(), 3);
+
+        // The options are forwarded to OpenOptions
+        dir.open_file()
+            .read(true)
+            .expect(Some(FileType::File))
+            .open("a/file")
+            .await
+            .unwrap();
+        assert_eq!(
+            dir.open_file()
+                .read(true)
+                .deadline(Some(Instant::now()))
+                .open("a/file")
+                .await
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ETIMEDOUT)
+        );
+
+        dir.open_file()
+            .write(true)
+            .create_new(true)
+            .open("a/new")
+            .await
+            .unwrap();
+        assert_eq!(
+            dir.open_file()
+                .write(true)
+                .create_new(true)
+                .open("a/new")
+                .await
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EEXIST)
+        );
+
+        dir.create_dir("b", 0o777, LookupFlags::empty())
+            .await
+            .unwrap();
+        dir.local_rename("a/new", "b/new", LookupFlags::empty())
+            .await
+            .unwrap();
+
+        let sub = dir.sub_dir("b", LookupFlags::empty()).await.unwrap();
+        let entries = sub.read_dir(".", LookupFlags::empty()).await.unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.name()).collect::<Vec<_>>(),
+            vec!["new"]
+        );
+        sub.remove_file("new", LookupFlags::empty()).await.unwrap();
+        dir.remove_dir("b", LookupFlags::empty()).await.unwrap();
+
+        // Escaping is still prevented
+        assert_eq!(
+            dir.metadata("..", LookupFlags::empty())
+                .await
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EXDEV)
+        );
+
+        // Arbitrary operations
+        let path = dir
+            .run(|dir| dir.canonicalize("a/./file", LookupFlags::empty()))
+            .await
+            .unwrap();
+        assert_eq!(path, std::path::Path::new("a/file"));
+    });
+}
@@ -0,0 +1,211 @@
+#![cfg(all(feature = "io-uring", target_os = "linux"))]
+
+use std::fs;
+use std::io::Read;
+use std::os::unix::prelude::*;
+
+use obnth::uring::{self, Ring};
+use obnth::{Dir, FileType, LookupFlags};
+
+fn setup() -> (tempfile::TempDir, Dir) {
+    let tmpdir = tempfile::tempdir().unwrap();
+
+    fs::create_dir(tmpdir.path().join("a")).unwrap();
+    fs::write(tmpdir.path().join("a/b"), b"b").unwrap();
+    fs::write(tmpdir.path().join("c"), b"c").unwrap();
+    std::os::unix::fs::symlink("../c", tmpdir.path().join("a/link")).unwrap();
+    std::os::unix::fs::symlink("../..", tmpdir.path().join("a/up")).unwrap();
+
+    let dir = Dir::open(tmpdir.path()).unwrap();
+    (tmpdir, dir)
+}
+
+fn read_all(mut file: fs::File) -> Vec<u8> {
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn test_open_many() {
+    let (_tmpdir, dir) = setup();
+
+    let paths = ["a/b", "c", "a/link", "noexist", "a/up/c", "../c", "a/../c"];
+
+    let check = |results: Vec<std::io::Result<fs::File>>| {
+        let mut results = results.into_iter();
+        assert_eq!(read_all(results.next().unwrap().unwrap()), b"b");
+        assert_eq!(read_all(results.next().unwrap().unwrap()), b"c");
+        assert_eq!(read_all(results.next().unwrap().unwrap()), b"c");
+        assert_eq!(
+            results.next().unwrap().unwrap_err().raw_os_error(),
+            Some(libc::ENOENT)
+        );
+        assert_eq!(
+            results.next().unwrap().unwrap_err().raw_os_error(),
+            Some(libc::EXDEV)
+        );
+        assert_eq!(
+            results.next().unwrap().unwrap_err().raw_os_error(),
+            Some(libc::EXDEV)
+        );
+        assert_eq!(read_all(results.next().unwrap().unwrap()), b"c");
+        assert!(results.next().is_none());
+    };
+
+    check(uring::open_many(
+        &dir,
+        &paths,
+        libc::O_RDONLY,
+        0,
+        LookupFlags::empty(),
+    ));
+
+    let mut ring = match Ring::with_entries(2) {
+        Ok(ring) => ring,
+        Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSYS) | Some(libc::EPERM)) => return,
+        Err(e) => panic!("{}", e),
+    };
+
+    // Split up into several chunks, and reused
+    for _ in 0..2 {
+        check(ring.open_many(&dir, &paths, libc::O_RDONLY, 0, LookupFlags::empty()));
+    }
+
+    // Lookup flags that openat2() can't handle fall back on the normal method
+    let mut results = ring.open_many(
+        &dir,
+        &["a/b", "a/../c"],
+        libc::O_RDONLY,
+        0,
+        LookupFlags::NO_DOTDOT,
+    );
+    assert_eq!(read_all(results.remove(0).unwrap()), b"b");
+    assert_eq!(
+        results.remove(0).unwrap_err().raw_os_error(),
+        Some(libc::EXDEV)
+    );
+
+    let results = ring.open_many(
+        &dir,
+        &["a/link", "a/b"],
+        libc::O_RDONLY,
+        0,
+        LookupFlags::NO_SYMLINKS,
+    );
+    assert_eq!(
+        results[0].as_ref().unwrap_err().raw_os_error(),
+        Some(libc::ELOOP)
+    );
+    assert!(results[1].is_ok());
+
+    // The files are opened close-on-exec
+    let results = ring.open_many(&dir, &["c"], libc::O_RDONLY, 0, LookupFlags::empty());
+    let fd = results[0].as_ref().unwrap().as_raw_fd();
+    assert_ne!(
+        unsafe { libc::fcntl(fd, libc::F_GETFD) } & libc::FD_CLOEXEC,
+        0
+    );
+
+    // Invalid flags are rejected before doing anything
+    let results = ring.open_many(
+        &dir,
+        &["new"],
+        libc::O_RDONLY | libc::O_CREAT | libc::O_DIRECTORY,
+        0o600,
+        LookupFlags::empty(),
+    );
+    assert_eq!(
+        results[0].as_ref().unwrap_err().raw_os_error(),
+        Some(libc::EINVAL)
+    );
+
+    assert!(ring
+        .open_many::<&str>(&dir, &[], libc::O_RDONLY, 0, LookupFlags::empty())
+        .is_empty());
+}
+
+#[test]
+fn test_open_many_protected_symlinks() {
+    let (tmpdir, dir) = setup();
+
+    fs::create_dir(tmpdir.path().join("sticky")).unwrap();
+    fs::set_permissions(
+        tmpdir.path().join("sticky"),
+        fs::Permissions::from_mode(0o1777),
+    )
+    .unwrap();
+    std::os::unix::fs::symlink("../c", tmpdir.path().join("sticky/own")).unwrap();
+    std::os::unix::fs::symlink("../c", tmpdir.path().join("sticky/other")).unwrap();
+
+    // Only root can create a symlink owned by another user
+    let is_root = unsafe { libc::geteuid() } == 0;
+    if is_root {
+        let path = std::ffi::CString::new(
+            tmpdir
+                .path()
+                .join("sticky/other")
+                .into_os_string()
+                .into_vec(),
+        )
+        .unwrap();
+        assert_eq!(unsafe { libc::lchown(path.as_ptr(), 4321, 4321) }, 0);
+    }
+
+    let mut ring = match Ring::with_entries(2) {
+        Ok(ring) => ring,
+        Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSYS) | Some(libc::EPERM)) => return,
+        Err(e) => panic!("{}", e),
+    };
+
+    let mut results = ring.open_many(
+        &dir,
+        &["sticky/own", "sticky/other"],
+        libc::O_RDONLY,
+        0,
+        LookupFlags::PROTECTED_SYMLINKS,
+    );
+    assert_eq!(read_all(results.remove(0).unwrap()), b"c");
+    if is_root {
+        assert_eq!(
+            results[0].as_ref().unwrap_err().raw_os_error(),
+            Some(libc::EACCES)
+        );
+    } else {
+        assert!(results[0].is_ok());
+    }
+}
+
+#[test]
+fn test_metadata_many() {
+    let (_tmpdir, dir) = setup();
+
+    let mut ring = match Ring::new() {
+        Ok(ring) => ring,
+        Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSYS) | Some(libc::EPERM)) => return,
+        Err(e) => panic!("{}", e),
+    };
+
+    let paths = ["a/b", "a", "a/link", ".", "noexist", "a/up/c"];
+    let results = ring.metadata_many(&dir, &paths, LookupFlags::empty());
+    assert_eq!(results.len(), paths.len());
+
+    for (path, res) in paths.iter().zip(results.iter()) {
+        match dir.metadata(*path, LookupFlags::empty()) {
+            Ok(meta) => {
+                let res = res.as_ref().unwrap();
+                assert_eq!(res.file_type(), meta.file_type(), "{}", path);
+                assert_eq!(res.ino(), meta.ino(), "{}", path);
+                assert_eq!(res.dev(), meta.dev(), "{}", path);
+            }
+            Err(e) => assert_eq!(
+                res.as_ref().unwrap_err().raw_os_error(),
+                e.raw_os_error(),
+                "{}",
+                path
+            ),
+        }
+    }
+
+    assert_eq!(results[2].as_ref().unwrap().file_type(), FileType::Symlink);
+}
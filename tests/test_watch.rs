@@ -0,0 +1,64 @@
+#![cfg(feature = "watch")]
+
+use obnth::watch::{WatchMask, Watcher};
+use obnth::{Dir, LookupFlags};
+
+#[test]
+fn test_watch() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let tmpdir = Dir::open(tmpdir.as_ref()).unwrap();
+
+    tmpdir
+        .create_dir("dir", 0o777, LookupFlags::empty())
+        .unwrap();
+    tmpdir.write("file", b"", LookupFlags::empty()).unwrap();
+    tmpdir
+        .symlink("link", "file", LookupFlags::empty())
+        .unwrap();
+
+    let mut watcher = Watcher::new().unwrap();
+    let dir_id = watcher
+        .add(&tmpdir, "dir", WatchMask::MODIFY, LookupFlags::empty())
+        .unwrap();
+    // Symlinks are followed (within the directory)
+    let file_id = watcher
+        .add(
+            &tmpdir,
+            "link",
+            WatchMask::MODIFY | WatchMask::DELETE,
+            LookupFlags::empty(),
+        )
+        .unwrap();
+
+    tmpdir.write("dir/new", b"", LookupFlags::empty()).unwrap();
+    let events = watcher.read_events().unwrap();
+    assert!(!events.is_empty());
+    assert!(events
+        .iter()
+        .all(|e| e.id() == dir_id && e.mask().contains(WatchMask::MODIFY)));
+
+    watcher.remove(dir_id).unwrap();
+
+    tmpdir.write("file", b"abc", LookupFlags::empty()).unwrap();
+    let events = watcher.read_events().unwrap();
+    assert!(!events.is_empty());
+    assert!(events
+        .iter()
+        .all(|e| e.id() == file_id && e.mask() == WatchMask::MODIFY));
+
+    tmpdir
+        .watch("dir", WatchMask::all(), LookupFlags::empty())
+        .unwrap();
+    // Watching a FIFO doesn't block waiting for a writer
+    tmpdir.mkfifo("fifo", 0o600, LookupFlags::empty()).unwrap();
+    watcher
+        .add(&tmpdir, "fifo", WatchMask::MODIFY, LookupFlags::empty())
+        .unwrap();
+    assert_eq!(
+        tmpdir
+            .watch("../x", WatchMask::MODIFY, LookupFlags::empty())
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EXDEV)
+    );
+}